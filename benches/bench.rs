@@ -0,0 +1,159 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use simplicity::bench_util;
+use simplicity::exec::BitMachine;
+use simplicity::extension::dummy::{DummyNode, TxEnv};
+use simplicity::{Program, Value};
+
+type Generator = fn(usize) -> (Program<DummyNode>, Value);
+
+const GENERATORS: [(&str, Generator, usize); 4] = [
+    ("deep_comp_chain", bench_util::deep_comp_chain, 1000),
+    ("wide_pair_tree", bench_util::wide_pair_tree, 10),
+    ("sha256_chain", bench_util::sha256_chain, 100),
+    ("case_ladder", bench_util::case_ladder, 1000),
+];
+
+fn bench_exec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("exec");
+    for &(name, generator, n) in &GENERATORS {
+        let (prog, input) = generator(n);
+        group.bench_function(BenchmarkId::new(name, n), |b| {
+            b.iter(|| {
+                let mut mac = BitMachine::for_program(&prog);
+                mac.input(&input).unwrap();
+                mac.exec(&prog, &TxEnv)
+            })
+        });
+    }
+    group.finish();
+}
+
+/// As `bench_exec`, but reusing one machine so that only execution is timed
+fn bench_exec_reused(c: &mut Criterion) {
+    let mut group = c.benchmark_group("exec_reused");
+    for &(name, generator, n) in &GENERATORS {
+        let (prog, input) = generator(n);
+        let mut mac = BitMachine::for_program(&prog);
+        group.bench_function(BenchmarkId::new(name, n), |b| {
+            b.iter(|| {
+                mac.reset();
+                mac.input(&input).unwrap();
+                mac.exec(&prog, &TxEnv)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for &(name, generator, n) in &GENERATORS {
+        let bytes = generator(n).0.serialize();
+        group.bench_function(BenchmarkId::new(name, n), |b| {
+            b.iter(|| Program::<DummyNode>::deserialize(&bytes).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for &(name, generator, n) in &GENERATORS {
+        let prog = generator(n).0;
+        group.bench_function(BenchmarkId::new(name, n), |b| b.iter(|| prog.serialize()));
+    }
+    group.finish();
+}
+
+fn bench_exec_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("exec_batch");
+    let (prog, input) = bench_util::deep_comp_chain(100);
+    for &n in &[100, 1000] {
+        let inputs = vec![input.clone(); n];
+        group.bench_function(BenchmarkId::new("per_call", n), |b| {
+            b.iter(|| {
+                inputs
+                    .iter()
+                    .map(|input| {
+                        let mut mac = BitMachine::for_program(&prog);
+                        mac.input(input).unwrap();
+                        mac.exec(&prog, &TxEnv)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_function(BenchmarkId::new("batch", n), |b| {
+            b.iter(|| prog.apply_batch(&inputs, &TxEnv))
+        });
+        #[cfg(feature = "rayon")]
+        group.bench_function(BenchmarkId::new("batch_par", n), |b| {
+            b.iter(|| prog.apply_batch_parallel(&inputs, &TxEnv))
+        });
+    }
+    group.finish();
+}
+
+fn bench_witness(c: &mut Criterion) {
+    let (prog, input) = bench_util::witness_hash_block();
+    c.bench_function("witness_hash_block", |b| {
+        b.iter(|| {
+            let mut mac = BitMachine::for_program(&prog);
+            mac.input(&input).unwrap();
+            mac.exec(&prog, &TxEnv)
+        })
+    });
+}
+
+/// Copying and running a program whose one witness node is shared by
+/// many others
+fn bench_shared_witness(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shared_witness");
+    let (prog, input) = bench_util::shared_witness(100);
+    group.bench_function("to_flat", |b| b.iter(|| prog.to_flat()));
+    group.bench_function("exec", |b| {
+        b.iter(|| {
+            let mut mac = BitMachine::for_program(&prog);
+            mac.input(&input).unwrap();
+            mac.exec(&prog, &TxEnv)
+        })
+    });
+    group.finish();
+}
+
+fn bench_frame_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_bytes");
+    for &(name, offset) in &[("aligned", 0), ("unaligned", 3)] {
+        group.bench_function(name, |b| {
+            b.iter(|| bench_util::frame_block_copy(offset, 100))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_exec,
+    bench_exec_reused,
+    bench_decode,
+    bench_encode,
+    bench_exec_batch,
+    bench_witness,
+    bench_shared_witness,
+    bench_frame_bytes
+);
+criterion_main!(benches);
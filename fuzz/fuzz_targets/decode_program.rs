@@ -0,0 +1,63 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+extern crate simplicity;
+
+use simplicity::bititer::BitIter;
+use simplicity::extension::dummy::DummyNode;
+use simplicity::Program;
+
+/// Largest input to try decoding; longer inputs only slow the fuzzer down
+/// without reaching any new code
+const MAX_LEN: usize = 64 * 1024;
+
+fn do_test(data: &[u8]) {
+    if data.len() > MAX_LEN {
+        return;
+    }
+    let mut iter = BitIter::new(data.iter().cloned());
+    // Decoding, including typechecking and witness decoding, must fail
+    // cleanly on bad input rather than panic
+    let _ = Program::<DummyNode>::decode(&mut iter);
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn empty_and_truncated() {
+        super::do_test(&[]);
+        super::do_test(&[0xff]);
+        super::do_test(&[0x91, 0x91]);
+    }
+}
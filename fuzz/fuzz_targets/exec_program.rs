@@ -0,0 +1,144 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+extern crate simplicity;
+
+use simplicity::bititer::BitIter;
+use simplicity::exec::BitMachine;
+use simplicity::extension::dummy::{DummyNode, TxEnv};
+use simplicity::{Program, Term, Value};
+
+/// Largest input to try decoding; longer inputs only slow the fuzzer down
+/// without reaching any new code
+const MAX_LEN: usize = 64 * 1024;
+/// Most Bit Machine memory, in bytes, to run a program with
+const MAX_MEMORY: usize = 1024 * 1024;
+/// Most nodes a program may execute; shared nodes let a short program run
+/// for exponentially many steps, which would hang the fuzzer
+const MAX_STEPS: u64 = 1 << 20;
+
+/// Upper bound on the number of nodes executed by running each node of a
+/// program, taking the longer branch of each `case`
+fn step_bounds(prog: &Program<DummyNode>) -> Vec<u64> {
+    let mut bounds = Vec::<u64>::with_capacity(prog.nodes.len());
+    for node in &prog.nodes {
+        let (left, right) = node.children();
+        let (left, right) = (left.map(|i| bounds[i]), right.map(|j| bounds[j]));
+        let children = match node.node {
+            Term::Case(..) => std::cmp::max(left.unwrap_or(0), right.unwrap_or(0)),
+            _ => left.unwrap_or(0).saturating_add(right.unwrap_or(0)),
+        };
+        bounds.push(children.saturating_add(1));
+    }
+    bounds
+}
+
+fn do_test(data: &[u8]) {
+    if data.len() > MAX_LEN {
+        return;
+    }
+    let mut iter = BitIter::new(data.iter().cloned());
+    let prog = match Program::<DummyNode>::decode(&mut iter) {
+        Ok(prog) => prog,
+        Err(_) => return,
+    };
+    if step_bounds(&prog).last().map_or(true, |&steps| steps > MAX_STEPS) {
+        return;
+    }
+    // The program's input is read from the bits following it
+    let input = match Value::from_bits_and_type(&mut iter, &prog.root_node().source_ty) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    let mut mac = match BitMachine::try_for_program(&prog, MAX_MEMORY) {
+        Ok(mac) => mac,
+        Err(_) => return,
+    };
+    // Failures, e.g. from `fail` or hidden nodes or jet assertions, are
+    // reported as errors; any panic is a bug
+    let _ = mac.exec_with_input(&prog, &input, &TxEnv);
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iden_unit_input() {
+        // iden, with and without its (empty) input
+        super::do_test(&[0x24]);
+        super::do_test(&[]);
+    }
+
+    #[test]
+    fn failing_assertion() {
+        use simplicity::encode::{BitWrite, BitWriter};
+        use simplicity::extension::jets::JetsNode;
+        use simplicity::{Term, UnTypedProg};
+
+        // eqv32 on unequal words fails without panicking
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Jet(JetsNode::EqV32)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let input = Value::prod(Value::u32(7), Value::u32(8));
+        let mut w = BitWriter::new(vec![]);
+        prog.encode(&mut w).unwrap();
+        for bit in input.iter_bits() {
+            w.write_bit(bit).unwrap();
+        }
+        w.flush_all().unwrap();
+        super::do_test(&w.into_inner());
+    }
+
+    #[test]
+    fn shared_comp_bound() {
+        use simplicity::UnTypedProg;
+
+        // Each `comp` runs the one before it twice
+        let mut nodes = vec![Term::Iden];
+        for _ in 0..100 {
+            nodes.push(Term::Comp(1, 1));
+        }
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(nodes),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let bounds = step_bounds(&prog);
+        assert_eq!(bounds[3], 15);
+        assert_eq!(*bounds.last().unwrap(), u64::max_value());
+    }
+}
@@ -0,0 +1,70 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+extern crate simplicity;
+
+use simplicity::bititer::BitIter;
+use simplicity::extension::dummy::DummyNode;
+use simplicity::Program;
+
+/// Largest input to try decoding; longer inputs only slow the fuzzer down
+/// without reaching any new code
+const MAX_LEN: usize = 64 * 1024;
+
+fn do_test(data: &[u8]) {
+    if data.len() > MAX_LEN {
+        return;
+    }
+    let mut iter = BitIter::new(data.iter().cloned());
+    if let Ok(prog) = Program::<DummyNode>::decode(&mut iter) {
+        // Programs, with their witnesses, survive a round trip, and
+        // re-encoding a decoded program changes nothing
+        let encoded = prog.serialize();
+        let decoded = Program::<DummyNode>::deserialize(&encoded).expect("decoding encoding");
+        assert_eq!(decoded.serialize(), encoded);
+        assert_eq!(decoded.root_node().cmr, prog.root_node().cmr);
+        assert_eq!(decoded.encoded_bit_len(), prog.encoded_bit_len());
+    }
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn witness_program() {
+        // test_vectors/text/witness.simpl, with a witness of 3
+        super::do_test(&[
+            0xc5, 0xd0, 0x28, 0xc0, 0x11, 0xe2, 0x00, 0x00, 0x00, 0x00, 0x10,
+        ]);
+    }
+}
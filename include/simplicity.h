@@ -0,0 +1,55 @@
+/* Rust Simplicity Library
+ *
+ * To the extent possible under law, the author(s) have dedicated all
+ * copyright and related and neighboring rights to this software to
+ * the public domain worldwide. This software is distributed without
+ * any warranty.
+ *
+ * C declarations for the functions of `src/capi.rs`, available when the
+ * crate is built with the `capi` feature. See that file for the
+ * ownership rules and the meaning of each status code.
+ */
+
+#ifndef SIMPLICITY_H
+#define SIMPLICITY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SIMPLICITY_OK 0
+#define SIMPLICITY_ERR_NULL_POINTER 1
+#define SIMPLICITY_ERR_DECODE 2
+#define SIMPLICITY_ERR_EXEC 3
+#define SIMPLICITY_ERR_BUFFER_TOO_SMALL 4
+#define SIMPLICITY_ERR_UNSUPPORTED 5
+#define SIMPLICITY_ERR_PANIC 6
+#define SIMPLICITY_ERR_MEMORY_LIMIT 7
+
+/* A decoded program, owned by the caller until passed to simplicity_free */
+typedef struct SimplicityProgram SimplicityProgram;
+
+/* Decode a program, with its witness data, storing a new handle in *out */
+int simplicity_decode(const uint8_t *bytes, size_t len, SimplicityProgram **out);
+
+/* Write the 32-byte CMR of a program to out32 */
+int simplicity_cmr(const SimplicityProgram *handle, uint8_t *out32);
+
+/* Run a program, which must take no input, writing its output to out_buf.
+ * The Bit Machine may use at most max_bytes bytes of memory. On entry
+ * *out_len is the size of out_buf; on return it is the size of the
+ * output. env is reserved and must be NULL. */
+int simplicity_exec(const SimplicityProgram *handle, const void *env,
+                    size_t max_bytes, uint8_t *out_buf, size_t *out_len);
+
+/* Free a program handle; does nothing if handle is NULL */
+void simplicity_free(SimplicityProgram *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SIMPLICITY_H */
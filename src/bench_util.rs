@@ -0,0 +1,247 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Benchmark Utilities
+//!
+//! Generators for families of programs with a tunable size, each returned
+//! along with a valid input, so that benchmarks of the decoder and the Bit
+//! Machine have stable workloads.
+//!
+
+use crate::bit_machine::frame::Frame;
+use crate::bititer::BitIter;
+use crate::encode::{self, BitWrite, BitWriter};
+use crate::extension::dummy::DummyNode;
+use crate::extension::jets::JetsNode;
+use crate::{Program, Term, UnTypedProg, Value};
+
+/// Finalize a list of witness-free nodes into a program
+fn finalize(nodes: Vec<Term<(), DummyNode>>) -> Program<DummyNode> {
+    Program::from_untyped_nodes(
+        UnTypedProg(nodes),
+        &mut BitIter::from(vec![0x00].into_iter()),
+    )
+    .expect("generated program typechecks")
+}
+
+/// `iden >>> (iden >>> ... (iden >>> adder32))` with `n` compositions,
+/// each of which allocates and copies a 64-bit frame
+pub fn deep_comp_chain(n: usize) -> (Program<DummyNode>, Value) {
+    let mut nodes = vec![Term::Iden, Term::Jet(JetsNode::Adder32)];
+    for _ in 0..n {
+        let idx = nodes.len();
+        nodes.push(Term::Comp(idx, 1));
+    }
+    let input = Value::prod(Value::u32(0x1234_5678), Value::u32(0x9abc_def0));
+    (finalize(nodes), input)
+}
+
+/// A complete binary tree of `pair`s of depth `n`, with `adder32` at every
+/// leaf. Each level is a single shared node, so the program has `n + 1`
+/// nodes but executes `2^n` leaves.
+pub fn wide_pair_tree(n: usize) -> (Program<DummyNode>, Value) {
+    let mut nodes = vec![Term::Jet(JetsNode::Adder32)];
+    for _ in 0..n {
+        nodes.push(Term::Pair(1, 1));
+    }
+    let input = Value::prod(Value::u32(0xdead_beef), Value::u32(0x0bad_f00d));
+    (finalize(nodes), input)
+}
+
+/// `blocks` iterations of the SHA256 compression function, each hashing
+/// the same 512-bit block into the running midstate
+pub fn sha256_chain(blocks: usize) -> (Program<DummyNode>, Value) {
+    // step: (midstate × block) → (midstate' × block)
+    let mut nodes = vec![
+        Term::Jet(JetsNode::Sha256HashBlock),
+        Term::Iden,
+        Term::Drop(1),
+        Term::Pair(3, 1),
+        Term::Iden,
+        Term::Take(1),
+    ];
+    let step = 3;
+    let mut rest = nodes.len() - 1;
+    for _ in 0..blocks {
+        let idx = nodes.len();
+        nodes.push(Term::Comp(idx - step, idx - rest));
+        rest = idx;
+    }
+
+    let iv = [
+        0x6a09_e667,
+        0xbb67_ae85,
+        0x3c6e_f372,
+        0xa54f_f53a,
+        0x510e_527f,
+        0x9b05_688c,
+        0x1f83_d9ab,
+        0x5be0_cd19,
+    ];
+    let word256 = |words: &[u32]| {
+        let prod = |a, b| Value::prod(a, b);
+        prod(
+            prod(
+                prod(Value::u32(words[0]), Value::u32(words[1])),
+                prod(Value::u32(words[2]), Value::u32(words[3])),
+            ),
+            prod(
+                prod(Value::u32(words[4]), Value::u32(words[5])),
+                prod(Value::u32(words[6]), Value::u32(words[7])),
+            ),
+        )
+    };
+    let block = Value::prod(word256(&[0; 8]), word256(&[0; 8]));
+    (finalize(nodes), Value::prod(word256(&iv), block))
+}
+
+/// Read a 64-byte block from one frame and write it to another, `iters`
+/// times, as jets reading and writing hash blocks do. Both frames start
+/// `offset` bits past a byte boundary. Returns the underlying data.
+pub fn frame_block_copy(offset: usize, iters: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..132).map(|i| i as u8).collect();
+    let mut block = [0; 64];
+    for _ in 0..iters {
+        Frame::new(offset, 512).read_bytes_into(&mut block, &data);
+        Frame::new(66 * 8 + offset, 512).write_bytes(&block, &mut data);
+    }
+    data
+}
+
+/// `sha256_hashblock` applied to a midstate and a block which are given
+/// as three 256-bit witnesses, so that executing it inserts 768 bits of
+/// witness data into the Bit Machine
+pub fn witness_hash_block() -> (Program<DummyNode>, Value) {
+    let nodes = vec![
+        Term::Witness(()),
+        Term::Witness(()),
+        Term::Witness(()),
+        Term::Pair(2, 1),
+        Term::Pair(4, 1),
+        Term::Jet(JetsNode::Sha256HashBlock),
+        Term::Comp(2, 1),
+    ];
+
+    let mut w = BitWriter::new(vec![]);
+    w.write_bit(true).unwrap();
+    encode::encode_natural(768, &mut w).unwrap();
+    for i in 0..96 {
+        w.write_u8(i as u8, 8).unwrap();
+    }
+    w.flush_all().unwrap();
+    let witness = w.into_inner();
+
+    let prog =
+        Program::from_untyped_nodes(UnTypedProg(nodes), &mut BitIter::from(witness.into_iter()))
+            .expect("generated program typechecks");
+    (prog, Value::Unit)
+}
+
+/// `n` iterations of the SHA256 compression function on a single
+/// 256-bit witness node, which is shared as the initial midstate and as
+/// both halves of every block
+pub fn shared_witness(n: usize) -> (Program<DummyNode>, Value) {
+    let mut nodes = vec![
+        Term::Witness(()),
+        Term::Pair(1, 1),
+        Term::Jet(JetsNode::Sha256HashBlock),
+    ];
+    let mut state = 0;
+    for _ in 0..n {
+        let idx = nodes.len();
+        nodes.push(Term::Pair(idx - state, idx - 1));
+        nodes.push(Term::Comp(1, idx - 1));
+        state = idx + 1;
+    }
+
+    let mut w = BitWriter::new(vec![]);
+    w.write_bit(true).unwrap();
+    encode::encode_natural(256, &mut w).unwrap();
+    for i in 0..32 {
+        w.write_u8(i as u8, 8).unwrap();
+    }
+    w.flush_all().unwrap();
+    let witness = w.into_inner();
+
+    let prog =
+        Program::from_untyped_nodes(UnTypedProg(nodes), &mut BitIter::from(witness.into_iter()))
+            .expect("generated program typechecks");
+    (prog, Value::Unit)
+}
+
+/// `n` nested `case`s, each branching on one bit of the input and then
+/// dropping it before continuing with the next
+pub fn case_ladder(n: usize) -> (Program<DummyNode>, Value) {
+    let mut nodes = vec![Term::Unit];
+    let mut input = Value::Unit;
+    for i in 0..n {
+        nodes.push(Term::Drop(1));
+        nodes.push(Term::Case(1, 1));
+        input = Value::prod(Value::u1((i % 2) as u8), input);
+    }
+    (finalize(nodes), input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::BitMachine;
+    use crate::extension::dummy::TxEnv;
+
+    fn run(prog: &Program<DummyNode>, input: &Value) -> Value {
+        assert_eq!(input.len(), prog.root_node().source_ty.bit_width());
+        let mut mac = BitMachine::for_program(prog);
+        mac.input(input).unwrap();
+        mac.exec(prog, &TxEnv)
+    }
+
+    #[test]
+    fn generators_execute() {
+        let (prog, input) = deep_comp_chain(10);
+        assert_eq!(prog.nodes.len(), 12);
+        assert_eq!(
+            run(&prog, &input),
+            Value::prod(Value::u1(0), Value::u32(0xacf1_3568))
+        );
+
+        let (prog, input) = wide_pair_tree(3);
+        assert_eq!(prog.nodes.len(), 4);
+        let leaf = Value::prod(Value::u1(0), Value::u32(0xea5b_aefc));
+        let mut expected = leaf;
+        for _ in 0..3 {
+            expected = Value::prod(expected.clone(), expected);
+        }
+        assert_eq!(run(&prog, &input), expected);
+
+        let (prog, input) = sha256_chain(2);
+        let output = run(&prog, &input);
+        assert_eq!(output.len(), 256);
+        let (one_block, input) = sha256_chain(1);
+        assert_ne!(run(&one_block, &input), output);
+
+        let data = frame_block_copy(0, 1);
+        assert_eq!(data[66..130], data[..64]);
+
+        let (prog, input) = witness_hash_block();
+        assert_eq!(run(&prog, &input).len(), 256);
+
+        let (prog, input) = shared_witness(4);
+        assert_eq!(prog.nodes.len(), 11);
+        assert_eq!(run(&prog, &input).len(), 256);
+
+        let (prog, input) = case_ladder(8);
+        assert_eq!(prog.root_node().source_ty.bit_width(), 8);
+        assert_eq!(run(&prog, &input), Value::Unit);
+    }
+}
@@ -0,0 +1,276 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Simplicity Verifier
+//!
+//! Command-line tool checking that a serialized program, with its
+//! witness data, decodes, typechecks and has a claimed CMR, e.g. for CI
+//! pipelines checking programs embedded in transactions. Programs are
+//! decoded with the Elements jets. Build it with the `verify_cli`
+//! feature.
+//!
+//! The exit code is 0 if the program is valid, 1 if it has a different
+//! CMR, 2 if it does not typecheck, and 3 if it cannot be read or
+//! parsed, including when the arguments are invalid.
+//!
+
+extern crate bitcoin_hashes;
+extern crate clap;
+extern crate serde_json;
+extern crate simplicity;
+
+use std::ffi::OsString;
+use std::{env, fs, process};
+
+use bitcoin_hashes::hex::FromHex;
+use clap::error::ErrorKind;
+use clap::{Arg, ArgAction, ArgGroup, Command};
+
+use simplicity::bititer::BitIter;
+use simplicity::cmr::Cmr;
+use simplicity::encode;
+use simplicity::extension::elements::ElementsNode;
+use simplicity::{Error, Program};
+
+/// Result of checking a program
+#[derive(Debug)]
+enum Outcome {
+    /// The program is valid, with the given CMR
+    Valid(Cmr),
+    /// The program is well-typed, but does not have the claimed CMR
+    CmrMismatch { expected: Cmr, found: Cmr },
+    /// The program does not typecheck
+    TypeError(Error),
+    /// The program, or the arguments, could not be read or parsed
+    ParseError(String),
+}
+
+impl Outcome {
+    /// Process exit code for the outcome
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Outcome::Valid(..) => 0,
+            Outcome::CmrMismatch { .. } => 1,
+            Outcome::TypeError(..) => 2,
+            Outcome::ParseError(..) => 3,
+        }
+    }
+
+    /// Description of why the program is invalid, if it is
+    fn error(&self) -> Option<String> {
+        match *self {
+            Outcome::Valid(..) => None,
+            Outcome::CmrMismatch {
+                ref expected,
+                ref found,
+            } => Some(format!(
+                "CMR mismatch: expected {}, found {}",
+                expected, found
+            )),
+            Outcome::TypeError(ref e) => Some(format!("type error: {}", e)),
+            Outcome::ParseError(ref e) => Some(format!("parse error: {}", e)),
+        }
+    }
+
+    /// JSON report of the outcome
+    fn to_json(&self) -> serde_json::Value {
+        let cmr = match *self {
+            Outcome::Valid(cmr) | Outcome::CmrMismatch { found: cmr, .. } => Some(cmr.to_string()),
+            _ => None,
+        };
+        serde_json::json!({
+            "valid": self.exit_code() == 0,
+            "exit_code": self.exit_code(),
+            "cmr": cmr,
+            "error": self.error(),
+        })
+    }
+}
+
+/// Check that the serialized program `bytes` is valid and has the CMR
+/// `expected`
+fn verify(bytes: &[u8], expected: Cmr) -> Outcome {
+    let mut iter = BitIter::from(bytes.iter().cloned());
+    let nodes = match encode::decode_program_no_witness::<_, ElementsNode>(&mut iter) {
+        Ok(nodes) => nodes,
+        Err(e) => return Outcome::ParseError(e.to_string()),
+    };
+    let program = match Program::from_untyped_nodes(nodes, &mut iter) {
+        Ok(program) => program,
+        Err(e) if e.is_type_error() => return Outcome::TypeError(e),
+        Err(e) => return Outcome::ParseError(e.to_string()),
+    };
+    let found = program.root_node().cmr;
+    if found == expected {
+        Outcome::Valid(found)
+    } else {
+        Outcome::CmrMismatch { expected, found }
+    }
+}
+
+/// Whether the arguments, including the program name, ask for a JSON
+/// report
+///
+/// This looks at the arguments before they are parsed, so that a report
+/// of invalid arguments is also given as JSON.
+fn wants_json<I: IntoIterator<Item = OsString>>(args: I) -> bool {
+    args.into_iter()
+        .skip(1)
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == "--output-json")
+}
+
+/// Parse the arguments and check the program they give
+fn run() -> Result<Outcome, String> {
+    let matches = Command::new("simplicity-verify")
+        .about("Check that a Simplicity program decodes, typechecks and has a given CMR")
+        .arg(
+            Arg::new("program-hex")
+                .long("program-hex")
+                .value_name("HEX")
+                .help("The serialized program, in hex"),
+        )
+        .arg(
+            Arg::new("program-file")
+                .long("program-file")
+                .value_name("PATH")
+                .help("A file containing the serialized program"),
+        )
+        .group(
+            ArgGroup::new("program")
+                .args(["program-hex", "program-file"])
+                .required(true),
+        )
+        .arg(
+            Arg::new("cmr")
+                .long("cmr")
+                .value_name("HEX")
+                .required(true)
+                .help("The claimed CMR of the program, in hex"),
+        )
+        .arg(
+            Arg::new("output-json")
+                .long("output-json")
+                .action(ArgAction::SetTrue)
+                .help("Print a JSON report instead of \"OK\" or an error"),
+        )
+        .try_get_matches()
+        .map_err(|e| match e.kind() {
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => e.exit(),
+            _ => e
+                .render()
+                .to_string()
+                .trim_start_matches("error: ")
+                .to_owned(),
+        })?;
+
+    let bytes = match matches.get_one::<String>("program-hex") {
+        Some(hex) => Vec::<u8>::from_hex(hex).map_err(|e| format!("bad program hex: {}", e))?,
+        None => {
+            let path = matches
+                .get_one::<String>("program-file")
+                .expect("program group is required");
+            fs::read(path).map_err(|e| format!("reading {}: {}", path, e))?
+        }
+    };
+    let cmr = matches.get_one::<String>("cmr").expect("cmr is required");
+    let cmr = match <[u8; 32]>::from_hex(cmr) {
+        Ok(cmr) => Cmr::from(cmr),
+        Err(e) => return Err(format!("bad CMR hex: {}", e)),
+    };
+    Ok(verify(&bytes, cmr))
+}
+
+fn main() {
+    let json = wants_json(env::args_os());
+    let outcome = run().unwrap_or_else(Outcome::ParseError);
+    if json {
+        println!("{}", outcome.to_json());
+    } else {
+        match outcome.error() {
+            None => println!("OK"),
+            Some(e) => eprintln!("{}", e.trim_end()),
+        }
+    }
+    process::exit(outcome.exit_code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simplicity::encode::{BitWrite, BitWriter};
+    use simplicity::Term;
+
+    /// Serialize a list of witness-free nodes, whether or not they
+    /// typecheck
+    fn serialize(nodes: &[Term<(), ElementsNode>]) -> Vec<u8> {
+        let mut w = BitWriter::new(vec![]);
+        encode::encode_natural(nodes.len(), &mut w).unwrap();
+        for node in nodes {
+            encode::encode_node_no_witness(node, &mut w).unwrap();
+        }
+        w.write_bit(false).unwrap();
+        w.flush_all().unwrap();
+        w.into_inner()
+    }
+
+    #[test]
+    fn outcomes() {
+        let bytes = serialize(&[Term::Unit]);
+        let cmr = Program::<ElementsNode>::deserialize(&bytes)
+            .unwrap()
+            .root_node()
+            .cmr;
+        assert_eq!(verify(&bytes, cmr).exit_code(), 0);
+        assert_eq!(verify(&bytes, Cmr::from([0; 32])).exit_code(), 1);
+
+        // injl unit >>> take unit, where take needs a product
+        let bytes = serialize(&[Term::Unit, Term::InjL(1), Term::Take(2), Term::Comp(2, 1)]);
+        let outcome = verify(&bytes, cmr);
+        assert_eq!(outcome.exit_code(), 2);
+        assert_eq!(outcome.to_json()["valid"], false);
+
+        assert_eq!(verify(&[0xff], cmr).exit_code(), 3);
+        // A truncated program
+        assert_eq!(verify(&[], cmr).exit_code(), 3);
+    }
+
+    #[test]
+    fn json_flag() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(wants_json(args(&[
+            "simplicity-verify",
+            "--cmr",
+            "00",
+            "--output-json"
+        ])));
+        // Found even when the other arguments are invalid
+        assert!(wants_json(args(&[
+            "simplicity-verify",
+            "--output-json",
+            "--bogus"
+        ])));
+        assert!(!wants_json(args(&[
+            "simplicity-verify",
+            "--program-hex",
+            "24"
+        ])));
+        assert!(!wants_json(args(&["--output-json"])));
+        assert!(!wants_json(args(&[
+            "simplicity-verify",
+            "--",
+            "--output-json"
+        ])));
+    }
+}
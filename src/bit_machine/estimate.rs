@@ -0,0 +1,242 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Resource Estimates
+//!
+//! Bounds on the memory a Bit Machine needs to run a program, computed
+//! from a description of the program's structure before it is built,
+//! e.g. to check that a program being generated will fit within a
+//! memory limit.
+//!
+//! Each node is described by combining the estimates of its children
+//! with a `ResourceEstimator`, giving the widths of any types which
+//! cannot be derived from them. The estimates agree with the bounds
+//! `Program` computes for each node, `extra_cells_bound` and
+//! `frame_count_bound`, and never fall below them.
+//!
+
+use std::cmp;
+
+/// Estimated resources needed to run a node, along with the widths of
+/// its source and target types
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ResourceEstimate {
+    /// Upper bound on the number of cells needed, not counting the
+    /// node's input and output frames
+    pub extra_cells: usize,
+    /// Upper bound on the number of frames needed on either frame
+    /// stack, not counting the node's input and output frames
+    pub frame_count: usize,
+    /// Width, in bits, of the node's source type
+    pub source_width: usize,
+    /// Width, in bits, of the node's target type
+    pub target_width: usize,
+}
+
+impl ResourceEstimate {
+    /// Number of bytes of data which a Bit Machine needs to run the
+    /// node as a whole program, as `Program::required_data_bytes`
+    pub fn data_bytes(&self) -> usize {
+        (self.source_width + self.target_width + self.extra_cells + 7) / 8
+    }
+
+    /// Whether the node, run as a whole program, fits in `max_bytes`
+    /// bytes of Bit Machine data
+    pub fn fits_in(&self, max_bytes: usize) -> bool {
+        self.data_bytes() <= max_bytes
+    }
+}
+
+/// Combines estimates of nodes into estimates of their parents
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ResourceEstimator;
+
+impl ResourceEstimator {
+    /// Create a new estimator
+    pub fn new() -> ResourceEstimator {
+        ResourceEstimator
+    }
+
+    /// A node which allocates no frames of its own: `iden`, `unit`, a
+    /// jet or a hidden node
+    pub fn leaf(&self, source_width: usize, target_width: usize) -> ResourceEstimate {
+        ResourceEstimate {
+            extra_cells: 0,
+            frame_count: 0,
+            source_width: source_width,
+            target_width: target_width,
+        }
+    }
+
+    /// A `witness` node
+    pub fn witness(&self, source_width: usize, target_width: usize) -> ResourceEstimate {
+        ResourceEstimate {
+            extra_cells: target_width,
+            ..self.leaf(source_width, target_width)
+        }
+    }
+
+    /// An `injl` or `injr` node, whose sum target type has the given width
+    pub fn inj(&self, child: ResourceEstimate, target_width: usize) -> ResourceEstimate {
+        ResourceEstimate {
+            target_width: target_width,
+            ..child
+        }
+    }
+
+    /// A `take` or `drop` node, whose product source type has the given
+    /// width
+    pub fn take_drop(&self, child: ResourceEstimate, source_width: usize) -> ResourceEstimate {
+        ResourceEstimate {
+            source_width: source_width,
+            ..child
+        }
+    }
+
+    /// A `comp` node
+    pub fn comp(&self, left: ResourceEstimate, right: ResourceEstimate) -> ResourceEstimate {
+        ResourceEstimate {
+            // The frame between the children holds `left`'s output
+            extra_cells: left.target_width + cmp::max(left.extra_cells, right.extra_cells),
+            frame_count: 1 + cmp::max(left.frame_count, right.frame_count),
+            source_width: left.source_width,
+            target_width: right.target_width,
+        }
+    }
+
+    /// A `case` node
+    pub fn case(&self, left: ResourceEstimate, right: ResourceEstimate) -> ResourceEstimate {
+        ResourceEstimate {
+            // Only one branch runs
+            extra_cells: cmp::max(left.extra_cells, right.extra_cells),
+            frame_count: cmp::max(left.frame_count, right.frame_count),
+            // The children's sources share the second component of the
+            // product, so the wider one is the wider arm of the sum
+            source_width: 1 + cmp::max(left.source_width, right.source_width),
+            target_width: cmp::max(left.target_width, right.target_width),
+        }
+    }
+
+    /// A `pair` node
+    pub fn pair(&self, left: ResourceEstimate, right: ResourceEstimate) -> ResourceEstimate {
+        ResourceEstimate {
+            extra_cells: cmp::max(left.extra_cells, right.extra_cells),
+            frame_count: cmp::max(left.frame_count, right.frame_count),
+            source_width: left.source_width,
+            target_width: left.target_width + right.target_width,
+        }
+    }
+
+    /// A `disconnect` node
+    pub fn disconnect(&self, left: ResourceEstimate, right: ResourceEstimate) -> ResourceEstimate {
+        ResourceEstimate {
+            // The frame holding `left`'s input is freed before `right`
+            // runs, but the one holding `left`'s output lives until
+            // `right` is done
+            extra_cells: left.target_width
+                + cmp::max(left.source_width + left.extra_cells, right.extra_cells),
+            frame_count: 1 + cmp::max(1, cmp::max(left.frame_count, right.frame_count)),
+            // `left` takes a CMR alongside the input
+            source_width: left.source_width.saturating_sub(256),
+            target_width: (left.target_width + right.target_width)
+                .saturating_sub(right.source_width),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::extension::dummy::DummyNode;
+    use crate::program::Program;
+    use crate::{Term, UnTypedProg};
+
+    /// Estimate the resources of the node with the given index from
+    /// its structure alone
+    fn estimate(prog: &Program<DummyNode>, idx: usize) -> ResourceEstimate {
+        let est = ResourceEstimator::new();
+        let node = &prog.nodes[idx];
+        let (source, target) = (node.source_ty.bit_width(), node.target_ty.bit_width());
+        match node.node {
+            Term::Iden | Term::Unit | Term::Hidden(..) | Term::Ext(..) | Term::Jet(..) => {
+                est.leaf(source, target)
+            }
+            Term::Witness(..) => est.witness(source, target),
+            Term::InjL(..) | Term::InjR(..) => est.inj(estimate(prog, node.left()), target),
+            Term::Take(..) | Term::Drop(..) => est.take_drop(estimate(prog, node.left()), source),
+            Term::Comp(..) => est.comp(estimate(prog, node.left()), estimate(prog, node.right())),
+            Term::Case(..) => est.case(estimate(prog, node.left()), estimate(prog, node.right())),
+            Term::Pair(..) => est.pair(estimate(prog, node.left()), estimate(prog, node.right())),
+            Term::Disconnect(..) => {
+                est.disconnect(estimate(prog, node.left()), estimate(prog, node.right()))
+            }
+            Term::Fail(..) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn matches_program_bounds() {
+        // disconnect (take iden) (iden >>> unit)
+        let disconnect = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Take(1),
+                Term::Iden,
+                Term::Unit,
+                Term::Comp(2, 1),
+                Term::Disconnect(4, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let progs = vec![
+            crate::bench_util::deep_comp_chain(5).0,
+            crate::bench_util::wide_pair_tree(4).0,
+            crate::bench_util::sha256_chain(2).0,
+            crate::bench_util::witness_hash_block().0,
+            crate::bench_util::case_ladder(4).0,
+            disconnect,
+        ];
+        for prog in &progs {
+            for node in &prog.nodes {
+                let est = estimate(prog, node.index);
+                assert!(est.extra_cells >= node.extra_cells_bound, "{}", node);
+                assert!(est.frame_count >= node.frame_count_bound, "{}", node);
+                assert_eq!(est.extra_cells, node.extra_cells_bound, "{}", node);
+                assert_eq!(est.frame_count, node.frame_count_bound, "{}", node);
+                assert_eq!(est.source_width, node.source_ty.bit_width(), "{}", node);
+                assert_eq!(est.target_width, node.target_ty.bit_width(), "{}", node);
+            }
+            let root = estimate(prog, prog.nodes.len() - 1);
+            assert_eq!(root.data_bytes(), prog.required_data_bytes());
+            assert!(root.fits_in(prog.required_data_bytes()));
+            assert!(!root.fits_in(prog.required_data_bytes() - 1));
+        }
+    }
+
+    #[test]
+    fn four_megabyte_limit() {
+        let est = ResourceEstimator::new();
+        // A long chain of compositions of 2^16-bit witnesses fits, but a
+        // 4 MiB witness alongside it does not
+        let mut chain = est.witness(0, 1 << 16);
+        for _ in 0..300 {
+            chain = est.comp(chain, est.witness(1 << 16, 1 << 16));
+        }
+        assert!(chain.fits_in(4 << 20));
+        let wider = est.pair(chain, est.witness(0, 1 << 25));
+        assert!(!wider.fits_in(4 << 20));
+    }
+}
@@ -18,10 +18,15 @@
 //! frame management optimizations which can be used to great benefit.
 //!
 
-use std::cmp;
+use std::sync::Arc;
+use std::{cmp, error, fmt, io, mem};
 
-use crate::core::types::FinalTypeInner;
+use crate::cmr::{Cmr, DISCONNECT_CMR_BITS};
+use crate::core::types::{FinalType, FinalTypeInner};
+use crate::encode::BitWriter;
 use crate::extension;
+use crate::program::{NodeId, ProgramNode};
+use crate::Error;
 use crate::Program;
 use crate::Term;
 use crate::Value;
@@ -30,6 +35,355 @@ use crate::extension::Jet as JetNode;
 
 use super::frame::Frame;
 
+/// Assert an invariant of the Bit Machine, in debug builds or when the
+/// `debug-checks` feature is enabled
+///
+/// These checks catch static bounds which are too small and frame
+/// bookkeeping errors, but are too slow to leave on in the main loop.
+macro_rules! check_invariant {
+    ($($arg:tt)+) => {
+        if cfg!(any(debug_assertions, feature = "debug-checks")) {
+            assert!($($arg)+);
+        }
+    };
+}
+
+/// Error which can occur while setting up or running the Bit Machine
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExecError {
+    /// The program would need more memory than the caller allows
+    TooMuchMemory {
+        /// Number of bytes the program needs
+        required: usize,
+        /// Maximum number of bytes the caller allows
+        limit: usize,
+    },
+    /// An input value does not have the program's source type
+    InputTypeMismatch,
+    /// Execution needed more cells or frames than the caller allows
+    MemoryExceeded,
+    /// Execution reached the given `fail` node
+    FailNode(NodeId),
+    /// The program takes an input, but none was given
+    MissingInput,
+    /// More than one input frame was given, e.g. by calling `input` twice
+    ExtraInput,
+    /// The input of the right child of the given `disconnect` node is
+    /// wider than the output of its left child, which is only possible
+    /// in a program which does not typecheck
+    DisconnectWidthMismatch(NodeId),
+    /// A branch of the sum in the input of the given `case` node is wider
+    /// than the sum allows for, which is only possible in a program which
+    /// does not typecheck
+    CaseWidthMismatch(NodeId),
+    /// The output given for the given jet node by
+    /// `BitMachine::resume_after_jet` does not have the jet's target type
+    JetOutputTypeMismatch(NodeId),
+    /// Executing the given jet node failed
+    JetFailed(NodeId, extension::JetFailed),
+    /// Execution reached the given hidden node, i.e. a pruned branch
+    HiddenNode(NodeId),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecError::TooMuchMemory { required, limit } => write!(
+                f,
+                "Program requires {} bytes of memory, exceeding the limit of {}",
+                required, limit
+            ),
+            ExecError::InputTypeMismatch => {
+                f.write_str("Input value does not have the program's source type")
+            }
+            ExecError::MemoryExceeded => f.write_str("Execution exceeded its memory bounds"),
+            ExecError::FailNode(idx) => write!(f, "Execution reached fail node {}", idx),
+            ExecError::MissingInput => f.write_str("Program takes an input, but none was given"),
+            ExecError::ExtraInput => f.write_str("More than one input was given"),
+            ExecError::DisconnectWidthMismatch(idx) => write!(
+                f,
+                "Right child of disconnect node {} takes more input than the left child outputs",
+                idx
+            ),
+            ExecError::CaseWidthMismatch(idx) => write!(
+                f,
+                "Branch of the input of case node {} is wider than its sum",
+                idx
+            ),
+            ExecError::JetOutputTypeMismatch(idx) => {
+                write!(f, "Output given for jet node {} has the wrong type", idx)
+            }
+            ExecError::JetFailed(idx, e) => write!(f, "Jet node {}: {}", idx, e),
+            ExecError::HiddenNode(idx) => write!(f, "Execution reached hidden node {}", idx),
+        }
+    }
+}
+
+impl error::Error for ExecError {}
+
+impl ExecError {
+    /// The node at which execution failed, if the error comes from a
+    /// particular node
+    pub fn node(&self) -> Option<NodeId> {
+        match *self {
+            ExecError::FailNode(idx)
+            | ExecError::DisconnectWidthMismatch(idx)
+            | ExecError::CaseWidthMismatch(idx)
+            | ExecError::JetOutputTypeMismatch(idx)
+            | ExecError::JetFailed(idx, _)
+            | ExecError::HiddenNode(idx) => Some(idx),
+            _ => None,
+        }
+    }
+}
+
+/// Peak resource usage observed by a Bit Machine
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ExecStats {
+    /// Largest number of cells allocated at once, including the
+    /// input and output frames
+    pub peak_cells: usize,
+    /// Largest number of frames on either of the read or write
+    /// frame stacks at once, including the input and output frames
+    pub peak_frames: usize,
+    /// Largest number of pending entries on the call stack at once
+    pub peak_call_stack_depth: usize,
+    /// Number of nodes executed
+    pub steps: u64,
+    /// Number of jets, and extension nodes, executed
+    pub jets: u64,
+}
+
+/// A single step of Bit Machine execution, as recorded by
+/// `BitMachine::exec_traced`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceEvent {
+    /// Began executing the node with the given index
+    Exec(usize),
+    /// Pushed a new write frame of the given number of bits
+    NewFrame(usize),
+    /// Moved the active write frame to the read frame stack
+    MoveFrame,
+    /// Dropped the active read frame
+    DropFrame,
+}
+
+/// State of a Bit Machine as it began executing a node
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct ExecStep {
+    /// Index of the node
+    pub node: usize,
+    /// Offset of the cursor within the active read frame, if any
+    pub read_cursor: Option<usize>,
+    /// Offset of the cursor within the active write frame, if any
+    pub write_cursor: Option<usize>,
+    /// Number of frames on the read frame stack
+    pub read_frames: usize,
+    /// Number of frames on the write frame stack
+    pub write_frames: usize,
+}
+
+/// The first point at which an execution trace differs from the
+/// expected one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TraceMismatch {
+    /// Index of the first differing event
+    pub index: usize,
+    /// The expected event, or `None` if the expected trace ended first
+    pub expected: Option<TraceEvent>,
+    /// The event which occurred, or `None` if execution ended first
+    pub found: Option<TraceEvent>,
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Trace diverges at event {}: expected {:?}, found {:?}",
+            self.index, self.expected, self.found
+        )
+    }
+}
+
+impl TraceMismatch {
+    /// Compare a recorded trace against an expected one, returning the
+    /// first divergence if any
+    pub fn compare(expected: &[TraceEvent], found: &[TraceEvent]) -> Result<(), TraceMismatch> {
+        for index in 0..cmp::max(expected.len(), found.len()) {
+            let (e, f) = (expected.get(index), found.get(index));
+            if e != f {
+                return Err(TraceMismatch {
+                    index,
+                    expected: e.cloned(),
+                    found: f.cloned(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resources used to decode and execute a program
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ResourceUsage {
+    /// Cost of execution, currently the number of nodes executed
+    pub cost_consumed: u64,
+    /// Largest number of cells allocated at once, including the
+    /// input and output frames
+    pub peak_cells: usize,
+    /// Largest number of frames on either frame stack at once,
+    /// including the input and output frames
+    pub peak_frames: usize,
+    /// Length of the program's encoding, including witness data
+    pub bytes_serialized: usize,
+}
+
+/// Decode a program, check that its root has the given CMR, and execute
+/// it on the given input, returning its output and resource usage
+///
+/// The program is rejected, rather than executed, if running it could
+/// need more than `max_bytes` of Bit Machine memory.
+pub fn verify_with_commitment<Ext: extension::Jet>(
+    bytes: &[u8],
+    cmr: &Cmr,
+    input: &Value,
+    max_bytes: usize,
+    txenv: &Ext::TxEnv,
+) -> Result<(Value, ResourceUsage), Error> {
+    let program = Program::<Ext>::deserialize(bytes)?;
+    if program.root_node().cmr != *cmr {
+        return Err(Error::CmrMismatch);
+    }
+    let mut mac = BitMachine::try_for_program(&program, max_bytes)?;
+    let output = mac.exec_with_input(&program, input, txenv)?;
+    Ok((output, mac.resource_usage(&program)))
+}
+
+/// Maximum depth the call stack of `BitMachine::exec` can reach while
+/// executing the given program, over all possible inputs
+///
+/// This is the `call_stack_bound` of the root node, computed when the
+/// program is finalized.
+pub fn call_stack_max_depth<Ext: extension::Jet>(program: &Program<Ext>) -> usize {
+    program.nodes.last().map_or(0, |root| root.call_stack_bound)
+}
+
+/// A given number of empty frames, to be overwritten as frames are pushed
+fn empty_frames(n: usize) -> Vec<Frame> {
+    vec![Frame::new(0, 0); n]
+}
+
+/// An entry of the Bit Machine's call stack, saying what to do once the
+/// current node is done
+enum CallStack {
+    Goto(usize),
+    MoveFrame,
+    DropFrame,
+    /// Copy and skip over the given number of bits, on behalf of the
+    /// `disconnect` node with the given index
+    CopyFwd(usize, usize),
+    Back(usize),
+}
+
+/// Execution of a program suspended just before a jet, as returned by
+/// `BitMachine::run_until_jet`
+pub struct MachineState {
+    /// The jet node, or breakpoint, which has not been executed
+    node: NodeId,
+    /// Input to the jet, or unit at a breakpoint
+    input: Value,
+    /// Whether execution continues by executing `node`, rather than from
+    /// the output given for it
+    pending: bool,
+    /// Entries left on the call stack
+    call_stack: Vec<CallStack>,
+    /// Read frames, write frames and cells in use before the program
+    /// started
+    base: (usize, usize, usize),
+}
+
+impl MachineState {
+    /// The jet node at which execution was suspended
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// The input to the jet
+    pub fn input(&self) -> &Value {
+        &self.input
+    }
+}
+
+impl fmt::Debug for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MachineState")
+            .field("node", &self.node)
+            .field("input", &self.input)
+            .field("call_stack_depth", &self.call_stack.len())
+            .finish()
+    }
+}
+
+/// Result of running a program up to its next jet
+#[derive(Debug)]
+pub enum JetStep {
+    /// Execution reached a jet, whose output must be given to
+    /// `BitMachine::resume_after_jet` to continue
+    Jet(MachineState),
+    /// Execution finished, with the given output
+    Done(Value),
+}
+
+/// Contents of the active frames when execution reached a breakpoint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BreakpointHit {
+    /// The node, which has not been executed
+    node: NodeId,
+    /// Bits of the active read frame, and the offset of its cursor
+    read_frame: Option<(Vec<bool>, usize)>,
+    /// Bits of the active write frame, and the offset of its cursor
+    write_frame: Option<(Vec<bool>, usize)>,
+}
+
+impl BreakpointHit {
+    /// The node at which execution paused
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// All bits of the active read frame, if any, which hold the node's
+    /// input from the cursor onwards
+    pub fn read_frame(&self) -> Option<&[bool]> {
+        self.read_frame.as_ref().map(|(bits, _)| &bits[..])
+    }
+
+    /// Offset of the cursor into the active read frame, if any
+    pub fn read_cursor(&self) -> Option<usize> {
+        self.read_frame.as_ref().map(|&(_, cursor)| cursor)
+    }
+
+    /// All bits of the active write frame, if any, of which those before
+    /// the cursor have been written
+    pub fn write_frame(&self) -> Option<&[bool]> {
+        self.write_frame.as_ref().map(|(bits, _)| &bits[..])
+    }
+
+    /// Offset of the cursor into the active write frame, if any
+    pub fn write_cursor(&self) -> Option<usize> {
+        self.write_frame.as_ref().map(|&(_, cursor)| cursor)
+    }
+}
+
+/// Result of running a program up to its next breakpoint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BreakpointStep {
+    /// Execution paused at a breakpoint, and continues on the next call
+    /// to `BitMachine::run_to_breakpoint`
+    Hit(BreakpointHit),
+    /// Execution finished, with the given output
+    Done(Value),
+}
+
 /// An execution context for a Simplicity program
 pub struct BitMachine {
     /// Space for bytes that read and write frames point to.
@@ -37,389 +391,2657 @@ pub struct BitMachine {
     pub(crate) data: Vec<u8>,
     /// Top of data stack; index of first non-allocated byte
     pub(crate) next_frame_start: usize,
-    /// Read frame stack
-    pub(crate) read: Vec<Frame>,
-    /// Write frame stack
-    pub(crate) write: Vec<Frame>,
+    /// Frames of both frame stacks: the read frame stack grows up from
+    /// the start, and the write frame stack grows down from the end
+    pub(crate) frames: Vec<Frame>,
+    /// Number of frames on the read frame stack
+    pub(crate) read_len: usize,
+    /// Index of the active write frame, or `frames.len()` if the write
+    /// frame stack is empty
+    pub(crate) write_top: usize,
+    /// Peak resource usage so far
+    stats: ExecStats,
+    /// Events recorded so far, if tracing
+    trace: Option<Vec<TraceEvent>>,
+    /// Index of the node which wrote each bit of the output frame so
+    /// far, if attributing output bits
+    output_sources: Option<Vec<usize>>,
+    /// State at each node executed so far, if recording steps
+    steps: Option<Vec<ExecStep>>,
+    /// Maximum number of cells and of frames per stack allowed, if
+    /// enforcing bounds at runtime
+    bounds: Option<(usize, usize)>,
+    /// Whether to stop execution on reaching a witness node
+    stop_at_witness: bool,
+    /// Index of the witness node at which execution stopped, if any
+    stopped_at_witness: Option<NodeId>,
+    /// Whether to suspend execution on reaching a jet
+    stop_at_jet: bool,
+    /// Nodes before which to pause execution
+    breakpoints: Vec<NodeId>,
+    /// Whether to pause execution on reaching a breakpoint
+    stop_at_breakpoint: bool,
+    /// Whether to pause execution before every node
+    stop_at_node: bool,
+    /// Execution suspended at a jet, once it has been
+    suspended: Option<MachineState>,
+    /// Execution to continue from, on the next run
+    resume: Option<MachineState>,
+    /// Type of the output of the last run, if it finished, in which case
+    /// the output is in the active write frame
+    output_ty: Option<Arc<FinalType>>,
+    /// Input given by `input`, to be written at the program's source type
+    /// when it is run
+    pending_input: Option<Value>,
 }
 
 impl BitMachine {
+    /// Number of bytes of data which a Bit Machine needs to execute
+    /// the given program, as computed from its static bounds
+    pub fn memory_required<Ext: extension::Jet>(program: &Program<Ext>) -> usize {
+        program.required_data_bytes()
+    }
+
     /// Construct a Bit Machine with enough space to execute
     /// the given program
+    ///
+    /// No limit is placed on the amount of memory allocated; for
+    /// untrusted programs use `try_for_program` instead.
     pub fn for_program<Ext: extension::Jet>(program: &Program<Ext>) -> BitMachine {
-        let prog = program.root_node();
-        let io_width = prog.source_ty.bit_width() + prog.target_ty.bit_width();
+        BitMachine::from_parts(program, vec![0; program.required_data_bytes()])
+    }
+
+    /// Construct a Bit Machine to execute the given program using `buffer`
+    /// for its data, e.g. one taken back from another machine with
+    /// `into_buffer`, failing if it is shorter than
+    /// `Program::required_data_bytes`
+    ///
+    /// The buffer is used as it is, without being zeroed or resized.
+    pub fn with_buffer<Ext: extension::Jet>(
+        program: &Program<Ext>,
+        buffer: Vec<u8>,
+    ) -> Result<BitMachine, ExecError> {
+        let required = program.required_data_bytes();
+        if buffer.len() < required {
+            return Err(ExecError::TooMuchMemory {
+                required: required,
+                limit: buffer.len(),
+            });
+        }
+        Ok(BitMachine::from_parts(program, buffer))
+    }
+
+    /// Give up the machine's data buffer, so that it can be reused by
+    /// `with_buffer`
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Construct a fresh Bit Machine for the given program around `data`
+    fn from_parts<Ext: extension::Jet>(program: &Program<Ext>, data: Vec<u8>) -> BitMachine {
+        // Frames are used only for nontrivial input and output
+        let n_frames = program.required_frame_capacity();
         BitMachine {
-            data: vec![0; (io_width + prog.extra_cells_bound + 7) / 8],
+            data: data,
             next_frame_start: 0,
-            // +1's for input and output; these are used only for nontrivial
-            read: Vec::with_capacity(prog.frame_count_bound + 1),
-            write: Vec::with_capacity(prog.frame_count_bound + 1),
+            frames: empty_frames(n_frames),
+            read_len: 0,
+            write_top: n_frames,
+            stats: ExecStats::default(),
+            trace: None,
+            output_sources: None,
+            steps: None,
+            bounds: None,
+            stop_at_witness: false,
+            stopped_at_witness: None,
+            stop_at_jet: false,
+            breakpoints: vec![],
+            stop_at_breakpoint: false,
+            stop_at_node: false,
+            suspended: None,
+            resume: None,
+            output_ty: None,
+            pending_input: None,
+        }
+    }
+
+    /// Construct a Bit Machine with enough space to execute the given
+    /// program, failing without allocating if this would take more than
+    /// `max_bytes` bytes of data
+    pub fn try_for_program<Ext: extension::Jet>(
+        program: &Program<Ext>,
+        max_bytes: usize,
+    ) -> Result<BitMachine, ExecError> {
+        let required = BitMachine::memory_required(program);
+        #[cfg(feature = "log")]
+        log::debug!(required = required, limit = max_bytes; "memory budget");
+        if required > max_bytes {
+            return Err(ExecError::TooMuchMemory {
+                required: required,
+                limit: max_bytes,
+            });
+        }
+        Ok(BitMachine::for_program(program))
+    }
+
+    /// Free all frames, so that the machine can run another program of
+    /// at most the same size without reallocating
+    ///
+    /// Data is not zeroed. This is sound because every cell is written
+    /// before it is read, including sum padding, which `write_padding`
+    /// fills with zeros so that the output frame holds the padded
+    /// encoding of its value.
+    pub fn reset(&mut self) {
+        self.next_frame_start = 0;
+        self.read_len = 0;
+        self.write_top = self.frames.len();
+        self.suspended = None;
+        self.resume = None;
+        self.output_ty = None;
+        self.pending_input = None;
+    }
+
+    /// Number of frames on the write frame stack
+    fn write_len(&self) -> usize {
+        self.frames.len() - self.write_top
+    }
+
+    /// Cursor of the active read frame, if any
+    fn saved_read_cursor(&self) -> Option<usize> {
+        self.read_len
+            .checked_sub(1)
+            .map(|idx| self.frames[idx].cursor)
+    }
+
+    /// Peak resource usage of all execution on this machine so far
+    pub fn stats(&self) -> ExecStats {
+        self.stats
+    }
+
+    /// Record an event, if tracing
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(ref mut trace) = self.trace {
+            trace.push(event);
         }
     }
 
     /// Push a new frame of given size onto the write frame stack
-    fn new_frame(&mut self, len: usize) {
-        // assert!(self.next_pos as usize + len < self.data.len() * 8);
-        // assert!(self.write.len() + self.read.len() < self.read.capacity());
+    fn new_frame(&mut self, len: usize) -> Result<(), ExecError> {
+        if let Some((max_cells, max_frames)) = self.bounds {
+            if self.next_frame_start + len > max_cells || self.write_len() >= max_frames {
+                return Err(ExecError::MemoryExceeded);
+            }
+        }
+        self.record(TraceEvent::NewFrame(len));
+        check_invariant!(
+            self.next_frame_start + len <= self.data.len() * 8,
+            "new frame of {} cells at {} exceeds the {} cells of data",
+            len,
+            self.next_frame_start,
+            self.data.len() * 8,
+        );
+        if self.read_len == self.write_top {
+            self.grow_frames();
+        }
 
-        self.write.push(Frame::new(self.next_frame_start, len));
+        self.write_top -= 1;
+        self.frames[self.write_top] = Frame::new(self.next_frame_start, len);
         self.next_frame_start += len;
+        self.stats.peak_cells = cmp::max(self.stats.peak_cells, self.next_frame_start);
+        self.stats.peak_frames = cmp::max(self.stats.peak_frames, self.write_len());
+        Ok(())
+    }
+
+    /// Make room between the frame stacks, for when the static bounds
+    /// were exceeded, e.g. by running a program other than the one the
+    /// machine was built for
+    #[cold]
+    fn grow_frames(&mut self) {
+        let extra = cmp::max(self.frames.len(), 2);
+        self.frames
+            .splice(self.read_len..self.read_len, empty_frames(extra));
+        self.write_top += extra;
     }
 
     /// Move the active write frame to the read frame stack
-    fn move_frame(&mut self) {
-        let mut _active_write_frame = self.write.pop().unwrap();
-        _active_write_frame.reset_cursor();
-        self.read.push(_active_write_frame);
+    fn move_frame(&mut self) -> Result<(), ExecError> {
+        if let Some((_, max_frames)) = self.bounds {
+            if self.read_len >= max_frames {
+                return Err(ExecError::MemoryExceeded);
+            }
+        }
+        self.record(TraceEvent::MoveFrame);
+        assert!(
+            self.write_top < self.frames.len(),
+            "Empty write frame stack"
+        );
+        // The slot above the read frame stack is free, unless it holds the
+        // active write frame itself
+        self.frames.swap(self.read_len, self.write_top);
+        self.frames[self.read_len].reset_cursor();
+        self.read_len += 1;
+        self.write_top += 1;
+        self.stats.peak_frames = cmp::max(self.stats.peak_frames, self.read_len);
+        Ok(())
     }
 
     /// Drop the active read frame
     fn drop_frame(&mut self) {
-        let active_read_frame = self.read.pop().unwrap();
+        self.record(TraceEvent::DropFrame);
+        assert!(self.read_len > 0, "Empty read frame stack");
+        self.read_len -= 1;
+        let active_read_frame = &self.frames[self.read_len];
         self.next_frame_start -= active_read_frame.len;
         assert_eq!(self.next_frame_start, active_read_frame.start);
     }
 
     /// Write a single bit to the active write frame
     pub(crate) fn write_bit(&mut self, bit: bool) {
-        self.write
-            .last_mut()
-            .expect("Empty write frame stack")
-            .write_bit(bit, &mut self.data);
+        self.frames[self.write_top].write_bit(bit, &mut self.data);
     }
 
-    /// Move the cursor of the active write frame forward by
-    /// the given number of bits
-    fn skip(&mut self, n: usize) {
-        let idx = self.write.len() - 1;
-        self.write[idx].move_cursor_forward(n);
+    /// Write the given number of zero bits, the padding of a sum value,
+    /// to the active write frame
+    ///
+    /// Data is reused by later frames without being cleared, so padding
+    /// must be written rather than skipped over for the contents of each
+    /// frame to be the padded encoding of its value.
+    pub(crate) fn write_padding(&mut self, n: usize) {
+        self.frames[self.write_top].write_zeros(n, &mut self.data);
     }
 
     /// Copy the given number of bits from the active read frame
     /// to the active write frame
     fn copy(&mut self, n: usize) {
-        let widx = self.write.len() - 1;
-        let ridx = self.read.len() - 1;
-        self.write[widx].copy_from(&self.read[ridx], n, &mut self.data);
+        // There may be no read frame at all when copying zero bits
+        if n == 0 {
+            return;
+        }
+        // The read frame stack lies entirely below the active write frame
+        let (read, write) = self.frames.split_at_mut(self.write_top);
+        write[0].copy_from(&read[self.read_len - 1], n, &mut self.data);
     }
 
     /// Move the cursor of the active read frame forward
     /// by the given number of bits
     fn fwd(&mut self, n: usize) {
-        let idx = self.read.len() - 1;
-        self.read[idx].move_cursor_forward(n);
+        self.frames[self.read_len - 1].move_cursor_forward(n);
     }
 
     /// Move the cursor of the active read frame back
     /// by the given number of bits
     fn back(&mut self, n: usize) {
-        let idx = self.read.len() - 1;
-        self.read[idx].move_cursor_backward(n);
+        self.frames[self.read_len - 1].move_cursor_backward(n);
+    }
+
+    /// Start and cursor of the active read frame, both as absolute bit
+    /// positions in the machine's memory, for jets whose output depends
+    /// on where their input lies
+    ///
+    /// Panics if there is no read frame.
+    pub fn read_cursor(&self) -> (usize, usize) {
+        let frame = &self.frames[self
+            .read_len
+            .checked_sub(1)
+            .expect("Empty read frame stack")];
+        (frame.start, frame.cursor)
+    }
+
+    /// Move the cursor of the active read frame, if any, back to
+    /// a previously saved position
+    fn restore_read_cursor(&mut self, cursor: Option<usize>) {
+        if let Some(cursor) = cursor {
+            self.frames[self.read_len - 1].cursor = cursor;
+        }
+    }
+
+    /// Whether the next `n` bits of the active read frame equal the `n`
+    /// bits after them, moving the cursor past both
+    pub(crate) fn read_equal_halves(&mut self, n: usize) -> bool {
+        let frame = &mut self.frames[self.read_len - 1];
+        let mut second = frame.clone();
+        second.move_cursor_forward(n);
+        let equal = frame.compare_and_advance(&mut second, n, &self.data);
+        frame.cursor = second.cursor;
+        equal
     }
 
     /// Write a big-endian u64 value to the active write frame
     pub(crate) fn write_u64(&mut self, value: u64) {
-        self.write
-            .last_mut()
-            .expect("Empty write frame stack")
-            .write_u64(value, &mut self.data);
+        self.frames[self.write_top].write_u64(value, &mut self.data);
     }
 
     /// Write a big-endian u32 value to the active write frame
     pub(crate) fn write_u32(&mut self, value: u32) {
-        self.write
-            .last_mut()
-            .expect("Empty write frame stack")
-            .write_u32(value, &mut self.data);
+        self.frames[self.write_top].write_u32(value, &mut self.data);
     }
 
     /// Write a big-endian u16 value to the active write frame
     pub(crate) fn write_u16(&mut self, value: u16) {
-        self.write
-            .last_mut()
-            .expect("Empty write frame stack")
-            .write_u16(value, &mut self.data);
+        self.frames[self.write_top].write_u16(value, &mut self.data);
     }
 
     /// Write a big-endian u8 value to the active write frame
     pub(crate) fn write_u8(&mut self, value: u8) {
-        self.write
-            .last_mut()
-            .expect("Empty write frame stack")
-            .write_u8(value, &mut self.data);
+        self.frames[self.write_top].write_u8(value, &mut self.data);
     }
 
     /// Read a big-endian u64 value from the active read frame
     pub(crate) fn read_u64(&mut self) -> u64 {
-        self.read
-            .last_mut()
-            .expect("Empty read frame stack")
-            .read_u64(&self.data)
+        self.frames[self.read_len - 1].read_u64(&self.data)
     }
 
     /// Read a big-endian u32 value from the active read frame
     pub(crate) fn read_u32(&mut self) -> u32 {
-        self.read
-            .last_mut()
-            .expect("Empty read frame stack")
-            .read_u32(&self.data)
+        self.frames[self.read_len - 1].read_u32(&self.data)
     }
 
     /// Read a big-endian u16 value from the active read frame
     pub(crate) fn read_u16(&mut self) -> u16 {
-        self.read
-            .last_mut()
-            .expect("Empty read frame stack")
-            .read_u16(&self.data)
+        self.frames[self.read_len - 1].read_u16(&self.data)
     }
 
     /// Read a big-endian u8 value from the active read frame
     pub(crate) fn read_u8(&mut self) -> u8 {
-        self.read
-            .last_mut()
-            .expect("Empty read frame stack")
-            .read_u8(&self.data)
+        self.frames[self.read_len - 1].read_u8(&self.data)
     }
 
     /// Read a bit from the active read frame
     pub(crate) fn read_bit(&mut self) -> bool {
-        self.read
-            .last_mut()
-            .expect("Empty read frame stack")
-            .read_bit(&self.data)
+        self.frames[self.read_len - 1].read_bit(&self.data)
     }
 
     /// Read 32 bytes from the active read frame
     pub(crate) fn read_32bytes(&mut self) -> [u8; 32] {
         let mut ret = [0u8; 32];
-        for byte in &mut ret {
-            *byte = self
-                .read
-                .last_mut()
-                .expect("Empty read frame stack")
-                .read_u8(&self.data);
-        }
+        self.read_bytes_into(&mut ret);
         ret
     }
 
-    /// Read the given number of bytes from the active read frame
-    pub(crate) fn read_bytes(&mut self, n: usize) -> Vec<u8> {
-        let mut ret = Vec::with_capacity(n);
-        for _i in 0..n {
-            ret.push(
-                self.read
-                    .last_mut()
-                    .expect("Empty read frame stack")
-                    .read_u8(&self.data),
-            );
-        }
-        ret
+    /// Fill a buffer with bytes read from the active read frame
+    pub(crate) fn read_bytes_into(&mut self, buf: &mut [u8]) {
+        self.frames[self.read_len - 1].read_bytes_into(buf, &self.data);
     }
 
-    /// Write a bit string to the active write frame
+    /// Write a whole number of bytes to the active write frame
     pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
-        for bit in bytes {
-            self.write_u8(*bit);
-        }
+        self.frames[self.write_top].write_bytes(bytes, &mut self.data);
+    }
+
+    /// Write the first `bit_len` bits of a byte string, most significant
+    /// bit of each byte first, to the active write frame
+    pub(crate) fn write_bits_from_bytes(&mut self, bytes: &[u8], bit_len: usize) {
+        self.frames[self.write_top].write_bits_from_bytes(bytes, bit_len, &mut self.data);
     }
 
     /// Write a value to the current write frame
+    #[cfg(test)]
     fn write_value(&mut self, val: &Value) {
-        // FIXME don't recurse
-        match *val {
-            Value::Unit => {}
-            Value::SumL(ref a) => {
-                self.write_bit(false);
-                self.write_value(a);
+        self.write_value_bits(val, None);
+    }
+
+    /// Write a value of the given type to the current write frame,
+    /// padding sum values out to the width of their type
+    fn write_padded_value(&mut self, val: &Value, ty: &FinalType) {
+        self.write_value_bits(val, Some(ty));
+    }
+
+    /// Write a value to the current write frame, padding sums if its type
+    /// is given
+    ///
+    /// Runs of bits which are not interrupted by padding are packed into
+    /// bytes and written a whole byte at a time.
+    fn write_value_bits(&mut self, val: &Value, ty: Option<&FinalType>) {
+        let mut stack = vec![(val, ty)];
+        // Bits not yet written, at the bottom of `pending`
+        let mut pending = 0u8;
+        let mut n_pending = 0;
+
+        while let Some((val, ty)) = stack.pop() {
+            match *val {
+                Value::Unit => match ty.map(|ty| &ty.ty) {
+                    None | Some(FinalTypeInner::Unit) => {}
+                    Some(_) => panic!("value {} does not have type {}", val, ty.unwrap()),
+                },
+                Value::SumL(ref a) | Value::SumR(ref a) => {
+                    let right = match *val {
+                        Value::SumR(..) => true,
+                        _ => false,
+                    };
+                    pending = (pending << 1) | right as u8;
+                    n_pending += 1;
+                    if n_pending == 8 {
+                        self.write_u8(pending);
+                        n_pending = 0;
+                    }
+
+                    let arm_ty = ty.map(|ty| match (right, &ty.ty, ty.sum_padding()) {
+                        (false, FinalTypeInner::Sum(l, _), Some((padding, _))) => (padding, &**l),
+                        (true, FinalTypeInner::Sum(_, r), Some((_, padding))) => (padding, &**r),
+                        _ => panic!("value {} does not have type {}", val, ty),
+                    });
+                    if let Some((padding, _)) = arm_ty {
+                        if padding > 0 {
+                            self.flush_bits(pending, n_pending);
+                            n_pending = 0;
+                            self.write_padding(padding);
+                        }
+                    }
+                    stack.push((a, arm_ty.map(|(_, arm_ty)| arm_ty)));
+                }
+                Value::Prod(ref a, ref b) => match ty.map(|ty| &ty.ty) {
+                    Some(FinalTypeInner::Product(l, r)) => {
+                        stack.push((b, Some(r)));
+                        stack.push((a, Some(l)));
+                    }
+                    Some(_) => panic!("value {} does not have type {}", val, ty.unwrap()),
+                    None => {
+                        stack.push((b, None));
+                        stack.push((a, None));
+                    }
+                },
             }
-            Value::SumR(ref a) => {
-                self.write_bit(true);
-                self.write_value(a);
+        }
+        self.flush_bits(pending, n_pending);
+    }
+
+    /// Write the bottom `n` bits of `bits` to the active write frame
+    fn flush_bits(&mut self, bits: u8, n: usize) {
+        for idx in (0..n).rev() {
+            self.write_bit(bits & (1 << idx) != 0);
+        }
+    }
+
+    /// Check that a value has the program's source type, load it as the
+    /// program's input and execute the program
+    ///
+    /// This is the recommended way to run a program; `input` and `exec`
+    /// remain available separately for advanced use.
+    pub fn exec_with_input<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        input: &Value,
+        txenv: &Ext::TxEnv,
+    ) -> Result<Value, ExecError> {
+        let source_ty = &program.root_node().source_ty;
+        if !input.is_of_type(source_ty) {
+            return Err(ExecError::InputTypeMismatch);
+        }
+        self.new_frame(source_ty.bit_width())?;
+        self.write_padded_value(input, source_ty);
+        self.move_frame()?;
+        self.exec_inner(program, txenv)
+    }
+
+    /// Give a value as input to the program
+    ///
+    /// This should be called exactly once before `exec`, unless the
+    /// program's input is the unit type. Once an input has been given, or
+    /// a program has been run, further inputs are rejected until the
+    /// machine is `reset`. The input is loaded into a read frame when the
+    /// program is run, padded as by `exec_with_input`, and execution fails
+    /// if it does not have the program's source type.
+    pub fn input(&mut self, input: &Value) -> Result<(), ExecError> {
+        if self.pending_input.is_some() || self.read_len > 0 || self.write_len() > 0 {
+            return Err(ExecError::ExtraInput);
+        }
+        self.pending_input = Some(input.clone());
+        Ok(())
+    }
+
+    /// Load the input given by `input` into a read frame, as is done by
+    /// `exec_with_input`, without tracing it
+    fn load_input(&mut self, input: &Value, source_ty: &FinalType) -> Result<(), ExecError> {
+        if self.read_len > 0 {
+            return Err(ExecError::ExtraInput);
+        }
+        if !input.is_of_type(source_ty) {
+            return Err(ExecError::InputTypeMismatch);
+        }
+        let trace = self.trace.take();
+        let result = self.new_frame(source_ty.bit_width()).and_then(|()| {
+            self.write_padded_value(input, source_ty);
+            self.move_frame()
+        });
+        self.trace = trace;
+        result
+    }
+
+    /// Execute a program in the Bit Machine, returning its output along
+    /// with the resources used by this execution
+    pub fn exec_with_stats<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> (Value, ResourceUsage) {
+        self.stats = ExecStats::default();
+        // Frames allocated before execution, i.e. the input frame
+        self.stats.peak_cells = self.next_frame_start;
+        self.stats.peak_frames = cmp::max(self.read_len, self.write_len());
+
+        let output = self.exec(program, txenv);
+        (output, self.resource_usage(program))
+    }
+
+    /// Resources used by the execution of a program since the
+    /// statistics were last reset
+    fn resource_usage<Ext: extension::Jet>(&self, program: &Program<Ext>) -> ResourceUsage {
+        let mut sink = BitWriter::new(io::sink());
+        let bits = program
+            .encode(&mut sink)
+            .expect("writing to a sink never fails");
+        ResourceUsage {
+            cost_consumed: self.stats.steps,
+            peak_cells: self.stats.peak_cells,
+            peak_frames: self.stats.peak_frames,
+            bytes_serialized: (bits + 7) / 8,
+        }
+    }
+
+    /// Execute a program on each of the given inputs in turn, reusing
+    /// this machine's memory, which must be large enough for the program
+    pub fn exec_batch<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+        inputs: &[Value],
+    ) -> Vec<Result<Value, ExecError>> {
+        inputs
+            .iter()
+            .map(|input| {
+                self.reset();
+                self.exec_with_input(program, input, txenv)
+            })
+            .collect()
+    }
+
+    /// Execute a program on each of the given inputs, sharding them
+    /// across one machine per thread
+    #[cfg(feature = "rayon")]
+    pub fn exec_batch_par<Ext>(
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+        inputs: &[Value],
+    ) -> Vec<Result<Value, ExecError>>
+    where
+        Ext: extension::Jet + Sync,
+        Ext::TxEnv: Sync,
+    {
+        use rayon::prelude::*;
+
+        let chunk_size = cmp::max(1, inputs.len() / rayon::current_num_threads());
+        inputs
+            .par_chunks(chunk_size)
+            .flat_map_iter(|chunk| {
+                BitMachine::for_program(program).exec_batch(program, txenv, chunk)
+            })
+            .collect()
+    }
+
+    /// Execute a program in the Bit Machine, returning its output along
+    /// with every frame operation and node visited during execution
+    pub fn exec_traced<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> (Value, Vec<TraceEvent>) {
+        self.trace = Some(vec![]);
+        let output = self.exec(program, txenv);
+        (output, self.trace.take().unwrap_or_default())
+    }
+
+    /// Execute a program in the Bit Machine, returning its output along
+    /// with each bit of the output frame, paired with the index of the
+    /// node which wrote it
+    ///
+    /// Bits copied to the output are attributed to the node which copied
+    /// them, e.g. an `iden` node, rather than to the node which first
+    /// computed them. The padding of a sum value is attributed to the
+    /// `injl` or `injr` node which wrote it.
+    pub fn exec_attributed<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> (Value, Vec<(bool, usize)>) {
+        let output_width = program.root_node().target_ty.bit_width();
+        if output_width == 0 {
+            return (self.exec(program, txenv), vec![]);
+        }
+        self.output_sources = Some(Vec::with_capacity(output_width));
+        let output = self.exec(program, txenv);
+        let sources = self.output_sources.take().unwrap_or_default();
+        let out_frame = &self.frames[self.write_top];
+        let bits = out_frame.to_frame_data(&self.data).zip(sources).collect();
+        (output, bits)
+    }
+
+    /// Execute a program on the given input, returning the result along
+    /// with the state of the machine as it began executing each node, up
+    /// to and including the node at which execution failed, if it did
+    pub(crate) fn exec_steps<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        input: &Value,
+        txenv: &Ext::TxEnv,
+    ) -> (Result<Value, ExecError>, Vec<ExecStep>) {
+        self.steps = Some(vec![]);
+        let result = self.exec_with_input(program, input, txenv);
+        (result, self.steps.take().unwrap_or_default())
+    }
+
+    /// Record the state of the machine as it begins executing a node
+    fn record_step(&mut self, node: usize) {
+        let read_cursor = self.read_len.checked_sub(1).map(|idx| {
+            let frame = &self.frames[idx];
+            frame.cursor - frame.start
+        });
+        let write_cursor = if self.write_len() > 0 {
+            let frame = &self.frames[self.write_top];
+            Some(frame.cursor - frame.start)
+        } else {
+            None
+        };
+        let step = ExecStep {
+            node: node,
+            read_cursor: read_cursor,
+            write_cursor: write_cursor,
+            read_frames: self.read_len,
+            write_frames: self.write_len(),
+        };
+        if let Some(ref mut steps) = self.steps {
+            steps.push(step);
+        }
+    }
+
+    /// Attribute any bits written to the output frame since this was last
+    /// called to the node with the given index, if attributing
+    fn attribute_output(&mut self, node: usize) {
+        if let Some(ref mut sources) = self.output_sources {
+            // The output frame is at the bottom of the write frame stack
+            let out_frame = self.frames.last().expect("output frame");
+            let written = out_frame.cursor - out_frame.start;
+            if written > sources.len() {
+                sources.resize(written, node);
             }
-            Value::Prod(ref a, ref b) => {
-                self.write_value(a);
-                self.write_value(b);
+        }
+    }
+
+    /// Execute a program in the Bit Machine, failing if at any point
+    /// more than `max_cells` cells would be allocated, or either frame
+    /// stack would hold more than `max_frames` frames
+    ///
+    /// Both limits include the input and output frames. Unlike the static
+    /// bounds used by `for_program`, these are checked as execution runs,
+    /// so they need not trust the program's own bounds. After a failure,
+    /// the machine must be `reset` before reuse.
+    pub fn exec_bounded<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+        max_cells: usize,
+        max_frames: usize,
+    ) -> Result<Value, ExecError> {
+        if self.next_frame_start > max_cells || self.read_len > max_frames {
+            return Err(ExecError::MemoryExceeded);
+        }
+        self.bounds = Some((max_cells, max_frames));
+        let result = self.exec_inner(program, txenv);
+        self.bounds = None;
+        #[cfg(feature = "log")]
+        log::debug!(
+            peak_cells = self.stats.peak_cells,
+            max_cells = max_cells,
+            peak_frames = self.stats.peak_frames,
+            max_frames = max_frames;
+            "memory bounds"
+        );
+        result
+    }
+
+    /// Load a value as the program's input and execute the program until
+    /// it reaches its first witness node, returning that node, or `None`
+    /// if the program finishes without reaching one
+    ///
+    /// This lets a witness be chosen once the part of the program which
+    /// runs before it is known. Afterwards, the machine must be `reset`
+    /// before reuse.
+    pub fn exec_until_witness<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        input: &Value,
+        txenv: &Ext::TxEnv,
+    ) -> Result<Option<NodeId>, ExecError> {
+        self.stop_at_witness = true;
+        self.stopped_at_witness = None;
+        let result = self.exec_with_input(program, input, txenv);
+        self.stop_at_witness = false;
+        result.map(|_| self.stopped_at_witness.take())
+    }
+
+    /// Execute a program until it reaches a jet, without executing the
+    /// jet, so that its output can be computed elsewhere
+    ///
+    /// The input, if any, must already have been added with `input`.
+    /// Extension nodes count as jets. If execution is suspended, it must
+    /// be continued with `resume_after_jet`, or the machine `reset`.
+    pub fn run_until_jet<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<JetStep, ExecError> {
+        self.stop_at_jet = true;
+        let result = self.run(program, txenv);
+        self.stop_at_jet = false;
+        result?;
+        Ok(match self.suspended.take() {
+            Some(state) => JetStep::Jet(state),
+            None => JetStep::Done(self.output_value(program)),
+        })
+    }
+
+    /// Pause execution just before the given node, each time it is reached
+    /// by `run_to_breakpoint`
+    pub fn add_breakpoint(&mut self, node: NodeId) {
+        if !self.breakpoints.contains(&node) {
+            self.breakpoints.push(node);
+        }
+    }
+
+    /// Execute a program until it reaches a node added with
+    /// `add_breakpoint`, without executing the node, so that the frames
+    /// it works on can be inspected
+    ///
+    /// The input, if any, must already have been added with `input`.
+    /// Calling this again continues execution from the breakpoint; the
+    /// machine may instead be `reset`. Fails with `Error::ForeignNodeId`,
+    /// without running, if a breakpoint is not a node of `program`.
+    pub fn run_to_breakpoint<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<BreakpointStep, Error> {
+        for &id in &self.breakpoints {
+            program.node(id)?;
+        }
+        self.stop_at_breakpoint = true;
+        let result = self.run(program, txenv);
+        self.stop_at_breakpoint = false;
+        result?;
+        Ok(match self.suspended.take() {
+            Some(state) => {
+                let hit = BreakpointHit {
+                    node: state.node,
+                    read_frame: self.read_len.checked_sub(1).map(|idx| self.frame_bits(idx)),
+                    write_frame: if self.write_len() > 0 {
+                        Some(self.frame_bits(self.write_top))
+                    } else {
+                        None
+                    },
+                };
+                self.resume = Some(state);
+                BreakpointStep::Hit(hit)
             }
+            None => BreakpointStep::Done(self.output_value(program)),
+        })
+    }
+
+    /// Continue running a program until it is about to execute its next
+    /// node, returning the index of that node, or `None` once the program
+    /// has finished
+    ///
+    /// The machine may instead be `reset` between calls.
+    pub(crate) fn run_to_next_node<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<Option<usize>, ExecError> {
+        self.stop_at_node = true;
+        let result = self.run(program, txenv);
+        self.stop_at_node = false;
+        result?;
+        Ok(self.suspended.take().map(|state| {
+            let idx = state.node.index();
+            self.resume = Some(state);
+            idx
+        }))
+    }
+
+    /// Bits of the output of the last program run on this machine, or
+    /// `None` if the last run did not finish or the machine has since
+    /// been `reset`
+    ///
+    /// These are the bits of the output's compact encoding, as given by
+    /// `Value::iter_bits`, read from the output frame by skipping the
+    /// padding of each sum, and so can be hashed without decoding the
+    /// output.
+    pub fn last_output_bits(&self) -> Option<Vec<bool>> {
+        let ty = self.output_ty.as_ref()?;
+        if ty.bit_width() == 0 {
+            return Some(vec![]);
         }
+        let mut frame = self.frames[self.write_top].clone();
+        frame.reset_cursor();
+        Some(compact_bits(frame.to_frame_data(&self.data), ty))
     }
 
-    /// Add a read frame with some given value in it, as input to the
-    /// program
-    pub fn input(&mut self, input: &Value) {
-        // FIXME typecheck this
-        self.new_frame(input.len());
-        self.write_value(input);
-        self.move_frame();
+    /// All bits of the frame with the given index, and the offset of its
+    /// cursor
+    fn frame_bits(&self, idx: usize) -> (Vec<bool>, usize) {
+        let mut frame = self.frames[idx].clone();
+        let cursor = frame.cursor - frame.start;
+        frame.reset_cursor();
+        (frame.to_frame_data(&self.data).collect(), cursor)
+    }
+
+    /// Continue execution suspended by `run_until_jet` as though the jet
+    /// had output `jet_output`, until the next jet
+    ///
+    /// Fails with `ExecError::JetOutputTypeMismatch` if `jet_output` does
+    /// not have the jet's target type. Panics if `state` is not from an
+    /// execution of `program` on this machine.
+    pub fn resume_after_jet<Ext: extension::Jet>(
+        &mut self,
+        state: MachineState,
+        jet_output: &Value,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<JetStep, ExecError> {
+        let node = program
+            .node(state.node)
+            .expect("machine state is from this program");
+        if !jet_output.is_of_type(&node.target_ty) {
+            return Err(ExecError::JetOutputTypeMismatch(state.node));
+        }
+        self.write_padded_value(jet_output, &node.target_ty);
+        self.resume = Some(state);
+        self.run_until_jet(program, txenv)
     }
 
     /// Execute a program in the Bit Machine
+    ///
+    /// Panics if execution reaches a `fail` node; `exec_with_input` and
+    /// `exec_bounded` return this as an error instead.
     pub fn exec<Ext: extension::Jet>(
         &mut self,
         program: &Program<Ext>,
         txenv: &Ext::TxEnv,
     ) -> Value {
-        enum CallStack {
-            Goto(usize),
-            MoveFrame,
-            DropFrame,
-            CopyFwd(usize),
-            Back(usize),
+        // There are no memory bounds outside of `exec_bounded`
+        self.exec_inner(program, txenv)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Execute a program in the Bit Machine, passing the bytes of its
+    /// output to `sink` a chunk at a time rather than decoding it
+    ///
+    /// The output is given as its padded bit encoding, as laid out in
+    /// the output frame, with the last byte filled out with zero bits.
+    /// The input, if any, must already have been added with `input`.
+    pub fn exec_stream_output<Ext: extension::Jet, F: FnMut(&[u8])>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+        mut sink: F,
+    ) -> Result<(), ExecError> {
+        self.run(program, txenv)?;
+
+        let output_width = program.root_node().target_ty.bit_width();
+        if output_width == 0 {
+            return Ok(());
+        }
+        let mut out_frame = self.frames[self.write_top].clone();
+        out_frame.reset_cursor();
+        let mut chunk = [0; 64];
+        let mut remaining = output_width;
+        while remaining >= 8 {
+            let chunk = &mut chunk[..cmp::min(remaining / 8, 64)];
+            out_frame.read_bytes_into(chunk, &self.data);
+            remaining -= 8 * chunk.len();
+            sink(chunk);
         }
+        if remaining > 0 {
+            let last = (0..remaining).fold(0u8, |acc, idx| {
+                acc | (out_frame.read_bit(&self.data) as u8) << (7 - idx)
+            });
+            sink(&[last]);
+        }
+        Ok(())
+    }
 
-        let mut ip = program.root_node();
-        let mut call_stack = vec![];
-        let mut iters = 0u64;
+    /// Execute a program in the Bit Machine, enforcing the memory bounds,
+    /// if any, and decode its output
+    fn exec_inner<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<Value, ExecError> {
+        self.run(program, txenv)?;
+        if self.stopped_at_witness.is_some() {
+            return Ok(Value::Unit);
+        }
+        Ok(self.output_value(program))
+    }
 
-        let input_width = ip.source_ty.bit_width();
-        if input_width > 0 && self.read.is_empty() {
-            panic!(
-                "Pleas call `Program::input` to add an input value for this program {}",
-                ip
-            );
+    /// Execute a program in the Bit Machine, leaving its output in the
+    /// active write frame, on the fast path if the program's features allow
+    fn run<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<(), ExecError> {
+        self.output_ty = None;
+        #[cfg(feature = "log")]
+        let _span = crate::logging::Span::enter("exec");
+        let result = if program.features().is_empty()
+            && self.trace.is_none()
+            && self.output_sources.is_none()
+            && self.steps.is_none()
+            && !self.stop_at_breakpoint
+            && !self.stop_at_node
+        {
+            self.exec_simple(program, txenv)
+        } else {
+            self.exec_full(program, txenv)
+        };
+        #[cfg(feature = "log")]
+        match (result, result.err().and_then(|e| e.node())) {
+            (Ok(()), _) => log::debug!(
+                peak_cells = self.stats.peak_cells,
+                peak_frames = self.stats.peak_frames,
+                jets = self.stats.jets;
+                "execution finished"
+            ),
+            (Err(e), Some(node)) => {
+                log::debug!(node = node.index(), error:% = e; "execution failed")
+            }
+            (Err(e), None) => log::debug!(error:% = e; "execution failed"),
         }
-        let output_width = ip.target_ty.bit_width();
-        if output_width > 0 {
-            self.new_frame(output_width);
+        result?;
+        if self.suspended.is_none() && self.stopped_at_witness.is_none() {
+            self.output_ty = Some(Arc::clone(&program.root_node().target_ty));
         }
+        Ok(())
+    }
+
+    /// Execute a program without witness, disconnect or extension nodes,
+    /// and without tracing or attributing output bits
+    fn exec_simple<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<(), ExecError> {
+        debug_assert!(program.features().is_empty());
+        self.exec_loop(program, txenv, true)
+    }
+
+    /// Execute a program with any features, tracing, attributing output
+    /// bits, recording steps and stopping at breakpoints as requested
+    fn exec_full<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+    ) -> Result<(), ExecError> {
+        self.exec_loop(program, txenv, false)
+    }
+
+    /// The Bit Machine main loop; if `simple` is set, the program has no
+    /// features and execution is not being traced, attributed, recorded
+    /// or stopped at breakpoints
+    ///
+    /// Always inlined into `exec_simple` and `exec_full`, so that `simple`
+    /// is a constant in each and the checks it guards are compiled out
+    #[inline(always)]
+    fn exec_loop<Ext: extension::Jet>(
+        &mut self,
+        program: &Program<Ext>,
+        txenv: &Ext::TxEnv,
+        simple: bool,
+    ) -> Result<(), ExecError> {
+        let root = program.root_node();
+        // Preallocated from the static bound, so never reallocated
+        let call_stack_bound = root.call_stack_bound;
+        #[cfg(feature = "log")]
+        let mut iters = 0u64;
+        // The node at which execution paused is executed on resuming
+        let mut skip_breakpoint = false;
+        let (mut ip, mut call_stack, base) = match self.resume.take() {
+            Some(state) if state.pending => {
+                skip_breakpoint = true;
+                (
+                    &program.nodes[state.node.index()],
+                    state.call_stack,
+                    state.base,
+                )
+            }
+            Some(state) => {
+                let mut call_stack = state.call_stack;
+                match self.next_node(program, &mut call_stack)? {
+                    Some(ip) => (ip, call_stack, state.base),
+                    None => return Ok(()),
+                }
+            }
+            None => {
+                if let Some(input) = self.pending_input.take() {
+                    self.load_input(&input, &root.source_ty)?;
+                }
+                // A program with unit input may be run without an input frame
+                let input_width = root.source_ty.bit_width();
+                match self.read_len {
+                    0 if input_width > 0 => return Err(ExecError::MissingInput),
+                    0 => {}
+                    1 if self.frames[0].len != input_width => {
+                        return Err(ExecError::InputTypeMismatch)
+                    }
+                    1 => {}
+                    _ => return Err(ExecError::ExtraInput),
+                }
+                let output_width = root.target_ty.bit_width();
+                if output_width > 0 {
+                    self.new_frame(output_width)?;
+                }
+
+                // Frames and cells in use before the program starts, against
+                // which its static bounds are checked
+                let base = (self.read_len, self.write_len(), self.next_frame_start);
+                (root, Vec::with_capacity(call_stack_bound), base)
+            }
+        };
+        let (base_read, base_write, base_cells) = base;
+        let (max_frames, max_cells) = (root.frame_count_bound, root.extra_cells_bound);
 
         'main_loop: loop {
-            iters += 1;
-            if iters % 1_000_000_000 == 0 {
-                println!("({:5} M) exec {}", iters / 1_000_000, ip);
+            if !simple
+                && (self.stop_at_node || self.stop_at_breakpoint)
+                && !mem::replace(&mut skip_breakpoint, false)
+                && (self.stop_at_node || self.breakpoints.iter().any(|id| id.index() == ip.index))
+            {
+                self.suspended = Some(MachineState {
+                    node: program.id(ip.index),
+                    input: Value::Unit,
+                    pending: true,
+                    call_stack: call_stack,
+                    base: base,
+                });
+                return Ok(());
+            }
+            self.stats.steps += 1;
+            check_invariant!(
+                self.read_len <= base_read + max_frames
+                    && self.write_len() <= base_write + max_frames,
+                "frame stacks of {} read and {} write frames exceed static bound {}",
+                self.read_len - base_read,
+                self.write_len() - base_write,
+                max_frames,
+            );
+            check_invariant!(
+                self.next_frame_start <= base_cells + max_cells,
+                "{} cells in use exceed static bound {}",
+                self.next_frame_start - base_cells,
+                max_cells,
+            );
+            if !simple {
+                self.record(TraceEvent::Exec(ip.index));
+                if self.steps.is_some() {
+                    self.record_step(ip.index);
+                }
+            }
+            #[cfg(feature = "log")]
+            {
+                iters += 1;
+                if iters % 1_000_000_000 == 0 {
+                    log::trace!(steps = iters, node = ip.index; "executing");
+                }
             }
 
             match ip.node {
                 Term::Unit => {}
                 Term::Iden => self.copy(ip.source_ty.bit_width()),
-                Term::InjL(t) => {
+                Term::InjL(..) => {
                     self.write_bit(false);
-                    if let FinalTypeInner::Sum(ref a, _) = ip.target_ty.ty {
-                        let aw = a.bit_width();
-                        self.skip(ip.target_ty.bit_width() - aw - 1);
-                        call_stack.push(CallStack::Goto(ip.index - t));
-                    } else {
-                        panic!("type error")
-                    }
+                    self.write_padding(ip.inj_padding().expect("type error"));
+                    call_stack.push(CallStack::Goto(ip.left()));
                 }
-                Term::InjR(t) => {
+                Term::InjR(..) => {
                     self.write_bit(true);
-                    if let FinalTypeInner::Sum(_, ref b) = ip.target_ty.ty {
-                        let bw = b.bit_width();
-                        self.skip(ip.target_ty.bit_width() - bw - 1);
-                        call_stack.push(CallStack::Goto(ip.index - t));
-                    } else {
-                        panic!("type error")
-                    }
+                    self.write_padding(ip.inj_padding().expect("type error"));
+                    call_stack.push(CallStack::Goto(ip.left()));
                 }
-                Term::Pair(s, t) => {
-                    call_stack.push(CallStack::Goto(ip.index - t));
-                    call_stack.push(CallStack::Goto(ip.index - s));
+                Term::Pair(..) => {
+                    call_stack.push(CallStack::Goto(ip.right()));
+                    call_stack.push(CallStack::Goto(ip.left()));
                 }
-                Term::Comp(s, t) => {
-                    let size = program.nodes[ip.index - s].target_ty.bit_width();
-                    self.new_frame(size);
+                Term::Comp(..) => {
+                    let size = program.nodes[ip.left()].target_ty.bit_width();
+                    self.new_frame(size)?;
 
                     call_stack.push(CallStack::DropFrame);
-                    call_stack.push(CallStack::Goto(ip.index - t));
+                    call_stack.push(CallStack::Goto(ip.right()));
                     call_stack.push(CallStack::MoveFrame);
-                    call_stack.push(CallStack::Goto(ip.index - s));
+                    call_stack.push(CallStack::Goto(ip.left()));
                 }
-                Term::Disconnect(s, t) => {
+                Term::Disconnect(..) => {
+                    let (s, t) = (ip.left(), ip.right());
+                    // The part of `s`'s output which is not `t`'s input is
+                    // copied directly to the output
+                    let s_target_size = program.nodes[s].target_ty.bit_width();
+                    let b_size = s_target_size
+                        .checked_sub(program.nodes[t].source_ty.bit_width())
+                        .ok_or_else(|| ExecError::DisconnectWidthMismatch(program.id(ip.index)))?;
+
+                    // Allocate the frame for `s`'s output first, so that the
+                    // frame for its input sits above it and can be freed as
+                    // soon as `s` is done
+                    self.new_frame(s_target_size)?;
+
                     // Write `t`'s CMR followed by `s` input to a new read frame
-                    let size = program.nodes[ip.index - s].source_ty.bit_width();
-                    assert!(size >= 256);
-                    self.new_frame(size);
-                    self.write_bytes(&program.nodes[ip.index - t].cmr);
-                    self.copy(size - 256);
-                    self.move_frame();
-
-                    let s_target_size = program.nodes[ip.index - s].target_ty.bit_width();
-                    self.new_frame(s_target_size);
+                    // Typechecking ensures that `s` takes a CMR as input
+                    let s_source_ty = &program.nodes[s].source_ty;
+                    check_invariant!(
+                        match s_source_ty.ty {
+                            FinalTypeInner::Product(ref cmr_ty, _) => {
+                                cmr_ty.bit_width() == DISCONNECT_CMR_BITS
+                            }
+                            _ => false,
+                        },
+                        "left child of disconnect does not take a CMR as input"
+                    );
+                    let size = s_source_ty.bit_width();
+                    self.new_frame(size)?;
+                    self.write_bytes(&program.nodes[t].cmr);
+                    self.copy(size - DISCONNECT_CMR_BITS);
+                    self.move_frame()?;
+
                     // Then recurse. Remembering that call stack pushes are executed
                     // in reverse order:
 
-                    // 3. Delete the two frames we created, which have both moved to the read stack
-                    call_stack.push(CallStack::DropFrame);
+                    // 3. Delete `s`'s output frame, which has moved to the read stack
                     call_stack.push(CallStack::DropFrame);
                     // 2. Copy the first half of `s`s output directly then execute `t` on the second half
-                    call_stack.push(CallStack::Goto(ip.index - t));
-                    let b_size = s_target_size - program.nodes[ip.index - t].source_ty.bit_width();
-                    call_stack.push(CallStack::CopyFwd(b_size));
-                    // 1. Execute `s` then move the write frame to the read frame for `t`
+                    call_stack.push(CallStack::Goto(t));
+                    call_stack.push(CallStack::CopyFwd(b_size, ip.index));
+                    // 1. Execute `s`, delete its input frame, then move the write
+                    //    frame to the read frame for `t`
                     call_stack.push(CallStack::MoveFrame);
-                    call_stack.push(CallStack::Goto(ip.index - s));
+                    call_stack.push(CallStack::DropFrame);
+                    call_stack.push(CallStack::Goto(s));
                 }
-                Term::Take(t) => call_stack.push(CallStack::Goto(ip.index - t)),
-                Term::Drop(t) => {
-                    if let FinalTypeInner::Product(ref a, _) = ip.source_ty.ty {
-                        let aw = a.bit_width();
-                        self.fwd(aw);
-                        call_stack.push(CallStack::Back(aw));
-                        call_stack.push(CallStack::Goto(ip.index - t));
-                    } else {
-                        panic!("type error")
-                    }
+                Term::Take(..) => call_stack.push(CallStack::Goto(ip.left())),
+                Term::Drop(..) => {
+                    let aw = ip.drop_width().expect("type error");
+                    self.fwd(aw);
+                    call_stack.push(CallStack::Back(aw));
+                    call_stack.push(CallStack::Goto(ip.left()));
                 }
-                Term::Case(s, t) => {
-                    let sw = self.read[self.read.len() - 1].peek_bit(&self.data);
-                    let aw;
-                    let bw;
-                    if let FinalTypeInner::Product(ref a, _) = ip.source_ty.ty {
-                        if let FinalTypeInner::Sum(ref a, ref b) = a.ty {
-                            aw = a.bit_width();
-                            bw = b.bit_width();
-                        } else {
-                            panic!("type error");
-                        }
-                    } else {
-                        panic!("type error");
-                    }
+                Term::Case(..) => {
+                    let sw = self.frames[self.read_len - 1].peek_bit(&self.data);
+                    let parts = ip.case_parts().expect("type error");
 
+                    // Skip the tag and padding to reach the arm's value
+                    let padding = if sw {
+                        parts.right_padding()
+                    } else {
+                        parts.left_padding()
+                    };
+                    let skip = 1 + padding
+                        .ok_or_else(|| ExecError::CaseWidthMismatch(program.id(ip.index)))?;
+                    self.fwd(skip);
+                    call_stack.push(CallStack::Back(skip));
                     if sw {
-                        self.fwd(1 + cmp::max(aw, bw) - bw);
-                        call_stack.push(CallStack::Back(1 + cmp::max(aw, bw) - bw));
-                        call_stack.push(CallStack::Goto(ip.index - t));
+                        call_stack.push(CallStack::Goto(ip.right()));
                     } else {
-                        self.fwd(1 + cmp::max(aw, bw) - aw);
-                        call_stack.push(CallStack::Back(1 + cmp::max(aw, bw) - aw));
-                        call_stack.push(CallStack::Goto(ip.index - s));
+                        call_stack.push(CallStack::Goto(ip.left()));
                     }
                 }
-                Term::Witness(ref value) => self.write_value(value),
-                Term::Hidden(ref h) => panic!("Hit hidden node {} at iter {}: {}", ip, iters, h),
-                Term::Ext(ref e) => e.exec(self, txenv),
-                /*
-                 */
-                Term::Jet(ref j) => j.exec(self, &()),
-                Term::Fail(..) => panic!("encountered fail node while executing"),
+                Term::Witness(..) if self.stop_at_witness => {
+                    self.stopped_at_witness = Some(program.id(ip.index));
+                    return Ok(());
+                }
+                // Values of zero-width types have no frame to be written to
+                Term::Witness(..) if ip.target_ty.bit_width() == 0 => {}
+                Term::Witness(ref value) => {
+                    let start = self.frames[self.write_top].cursor;
+                    self.write_padded_value(value, &ip.target_ty);
+                    check_invariant!(
+                        self.frames[self.write_top].cursor - start == ip.target_ty.bit_width(),
+                        "witness value written at the wrong width",
+                    );
+                }
+                Term::Ext(..) | Term::Jet(..) if self.stop_at_jet => {
+                    let input = if ip.source_ty.bit_width() > 0 {
+                        let frame = &self.frames[self.read_len - 1];
+                        Value::from_padded_bits_and_type(
+                            &mut frame.to_frame_data(&self.data),
+                            &ip.source_ty,
+                        )
+                        .expect("reading jet input")
+                    } else {
+                        Value::Unit
+                    };
+                    self.suspended = Some(MachineState {
+                        node: program.id(ip.index),
+                        input: input,
+                        pending: false,
+                        call_stack: call_stack,
+                        base: base,
+                    });
+                    return Ok(());
+                }
+                Term::Hidden(..) => {
+                    #[cfg(feature = "log")]
+                    log::debug!(node = ip.index; "reached pruned branch");
+                    return Err(ExecError::HiddenNode(program.id(ip.index)));
+                }
+                // Jets read their input by advancing the cursor of the active
+                // read frame, which other nodes may still need to read from
+                Term::Ext(ref e) => {
+                    self.stats.jets += 1;
+                    let cursor = self.saved_read_cursor();
+                    e.exec(self, txenv)
+                        .map_err(|e| ExecError::JetFailed(program.id(ip.index), e))?;
+                    self.restore_read_cursor(cursor);
+                }
+                Term::Jet(ref j) => {
+                    self.stats.jets += 1;
+                    let cursor = self.saved_read_cursor();
+                    j.exec(self, &())
+                        .map_err(|e| ExecError::JetFailed(program.id(ip.index), e))?;
+                    self.restore_read_cursor(cursor);
+                }
+                Term::Fail(..) => return Err(ExecError::FailNode(program.id(ip.index))),
+            }
+            if !simple {
+                self.attribute_output(ip.index);
             }
+            check_invariant!(
+                call_stack.len() <= call_stack_bound,
+                "call stack of {} entries exceeds static bound {}",
+                call_stack.len(),
+                call_stack_bound,
+            );
+            self.stats.peak_call_stack_depth =
+                cmp::max(self.stats.peak_call_stack_depth, call_stack.len());
 
-            ip = loop {
-                match call_stack.pop() {
-                    Some(CallStack::Goto(next)) => break &program.nodes[next],
-                    Some(CallStack::MoveFrame) => self.move_frame(),
-                    Some(CallStack::DropFrame) => self.drop_frame(),
-                    Some(CallStack::CopyFwd(n)) => {
-                        self.copy(n);
-                        self.fwd(n);
-                    }
-                    Some(CallStack::Back(n)) => self.back(n),
-                    None => break 'main_loop,
-                };
+            ip = match self.next_node(program, &mut call_stack)? {
+                Some(next) => next,
+                None => break 'main_loop,
             };
         }
+        check_invariant!(
+            call_stack.capacity() == call_stack_bound,
+            "call stack was reallocated"
+        );
+        Ok(())
+    }
 
-        if output_width > 0 {
-            let out_frame = self.write.last_mut().unwrap();
+    /// Pop entries off the call stack, carrying out each one, until one
+    /// says which node to execute next, or the stack is empty
+    fn next_node<'p, Ext: extension::Jet>(
+        &mut self,
+        program: &'p Program<Ext>,
+        call_stack: &mut Vec<CallStack>,
+    ) -> Result<Option<&'p ProgramNode<Ext>>, ExecError> {
+        loop {
+            match call_stack.pop() {
+                Some(CallStack::Goto(next)) => return Ok(Some(&program.nodes[next])),
+                Some(CallStack::MoveFrame) => self.move_frame()?,
+                Some(CallStack::DropFrame) => self.drop_frame(),
+                Some(CallStack::CopyFwd(n, node)) => {
+                    self.copy(n);
+                    self.fwd(n);
+                    self.attribute_output(node);
+                }
+                Some(CallStack::Back(n)) => self.back(n),
+                None => return Ok(None),
+            };
+        }
+    }
+
+    /// Decode the output of a program which has just been executed from
+    /// the active write frame
+    fn output_value<Ext: extension::Jet>(&mut self, program: &Program<Ext>) -> Value {
+        if program.root_node().target_ty.bit_width() > 0 {
+            let out_frame = &mut self.frames[self.write_top];
             out_frame.reset_cursor();
-            Value::from_bits_and_type(
+            let target_ty = &program.root_node().target_ty;
+            let output = Value::from_padded_bits_and_type(
                 &mut out_frame.to_frame_data(&self.data),
-                &program.root_node().target_ty,
+                target_ty,
             )
-            .expect("unwrapping output value")
+            .expect("unwrapping output value");
+            check_invariant!(
+                out_frame
+                    .to_frame_data(&self.data)
+                    .eq(output.iter_padded_bits(target_ty).unwrap()),
+                "output frame has nonzero padding",
+            );
+            output
         } else {
             Value::Unit
         }
     }
 }
+
+/// The compact encoding, as given by `Value::iter_bits`, of the value of
+/// type `ty` laid out as the given bits of a Bit Machine frame
+fn compact_bits<I: Iterator<Item = bool>>(mut padded: I, ty: &FinalType) -> Vec<bool> {
+    let mut ret = Vec::with_capacity(ty.bit_width());
+    let mut stack = vec![ty];
+    while let Some(ty) = stack.pop() {
+        match ty.ty {
+            FinalTypeInner::Unit => {}
+            FinalTypeInner::Sum(ref l, ref r) => {
+                let (l_padding, r_padding) = ty.sum_padding().expect("sum type");
+                let tag = padded.next().expect("frame as wide as its type");
+                let (arm, padding) = if tag { (r, r_padding) } else { (l, l_padding) };
+                ret.push(tag);
+                for _ in 0..padding {
+                    padded.next();
+                }
+                stack.push(arm);
+            }
+            FinalTypeInner::Product(ref l, ref r) => {
+                stack.push(r);
+                stack.push(l);
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::core::term::UnTypedProg;
+    use crate::core::types;
+    use crate::extension::dummy::{DummyNode, TxEnv};
+    use crate::extension::jets::JetsNode;
+
+    /// `case (injl (take iden)) (injr (take iden))` on (A + B) × 1,
+    /// built with explicit types so that either arm may be any type
+    fn sum_roundtrip(a: Arc<FinalType>, b: Arc<FinalType>) -> Program<DummyNode> {
+        use crate::program::{FlatNode, FlatProgram};
+
+        let unit = Arc::new(FinalType::unit());
+        let sum = Arc::new(FinalType::sum(a.clone(), b.clone()));
+        let with_unit = |ty: &Arc<FinalType>| Arc::new(FinalType::prod(ty.clone(), unit.clone()));
+        let node = |term, source_ty: &Arc<FinalType>, target_ty: &Arc<FinalType>| FlatNode {
+            term: term,
+            source_ty: source_ty.clone(),
+            target_ty: target_ty.clone(),
+        };
+        FlatProgram {
+            nodes: vec![
+                node(Term::Iden, &a, &a),
+                node(Term::Take(0), &with_unit(&a), &a),
+                node(Term::InjL(1), &with_unit(&a), &sum),
+                node(Term::Iden, &b, &b),
+                node(Term::Take(3), &with_unit(&b), &b),
+                node(Term::InjR(4), &with_unit(&b), &sum),
+                node(Term::Case(2, 5), &with_unit(&sum), &sum),
+            ],
+        }
+        .to_program()
+        .unwrap()
+    }
+
+    #[test]
+    fn zero_width_sum_arms() {
+        let unit = Arc::new(FinalType::unit());
+        let word8 = types::pow2_types()[4].clone();
+        // Each with the read cursor, past the tag and padding, at which
+        // the left and right arms start
+        let arms = [
+            // 2, where both arms have zero width and there is no padding
+            (unit.clone(), unit.clone(), Value::Unit, Value::Unit, (1, 1)),
+            // 1 + 2^8 and 2^8 + 1, with 8 bits of padding on one side
+            (
+                unit.clone(),
+                word8.clone(),
+                Value::Unit,
+                Value::u8(0xa5),
+                (9, 1),
+            ),
+            (
+                word8.clone(),
+                unit.clone(),
+                Value::u8(0x5a),
+                Value::Unit,
+                (1, 9),
+            ),
+        ];
+        for (a, b, a_value, b_value, (a_cursor, b_cursor)) in arms.iter().cloned() {
+            let prog = sum_roundtrip(a, b);
+            let sides = [
+                (Value::sum_l(a_value), 1, a_cursor),
+                (Value::sum_r(b_value), 4, b_cursor),
+            ];
+            for (value, arm, cursor) in sides.iter().cloned() {
+                let mut mac = BitMachine::for_program(&prog);
+                let input = Value::prod(value.clone(), Value::Unit);
+                assert_eq!(
+                    mac.exec_with_input(&prog, &input, &TxEnv),
+                    Ok(value.clone())
+                );
+
+                // Break at the `take` of the arm to see where it reads,
+                // with the input padded as by `exec_with_input`
+                let source_ty = &prog.root_node().source_ty;
+                let mut mac = BitMachine::for_program(&prog);
+                mac.new_frame(source_ty.bit_width()).unwrap();
+                mac.write_padded_value(&input, source_ty);
+                mac.move_frame().unwrap();
+                mac.add_breakpoint(prog.node_id(arm).unwrap());
+                let hit = match mac.run_to_breakpoint(&prog, &TxEnv) {
+                    Ok(BreakpointStep::Hit(hit)) => hit,
+                    other => panic!("expected a breakpoint, got {:?}", other),
+                };
+                assert_eq!(hit.node().index(), arm);
+                assert_eq!(hit.read_cursor(), Some(cursor));
+                assert_eq!(
+                    mac.run_to_breakpoint(&prog, &TxEnv).unwrap(),
+                    BreakpointStep::Done(value)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn padded_sum_output() {
+        // case (injl injl unit) (injr unit) &&& iden, with output type
+        // (2 + 1) × (2 × 1); the right arm of the sum is padded
+        let prog = UnTypedProg(vec![
+            Term::Unit,
+            Term::InjL(1),
+            Term::InjL(1),
+            Term::Unit,
+            Term::InjR(1),
+            Term::Case(3, 1),
+            Term::Iden,
+            Term::Pair(2, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().target_ty.bit_width(), 3);
+
+        let input = Value::prod(Value::u1(1), Value::Unit);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let output = mac.exec(&prog, &TxEnv);
+        assert_eq!(output, Value::prod(Value::sum_r(Value::Unit), input));
+        // goto(iden), then back and goto(injl) or goto(injr) from the case
+        assert_eq!(call_stack_max_depth(&prog), 3);
+        assert_eq!(mac.stats().peak_call_stack_depth, 3);
+
+        let input = Value::prod(Value::u1(0), Value::Unit);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let output = mac.exec(&prog, &TxEnv);
+        assert_eq!(
+            output,
+            Value::prod(Value::sum_l(Value::sum_l(Value::Unit)), input)
+        );
+
+        // Padding is written even over stale data, e.g. from dropped frames
+        let input = Value::prod(Value::u1(1), Value::Unit);
+        let mut mac = BitMachine::for_program(&prog);
+        for byte in &mut mac.data {
+            *byte = 0xff;
+        }
+        mac.input(&input).unwrap();
+        mac.exec(&prog, &TxEnv);
+        let out_frame = &mac.frames[mac.frames.len() - 1];
+        let bits: Vec<bool> = out_frame.to_frame_data(&mac.data).collect();
+        assert_eq!(bits, vec![true, false, true]);
+        // The output bits are its compact encoding, without the padding
+        let output = Value::prod(Value::sum_r(Value::Unit), input);
+        assert_eq!(output.iter_bits().collect::<Vec<_>>(), vec![true, true]);
+        assert_eq!(mac.last_output_bits(), Some(vec![true, true]));
+    }
+
+    #[test]
+    fn last_output_bits() {
+        let (prog, input) = crate::bench_util::deep_comp_chain(2);
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(mac.last_output_bits(), None);
+        let output = mac.exec_with_input(&prog, &input, &TxEnv).unwrap();
+        let bits = mac.last_output_bits().unwrap();
+        assert_eq!(bits.len(), 33);
+        assert_eq!(bits, output.iter_bits().collect::<Vec<_>>());
+        mac.reset();
+        assert_eq!(mac.last_output_bits(), None);
+
+        // Not while paused at a breakpoint
+        mac.input(&input).unwrap();
+        mac.add_breakpoint(prog.node_id(1).unwrap());
+        assert_matches!(
+            mac.run_to_breakpoint(&prog, &TxEnv),
+            Ok(BreakpointStep::Hit(..))
+        );
+        assert_eq!(mac.last_output_bits(), None);
+        assert_eq!(
+            mac.run_to_breakpoint(&prog, &TxEnv).unwrap(),
+            BreakpointStep::Done(output)
+        );
+        assert_eq!(mac.last_output_bits(), Some(bits));
+
+        // Programs with unit output have no output frame
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Unit]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let mut mac = BitMachine::for_program(&prog);
+        mac.exec(&prog, &TxEnv);
+        assert_eq!(mac.last_output_bits(), Some(vec![]));
+
+        // Sum padding is skipped, whichever arm is taken
+        let unit = Arc::new(FinalType::unit());
+        let word8 = types::pow2_types()[4].clone();
+        let prog = sum_roundtrip(unit, word8);
+        for value in &[Value::sum_l(Value::Unit), Value::sum_r(Value::u8(0xa5))] {
+            let input = Value::prod(value.clone(), Value::Unit);
+            let mut mac = BitMachine::for_program(&prog);
+            mac.exec_with_input(&prog, &input, &TxEnv).unwrap();
+            let bits: Vec<_> = value.iter_bits().collect();
+            assert_eq!(mac.last_output_bits(), Some(bits));
+        }
+    }
+
+    #[test]
+    fn run_until_jet() {
+        // pair adder32 adder32, with each jet's output given by hand
+        let (prog, input) = crate::bench_util::wide_pair_tree(1);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+
+        let first = match mac.run_until_jet(&prog, &TxEnv) {
+            Ok(JetStep::Jet(state)) => state,
+            other => panic!("expected a jet, got {:?}", other),
+        };
+        assert_eq!(
+            prog.node(first.node()).unwrap().node,
+            Term::Jet(JetsNode::Adder32)
+        );
+        assert_eq!(first.input(), &input);
+        let second = match mac.resume_after_jet(
+            first,
+            &Value::prod(Value::u1(1), Value::u32(7)),
+            &prog,
+            &TxEnv,
+        ) {
+            Ok(JetStep::Jet(state)) => state,
+            other => panic!("expected a jet, got {:?}", other),
+        };
+        assert_eq!(second.input(), &input);
+        assert_matches!(
+            mac.resume_after_jet(
+                second,
+                &Value::prod(Value::u1(0), Value::u32(9)),
+                &prog,
+                &TxEnv
+            ),
+            Ok(JetStep::Done(ref output)) if *output == Value::prod(
+                Value::prod(Value::u1(1), Value::u32(7)),
+                Value::prod(Value::u1(0), Value::u32(9)),
+            )
+        );
+
+        // An output of the wrong type is rejected
+        mac.reset();
+        mac.input(&input).unwrap();
+        let state = match mac.run_until_jet(&prog, &TxEnv) {
+            Ok(JetStep::Jet(state)) => state,
+            other => panic!("expected a jet, got {:?}", other),
+        };
+        let node = state.node();
+        assert_eq!(
+            mac.resume_after_jet(state, &Value::u8(7), &prog, &TxEnv)
+                .unwrap_err(),
+            ExecError::JetOutputTypeMismatch(node)
+        );
+
+        // A program without jets runs to the end
+        let (ladder, input) = crate::bench_util::case_ladder(3);
+        let mut mac = BitMachine::for_program(&ladder);
+        mac.input(&input).unwrap();
+        assert_matches!(
+            mac.run_until_jet(&ladder, &TxEnv),
+            Ok(JetStep::Done(Value::Unit))
+        );
+    }
+
+    #[test]
+    fn run_to_breakpoint() {
+        // adder32 >>> iden, breaking before each child
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Jet(JetsNode::Adder32),
+                Term::Iden,
+                Term::Comp(2, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let input = Value::prod(Value::u32(0xffff_ffff), Value::u32(3));
+        let sum = Value::prod(Value::u1(1), Value::u32(2));
+        let root = prog.root_node();
+        let bits =
+            |value: &Value, ty| -> Vec<bool> { value.iter_padded_bits(ty).unwrap().collect() };
+
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        mac.add_breakpoint(prog.node_id(1).unwrap());
+        mac.add_breakpoint(prog.node_id(0).unwrap());
+        let hit = match mac.run_to_breakpoint(&prog, &TxEnv) {
+            Ok(BreakpointStep::Hit(hit)) => hit,
+            other => panic!("expected a breakpoint, got {:?}", other),
+        };
+        assert_eq!(hit.node().index(), 0);
+        let input_bits = bits(&input, &root.source_ty);
+        assert_eq!(hit.read_frame(), Some(&input_bits[..]));
+        assert_eq!(hit.read_cursor(), Some(0));
+        assert_eq!(hit.write_frame().map(<[bool]>::len), Some(33));
+        assert_eq!(hit.write_cursor(), Some(0));
+
+        // The right child of the comp reads the output of the left
+        let hit = match mac.run_to_breakpoint(&prog, &TxEnv) {
+            Ok(BreakpointStep::Hit(hit)) => hit,
+            other => panic!("expected a breakpoint, got {:?}", other),
+        };
+        assert_eq!(hit.node().index(), 1);
+        let sum_bits = bits(&sum, &root.target_ty);
+        assert_eq!(hit.read_frame(), Some(&sum_bits[..]));
+        assert_eq!(hit.read_cursor(), Some(0));
+        assert_eq!(hit.write_cursor(), Some(0));
+        assert_eq!(
+            mac.run_to_breakpoint(&prog, &TxEnv).unwrap(),
+            BreakpointStep::Done(sum.clone())
+        );
+
+        // Breakpoints are ignored outside of `run_to_breakpoint`
+        mac.reset();
+        mac.input(&input).unwrap();
+        assert_eq!(mac.exec(&prog, &TxEnv), sum);
+
+        // A breakpoint from another program is rejected before running
+        let other = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Iden]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        mac.reset();
+        mac.input(&input).unwrap();
+        mac.add_breakpoint(other.node_id(0).unwrap());
+        assert_matches!(
+            mac.run_to_breakpoint(&prog, &TxEnv),
+            Err(Error::ForeignNodeId(id)) if id == other.node_id(0).unwrap()
+        );
+    }
+
+    #[test]
+    fn disconnect_payload() {
+        // adder32 >>> disconnect iden iden, which outputs the CMR of `iden`
+        // followed by the 33-bit payload from adder32
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Iden,
+            Term::Iden,
+            Term::Disconnect(2, 1),
+            Term::Comp(4, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let s_source = &prog.nodes[1].source_ty;
+        assert_eq!(s_source.bit_width(), DISCONNECT_CMR_BITS + 33);
+
+        let cmr_ty = &types::pow2_types()[9];
+        assert_eq!(cmr_ty.bit_width(), DISCONNECT_CMR_BITS);
+        let cmr_bytes = prog.nodes[2].cmr.into_inner();
+        let cmr_value =
+            Value::from_bits_and_type(&mut BitIter::from(cmr_bytes.iter().cloned()), cmr_ty)
+                .unwrap();
+
+        let input = Value::u64(0xffff_fffe_0000_0003);
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &input, &TxEnv),
+            Ok(Value::prod(
+                cmr_value,
+                Value::prod(Value::u1(1), Value::u32(1))
+            ))
+        );
+    }
+
+    #[test]
+    fn disconnect_width_mismatch() {
+        // As in `disconnect_payload`, but with the input of the right
+        // child made wider than the output of the left child
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Iden,
+            Term::Iden,
+            Term::Disconnect(2, 1),
+            Term::Comp(4, 1),
+        ]);
+        let mut prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let wide = types::pow2_types()[10].clone();
+        prog.nodes[2].source_ty = wide.clone();
+        prog.nodes[2].target_ty = wide;
+
+        let input = Value::u64(0xffff_fffe_0000_0003);
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &input, &TxEnv),
+            Err(ExecError::DisconnectWidthMismatch(prog.node_id(3).unwrap()))
+        );
+    }
+
+    #[test]
+    fn zero_width_witness() {
+        // take witness >>> take witness, where the witness is of type 1 × 1
+        let prog = UnTypedProg(vec![Term::Witness(()), Term::Take(1), Term::Comp(1, 1)]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().target_ty.bit_width(), 0);
+
+        let input = Value::prod(Value::Unit, Value::Unit);
+        let mut mac = BitMachine::for_program(&prog);
+        assert!(mac.exec_with_input(&prog, &input, &TxEnv).is_ok());
+    }
+
+    #[test]
+    fn zero_width_witness_pair() {
+        // pair witness witness, where both witnesses are of type 1
+        let prog = UnTypedProg(vec![Term::Witness(()), Term::Witness(()), Term::Pair(2, 1)]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().target_ty.bit_width(), 0);
+
+        let mut mac = BitMachine::for_program(&prog);
+        assert!(mac.exec_with_input(&prog, &Value::Unit, &TxEnv).is_ok());
+        let mut mac = BitMachine::for_program(&prog);
+        assert!(mac.exec_attributed(&prog, &TxEnv).1.is_empty());
+    }
+
+    #[test]
+    fn exec_attributed() {
+        // adder32 >>> pair (injl iden) (injr unit)
+        let prog = UnTypedProg(vec![
+            Term::Iden,
+            Term::InjL(1),
+            Term::Unit,
+            Term::InjR(1),
+            Term::Pair(3, 1),
+            Term::Jet(JetsNode::Adder32),
+            Term::Comp(1, 2),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&Value::u64(0x8000_0000_8000_0001)).unwrap();
+        let (output, bits) = mac.exec_attributed(&prog, &TxEnv);
+        let sum = Value::prod(Value::u1(1), Value::u32(1));
+        assert_eq!(
+            output,
+            Value::prod(Value::sum_l(sum.clone()), Value::sum_r(Value::Unit))
+        );
+        // The tag of each sum comes from its injection, and the sum from
+        // the `iden` node which copied it out of the jet's output
+        let mut expected = vec![(false, 1)];
+        expected.extend(sum.iter_bits().map(|bit| (bit, 0)));
+        expected.push((true, 3));
+        assert_eq!(bits, expected);
+
+        // adder32 >>> disconnect iden iden, as in `disconnect_payload`:
+        // the CMR is copied to the output by the disconnect node itself
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Iden,
+            Term::Iden,
+            Term::Disconnect(2, 1),
+            Term::Comp(4, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&Value::u64(0xffff_fffe_0000_0003)).unwrap();
+        let (output, bits) = mac.exec_attributed(&prog, &TxEnv);
+        assert!(bits.iter().map(|&(bit, _)| bit).eq(output.iter_bits()));
+        let sources: Vec<usize> = bits.iter().map(|&(_, node)| node).collect();
+        let mut expected = vec![3; DISCONNECT_CMR_BITS];
+        expected.extend(vec![2; 33]);
+        assert_eq!(sources, expected);
+    }
+
+    #[test]
+    fn disconnect_bounds() {
+        // disconnect (take iden) (iden >>> unit)
+        let prog = UnTypedProg(vec![
+            Term::Iden,
+            Term::Take(1),
+            Term::Iden,
+            Term::Unit,
+            Term::Comp(2, 1),
+            Term::Disconnect(4, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let root = prog.root_node();
+        assert_eq!(root.target_ty.bit_width(), 128);
+        // 256 bits of `s` output, plus the 256-bit input frame of `s`, which
+        // is freed before `t` allocates its 128-bit intermediate frame
+        assert_eq!(root.extra_cells_bound, 512);
+        assert_eq!(root.frame_count_bound, 2);
+
+        // The old bound of 640 extra cells would need 96 bytes
+        let mut mac = BitMachine::try_for_program(&prog, 80).unwrap();
+        let output = mac.exec(&prog, &TxEnv);
+        let cmr_bits = Value::from_bits_and_type(
+            &mut BitIter::from(prog.nodes[4].cmr[..16].iter().cloned()),
+            &types::pow2_types()[8],
+        )
+        .unwrap();
+        assert_eq!(output, Value::prod(cmr_bits, Value::Unit));
+
+        let stats = mac.stats();
+        assert_eq!(stats.peak_cells, 128 + root.extra_cells_bound);
+        assert_eq!(stats.peak_frames, root.frame_count_bound + 1);
+        // drop, goto, copy, move, drop, then `iden >>> unit` under one entry
+        assert_eq!(call_stack_max_depth(&prog), 6);
+        assert_eq!(stats.peak_call_stack_depth, 6);
+    }
+
+    #[test]
+    fn resource_usage() {
+        // disconnect (take iden) (iden >>> unit)
+        let prog = UnTypedProg(vec![
+            Term::Iden,
+            Term::Take(1),
+            Term::Iden,
+            Term::Unit,
+            Term::Comp(2, 1),
+            Term::Disconnect(4, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let bytes = prog.serialize();
+        let expected = ResourceUsage {
+            cost_consumed: 6,
+            peak_cells: 640,
+            peak_frames: 3,
+            bytes_serialized: 7,
+        };
+
+        let mut mac = BitMachine::for_program(&prog);
+        let (output, usage) = mac.exec_with_stats(&prog, &TxEnv);
+        assert_eq!(usage, expected);
+        assert_eq!(bytes.len(), usage.bytes_serialized);
+
+        let cmr = prog.root_node().cmr;
+        let verify = |cmr: &Cmr, input: &Value, max_bytes: usize| {
+            verify_with_commitment::<DummyNode>(&bytes, cmr, input, max_bytes, &TxEnv)
+        };
+        let (verified, usage) = verify(&cmr, &Value::Unit, 1024).unwrap();
+        assert_eq!(verified, output);
+        assert_eq!(usage, expected);
+
+        let wrong_cmr = prog.nodes[0].cmr;
+        assert_matches!(
+            verify(&wrong_cmr, &Value::Unit, 1024),
+            Err(Error::CmrMismatch)
+        );
+        assert_matches!(
+            verify(&cmr, &Value::Unit, 79),
+            Err(Error::Exec(ExecError::TooMuchMemory {
+                required: 80,
+                limit: 79
+            }))
+        );
+        assert_matches!(
+            verify(&cmr, &Value::u1(1), 1024),
+            Err(Error::Exec(ExecError::InputTypeMismatch))
+        );
+
+        // A program which takes an input
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Jet(JetsNode::Adder32)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let bytes = prog.serialize();
+        let cmr = prog.root_node().cmr;
+        let input = Value::prod(Value::u32(2), Value::u32(3));
+        let (output, _) =
+            verify_with_commitment::<DummyNode>(&bytes, &cmr, &input, 1024, &TxEnv).unwrap();
+        assert_eq!(output, Value::prod(Value::u1(0), Value::u32(5)));
+    }
+
+    #[test]
+    fn case_bounds() {
+        // case (iden >>> ... >>> drop eqv32) (iden >>> iden >>> drop eqv32)
+        // with ten and two compositions in the left and right arms
+        let mut nodes = vec![Term::Jet(JetsNode::EqV32), Term::Drop(1), Term::Iden];
+        let mut arms = vec![];
+        for &depth in &[10, 2] {
+            let mut arm = 1;
+            for _ in 0..depth {
+                let idx = nodes.len();
+                nodes.push(Term::Comp(idx - 2, idx - arm));
+                arm = idx;
+            }
+            arms.push(arm);
+        }
+        let idx = nodes.len();
+        nodes.push(Term::Case(idx - arms[0], idx - arms[1]));
+
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(nodes),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let root = prog.root_node();
+        // Each composition allocates a frame for the 64-bit output of `iden`,
+        // which drops the 1-bit sum tag
+        assert_eq!(root.source_ty.bit_width(), 65);
+        assert_eq!(root.frame_count_bound, 10);
+        assert_eq!(root.extra_cells_bound, 10 * 64);
+
+        let word = Value::u64(0x0000_0005_0000_0005);
+        for &(ref input, frames) in &[(Value::u1(0), 10), (Value::u1(1), 2)] {
+            let mut mac = BitMachine::for_program(&prog);
+            mac.input(&Value::prod(input.clone(), word.clone()))
+                .unwrap();
+            assert_eq!(mac.exec(&prog, &TxEnv), Value::Unit);
+
+            // The bounds are exact for the left arm, with the input frame
+            // as the only frame not counted by them
+            let stats = mac.stats();
+            assert_eq!(stats.peak_frames, frames + 1);
+            assert_eq!(stats.peak_cells, 65 + frames * 64);
+            assert!(stats.peak_frames <= root.frame_count_bound + 1);
+            assert!(stats.peak_cells <= 65 + root.extra_cells_bound);
+            // back, then one `drop` entry left by each composition but the
+            // innermost, which pushes 4 entries before running `iden`
+            assert_eq!(stats.peak_call_stack_depth, 1 + (frames - 1) + 4);
+        }
+        assert_eq!(call_stack_max_depth(&prog), 14);
+    }
+
+    #[test]
+    fn memory_cap() {
+        // 768-bit input, 256-bit output
+        let prog = UnTypedProg(vec![Term::Jet(JetsNode::Sha256HashBlock)]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+
+        assert_eq!(BitMachine::memory_required(&prog), 128);
+        assert_eq!(
+            BitMachine::try_for_program(&prog, 127).err(),
+            Some(ExecError::TooMuchMemory {
+                required: 128,
+                limit: 127,
+            })
+        );
+        let mac = BitMachine::try_for_program(&prog, 128).unwrap();
+        assert_eq!(mac.data.len(), 128);
+    }
+
+    #[test]
+    fn with_buffer() {
+        let prog = crate::bench_util::deep_comp_chain(4).0;
+        let required = prog.required_data_bytes();
+        assert_eq!(required, BitMachine::memory_required(&prog));
+        assert_eq!(
+            prog.required_frame_capacity(),
+            BitMachine::for_program(&prog).frames.len()
+        );
+
+        let input = Value::prod(Value::u32(0x1234_5678), Value::u32(0x9abc_def0));
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let expected = mac.exec(&prog, &TxEnv);
+
+        assert_eq!(
+            BitMachine::with_buffer(&prog, vec![0xff; required - 1]).err(),
+            Some(ExecError::TooMuchMemory {
+                required: required,
+                limit: required - 1,
+            })
+        );
+        // A dirty buffer of exactly the required size, passed from one
+        // machine to the next
+        let mut buffer = vec![0xff; required];
+        for _ in 0..2 {
+            let mut mac = BitMachine::with_buffer(&prog, buffer).unwrap();
+            mac.input(&input).unwrap();
+            assert_eq!(mac.exec(&prog, &TxEnv), expected);
+            buffer = mac.into_buffer();
+            assert_eq!(buffer.len(), required);
+        }
+    }
+
+    #[test]
+    fn exec_batch() {
+        // 32-bit xorshift, for reproducible inputs
+        let mut state = 0x1234_5678u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let prog = crate::bench_util::deep_comp_chain(4).0;
+        let mut inputs: Vec<Value> = (0..50)
+            .map(|_| Value::prod(Value::u32(next()), Value::u32(next())))
+            .collect();
+
+        let expected: Vec<Result<Value, ExecError>> = inputs
+            .iter()
+            .map(|input| {
+                let mut mac = BitMachine::for_program(&prog);
+                mac.input(input).unwrap();
+                Ok(mac.exec(&prog, &TxEnv))
+            })
+            .collect();
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(mac.exec_batch(&prog, &TxEnv, &inputs), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(BitMachine::exec_batch_par(&prog, &TxEnv, &inputs), expected);
+
+        // An ill-typed input fails without disturbing the others
+        inputs[1] = Value::u32(0);
+        let results = mac.exec_batch(&prog, &TxEnv, &inputs[..3]);
+        assert_eq!(results[0], expected[0]);
+        assert_eq!(results[1], Err(ExecError::InputTypeMismatch));
+        assert_eq!(results[2], expected[2]);
+    }
+
+    #[test]
+    fn exec_bounded() {
+        let (prog, input) = crate::bench_util::deep_comp_chain(3);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let output = mac.exec(&prog, &TxEnv);
+        let stats = mac.stats();
+
+        let run = |max_cells, max_frames| {
+            let mut mac = BitMachine::for_program(&prog);
+            mac.input(&input).unwrap();
+            mac.exec_bounded(&prog, &TxEnv, max_cells, max_frames)
+        };
+        assert_eq!(run(stats.peak_cells, stats.peak_frames), Ok(output));
+        assert_eq!(
+            run(stats.peak_cells - 1, stats.peak_frames),
+            Err(ExecError::MemoryExceeded)
+        );
+        assert_eq!(
+            run(stats.peak_cells, stats.peak_frames - 1),
+            Err(ExecError::MemoryExceeded)
+        );
+    }
+
+    #[test]
+    fn call_stack_preallocated() {
+        for n in 1..8 {
+            let (prog, input) = crate::bench_util::deep_comp_chain(n);
+            let bound = prog.root_node().call_stack_bound;
+            let mut mac = BitMachine::for_program(&prog);
+            mac.exec_with_input(&prog, &input, &TxEnv).unwrap();
+            // One `drop` entry left by each composition but the innermost,
+            // which pushes 4. The bound is attained, and the call stack
+            // never outgrew the capacity reserved for it (checked by `exec`)
+            assert_eq!(bound, n + 3);
+            assert_eq!(mac.stats().peak_call_stack_depth, bound);
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "debug-checks"))]
+    #[should_panic(expected = "exceeds the")]
+    fn undersized_machine() {
+        // A machine built for a shallow chain has too little data for a
+        // deeper one
+        let (small, _) = crate::bench_util::deep_comp_chain(1);
+        let (prog, input) = crate::bench_util::deep_comp_chain(5);
+        let mut mac = BitMachine::for_program(&small);
+        let _ = mac.exec_with_input(&prog, &input, &TxEnv);
+    }
+
+    #[test]
+    fn input_misuse() {
+        let (prog, input) = crate::bench_util::deep_comp_chain(2);
+        let run = |inputs: &[&Value]| {
+            let mut mac = BitMachine::for_program(&prog);
+            for input in inputs {
+                mac.input(input)?;
+            }
+            mac.exec_bounded(&prog, &TxEnv, usize::max_value(), usize::max_value())
+        };
+        assert!(run(&[&input]).is_ok());
+        assert_eq!(run(&[]), Err(ExecError::MissingInput));
+        assert_eq!(run(&[&input, &input]), Err(ExecError::ExtraInput));
+        assert_eq!(run(&[&Value::u32(1)]), Err(ExecError::InputTypeMismatch));
+        // An input on top of the one added by `exec_with_input`
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        assert_eq!(
+            mac.exec_with_input(&prog, &input, &TxEnv),
+            Err(ExecError::ExtraInput)
+        );
+
+        // A program with unit input runs with or without a unit input,
+        // but not with any other
+        let (unit_prog, _) = crate::bench_util::witness_hash_block();
+        let run = |inputs: &[&Value]| {
+            let mut mac = BitMachine::for_program(&unit_prog);
+            for input in inputs {
+                mac.input(input)?;
+            }
+            mac.exec_bounded(&unit_prog, &TxEnv, usize::max_value(), usize::max_value())
+        };
+        let output = run(&[]);
+        assert!(output.is_ok());
+        assert_eq!(run(&[&Value::Unit]), output);
+        assert_eq!(run(&[&Value::u8(0)]), Err(ExecError::InputTypeMismatch));
+    }
+
+    #[test]
+    fn padded_sum_input() {
+        // case (take adder32) (drop adder32), whose input type
+        // (2^64 + 1) × 2^64 pads the right arm of the sum by 64 bits
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Take(1),
+            Term::Drop(2),
+            Term::Case(2, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().source_ty.bit_width(), 129);
+
+        let input = Value::prod(Value::sum_r(Value::Unit), Value::u64(0x0000_0003_0000_0005));
+        let expected = Value::prod(Value::u1(0), Value::u32(8));
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        assert_eq!(mac.exec(&prog, &TxEnv), expected);
+        mac.reset();
+        assert_eq!(mac.exec_with_input(&prog, &input, &TxEnv), Ok(expected));
+
+        // The input is checked against the source type, not just its width
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&Value::prod(Value::u1(0), Value::u64(0)))
+            .unwrap();
+        assert_eq!(
+            mac.exec_bounded(&prog, &TxEnv, usize::max_value(), usize::max_value()),
+            Err(ExecError::InputTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn double_input() {
+        let (prog, input) = crate::bench_util::deep_comp_chain(2);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        assert_eq!(mac.input(&input), Err(ExecError::ExtraInput));
+        // The rejected input left the frame stack untouched
+        let output = mac.exec_bounded(&prog, &TxEnv, usize::max_value(), usize::max_value());
+        assert!(output.is_ok());
+
+        // Nor may an input be given after a run, until the machine is reset
+        assert_eq!(mac.input(&input), Err(ExecError::ExtraInput));
+        mac.reset();
+        mac.input(&input).unwrap();
+        assert_eq!(
+            mac.exec_bounded(&prog, &TxEnv, usize::max_value(), usize::max_value()),
+            output
+        );
+    }
+
+    #[test]
+    fn read_cursor() {
+        let (prog, _) = crate::bench_util::deep_comp_chain(1);
+        let mut mac = BitMachine::for_program(&prog);
+        mac.new_frame(8).unwrap();
+        mac.write_value(&Value::u8(0x5a));
+        mac.move_frame().unwrap();
+        assert_eq!(mac.read_cursor(), (0, 0));
+
+        // A second read frame starts after the first
+        mac.new_frame(16).unwrap();
+        mac.write_value(&Value::u16(0x1234));
+        mac.move_frame().unwrap();
+        assert_eq!(mac.read_cursor(), (8, 8));
+        mac.fwd(3);
+        mac.fwd(4);
+        assert_eq!(mac.read_cursor(), (8, 15));
+        mac.back(2);
+        assert_eq!(mac.read_cursor(), (8, 13));
+
+        mac.drop_frame();
+        assert_eq!(mac.read_cursor(), (0, 0));
+    }
+
+    #[test]
+    fn jet_keeps_read_cursor() {
+        // pair adder32 iden: the jet reads the whole input, which the
+        // `iden` node must then read again from the start
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Iden,
+            Term::Pair(2, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+
+        let input = Value::prod(Value::u32(7), Value::u32(9));
+        let mut mac = BitMachine::for_program(&prog);
+        let output = mac.exec_with_input(&prog, &input, &TxEnv).unwrap();
+        assert_eq!(
+            output,
+            Value::prod(Value::prod(Value::u1(0), Value::u32(16)), input)
+        );
+    }
+
+    #[test]
+    fn write_value_bulk() {
+        // Write into a fresh 82-bit frame, starting 3 bits in so that
+        // bytes are unaligned
+        let write_frame = |write: &dyn Fn(&mut BitMachine)| {
+            let mut mac = BitMachine {
+                data: vec![0; 11],
+                next_frame_start: 3,
+                frames: vec![],
+                read_len: 0,
+                write_top: 0,
+                stats: ExecStats::default(),
+                trace: None,
+                output_sources: None,
+                steps: None,
+                bounds: None,
+                stop_at_witness: false,
+                stopped_at_witness: None,
+                stop_at_jet: false,
+                breakpoints: vec![],
+                stop_at_breakpoint: false,
+                stop_at_node: false,
+                suspended: None,
+                resume: None,
+                output_ty: None,
+                pending_input: None,
+            };
+            mac.new_frame(82).unwrap();
+            write(&mut mac);
+            mac.data
+        };
+
+        let word = Value::prod(Value::u32(0xdead_beef), Value::u32(0x0bad_f00d));
+        let opt =
+            |v: Option<u8>| v.map_or(Value::sum_l(Value::Unit), |x| Value::sum_r(Value::u8(x)));
+        let pow2 = types::pow2_types();
+        let opt_ty = Arc::new(FinalType::sum(pow2[0].clone(), pow2[4].clone()));
+        let opts_ty = Arc::new(FinalType::prod(opt_ty.clone(), opt_ty));
+        let opt_word_ty = FinalType::prod(pow2[7].clone(), opts_ty);
+
+        for val in &[
+            Value::prod(word.clone(), Value::prod(opt(Some(0x5a)), opt(None))),
+            Value::prod(word.clone(), Value::prod(opt(None), opt(Some(0xff)))),
+        ] {
+            let compact = write_frame(&|mac| mac.write_value(val));
+            let bitwise = write_frame(&|mac| val.iter_bits().for_each(|b| mac.write_bit(b)));
+            assert_eq!(compact, bitwise);
+
+            let padded = write_frame(&|mac| mac.write_padded_value(val, &opt_word_ty));
+            let bitwise = write_frame(&|mac| {
+                val.iter_padded_bits(&opt_word_ty)
+                    .unwrap()
+                    .for_each(|b| mac.write_bit(b))
+            });
+            assert_eq!(padded, bitwise);
+        }
+    }
+
+    #[test]
+    fn simple_path_matches() {
+        use crate::bench_util;
+        use crate::program::ProgramFeatures;
+
+        let corpus = vec![
+            bench_util::deep_comp_chain(20),
+            bench_util::wide_pair_tree(4),
+            bench_util::sha256_chain(2),
+            bench_util::case_ladder(20),
+            bench_util::witness_hash_block(),
+        ];
+        for (prog, input) in &corpus {
+            // Tracing forces the general path
+            let mut general = BitMachine::for_program(prog);
+            general.input(input).unwrap();
+            let (expected, _) = general.exec_traced(prog, &TxEnv);
+
+            let mut mac = BitMachine::for_program(prog);
+            mac.input(input).unwrap();
+            assert_eq!(mac.exec(prog, &TxEnv), expected);
+            assert_eq!(mac.stats(), general.stats());
+        }
+
+        assert!(corpus[0].0.features().is_empty());
+        assert_eq!(corpus[4].0.features(), ProgramFeatures::WITNESS);
+    }
+
+    #[test]
+    fn edited_nodes_run() {
+        let mut prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Iden]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert!(prog.features().is_empty());
+
+        // Features are found when the program is built, so editing the
+        // nodes leaves them out of date, but the fast path still runs
+        // nodes which need special handling
+        prog.nodes[0].node = Term::Witness(Value::Unit);
+        assert!(prog.features().is_empty());
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &Value::Unit, &TxEnv),
+            Ok(Value::Unit)
+        );
+    }
+
+    #[test]
+    fn wide_sum_output() {
+        // case (injl (drop sha256_hashblock)) (injr unit), with output type
+        // 2^256 + 1, whose right arm is padded by 256 bits
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Sha256HashBlock),
+            Term::Drop(1),
+            Term::InjL(1),
+            Term::Unit,
+            Term::InjR(1),
+            Term::Case(3, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let root = prog.root_node();
+        assert_eq!(root.target_ty.bit_width(), 257);
+        // Input and output frames, with no extra frames for the jet
+        assert_eq!(BitMachine::memory_required(&prog), (1 + 768 + 257 + 7) / 8);
+
+        let word = Value::prod(
+            Value::prod(Value::u64(1), Value::u64(2)),
+            Value::prod(Value::u64(3), Value::u64(4)),
+        );
+        let block = Value::prod(word.clone(), word.clone());
+        for &bit in &[0, 1] {
+            let input = Value::prod(Value::u1(bit), Value::prod(word.clone(), block.clone()));
+            let mut mac = BitMachine::for_program(&prog);
+            for byte in &mut mac.data {
+                *byte = 0xff;
+            }
+            mac.input(&input).unwrap();
+            let output = mac.exec(&prog, &TxEnv);
+            assert!(output.is_of_type(&root.target_ty));
+            match output {
+                Value::SumL(ref hash) => assert_eq!((bit, hash.len()), (0, 256)),
+                Value::SumR(ref unit) => assert_eq!((bit, &**unit), (1, &Value::Unit)),
+                _ => unreachable!(),
+            }
+            assert!(mac.stats().peak_cells <= mac.data.len() * 8);
+        }
+    }
+
+    #[test]
+    fn stream_output() {
+        use crate::bench_util;
+
+        // The padded bit encoding of the decoded output, zero-filled to
+        // a whole number of bytes
+        let expected_bytes = |prog: &Program<DummyNode>, input: &Value| {
+            let target_ty = &prog.root_node().target_ty;
+            let mut mac = BitMachine::for_program(prog);
+            mac.input(input).unwrap();
+            let output = mac.exec(prog, &TxEnv);
+            let mut bits: Vec<bool> = output.iter_padded_bits(target_ty).unwrap().collect();
+            bits.resize((bits.len() + 7) / 8 * 8, false);
+            crate::core::bitvec_to_bytevec(bits)
+        };
+
+        for (prog, input) in &[
+            bench_util::deep_comp_chain(20),
+            bench_util::wide_pair_tree(4),
+            bench_util::sha256_chain(2),
+            bench_util::case_ladder(20),
+            bench_util::witness_hash_block(),
+        ] {
+            let mut mac = BitMachine::for_program(prog);
+            mac.input(input).unwrap();
+            let mut streamed = vec![];
+            mac.exec_stream_output(prog, &TxEnv, |chunk| streamed.extend_from_slice(chunk))
+                .unwrap();
+            assert_eq!(streamed, expected_bytes(prog, input));
+        }
+
+        // An output wider than one chunk, and not a whole number of bytes:
+        // the 257-bit `wide_sum_output` program, paired with itself twice
+        let prog = UnTypedProg(vec![
+            Term::Jet(JetsNode::Sha256HashBlock),
+            Term::Drop(1),
+            Term::InjL(1),
+            Term::Unit,
+            Term::InjR(1),
+            Term::Case(3, 1),
+            Term::Pair(1, 1),
+            Term::Pair(1, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().target_ty.bit_width(), 4 * 257);
+        let word = Value::prod(
+            Value::prod(Value::u64(1), Value::u64(2)),
+            Value::prod(Value::u64(3), Value::u64(4)),
+        );
+        let block = Value::prod(word.clone(), word.clone());
+        let input = Value::prod(Value::u1(0), Value::prod(word, block));
+        let mut mac = BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let mut chunks = vec![];
+        mac.exec_stream_output(&prog, &TxEnv, |chunk| chunks.push(chunk.to_vec()))
+            .unwrap();
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![64, 64, 1]
+        );
+        assert_eq!(chunks.concat(), expected_bytes(&prog, &input));
+    }
+
+    #[test]
+    fn exec_with_input() {
+        // case (injl unit) (injr (take adder32)), whose input type is
+        // (1 + 2^64) × 1, so the left arm of the input sum is padded
+        let prog = UnTypedProg(vec![
+            Term::Unit,
+            Term::InjL(1),
+            Term::Jet(JetsNode::Adder32),
+            Term::Take(1),
+            Term::InjR(1),
+            Term::Case(4, 1),
+        ]);
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            prog,
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.root_node().source_ty.bit_width(), 65);
+
+        let cases = [
+            (Value::sum_l(Value::Unit), Value::sum_l(Value::Unit)),
+            (
+                Value::sum_r(Value::prod(Value::u32(1), Value::u32(2))),
+                Value::sum_r(Value::prod(Value::u1(0), Value::u32(3))),
+            ),
+        ];
+        for (input, output) in &cases {
+            let input = Value::prod(input.clone(), Value::Unit);
+            let mut mac = BitMachine::for_program(&prog);
+            assert_eq!(
+                mac.exec_with_input(&prog, &input, &TxEnv).as_ref(),
+                Ok(output)
+            );
+        }
+
+        // A mistyped input is rejected before anything is allocated
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &Value::u2(2), &TxEnv),
+            Err(ExecError::InputTypeMismatch)
+        );
+        assert_eq!((mac.read_len, mac.write_top), (0, mac.frames.len()));
+        assert_eq!(mac.next_frame_start, 0);
+    }
+
+    /// Log records emitted by each thread, as (level, message, `node` field)
+    #[cfg(feature = "log")]
+    type Records = Vec<(std::thread::ThreadId, log::Level, String, Option<u64>)>;
+
+    /// Logger which keeps every record, for inspection by tests
+    #[cfg(feature = "log")]
+    struct CaptureLogger(std::sync::Mutex<Records>);
+
+    #[cfg(feature = "log")]
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let node = record
+                .key_values()
+                .get(log::kv::Key::from("node"))
+                .and_then(|value| value.to_u64());
+            self.0.lock().unwrap().push((
+                std::thread::current().id(),
+                record.level(),
+                record.args().to_string(),
+                node,
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "log")]
+    static CAPTURE: CaptureLogger = CaptureLogger(std::sync::Mutex::new(vec![]));
+
+    #[test]
+    fn hidden_node() {
+        // The right branch is taken, reaching the hidden node
+        let prog = crate::text::parse_program::<DummyNode, _>(
+            "main := comp (pair (injr unit) unit) (case unit (hidden 0x0000000000000000000000000000000000000000000000000000000000000000))",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &Value::Unit, &TxEnv),
+            Err(ExecError::HiddenNode(prog.node_id(5).unwrap()))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn log_failing_program() {
+        // Another test may have installed the logger already
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        // The right branch is taken, reaching the hidden node
+        let prog = crate::text::parse_program::<DummyNode, _>(
+            "main := comp (pair (injr unit) unit) (case unit (hidden 0x0000000000000000000000000000000000000000000000000000000000000000))",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let mut mac = BitMachine::for_program(&prog);
+        let node = match mac.exec_with_input(&prog, &Value::Unit, &TxEnv) {
+            Err(ExecError::HiddenNode(node)) => node,
+            x => panic!("unexpected result {:?}", x),
+        };
+
+        assert!(BitMachine::try_for_program(&prog, 0).is_err());
+
+        let thread = std::thread::current().id();
+        let records: Vec<_> = CAPTURE
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.0 == thread)
+            .map(|record| (record.1, record.2.clone(), record.3))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (log::Level::Trace, "enter finalize".to_owned(), None),
+                (log::Level::Trace, "exit finalize".to_owned(), None),
+                (log::Level::Trace, "enter exec".to_owned(), None),
+                (
+                    log::Level::Debug,
+                    "reached pruned branch".to_owned(),
+                    Some(node.index() as u64)
+                ),
+                (
+                    log::Level::Debug,
+                    "execution failed".to_owned(),
+                    Some(node.index() as u64)
+                ),
+                (log::Level::Trace, "exit exec".to_owned(), None),
+                (log::Level::Debug, "memory budget".to_owned(), None),
+            ]
+        );
+    }
+}
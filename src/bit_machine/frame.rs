@@ -26,7 +26,7 @@ use std::ops::{Add, Shl};
 /// Context to access a sub-slice of [`super::exec::BitMachine`]'s data.
 /// Read and write operations require a reference to the data,
 /// as it is not contained in this struct.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct Frame {
     /// Current position of the cursor.
     /// For read frames, this is the next bit which is to be read.
@@ -103,29 +103,98 @@ impl Frame {
 
     /// Write a big-endian u8 value and advance the cursor.
     pub(crate) fn write_u8(&mut self, value: u8, data: &mut [u8]) {
-        for idx in 0..8 {
-            self.write_bit(value & (1 << (7 - idx)) != 0, data);
+        let (byte_index, bit_index) = get_indices(self.cursor);
+        if bit_index == 0 {
+            data[byte_index] = value;
+        } else {
+            // Split the value over the end of one byte and the start of the next
+            let keep = 0xff << (8 - bit_index);
+            data[byte_index] = (data[byte_index] & keep) | (value >> bit_index);
+            data[byte_index + 1] = (data[byte_index + 1] & !keep) | (value << (8 - bit_index));
         }
+        self.cursor += 8;
     }
 
-    /// Write a big-endian u8 value and advance the cursor.
+    /// Write a big-endian u16 value and advance the cursor.
     pub(crate) fn write_u16(&mut self, value: u16, data: &mut [u8]) {
-        for idx in 0..16 {
-            self.write_bit(value & (1 << (15 - idx)) != 0, data);
+        for byte in &value.to_be_bytes() {
+            self.write_u8(*byte, data);
         }
     }
 
-    /// Write a big-endian u8 value and advance the cursor.
+    /// Write a big-endian u32 value and advance the cursor.
     pub(crate) fn write_u32(&mut self, value: u32, data: &mut [u8]) {
-        for idx in 0..32 {
-            self.write_bit(value & (1 << (31 - idx)) != 0, data);
+        for byte in &value.to_be_bytes() {
+            self.write_u8(*byte, data);
         }
     }
 
-    /// Write a big-endian u8 value and advance the cursor.
+    /// Write a big-endian u64 value and advance the cursor.
     pub(crate) fn write_u64(&mut self, value: u64, data: &mut [u8]) {
-        for idx in 0..64 {
-            self.write_bit(value & (1 << (63 - idx)) != 0, data);
+        write_u64_at(data, self.cursor, value);
+        self.cursor += 64;
+    }
+
+    /// Fill a buffer with bytes read from the frame and advance the cursor.
+    pub(crate) fn read_bytes_into(&mut self, buf: &mut [u8], data: &[u8]) {
+        let (byte_index, bit_index) = get_indices(self.cursor);
+        if bit_index == 0 {
+            buf.copy_from_slice(&data[byte_index..byte_index + buf.len()]);
+        } else {
+            let done = 8 * (buf.len() / 8);
+            let mut chunks = buf.chunks_exact_mut(8);
+            for (i, chunk) in chunks.by_ref().enumerate() {
+                chunk.copy_from_slice(&read_u64_at(data, self.cursor + 64 * i).to_be_bytes());
+            }
+            for (i, byte) in chunks.into_remainder().iter_mut().enumerate() {
+                let idx = byte_index + done + i;
+                *byte = (data[idx] << bit_index) | (data[idx + 1] >> (8 - bit_index));
+            }
+        }
+        self.cursor += 8 * buf.len();
+    }
+
+    /// Write a whole number of bytes and advance the cursor.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8], data: &mut [u8]) {
+        let (byte_index, bit_index) = get_indices(self.cursor);
+        if bit_index == 0 {
+            data[byte_index..byte_index + bytes.len()].copy_from_slice(bytes);
+            self.cursor += 8 * bytes.len();
+        } else {
+            let mut chunks = bytes.chunks_exact(8);
+            for chunk in chunks.by_ref() {
+                let mut word = [0; 8];
+                word.copy_from_slice(chunk);
+                self.write_u64(u64::from_be_bytes(word), data);
+            }
+            for byte in chunks.remainder() {
+                self.write_u8(*byte, data);
+            }
+        }
+    }
+
+    /// Write the first `bit_len` bits of a byte string, most significant
+    /// bit of each byte first, and advance the cursor.
+    pub(crate) fn write_bits_from_bytes(&mut self, bytes: &[u8], bit_len: usize, data: &mut [u8]) {
+        let whole = bit_len / 8;
+        self.write_bytes(&bytes[..whole], data);
+        for idx in 0..bit_len % 8 {
+            self.write_bit(bytes[whole] & (0x80 >> idx) != 0, data);
+        }
+    }
+
+    /// Write the given number of zero bits and advance the cursor.
+    pub(crate) fn write_zeros(&mut self, len: usize, data: &mut [u8]) {
+        let end = self.cursor + len;
+        while self.cursor < end && self.cursor % 8 != 0 {
+            self.write_bit(false, data);
+        }
+        while self.cursor + 8 <= end {
+            data[self.cursor / 8] = 0;
+            self.cursor += 8;
+        }
+        while self.cursor < end {
+            self.write_bit(false, data);
         }
     }
 
@@ -141,11 +210,115 @@ impl Frame {
 
     /// Copy a bit string of given length from another frame into the present one.
     pub(crate) fn copy_from(&mut self, other: &Self, len: usize, data: &mut [u8]) {
-        for i in 0..len {
+        let mut i = 0;
+        if self.cursor % 8 == 0 && other.cursor % 8 == 0 {
+            // Whole bytes can be copied directly when both cursors are aligned
+            let (src, whole) = (other.cursor / 8, len / 8);
+            data.copy_within(src..src + whole, self.cursor / 8);
+            self.cursor += 8 * whole;
+            i = 8 * whole;
+        }
+        while i + 64 <= len {
+            let word = read_u64_at(data, other.cursor + i);
+            write_u64_at(data, self.cursor, word);
+            self.cursor += 64;
+            i += 64;
+        }
+        while i < len {
             let (other_byte_index, other_bit_index) = get_indices(other.cursor + i);
             let bit = data[other_byte_index] & (1 << (7 - other_bit_index)) != 0;
             self.write_bit(bit, data);
+            i += 1;
+        }
+    }
+
+    /// Copy the rest of another frame, from its cursor to its end, into
+    /// the present one.
+    pub(crate) fn copy_remaining_from(&mut self, other: &Self, data: &mut [u8]) {
+        let len = other.start + other.len - other.cursor;
+        self.copy_from(other, len, data);
+    }
+
+    /// Whether the next `n` bits of this frame and of another are equal,
+    /// without moving either cursor.
+    pub(crate) fn compare(&self, other: &Self, n: usize, data: &[u8]) -> bool {
+        let (a, b) = (self.cursor, other.cursor);
+        if a % 8 == 0 && b % 8 == 0 {
+            let (a, b, whole) = (a / 8, b / 8, n / 8);
+            if data[a..a + whole] != data[b..b + whole] {
+                return false;
+            }
+            if n % 8 == 0 {
+                return true;
+            }
+            let mask = 0xffu8 << (8 - n % 8);
+            return data[a + whole] & mask == data[b + whole] & mask;
+        }
+
+        let mut i = 0;
+        while i + 64 <= n {
+            if read_u64_at(data, a + i) != read_u64_at(data, b + i) {
+                return false;
+            }
+            i += 64;
         }
+        (i..n).all(|i| Frame::new(a + i, 1).peek_bit(data) == Frame::new(b + i, 1).peek_bit(data))
+    }
+
+    /// Whether the next `n` bits of this frame and of another are equal,
+    /// moving both cursors past them.
+    pub(crate) fn compare_and_advance(&mut self, other: &mut Self, n: usize, data: &[u8]) -> bool {
+        let equal = self.compare(other, n, data);
+        self.cursor += n;
+        other.cursor += n;
+        equal
+    }
+
+    /// Number of set bits in the frame, wherever the cursor is.
+    pub(crate) fn count_ones(&self, data: &[u8]) -> usize {
+        self.masked_bytes(data)
+            .map(|(byte, mask)| (byte & mask).count_ones() as usize)
+            .sum()
+    }
+
+    /// Number of unset bits in the frame, wherever the cursor is.
+    pub(crate) fn count_zeros(&self, data: &[u8]) -> usize {
+        self.len - self.count_ones(data)
+    }
+
+    /// Whether every bit in the frame is unset, stopping at the first
+    /// byte with a set bit.
+    pub(crate) fn is_all_zeros(&self, data: &[u8]) -> bool {
+        self.masked_bytes(data).all(|(byte, mask)| byte & mask == 0)
+    }
+
+    /// Whether every bit in the frame is set, stopping at the first byte
+    /// with an unset bit.
+    pub(crate) fn is_all_ones(&self, data: &[u8]) -> bool {
+        self.masked_bytes(data)
+            .all(|(byte, mask)| byte & mask == mask)
+    }
+
+    /// The bytes of data overlapping the frame, each with a mask of the
+    /// bits in it which belong to the frame.
+    fn masked_bytes<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = (u8, u8)> + 'a {
+        let (start, end) = (self.start, self.start + self.len);
+        let bytes = if self.len == 0 {
+            &data[0..0]
+        } else {
+            &data[start / 8..(end + 7) / 8]
+        };
+        let last = bytes.len().saturating_sub(1);
+        bytes.iter().enumerate().map(move |(i, &byte)| {
+            let mut mask = 0xff;
+            if i == 0 {
+                mask &= 0xff >> (start % 8);
+            }
+            if i == last && end % 8 != 0 {
+                mask &= 0xff << (8 - end % 8);
+            }
+            (byte, mask)
+        })
     }
 
     /// Extend the present frame with a read-only reference the the data
@@ -261,6 +434,35 @@ impl<'a> Iterator for FrameData<'a> {
     }
 }
 
+/// Read the 64 bits starting at bit position `pos` as a big-endian u64
+fn read_u64_at(data: &[u8], pos: usize) -> u64 {
+    let (byte_index, bit_index) = get_indices(pos);
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[byte_index..byte_index + 8]);
+    let word = u64::from_be_bytes(bytes);
+    if bit_index == 0 {
+        word
+    } else {
+        (word << bit_index) | u64::from(data[byte_index + 8] >> (8 - bit_index))
+    }
+}
+
+/// Write a big-endian u64 to the 64 bits starting at bit position `pos`
+fn write_u64_at(data: &mut [u8], pos: usize, value: u64) {
+    let (byte_index, bit_index) = get_indices(pos);
+    if bit_index == 0 {
+        data[byte_index..byte_index + 8].copy_from_slice(&value.to_be_bytes());
+    } else {
+        // Keep the bits before `pos` in the first byte and after the
+        // written bits in the last byte
+        let keep = 0xffu8 << (8 - bit_index);
+        let first = data[byte_index] & keep;
+        data[byte_index..byte_index + 8].copy_from_slice(&(value >> bit_index).to_be_bytes());
+        data[byte_index] |= first;
+        data[byte_index + 8] = (data[byte_index + 8] & !keep) | ((value as u8) << (8 - bit_index));
+    }
+}
+
 fn get_indices(cursor: usize) -> (usize, usize) {
     let byte_index = cursor / 8;
     let bit_index = cursor % 8;
@@ -274,6 +476,7 @@ mod tests {
     use crate::core::bitvec_to_bytevec;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn read_unsigned() {
         let bytes = (0..100).collect::<Vec<u8>>();
         let mut frame = Frame::new(0, 100 * 8);
@@ -330,4 +533,183 @@ mod tests {
 
         assert_eq!(bytes, computed_bytes);
     }
+
+    #[test]
+    fn write_unaligned() {
+        for offset in 0..8 {
+            let mut bytewise = vec![0xa5; 4];
+            let mut bitwise = bytewise.clone();
+            let mut frame = Frame::new(offset, 16);
+            frame.write_u16(0x3cf0, &mut bytewise);
+            let mut frame = Frame::new(offset, 16);
+            for idx in 0..16 {
+                frame.write_bit(0x3cf0 & (1 << (15 - idx)) != 0, &mut bitwise);
+            }
+            assert_eq!(bytewise, bitwise);
+        }
+    }
+
+    #[test]
+    fn chunked_copy_and_zeros() {
+        // 16-bit xorshift, for reproducible data
+        let mut state = 0xace1u16;
+        let mut next = || {
+            state ^= state << 7;
+            state ^= state >> 9;
+            state ^= state << 8;
+            state
+        };
+
+        for _ in 0..200 {
+            let data: Vec<u8> = (0..64).map(|_| next() as u8).collect();
+            let src = usize::from(next()) % 64;
+            let dst = 256 + usize::from(next()) % 64;
+            let len = usize::from(next()) % 192;
+
+            let mut chunked = data.clone();
+            let mut bitwise = data.clone();
+            let mut frame = Frame::new(dst, len);
+            frame.copy_from(&Frame::new(src, len), len, &mut chunked);
+            let mut frame = Frame::new(dst, len);
+            for i in 0..len {
+                let bit = Frame::new(src + i, 1).peek_bit(&bitwise);
+                frame.write_bit(bit, &mut bitwise);
+            }
+            assert_eq!(chunked, bitwise);
+
+            let mut frame = Frame::new(dst, len);
+            frame.write_zeros(len, &mut chunked);
+            let mut frame = Frame::new(dst, len);
+            for _ in 0..len {
+                frame.write_bit(false, &mut bitwise);
+            }
+            assert_eq!(chunked, bitwise);
+            assert_eq!(frame.cursor, dst + len);
+        }
+    }
+
+    #[test]
+    fn copy_remaining() {
+        let data: Vec<u8> = (0..64).map(|i| (i * 91 + 7) as u8).collect();
+        for &(start, dst) in &[(0, 256), (8, 264), (3, 256), (8, 261), (5, 259)] {
+            for read in &[0, 1, 8, 13, 64, 70] {
+                let mut other = Frame::new(start, 180);
+                other.move_cursor_forward(*read);
+
+                let mut remaining = data.clone();
+                let mut frame = Frame::new(dst, 180);
+                frame.copy_remaining_from(&other, &mut remaining);
+                assert_eq!(frame.cursor, dst + 180 - read);
+
+                let mut explicit = data.clone();
+                Frame::new(dst, 180).copy_from(&other, 180 - read, &mut explicit);
+                assert_eq!(remaining, explicit);
+
+                let copied = Frame::new(dst, 180 - read).to_frame_data(&remaining);
+                assert!(copied.eq(other.to_frame_data(&data)));
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_aligned_and_unaligned() {
+        // 67 bytes, which is not a whole number of 64-bit words
+        let bytes: Vec<u8> = (0..67).map(|i| (i * 37) as u8).collect();
+        for offset in 0..8 {
+            let mut bulk = vec![0xa5; 69];
+            let mut bitwise = bulk.clone();
+            Frame::new(offset, 536).write_bytes(&bytes, &mut bulk);
+            let mut frame = Frame::new(offset, 536);
+            for byte in &bytes {
+                for idx in 0..8 {
+                    frame.write_bit(byte & (1 << (7 - idx)) != 0, &mut bitwise);
+                }
+            }
+            assert_eq!(bulk, bitwise);
+
+            let mut frame = Frame::new(offset, 536);
+            let mut buf = [0; 67];
+            frame.read_bytes_into(&mut buf, &bulk);
+            assert_eq!(&buf[..], &bytes[..]);
+            assert_eq!(frame.cursor, offset + 536);
+        }
+    }
+
+    #[test]
+    fn partial_bytes() {
+        let bytes: Vec<u8> = (0..12).map(|i| (i * 91 + 7) as u8).collect();
+        for offset in 0..8 {
+            for &bit_len in &[0, 1, 7, 8, 9, 20, 33, 63, 64, 65, 90, 96] {
+                let mut bulk = vec![0xa5; 14];
+                let mut bitwise = bulk.clone();
+                let mut frame = Frame::new(offset, bit_len);
+                frame.write_bits_from_bytes(&bytes, bit_len, &mut bulk);
+                assert_eq!(frame.cursor, offset + bit_len);
+
+                let mut frame = Frame::new(offset, bit_len);
+                for idx in 0..bit_len {
+                    let bit = bytes[idx / 8] & (0x80 >> (idx % 8)) != 0;
+                    frame.write_bit(bit, &mut bitwise);
+                }
+                assert_eq!(bulk, bitwise, "offset {} length {}", offset, bit_len);
+            }
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let data: Vec<u8> = (0..48).map(|i| (i * 91 + 7) as u8).collect();
+        // The same bytes again, with one bit flipped
+        let mut copy = data[..24].to_vec();
+        copy.extend_from_slice(&data[..24]);
+        copy[24 + 13] ^= 0x10;
+
+        for &(a, b) in &[(0, 192), (3, 195), (8, 200), (5, 197), (0, 3)] {
+            for n in 0..180 {
+                let frame = Frame::new(a, n);
+                let other = Frame::new(b, n);
+                for data in &[&data, &copy] {
+                    let bitwise = frame
+                        .to_frame_data(data)
+                        .take(n)
+                        .eq(other.to_frame_data(data).take(n));
+                    assert_eq!(frame.compare(&other, n, data), bitwise, "{} {} {}", a, b, n);
+                }
+                assert_eq!((frame.cursor, other.cursor), (a, b));
+            }
+        }
+        // The flipped bit is bit 107 of the second copy
+        assert!(Frame::new(3, 0).compare(&Frame::new(195, 0), 104, &copy));
+        assert!(!Frame::new(3, 0).compare(&Frame::new(195, 0), 105, &copy));
+
+        let mut frame = Frame::new(8, 256);
+        let mut other = Frame::new(200, 256);
+        assert!(frame.compare_and_advance(&mut other, 99, &copy));
+        assert!(!frame.compare_and_advance(&mut other, 10, &copy));
+        assert_eq!((frame.cursor, other.cursor), (117, 309));
+    }
+
+    #[test]
+    fn count_bits() {
+        let data: Vec<u8> = (0..12).map(|i| (i * 91 + 7) as u8).collect();
+        for start in 0..9 {
+            for len in 0..(96 - start) {
+                let frame = Frame::new(start, len);
+                let ones = frame.to_frame_data(&data).filter(|&bit| bit).count();
+                assert_eq!(frame.count_ones(&data), ones);
+                assert_eq!(frame.count_zeros(&data), len - ones);
+                assert_eq!(frame.is_all_zeros(&data), ones == 0);
+                assert_eq!(frame.is_all_ones(&data), ones == len);
+            }
+        }
+
+        // Bits either side of the frame are not counted
+        let data = [0xf0, 0x0f];
+        let frame = Frame::new(4, 8);
+        assert!(frame.is_all_zeros(&data));
+        assert_eq!(frame.count_zeros(&data), 8);
+        let data = [0x0f, 0xf0];
+        assert!(frame.is_all_ones(&data));
+        assert_eq!(frame.count_ones(&data), 8);
+    }
 }
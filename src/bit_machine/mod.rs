@@ -1,4 +1,7 @@
 #![allow(dead_code)]
 
+pub mod estimate;
 pub mod exec;
-mod frame;
+pub(crate) mod frame;
+#[cfg(feature = "exec_trace")]
+pub mod trace;
@@ -0,0 +1,237 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Execution Traces
+//!
+//! A serializable record of each step of a program's execution, for
+//! comparing runs of the Bit Machine against each other or against
+//! another implementation, such as the C one.
+//!
+//! A trace has one entry per node executed, in order, holding the
+//! node's index and combinator, the offsets of the cursors within the
+//! active read and write frames, and the number of frames on each frame
+//! stack, all as they were when the node began executing. Traces of
+//! long executions are large, so this module is behind the `exec_trace`
+//! feature.
+//!
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::exec::BitMachine;
+use crate::extension;
+use crate::{Program, Value};
+
+/// The state of the Bit Machine as it began executing a node
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Index of the node
+    pub node: usize,
+    /// The node's combinator, in the syntax of the `text` module, e.g.
+    /// `comp` or `jet_adder32`
+    pub term: String,
+    /// Offset of the cursor within the active read frame, if any
+    pub read_cursor: Option<usize>,
+    /// Offset of the cursor within the active write frame, if any
+    pub write_cursor: Option<usize>,
+    /// Number of frames on the read frame stack
+    pub read_frames: usize,
+    /// Number of frames on the write frame stack
+    pub write_frames: usize,
+}
+
+/// Every step of a program's execution on some input, with its outcome
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ExecTrace {
+    /// The steps of execution, in order
+    pub steps: Vec<TraceStep>,
+    /// The program's output, displayed as a value, if it succeeded
+    pub output: Option<String>,
+    /// The error which execution failed with, if it did
+    pub error: Option<String>,
+}
+
+/// The first step at which two traces differ
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceDivergence {
+    /// Index of the first differing step, or the number of steps if the
+    /// traces differ only in their outcomes
+    pub index: usize,
+    /// The step of the first trace, or `None` if it ended first
+    pub left: Option<TraceStep>,
+    /// The step of the second trace, or `None` if it ended first
+    pub right: Option<TraceStep>,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Traces diverge at step {}: {:?} vs {:?}",
+            self.index, self.left, self.right
+        )
+    }
+}
+
+impl ExecTrace {
+    /// Execute a program on the given input, recording every step
+    ///
+    /// If the input has the wrong type, the trace has no steps.
+    pub fn record<Ext>(program: &Program<Ext>, input: &Value, txenv: &Ext::TxEnv) -> ExecTrace
+    where
+        Ext: extension::Jet,
+    {
+        let mut mac = BitMachine::for_program(program);
+        let (result, steps) = mac.exec_steps(program, input, txenv);
+        let steps = steps
+            .into_iter()
+            .map(|step| TraceStep {
+                node: step.node,
+                term: program.nodes[step.node].node.name(),
+                read_cursor: step.read_cursor,
+                write_cursor: step.write_cursor,
+                read_frames: step.read_frames,
+                write_frames: step.write_frames,
+            })
+            .collect();
+        let (output, error) = match result {
+            Ok(value) => (Some(value.to_string()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        ExecTrace {
+            steps: steps,
+            output: output,
+            error: error,
+        }
+    }
+
+    /// The first step at which this trace differs from another, or
+    /// `None` if they are the same
+    pub fn diff(&self, other: &ExecTrace) -> Option<TraceDivergence> {
+        let len = self.steps.len().max(other.steps.len());
+        for index in 0..len {
+            let (left, right) = (self.steps.get(index), other.steps.get(index));
+            if left != right {
+                return Some(TraceDivergence {
+                    index: index,
+                    left: left.cloned(),
+                    right: right.cloned(),
+                });
+            }
+        }
+        if self.output != other.output || self.error != other.error {
+            return Some(TraceDivergence {
+                index: len,
+                left: None,
+                right: None,
+            });
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::extension::dummy::{DummyNode, TxEnv};
+    use crate::text;
+
+    #[test]
+    fn record_and_diff() {
+        let prog = text::parse_program::<DummyNode, _>(
+            "main := comp (injl unit) iden",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let trace = ExecTrace::record(&prog, &Value::Unit, &TxEnv);
+        assert_eq!(trace, ExecTrace::record(&prog, &Value::Unit, &TxEnv));
+        assert_eq!(trace.diff(&trace), None);
+
+        // The schema, as JSON
+        assert_eq!(
+            serde_json::to_string_pretty(&trace).unwrap(),
+            SNAPSHOT.trim(),
+        );
+        let parsed: ExecTrace = serde_json::from_str(SNAPSHOT).unwrap();
+        assert_eq!(parsed, trace);
+
+        let mut altered = trace.clone();
+        altered.steps[2].write_cursor = Some(0);
+        assert_eq!(
+            trace.diff(&altered),
+            Some(TraceDivergence {
+                index: 2,
+                left: Some(trace.steps[2].clone()),
+                right: Some(altered.steps[2].clone()),
+            })
+        );
+        altered = trace.clone();
+        altered.steps.pop();
+        assert_eq!(trace.diff(&altered).unwrap().index, 3);
+        altered = trace.clone();
+        altered.output = None;
+        altered.error = Some("Reached a fail node".to_owned());
+        assert_eq!(trace.diff(&altered).unwrap().index, 4);
+
+        // Input of the wrong type
+        let trace = ExecTrace::record(&prog, &Value::u1(0), &TxEnv);
+        assert!(trace.steps.is_empty());
+        assert!(trace.error.is_some());
+    }
+
+    /// Trace of `comp (injl unit) iden`. The input and output frames
+    /// are on the frame stacks throughout, the input frame being empty.
+    const SNAPSHOT: &str = r#"
+{
+  "steps": [
+    {
+      "node": 3,
+      "term": "comp",
+      "read_cursor": 0,
+      "write_cursor": 0,
+      "read_frames": 1,
+      "write_frames": 1
+    },
+    {
+      "node": 1,
+      "term": "injl",
+      "read_cursor": 0,
+      "write_cursor": 0,
+      "read_frames": 1,
+      "write_frames": 2
+    },
+    {
+      "node": 0,
+      "term": "unit",
+      "read_cursor": 0,
+      "write_cursor": 1,
+      "read_frames": 1,
+      "write_frames": 2
+    },
+    {
+      "node": 2,
+      "term": "iden",
+      "read_cursor": 0,
+      "write_cursor": 0,
+      "read_frames": 2,
+      "write_frames": 1
+    }
+  ],
+  "output": "0",
+  "error": null
+}
+"#;
+}
@@ -101,6 +101,11 @@ impl<I: Iterator<Item = u8>> BitIter<I> {
     pub fn n_total_read(&self) -> usize {
         self.total_read
     }
+
+    /// Accessor for the underlying bytewise iterator
+    pub(crate) fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,176 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Program Cache
+//!
+//! A bounded cache of decoded programs, so that a validator which sees the
+//! same program many times only has to decode and typecheck it once.
+//!
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::{sha256, Hash};
+
+use crate::cmr::Cmr;
+use crate::extension;
+use crate::{Error, Program};
+
+/// A cached program, along with the data needed to find and evict it
+struct CacheEntry<Ext> {
+    program: Arc<Program<Ext>>,
+    bytes_hash: sha256::Hash,
+    last_used: u64,
+}
+
+struct CacheInner<Ext> {
+    /// Cached programs, by the CMR of their root
+    programs: HashMap<Cmr, CacheEntry<Ext>>,
+    /// CMRs of cached programs, by the hash of their encoding. Programs
+    /// which differ only in their witness data have the same CMR, so
+    /// lookups by encoding must also check the hash stored in the entry.
+    by_bytes: HashMap<sha256::Hash, Cmr>,
+    /// Counter used to order entries by last use
+    tick: u64,
+}
+
+/// Thread-safe cache of decoded programs, keyed by CMR, which holds at
+/// most a fixed number of programs and evicts the least recently used
+pub struct ProgramCache<Ext> {
+    inner: Mutex<CacheInner<Ext>>,
+    capacity: usize,
+}
+
+impl<Ext: extension::Jet> ProgramCache<Ext> {
+    /// Create a new cache holding at most `capacity` programs
+    pub fn new(capacity: usize) -> ProgramCache<Ext> {
+        ProgramCache {
+            inner: Mutex::new(CacheInner {
+                programs: HashMap::new(),
+                by_bytes: HashMap::new(),
+                tick: 0,
+            }),
+            capacity: capacity,
+        }
+    }
+
+    /// Number of programs currently in the cache
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().programs.len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look up a cached program by the CMR of its root
+    pub fn get(&self, cmr: &Cmr) -> Option<Arc<Program<Ext>>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.programs.get_mut(cmr).map(|entry| {
+            entry.last_used = tick;
+            Arc::clone(&entry.program)
+        })
+    }
+
+    /// Return the cached program with the given encoding, decoding it
+    /// and adding it to the cache if it is not already there
+    pub fn get_or_decode(&self, bytes: &[u8]) -> Result<Arc<Program<Ext>>, Error> {
+        let bytes_hash = sha256::Hash::hash(bytes);
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.tick += 1;
+            let tick = inner.tick;
+            if let Some(cmr) = inner.by_bytes.get(&bytes_hash).cloned() {
+                if let Some(entry) = inner.programs.get_mut(&cmr) {
+                    entry.last_used = tick;
+                    return Ok(Arc::clone(&entry.program));
+                }
+            }
+        }
+
+        // Decode without holding the lock, so that other threads can
+        // use the cache in the meantime
+        let program = Arc::new(Program::deserialize(bytes)?);
+        if self.capacity == 0 {
+            return Ok(program);
+        }
+        let cmr = program.root_node().cmr;
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        if !inner.programs.contains_key(&cmr) && inner.programs.len() >= self.capacity {
+            let lru = inner
+                .programs
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(cmr, _)| *cmr)
+                .expect("cache with nonzero capacity is full");
+            let evicted = inner.programs.remove(&lru).unwrap();
+            inner.by_bytes.remove(&evicted.bytes_hash);
+        }
+        let entry = CacheEntry {
+            program: Arc::clone(&program),
+            bytes_hash: bytes_hash,
+            last_used: tick,
+        };
+        if let Some(replaced) = inner.programs.insert(cmr, entry) {
+            inner.by_bytes.remove(&replaced.bytes_hash);
+        }
+        inner.by_bytes.insert(bytes_hash, cmr);
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::dummy::DummyNode;
+
+    #[test]
+    fn cache_hit_and_eviction() {
+        // unit, injl unit, injr unit
+        let unit = [0x24];
+        let injl = [0x89, 0x20];
+        let injr = [0x89, 0x28];
+
+        let cache = ProgramCache::<DummyNode>::new(2);
+        let first = cache.get_or_decode(&unit).unwrap();
+        let second = cache.get_or_decode(&unit).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        let injl_prog = cache.get_or_decode(&injl).unwrap();
+        assert_eq!(cache.len(), 2);
+        // Touch `unit` so that `injl` is the least recently used
+        let unit_cmr = first.root_node().cmr;
+        assert!(cache.get(&unit_cmr).is_some());
+
+        let injr_prog = cache.get_or_decode(&injr).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&injl_prog.root_node().cmr).is_none());
+        assert!(Arc::ptr_eq(&cache.get(&unit_cmr).unwrap(), &first));
+        assert!(Arc::ptr_eq(
+            &cache.get_or_decode(&injr).unwrap(),
+            &injr_prog
+        ));
+
+        // Bad encodings are not cached
+        assert!(cache.get_or_decode(&[]).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
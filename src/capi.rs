@@ -0,0 +1,330 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # C API
+//!
+//! `extern "C"` functions for decoding, inspecting and running programs
+//! from other languages, declared for C in `include/simplicity.h`. To
+//! build a shared library, enable the `capi` feature and build the crate
+//! as a `cdylib`, e.g. with `cargo rustc --features capi --crate-type cdylib`.
+//!
+//! Programs are decoded with the core jets only, and are run without a
+//! transaction environment, so the `env` argument of `simplicity_exec`
+//! must be null. Like Simplicity programs in transactions, they take no
+//! input.
+//!
+//! ## Ownership
+//!
+//! `simplicity_decode` gives the caller a handle which it owns until it
+//! passes the handle to `simplicity_free`, exactly once. Every other
+//! function borrows the handle only for the duration of the call. Byte
+//! buffers are always owned by the caller, and are never kept after a
+//! call returns.
+//!
+//! ## Errors
+//!
+//! Every function returns one of the `SIMPLICITY_*` status codes below.
+//! Panics never cross the API boundary: they are caught and reported as
+//! `SIMPLICITY_ERR_PANIC`. On error, outputs are left unchanged, except
+//! for the length written by `simplicity_exec`.
+//!
+
+use std::os::raw::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use crate::exec::{BitMachine, ExecError};
+use crate::extension::dummy::{DummyNode, TxEnv};
+use crate::extension::JetFailed;
+use crate::Program;
+
+/// The call succeeded
+pub const SIMPLICITY_OK: c_int = 0;
+/// A required pointer argument was null
+pub const SIMPLICITY_ERR_NULL_POINTER: c_int = 1;
+/// The bytes given to `simplicity_decode` are not a valid program
+pub const SIMPLICITY_ERR_DECODE: c_int = 2;
+/// Execution failed, e.g. by reaching a `fail` node
+pub const SIMPLICITY_ERR_EXEC: c_int = 3;
+/// The output buffer given to `simplicity_exec` is too small
+pub const SIMPLICITY_ERR_BUFFER_TOO_SMALL: c_int = 4;
+/// The program, or the arguments, are not supported by this API
+pub const SIMPLICITY_ERR_UNSUPPORTED: c_int = 5;
+/// The library panicked
+pub const SIMPLICITY_ERR_PANIC: c_int = 6;
+/// Running the program would take more memory than the caller allows
+pub const SIMPLICITY_ERR_MEMORY_LIMIT: c_int = 7;
+
+/// A decoded program, owned by the caller through a pointer given by
+/// `simplicity_decode`
+pub struct SimplicityProgram {
+    program: Program<DummyNode>,
+}
+
+/// Run `f`, reporting a panic as an error code
+fn catch_panic<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(SIMPLICITY_ERR_PANIC)
+}
+
+/// The status code reporting an execution error
+fn exec_error_code(error: ExecError) -> c_int {
+    match error {
+        ExecError::TooMuchMemory { .. } => SIMPLICITY_ERR_MEMORY_LIMIT,
+        ExecError::JetFailed(_, JetFailed::Unsupported) => SIMPLICITY_ERR_UNSUPPORTED,
+        _ => SIMPLICITY_ERR_EXEC,
+    }
+}
+
+/// Decode a program, with its witness data, from `len` bytes at `bytes`,
+/// storing a new handle to it in `*out`
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes, and `out` must be valid
+/// for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn simplicity_decode(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut SimplicityProgram,
+) -> c_int {
+    if bytes.is_null() || out.is_null() {
+        return SIMPLICITY_ERR_NULL_POINTER;
+    }
+    let bytes = slice::from_raw_parts(bytes, len);
+    catch_panic(|| match Program::<DummyNode>::deserialize(bytes) {
+        Ok(program) => {
+            *out = Box::into_raw(Box::new(SimplicityProgram { program: program }));
+            SIMPLICITY_OK
+        }
+        Err(_) => SIMPLICITY_ERR_DECODE,
+    })
+}
+
+/// Write the 32-byte CMR of a program to `out32`
+///
+/// # Safety
+///
+/// `handle` must have been given by `simplicity_decode` and not freed,
+/// and `out32` must point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn simplicity_cmr(handle: *const SimplicityProgram, out32: *mut u8) -> c_int {
+    if handle.is_null() || out32.is_null() {
+        return SIMPLICITY_ERR_NULL_POINTER;
+    }
+    let cmr = (*handle).program.root_node().cmr.into_inner();
+    ptr::copy_nonoverlapping(cmr.as_ptr(), out32, cmr.len());
+    SIMPLICITY_OK
+}
+
+/// Run a program, writing its output to `out_buf`
+///
+/// The Bit Machine may use at most `max_bytes` bytes of memory, or else
+/// `SIMPLICITY_ERR_MEMORY_LIMIT` is returned without running the program.
+/// On entry `*out_len` is the size of `out_buf`. On success, and on
+/// `SIMPLICITY_ERR_BUFFER_TOO_SMALL`, it is set to the number of bytes
+/// of output, which is the output's padded bit encoding with the last
+/// byte filled out with zero bits. `out_buf` may be null if `*out_len`
+/// is zero. `env` is reserved and must be null.
+///
+/// # Safety
+///
+/// `handle` must have been given by `simplicity_decode` and not freed,
+/// `out_len` must be valid for reading and writing, and `out_buf` must
+/// point to `*out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn simplicity_exec(
+    handle: *const SimplicityProgram,
+    env: *const c_void,
+    max_bytes: usize,
+    out_buf: *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    if handle.is_null() || out_len.is_null() || (out_buf.is_null() && *out_len > 0) {
+        return SIMPLICITY_ERR_NULL_POINTER;
+    }
+    if !env.is_null() {
+        return SIMPLICITY_ERR_UNSUPPORTED;
+    }
+    let program = &(*handle).program;
+    let root = program.root_node();
+    if root.source_ty.bit_width() > 0 {
+        return SIMPLICITY_ERR_UNSUPPORTED;
+    }
+    let needed = (root.target_ty.bit_width() + 7) / 8;
+    if needed > *out_len {
+        *out_len = needed;
+        return SIMPLICITY_ERR_BUFFER_TOO_SMALL;
+    }
+
+    catch_panic(|| {
+        let mut mac = match BitMachine::try_for_program(program, max_bytes) {
+            Ok(mac) => mac,
+            Err(e) => return exec_error_code(e),
+        };
+        let mut output = Vec::with_capacity(needed);
+        match mac.exec_stream_output(program, &TxEnv, |chunk| output.extend_from_slice(chunk)) {
+            Ok(()) => {
+                if needed > 0 {
+                    ptr::copy_nonoverlapping(output.as_ptr(), out_buf, needed);
+                }
+                *out_len = needed;
+                SIMPLICITY_OK
+            }
+            Err(e) => exec_error_code(e),
+        }
+    })
+}
+
+/// Free a program handle given by `simplicity_decode`. Does nothing if
+/// `handle` is null.
+///
+/// # Safety
+///
+/// `handle` must be null, or have been given by `simplicity_decode` and
+/// not already freed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn simplicity_free(handle: *mut SimplicityProgram) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::text;
+
+    /// Encoding of the program with the given source, without witnesses
+    fn program_bytes(src: &str) -> Vec<u8> {
+        text::parse_program::<DummyNode, _>(src, &mut BitIter::from(vec![0x00].into_iter()))
+            .unwrap()
+            .serialize()
+    }
+
+    /// Decode a program through the C API, failing on error
+    fn decode(bytes: &[u8]) -> *mut SimplicityProgram {
+        let mut handle = ptr::null_mut();
+        let status = unsafe { simplicity_decode(bytes.as_ptr(), bytes.len(), &mut handle) };
+        assert_eq!(status, SIMPLICITY_OK);
+        assert!(!handle.is_null());
+        handle
+    }
+
+    /// Memory limit for running test programs
+    const MAX_BYTES: usize = 1 << 20;
+
+    /// Run a program through the C API with an output buffer of the
+    /// given size, giving the status and the output
+    fn exec(handle: *const SimplicityProgram, buf_len: usize) -> (c_int, Vec<u8>) {
+        let mut buf = vec![0; buf_len];
+        let mut len = buf_len;
+        let status =
+            unsafe { simplicity_exec(handle, ptr::null(), MAX_BYTES, buf.as_mut_ptr(), &mut len) };
+        buf.truncate(len);
+        (status, buf)
+    }
+
+    #[test]
+    fn decode_cmr_exec() {
+        let src = "main := const 0xdeadbeef";
+        let bytes = program_bytes(src);
+        let handle = decode(&bytes);
+
+        let mut cmr = [0; 32];
+        assert_eq!(
+            unsafe { simplicity_cmr(handle, cmr.as_mut_ptr()) },
+            SIMPLICITY_OK
+        );
+        let expected = Program::<DummyNode>::deserialize(&bytes).unwrap();
+        assert_eq!(cmr, expected.root_node().cmr.into_inner());
+
+        assert_eq!(
+            exec(handle, 8),
+            (SIMPLICITY_OK, vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        // The required size is reported when the buffer is too small
+        assert_eq!(
+            exec(handle, 3),
+            (SIMPLICITY_ERR_BUFFER_TOO_SMALL, vec![0; 3])
+        );
+        let mut len = 0;
+        let status =
+            unsafe { simplicity_exec(handle, ptr::null(), MAX_BYTES, ptr::null_mut(), &mut len) };
+        assert_eq!((status, len), (SIMPLICITY_ERR_BUFFER_TOO_SMALL, 4));
+
+        // The output and the machine's memory both count towards the limit
+        let mut buf = [0; 4];
+        let mut len = 4;
+        let status = unsafe { simplicity_exec(handle, ptr::null(), 3, buf.as_mut_ptr(), &mut len) };
+        assert_eq!(status, SIMPLICITY_ERR_MEMORY_LIMIT);
+        let status = unsafe { simplicity_exec(handle, ptr::null(), 4, buf.as_mut_ptr(), &mut len) };
+        assert_eq!((status, buf), (SIMPLICITY_OK, [0xde, 0xad, 0xbe, 0xef]));
+
+        unsafe { simplicity_free(handle) };
+    }
+
+    #[test]
+    fn errors() {
+        let mut handle = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                simplicity_decode(ptr::null(), 0, &mut handle),
+                SIMPLICITY_ERR_NULL_POINTER
+            );
+            assert_eq!(
+                simplicity_decode([0xff].as_ptr(), 1, &mut handle),
+                SIMPLICITY_ERR_DECODE
+            );
+            assert!(handle.is_null());
+            assert_eq!(
+                simplicity_cmr(ptr::null(), [0; 32].as_mut_ptr()),
+                SIMPLICITY_ERR_NULL_POINTER
+            );
+            simplicity_free(ptr::null_mut());
+        }
+
+        // Reaching a hidden node
+        let handle = decode(&program_bytes(
+            "main := comp (pair (injr unit) unit) (case unit (hidden 0x0000000000000000000000000000000000000000000000000000000000000000))",
+        ));
+        assert_eq!(exec(handle, 0).0, SIMPLICITY_ERR_EXEC);
+        let env = 1u8;
+        let mut len = 0;
+        let status = unsafe {
+            simplicity_exec(
+                handle,
+                &env as *const u8 as *const c_void,
+                MAX_BYTES,
+                ptr::null_mut(),
+                &mut len,
+            )
+        };
+        assert_eq!(status, SIMPLICITY_ERR_UNSUPPORTED);
+        unsafe { simplicity_free(handle) };
+
+        // Programs which take an input
+        let handle = decode(&program_bytes("main := comp iden jet_adder32"));
+        assert_eq!(exec(handle, 8).0, SIMPLICITY_ERR_UNSUPPORTED);
+        unsafe { simplicity_free(handle) };
+
+        // A verification jet failing
+        let handle = decode(&program_bytes(
+            "main := comp (pair (const 0x00000001) (const 0x00000002)) jet_eqv32",
+        ));
+        assert_eq!(exec(handle, 0).0, SIMPLICITY_ERR_EXEC);
+        unsafe { simplicity_free(handle) };
+    }
+}
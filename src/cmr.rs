@@ -20,6 +20,10 @@
 use bitcoin_hashes::{sha256, Hash, HashEngine};
 use std::{fmt, ops};
 
+/// Number of bits of CMR which `disconnect` passes to its left child,
+/// ahead of the rest of its input
+pub const DISCONNECT_CMR_BITS: usize = 256;
+
 /// Commitment Merkle Root
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Cmr(sha256::Midstate);
@@ -0,0 +1,277 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Compact JSON
+//!
+//! A JSON representation of values meant to be read by people, e.g. in
+//! the output of command-line tools: `null` for the unit value, `false`
+//! and `true` for bits, `{"u32": 42}` for words of up to 64 bits,
+//! `{"l": v}` and `{"r": v}` for other sum values, and `[a, b]` for other
+//! products, including wider words. Values are encoded without their
+//! types, which are needed to decode them.
+//!
+
+use std::sync::Arc;
+use std::{error, fmt};
+
+use serde_json::Map;
+
+use crate::core::types::{FinalType, FinalTypeInner};
+use crate::Value;
+
+/// Error decoding a value from compact JSON
+#[derive(Clone, PartialEq, Debug)]
+pub enum JsonError {
+    /// The JSON does not encode a value of the given type
+    TypeMismatch {
+        /// The type, or the part of it, being decoded
+        expected: Arc<FinalType>,
+        /// The JSON, or the part of it, being decoded
+        found: serde_json::Value,
+    },
+    /// An integer does not fit in a word of the given width
+    OutOfRange {
+        /// Width of the word in bits
+        width: usize,
+        /// The integer
+        value: u64,
+    },
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::TypeMismatch {
+                ref expected,
+                ref found,
+            } => write!(f, "JSON {} is not a value of type {}", found, expected),
+            JsonError::OutOfRange { width, value } => {
+                write!(f, "{} does not fit in a {}-bit word", value, width)
+            }
+        }
+    }
+}
+
+impl error::Error for JsonError {}
+
+/// Width of a value which is a word, i.e. a bit or a product of two
+/// words of the same width
+fn word_width(value: &Value) -> Option<usize> {
+    match *value {
+        Value::SumL(ref a) | Value::SumR(ref a) if **a == Value::Unit => Some(1),
+        Value::Prod(ref a, ref b) => match (word_width(a), word_width(b)) {
+            (Some(a), Some(b)) if a == b => Some(a + b),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Value {
+    /// Encode the value as compact JSON
+    pub fn to_compact_json(&self) -> serde_json::Value {
+        match word_width(self) {
+            Some(width) if (2..=64).contains(&width) => {
+                let n = self
+                    .iter_bits()
+                    .fold(0u64, |n, bit| (n << 1) | u64::from(bit));
+                let mut map = Map::new();
+                map.insert(format!("u{}", width), n.into());
+                return serde_json::Value::Object(map);
+            }
+            _ => {}
+        }
+
+        match *self {
+            Value::Unit => serde_json::Value::Null,
+            Value::SumL(ref a) if **a == Value::Unit => serde_json::Value::Bool(false),
+            Value::SumR(ref a) if **a == Value::Unit => serde_json::Value::Bool(true),
+            Value::SumL(ref a) | Value::SumR(ref a) => {
+                let key = match *self {
+                    Value::SumL(..) => "l",
+                    _ => "r",
+                };
+                let mut map = Map::new();
+                map.insert(key.to_owned(), a.to_compact_json());
+                serde_json::Value::Object(map)
+            }
+            Value::Prod(ref a, ref b) => {
+                serde_json::Value::Array(vec![a.to_compact_json(), b.to_compact_json()])
+            }
+        }
+    }
+
+    /// Decode a value of the given type from compact JSON
+    ///
+    /// Bits and words are accepted for any type with a value of the
+    /// same shape, e.g. `false` for any sum whose left type is the unit.
+    pub fn from_compact_json(json: &serde_json::Value, ty: &FinalType) -> Result<Value, JsonError> {
+        let mismatch = || JsonError::TypeMismatch {
+            expected: Arc::new(ty.clone()),
+            found: json.clone(),
+        };
+        let unit = FinalTypeInner::Unit;
+        match (json, &ty.ty) {
+            (serde_json::Value::Null, FinalTypeInner::Unit) => Ok(Value::Unit),
+            (serde_json::Value::Bool(false), FinalTypeInner::Sum(a, _)) if a.ty == unit => {
+                Ok(Value::SumL(Box::new(Value::Unit)))
+            }
+            (serde_json::Value::Bool(true), FinalTypeInner::Sum(_, b)) if b.ty == unit => {
+                Ok(Value::SumR(Box::new(Value::Unit)))
+            }
+            (serde_json::Value::Array(items), FinalTypeInner::Product(a, b))
+                if items.len() == 2 =>
+            {
+                Ok(Value::Prod(
+                    Box::new(Value::from_compact_json(&items[0], a)?),
+                    Box::new(Value::from_compact_json(&items[1], b)?),
+                ))
+            }
+            (serde_json::Value::Object(map), _) if map.len() == 1 => {
+                let (key, inner) = map.iter().next().expect("one entry");
+                match (&key[..], &ty.ty) {
+                    ("l", FinalTypeInner::Sum(a, _)) => {
+                        Ok(Value::SumL(Box::new(Value::from_compact_json(inner, a)?)))
+                    }
+                    ("r", FinalTypeInner::Sum(_, b)) => {
+                        Ok(Value::SumR(Box::new(Value::from_compact_json(inner, b)?)))
+                    }
+                    _ => {
+                        if !key.starts_with('u') {
+                            return Err(mismatch());
+                        }
+                        let width = match key[1..].parse::<usize>() {
+                            Ok(width) if (2..=64).contains(&width) => width,
+                            _ => return Err(mismatch()),
+                        };
+                        let n = inner.as_u64().ok_or_else(mismatch)?;
+                        if width < 64 && n >> width != 0 {
+                            return Err(JsonError::OutOfRange {
+                                width: width,
+                                value: n,
+                            });
+                        }
+                        // The word's bits must make up exactly a value of the type
+                        let mut bits = (0..width).rev().map(|i| n & (1 << i) != 0);
+                        match Value::from_bits_and_type(&mut bits, ty) {
+                            Ok(value)
+                                if bits.next().is_none() && word_width(&value) == Some(width) =>
+                            {
+                                Ok(value)
+                            }
+                            _ => Err(mismatch()),
+                        }
+                    }
+                }
+            }
+            _ => Err(mismatch()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types;
+    use serde_json::json;
+
+    #[test]
+    fn roundtrip() {
+        let pow2 = types::pow2_types();
+        let unit = Arc::new(FinalType::unit());
+        let bit = pow2[1].clone();
+        let sum =
+            |a: &Arc<FinalType>, b: &Arc<FinalType>| Arc::new(FinalType::sum(a.clone(), b.clone()));
+        let prod = |a: &Arc<FinalType>, b: &Arc<FinalType>| {
+            Arc::new(FinalType::prod(a.clone(), b.clone()))
+        };
+
+        let word128 = Value::prod(Value::u64(1), Value::u64(u64::max_value()));
+        let cases = vec![
+            (Value::Unit, unit.clone(), json!(null)),
+            (Value::u1(0), bit.clone(), json!(false)),
+            (Value::u1(1), bit.clone(), json!(true)),
+            (Value::u8(0xa5), pow2[4].clone(), json!({"u8": 0xa5})),
+            (Value::u32(42), pow2[6].clone(), json!({"u32": 42})),
+            (
+                Value::u64(u64::max_value()),
+                pow2[7].clone(),
+                json!({ "u64": u64::max_value() }),
+            ),
+            // Wider words are products of words
+            (
+                word128,
+                pow2[8].clone(),
+                json!([{"u64": 1}, { "u64": u64::max_value() }]),
+            ),
+            (
+                Value::sum_r(Value::u8(7)),
+                sum(&unit, &pow2[4]),
+                json!({"r": {"u8": 7}}),
+            ),
+            (
+                Value::sum_l(Value::Unit),
+                sum(&unit, &pow2[4]),
+                json!(false),
+            ),
+            (
+                Value::prod(Value::u1(1), Value::Unit),
+                prod(&bit, &unit),
+                json!([true, null]),
+            ),
+        ];
+        for (value, ty, json) in cases {
+            assert!(value.is_of_type(&ty));
+            assert_eq!(value.to_compact_json(), json);
+            assert_eq!(Value::from_compact_json(&json, &ty), Ok(value));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let pow2 = types::pow2_types();
+        let mismatch = |json: serde_json::Value, ty: &Arc<FinalType>| {
+            assert_eq!(
+                Value::from_compact_json(&json, ty),
+                Err(JsonError::TypeMismatch {
+                    expected: ty.clone(),
+                    found: json,
+                })
+            );
+        };
+        mismatch(json!(true), &Arc::new(FinalType::unit()));
+        mismatch(json!([null]), &pow2[2]);
+        mismatch(json!({"u16": 1}), &pow2[4]);
+        mismatch(json!({"u8": "1"}), &pow2[4]);
+        mismatch(json!({"x": 1}), &pow2[4]);
+        // Errors name the part of the value which does not match
+        assert_eq!(
+            Value::from_compact_json(&json!([true, 1]), &pow2[2]),
+            Err(JsonError::TypeMismatch {
+                expected: pow2[1].clone(),
+                found: json!(1),
+            })
+        );
+
+        let err = Value::from_compact_json(&json!({"u8": 256}), &pow2[4]).unwrap_err();
+        assert_eq!(
+            err,
+            JsonError::OutOfRange {
+                width: 8,
+                value: 256,
+            }
+        );
+        assert_eq!(err.to_string(), "256 does not fit in a 8-bit word");
+    }
+}
@@ -13,6 +13,8 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+#[cfg(feature = "json")]
+pub mod json;
 /// Core Module for simplicity
 pub mod term;
 pub mod types;
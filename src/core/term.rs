@@ -2,6 +2,7 @@ use super::types;
 use crate::Error;
 use crate::{cmr, extension};
 use std::collections::HashMap;
+use std::error;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
@@ -74,6 +75,71 @@ pub enum DagTerm<Witness, Extension> {
     Jet(extension::jets::JetsNode),
 }
 
+impl<Witness, Extension> Term<Witness, Extension> {
+    /// The relative offsets of the left and right children of this node,
+    /// for those combinators which have them
+    pub fn child_offsets(&self) -> (Option<usize>, Option<usize>) {
+        match *self {
+            Term::Iden
+            | Term::Unit
+            | Term::Witness(..)
+            | Term::Fail(..)
+            | Term::Hidden(..)
+            | Term::Ext(..)
+            | Term::Jet(..) => (None, None),
+            Term::InjL(i) | Term::InjR(i) | Term::Take(i) | Term::Drop(i) => (Some(i), None),
+            Term::Comp(i, j) | Term::Case(i, j) | Term::Pair(i, j) | Term::Disconnect(i, j) => {
+                (Some(i), Some(j))
+            }
+        }
+    }
+
+    /// Replace each child reference of this node by `f` applied to it
+    pub fn map_children<F: Fn(usize) -> usize>(self, f: F) -> Self {
+        match self {
+            Term::InjL(i) => Term::InjL(f(i)),
+            Term::InjR(i) => Term::InjR(f(i)),
+            Term::Take(i) => Term::Take(f(i)),
+            Term::Drop(i) => Term::Drop(f(i)),
+            Term::Comp(i, j) => Term::Comp(f(i), f(j)),
+            Term::Case(i, j) => Term::Case(f(i), f(j)),
+            Term::Pair(i, j) => Term::Pair(f(i), f(j)),
+            Term::Disconnect(i, j) => Term::Disconnect(f(i), f(j)),
+            Term::Iden
+            | Term::Unit
+            | Term::Witness(..)
+            | Term::Fail(..)
+            | Term::Hidden(..)
+            | Term::Ext(..)
+            | Term::Jet(..) => self,
+        }
+    }
+}
+
+impl<Witness, Extension: fmt::Display> Term<Witness, Extension> {
+    /// Name of this node's combinator in the syntax of the `text` module,
+    /// with jets named `jet_<name>` and extension jets `ext_<name>`
+    pub fn name(&self) -> String {
+        match *self {
+            Term::Iden => "iden".to_owned(),
+            Term::Unit => "unit".to_owned(),
+            Term::InjL(..) => "injl".to_owned(),
+            Term::InjR(..) => "injr".to_owned(),
+            Term::Take(..) => "take".to_owned(),
+            Term::Drop(..) => "drop".to_owned(),
+            Term::Comp(..) => "comp".to_owned(),
+            Term::Case(..) => "case".to_owned(),
+            Term::Pair(..) => "pair".to_owned(),
+            Term::Disconnect(..) => "disconnect".to_owned(),
+            Term::Witness(..) => "witness".to_owned(),
+            Term::Fail(..) => "fail".to_owned(),
+            Term::Hidden(..) => "hidden".to_owned(),
+            Term::Ext(ref e) => format!("ext_{}", e),
+            Term::Jet(ref j) => format!("jet_{}", j),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct UnTypedProg<Witness, Extension>(pub Vec<Term<Witness, Extension>>);
 
@@ -251,6 +317,33 @@ where
     }
 }
 
+/// Error converting between values and their bit encodings
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueError {
+    /// The number of bits given did not match the width of the type
+    LengthMismatch {
+        /// Bit width of the type
+        expected: usize,
+        /// Number of bits given
+        found: usize,
+    },
+    /// The value does not have the given type
+    TypeMismatch,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueError::LengthMismatch { expected, found } => {
+                write!(f, "Expected {} bits, found {}", expected, found)
+            }
+            ValueError::TypeMismatch => f.write_str("Value does not have the given type"),
+        }
+    }
+}
+
+impl error::Error for ValueError {}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum Value {
     Unit,
@@ -352,6 +445,211 @@ impl Value {
         ret
     }
 
+    /// Iterate over the bits of the value, in the order they are encoded
+    /// in witness data (that is, without padding for sum types)
+    pub fn iter_bits(&self) -> ValueBitIter<'_> {
+        ValueBitIter { stack: vec![self] }
+    }
+
+    /// Whether two values have the same bit representation, even if
+    /// their structure (and so their types) differ, e.g. the 2-bit word
+    /// `(0, 1)` and `injl(injr(unit))`
+    pub fn eq_mod_type(&self, other: &Value) -> bool {
+        self.iter_bits().eq(other.iter_bits())
+    }
+
+    /// Whether two values, read as the given type, are the same value
+    ///
+    /// Each value's bit representation is decoded as `ty`, so values whose
+    /// structure differs from `ty` compare equal if they encode the same
+    /// value of it. A value whose bits are not exactly an encoding of a
+    /// value of `ty` is unequal to everything.
+    pub fn eq_as_type(&self, other: &Value, ty: &types::FinalType) -> bool {
+        let as_type = |value: &Value| {
+            let mut bits = value.iter_bits();
+            let ret = Value::from_bits_and_type(&mut bits, ty).ok();
+            if bits.next().is_some() {
+                None
+            } else {
+                ret
+            }
+        };
+        match (as_type(self), as_type(other)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Convert a word of type `source_ty` to the word type `target_ty`,
+    /// zero-extending it if the target is wider and keeping its low bits
+    /// if it is narrower
+    ///
+    /// Returns `None` if either type is not a word type (`2`, or the
+    /// product of two equal word types) or if the value does not have
+    /// type `source_ty`.
+    pub fn pad_to_type(
+        &self,
+        source_ty: &types::FinalType,
+        target_ty: &types::FinalType,
+    ) -> Option<Value> {
+        self.resize_word(source_ty, target_ty, false)
+    }
+
+    /// Convert a word of type `source_ty` to the word type `target_ty`,
+    /// as `pad_to_type` does, except that the word is taken to be a
+    /// two's complement signed integer and extended with its sign bit
+    pub fn sign_extend(
+        &self,
+        source_ty: &types::FinalType,
+        target_ty: &types::FinalType,
+    ) -> Option<Value> {
+        self.resize_word(source_ty, target_ty, true)
+    }
+
+    /// Common implementation of `pad_to_type` and `sign_extend`
+    fn resize_word(
+        &self,
+        source_ty: &types::FinalType,
+        target_ty: &types::FinalType,
+        signed: bool,
+    ) -> Option<Value> {
+        if !source_ty.is_word() || !target_ty.is_word() || !self.is_of_type(source_ty) {
+            return None;
+        }
+        let (from, to) = (source_ty.bit_width(), target_ty.bit_width());
+        let bits: Vec<bool> = self.iter_bits().collect();
+        let extension = signed && bits[0];
+        let mut bits = if to > from {
+            vec![extension; to - from].into_iter().chain(bits).collect()
+        } else {
+            bits[from - to..].to_vec()
+        }
+        .into_iter();
+        Some(Value::from_bits_and_type(&mut bits, target_ty).expect("width matches type"))
+    }
+
+    /// Number of bits in which the bit representations of two values
+    /// differ, or `None` if they have different lengths
+    pub fn hamming_distance(&self, other: &Value) -> Option<usize> {
+        if self.len() != other.len() {
+            return None;
+        }
+        Some(
+            self.iter_bits()
+                .zip(other.iter_bits())
+                .filter(|(a, b)| a != b)
+                .count(),
+        )
+    }
+
+    /// Whether the value, read as the given type, is zero
+    ///
+    /// Words are zero when all their bits are clear, unit is always zero
+    /// and products are zero when both halves are. Values of any other
+    /// sum type carry a tag and are never zero, and neither is a value
+    /// which is not of type `ty`.
+    pub fn is_zero(&self, ty: &types::FinalType) -> bool {
+        self.is_constant(ty, false)
+    }
+
+    /// Whether the value, read as the given type, has all its bits set
+    ///
+    /// This follows the same rules as `is_zero`, with words being
+    /// maximal when all their bits are set.
+    pub fn is_max(&self, ty: &types::FinalType) -> bool {
+        self.is_constant(ty, true)
+    }
+
+    /// Common implementation of `is_zero` and `is_max`
+    fn is_constant(&self, ty: &types::FinalType, bit: bool) -> bool {
+        if ty.is_word() {
+            return self.is_of_type(ty) && self.iter_bits().all(|b| b == bit);
+        }
+        match (self, &ty.ty) {
+            (Value::Unit, types::FinalTypeInner::Unit) => true,
+            (Value::Prod(a, b), types::FinalTypeInner::Product(l, r)) => {
+                a.is_constant(l, bit) && b.is_constant(r, bit)
+            }
+            _ => false,
+        }
+    }
+
+    /// Number of set bits in the value as it is laid out in a Bit Machine
+    /// frame of the given type
+    pub fn popcount(&self, ty: &types::FinalType) -> Result<usize, ValueError> {
+        Ok(self.iter_padded_bits(ty)?.filter(|&b| b).count())
+    }
+
+    /// Whether the value is a member of the given type
+    pub fn is_of_type(&self, ty: &types::FinalType) -> bool {
+        match (self, &ty.ty) {
+            (Value::Unit, types::FinalTypeInner::Unit) => true,
+            (Value::SumL(a), types::FinalTypeInner::Sum(l, _)) => a.is_of_type(l),
+            (Value::SumR(a), types::FinalTypeInner::Sum(_, r)) => a.is_of_type(r),
+            (Value::Prod(a, b), types::FinalTypeInner::Product(l, r)) => {
+                a.is_of_type(l) && b.is_of_type(r)
+            }
+            _ => false,
+        }
+    }
+
+    /// Iterate over the bits of the value as it is laid out in a Bit
+    /// Machine frame, where each sum value is padded out to the width of
+    /// its type. Padding bits are zero.
+    pub fn iter_padded_bits<'a>(
+        &'a self,
+        ty: &'a types::FinalType,
+    ) -> Result<PaddedBitIter<'a>, ValueError> {
+        if self.is_of_type(ty) {
+            Ok(PaddedBitIter {
+                stack: vec![PaddedItem::Value(self, ty)],
+            })
+        } else {
+            Err(ValueError::TypeMismatch)
+        }
+    }
+
+    /// Decode a value of the given type from exactly `ty.bit_width()` bits,
+    /// as they are laid out in a Bit Machine frame. This is the inverse of
+    /// `iter_padded_bits`.
+    pub fn from_padded_bits(bits: &[bool], ty: &types::FinalType) -> Result<Value, ValueError> {
+        if bits.len() != ty.bit_width() {
+            return Err(ValueError::LengthMismatch {
+                expected: ty.bit_width(),
+                found: bits.len(),
+            });
+        }
+        Ok(
+            Value::from_padded_bits_and_type(&mut bits.iter().cloned(), ty)
+                .expect("length matches type width"),
+        )
+    }
+
+    /// Append the bits of the value, which must have the given type, to
+    /// `out` as they are encoded in the witness section of a program:
+    /// without padding for sum types, unlike the layout of a Bit Machine
+    /// frame. This is the inverse of `decode_witness`.
+    pub fn encode_witness(
+        &self,
+        ty: &types::FinalType,
+        out: &mut Vec<bool>,
+    ) -> Result<(), ValueError> {
+        if !self.is_of_type(ty) {
+            return Err(ValueError::TypeMismatch);
+        }
+        out.extend(self.iter_bits());
+        Ok(())
+    }
+
+    /// Decode a value of the given type from the witness section of a
+    /// program, as written by `encode_witness`, consuming only its bits
+    pub fn decode_witness<Bits: Iterator<Item = bool>>(
+        bits: &mut Bits,
+        ty: &types::FinalType,
+    ) -> Result<Value, Error> {
+        Value::from_bits_and_type(bits, ty)
+    }
+
     /// Convenience constructor for a left sum of a value
     pub fn sum_l(a: Value) -> Value {
         Value::SumL(Box::new(a))
@@ -368,6 +666,82 @@ impl Value {
     }
 }
 
+/// Iterator over the bits of a value, created by `Value::iter_bits`
+pub struct ValueBitIter<'a> {
+    stack: Vec<&'a Value>,
+}
+
+impl<'a> Iterator for ValueBitIter<'a> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        loop {
+            match *self.stack.pop()? {
+                Value::Unit => {}
+                Value::SumL(ref l) => {
+                    self.stack.push(l);
+                    return Some(false);
+                }
+                Value::SumR(ref r) => {
+                    self.stack.push(r);
+                    return Some(true);
+                }
+                Value::Prod(ref l, ref r) => {
+                    self.stack.push(r);
+                    self.stack.push(l);
+                }
+            }
+        }
+    }
+}
+
+enum PaddedItem<'a> {
+    Value(&'a Value, &'a types::FinalType),
+    Padding(usize),
+}
+
+/// Iterator over the padded bits of a value, created by
+/// `Value::iter_padded_bits`
+pub struct PaddedBitIter<'a> {
+    stack: Vec<PaddedItem<'a>>,
+}
+
+impl<'a> Iterator for PaddedBitIter<'a> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        loop {
+            match self.stack.pop()? {
+                PaddedItem::Padding(0) => {}
+                PaddedItem::Padding(n) => {
+                    self.stack.push(PaddedItem::Padding(n - 1));
+                    return Some(false);
+                }
+                PaddedItem::Value(value, ty) => match (value, &ty.ty) {
+                    (Value::Unit, _) => {}
+                    (Value::SumL(a), types::FinalTypeInner::Sum(l, _)) => {
+                        let (padding, _) = ty.sum_padding().expect("sum type");
+                        self.stack.push(PaddedItem::Value(a, l));
+                        self.stack.push(PaddedItem::Padding(padding));
+                        return Some(false);
+                    }
+                    (Value::SumR(a), types::FinalTypeInner::Sum(_, r)) => {
+                        let (_, padding) = ty.sum_padding().expect("sum type");
+                        self.stack.push(PaddedItem::Value(a, r));
+                        self.stack.push(PaddedItem::Padding(padding));
+                        return Some(true);
+                    }
+                    (Value::Prod(a, b), types::FinalTypeInner::Product(l, r)) => {
+                        self.stack.push(PaddedItem::Value(b, r));
+                        self.stack.push(PaddedItem::Value(a, l));
+                    }
+                    _ => unreachable!("value was checked against its type"),
+                },
+            }
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -404,14 +778,367 @@ impl Value {
         match ty.ty {
             types::FinalTypeInner::Unit => Ok(Value::Unit),
             types::FinalTypeInner::Sum(ref l, ref r) => match bits.next() {
-                Some(false) => Ok(Value::SumL(Box::new(Value::from_bits_and_type(bits, &*l)?))),
-                Some(true) => Ok(Value::SumR(Box::new(Value::from_bits_and_type(bits, &*r)?))),
+                Some(false) => Ok(Value::SumL(Box::new(Value::from_bits_and_type(bits, l)?))),
+                Some(true) => Ok(Value::SumR(Box::new(Value::from_bits_and_type(bits, r)?))),
                 None => Err(Error::EndOfStream),
             },
             types::FinalTypeInner::Product(ref l, ref r) => Ok(Value::Prod(
-                Box::new(Value::from_bits_and_type(&mut *bits, &*l)?),
-                Box::new(Value::from_bits_and_type(bits, &*r)?),
+                Box::new(Value::from_bits_and_type(&mut *bits, l)?),
+                Box::new(Value::from_bits_and_type(bits, r)?),
+            )),
+        }
+    }
+
+    /// Decode a value of the given type from the contents of a Bit Machine
+    /// frame, where each sum value is padded out to the width of its type
+    /// by padding bits between the tag and the data of the narrower arm
+    pub fn from_padded_bits_and_type<Bits: Iterator<Item = bool>>(
+        bits: &mut Bits,
+        ty: &types::FinalType,
+    ) -> Result<Value, Error> {
+        match ty.ty {
+            types::FinalTypeInner::Unit => Ok(Value::Unit),
+            types::FinalTypeInner::Sum(ref l, ref r) => {
+                let (l_padding, r_padding) = ty.sum_padding().expect("sum type");
+                let (arm, is_right, padding) = match bits.next() {
+                    Some(false) => (l, false, l_padding),
+                    Some(true) => (r, true, r_padding),
+                    None => return Err(Error::EndOfStream),
+                };
+                for _ in 0..padding {
+                    if bits.next().is_none() {
+                        return Err(Error::EndOfStream);
+                    }
+                }
+                let value = Box::new(Value::from_padded_bits_and_type(bits, arm)?);
+                if is_right {
+                    Ok(Value::SumR(value))
+                } else {
+                    Ok(Value::SumL(value))
+                }
+            }
+            types::FinalTypeInner::Product(ref l, ref r) => Ok(Value::Prod(
+                Box::new(Value::from_padded_bits_and_type(&mut *bits, l)?),
+                Box::new(Value::from_padded_bits_and_type(bits, r)?),
             )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::FinalType;
+    use std::sync::Arc;
+
+    #[test]
+    fn name() {
+        use crate::extension::dummy::DummyNode;
+        use crate::extension::jets::JetsNode;
+
+        assert_eq!(Term::<(), DummyNode>::Comp(2, 1).name(), "comp");
+        assert_eq!(Term::<(), DummyNode>::Witness(()).name(), "witness");
+        assert_eq!(
+            Term::<(), DummyNode>::Jet(JetsNode::Adder32).name(),
+            "jet_adder32"
+        );
+    }
+
+    #[test]
+    fn witness_encoding() {
+        let unit = Arc::new(FinalType::unit());
+        let bit = Arc::new(FinalType::sum(unit.clone(), unit.clone()));
+        let word2 = Arc::new(FinalType::prod(bit.clone(), bit.clone()));
+        // 1 + 2^2 and (1 + 2^2) × (2 + 1): sums with arms of unequal widths
+        let option = Arc::new(FinalType::sum(unit.clone(), word2.clone()));
+        let wide_left = Arc::new(FinalType::sum(bit.clone(), unit.clone()));
+        let pair = FinalType::prod(option.clone(), wide_left);
+
+        let cases = vec![
+            (Value::Unit, FinalType::unit(), vec![]),
+            (Value::u2(2), (*word2).clone(), vec![true, false]),
+            (Value::sum_l(Value::Unit), (*option).clone(), vec![false]),
+            (
+                Value::sum_r(Value::u2(1)),
+                (*option).clone(),
+                vec![true, false, true],
+            ),
+            (
+                Value::prod(Value::sum_l(Value::Unit), Value::sum_r(Value::Unit)),
+                pair.clone(),
+                vec![false, true],
+            ),
+            (
+                Value::prod(Value::sum_r(Value::u2(3)), Value::sum_l(Value::u1(0))),
+                pair.clone(),
+                vec![true, true, true, false, false],
+            ),
+        ];
+        for (value, ty, expected) in cases {
+            let mut bits = vec![true];
+            value.encode_witness(&ty, &mut bits).unwrap();
+            // Bits are appended, without padding
+            assert!(bits[0]);
+            assert_eq!(bits[1..], expected[..]);
+
+            // Decoding stops after the value's bits
+            bits.push(false);
+            let mut iter = bits[1..].iter().cloned();
+            assert_eq!(Value::decode_witness(&mut iter, &ty).unwrap(), value);
+            assert_eq!(iter.collect::<Vec<_>>(), vec![false]);
+        }
+
+        let mut bits = vec![];
+        assert_eq!(
+            Value::u2(0).encode_witness(&option, &mut bits),
+            Err(ValueError::TypeMismatch)
+        );
+        assert!(bits.is_empty());
+        assert!(Value::decode_witness(&mut vec![true, false].into_iter(), &option).is_err());
+    }
+
+    #[test]
+    fn padded_sum_decoding() {
+        let unit = Arc::new(FinalType::unit());
+        let bit = Arc::new(FinalType::sum(unit.clone(), unit.clone()));
+        // (2 + 1) × 2: the right arm of the sum has one bit of padding
+        let wide_left = Arc::new(FinalType::sum(bit.clone(), unit.clone()));
+        let ty = FinalType::prod(wide_left, bit.clone());
+        assert_eq!(ty.bit_width(), 3);
+
+        let bits = [true, false, true];
+        let value = Value::from_padded_bits_and_type(&mut bits.iter().cloned(), &ty).unwrap();
+        assert_eq!(value, Value::prod(Value::sum_r(Value::Unit), Value::u1(1)));
+        // Reading the padding as data gives the wrong value
+        let compact = Value::from_bits_and_type(&mut bits.iter().cloned(), &ty).unwrap();
+        assert_ne!(compact, value);
+
+        let bits = [false, true, true];
+        let value = Value::from_padded_bits_and_type(&mut bits.iter().cloned(), &ty).unwrap();
+        assert_eq!(value, Value::prod(Value::sum_l(Value::u1(1)), Value::u1(1)));
+
+        // 1 + 2^2: the left arm is padded, and padding precedes the data
+        let word2 = Arc::new(FinalType::prod(bit.clone(), bit));
+        let ty = FinalType::prod(Arc::new(FinalType::sum(unit, word2)), Arc::new(ty));
+        let bits = [false, true, true, false, true, true];
+        let value = Value::from_padded_bits_and_type(&mut bits.iter().cloned(), &ty).unwrap();
+        assert_eq!(
+            value,
+            Value::prod(
+                Value::sum_l(Value::Unit),
+                Value::prod(Value::sum_l(Value::u1(1)), Value::u1(1)),
+            )
+        );
+        let bits = [true, true, false, false, true, true];
+        let value = Value::from_padded_bits_and_type(&mut bits.iter().cloned(), &ty).unwrap();
+        assert_eq!(
+            value,
+            Value::prod(
+                Value::sum_r(Value::u2(2)),
+                Value::prod(Value::sum_l(Value::u1(1)), Value::u1(1)),
+            )
+        );
+
+        // Truncated padding
+        assert_matches!(
+            Value::from_padded_bits_and_type(&mut [false, true].iter().cloned(), &ty),
+            Err(Error::EndOfStream)
+        );
+    }
+
+    /// All values of a given type
+    fn all_values(ty: &FinalType) -> Vec<Value> {
+        match ty.ty {
+            types::FinalTypeInner::Unit => vec![Value::Unit],
+            types::FinalTypeInner::Sum(ref l, ref r) => {
+                let mut ret: Vec<_> = all_values(l).into_iter().map(Value::sum_l).collect();
+                ret.extend(all_values(r).into_iter().map(Value::sum_r));
+                ret
+            }
+            types::FinalTypeInner::Product(ref l, ref r) => {
+                let mut ret = vec![];
+                for a in all_values(l) {
+                    for b in all_values(r) {
+                        ret.push(Value::prod(a.clone(), b));
+                    }
+                }
+                ret
+            }
+        }
+    }
+
+    #[test]
+    fn padded_bits_roundtrip() {
+        let unit = Arc::new(FinalType::unit());
+        let bit = Arc::new(FinalType::sum(unit.clone(), unit.clone()));
+        let word2 = Arc::new(FinalType::prod(bit.clone(), bit.clone()));
+        // (2 + (1 + 2^2)) × (1 + 2): nested sums with padding on both sides
+        let inner = Arc::new(FinalType::sum(unit.clone(), word2));
+        let left = Arc::new(FinalType::sum(bit.clone(), inner));
+        let right = Arc::new(FinalType::sum(unit, bit));
+        let ty = FinalType::prod(left, right);
+        assert_eq!(ty.bit_width(), 6);
+
+        let values = all_values(&ty);
+        assert_eq!(values.len(), 21);
+        for value in values {
+            let bits: Vec<bool> = value.iter_padded_bits(&ty).unwrap().collect();
+            assert_eq!(bits.len(), ty.bit_width());
+            assert_eq!(Value::from_padded_bits(&bits, &ty), Ok(value));
+        }
+
+        assert_eq!(
+            Value::from_padded_bits(&[false; 5], &ty),
+            Err(ValueError::LengthMismatch {
+                expected: 6,
+                found: 5,
+            })
+        );
+        assert_eq!(
+            Value::from_padded_bits(&[false; 7], &ty),
+            Err(ValueError::LengthMismatch {
+                expected: 6,
+                found: 7,
+            })
+        );
+        assert!(Value::u8(0).iter_padded_bits(&ty).is_err());
+    }
+
+    #[test]
+    fn eq_mod_type() {
+        let word = Value::u2(1);
+        let nested = Value::sum_l(Value::sum_r(Value::Unit));
+        assert_ne!(word, nested);
+        assert!(word.eq_mod_type(&nested));
+        assert!(!word.eq_mod_type(&Value::sum_r(Value::sum_l(Value::Unit))));
+        assert!(!word.eq_mod_type(&Value::u1(0)));
+
+        assert_eq!(word.hamming_distance(&nested), Some(0));
+        assert_eq!(Value::u8(0x0f).hamming_distance(&Value::u8(0xf1)), Some(7));
+        assert_eq!(word.hamming_distance(&Value::u4(1)), None);
+    }
+
+    #[test]
+    fn pad_to_type() {
+        let pow2 = types::pow2_types();
+        let (word8, word32, word64) = (&pow2[4], &pow2[6], &pow2[7]);
+        assert_eq!(
+            Value::u32(42).pad_to_type(word32, word64),
+            Some(Value::u64(42))
+        );
+        assert_eq!(
+            Value::u32(0xffff_fffe).pad_to_type(word32, word64),
+            Some(Value::u64(0xffff_fffe))
+        );
+        assert_eq!(
+            Value::u32(0x1234_5678).pad_to_type(word32, word8),
+            Some(Value::u8(0x78))
+        );
+        assert_eq!(
+            Value::u32(42).pad_to_type(word32, word32),
+            Some(Value::u32(42))
+        );
+
+        // -2 and 42, as signed integers
+        assert_eq!(
+            Value::u32(0xffff_fffe).sign_extend(word32, word64),
+            Some(Value::u64(0xffff_ffff_ffff_fffe))
+        );
+        assert_eq!(
+            Value::u32(42).sign_extend(word32, word64),
+            Some(Value::u64(42))
+        );
+        assert_eq!(
+            Value::u8(0x80).sign_extend(word8, word32),
+            Some(Value::u32(0xffff_ff80))
+        );
+        assert_eq!(
+            Value::u32(0xffff_fffe).sign_extend(word32, word8),
+            Some(Value::u8(0xfe))
+        );
+
+        // Not a word type, or not a value of the source type
+        let unit = Arc::new(FinalType::unit());
+        let not_word = FinalType::sum(unit.clone(), word8.clone());
+        assert_eq!(Value::u8(1).pad_to_type(word8, &not_word), None);
+        assert_eq!(Value::u8(1).pad_to_type(&unit, word32), None);
+        assert_eq!(Value::u8(1).pad_to_type(word32, word64), None);
+    }
+
+    #[test]
+    fn eq_as_type() {
+        let unit = Arc::new(FinalType::unit());
+        let bit = Arc::new(FinalType::sum(unit.clone(), unit.clone()));
+        let word2 = FinalType::prod(bit.clone(), bit.clone());
+
+        // A 2-bit word built as nested sums rather than a product of bits
+        let word = Value::u2(1);
+        let nested = Value::sum_l(Value::sum_r(Value::Unit));
+        assert_ne!(word, nested);
+        assert!(word.eq_as_type(&nested, &word2));
+        assert!(nested.eq_as_type(&word, &word2));
+        assert!(!word.eq_as_type(&Value::u2(2), &word2));
+
+        // 2 × 2^2 as a 3-bit value whose left bit is boxed differently
+        let wide = FinalType::prod(bit, Arc::new(word2.clone()));
+        let a = Value::prod(Value::u1(1), Value::u2(3));
+        let b = Value::prod(Value::prod(Value::Unit, Value::u1(1)), Value::u2(3));
+        assert!(a.eq_as_type(&b, &wide));
+
+        // Bits which are too short or too long for the type
+        assert!(!word.eq_as_type(&Value::u1(0), &word2));
+        assert!(!Value::u4(1).eq_as_type(&Value::u4(1), &word2));
+        // The unit type has only one value
+        assert!(Value::Unit.eq_as_type(&Value::Unit, &FinalType::unit()));
+        assert!(!Value::u1(0).eq_as_type(&Value::Unit, &FinalType::unit()));
+    }
+
+    #[test]
+    fn zero_max_popcount() {
+        let pow2 = types::pow2_types();
+        for (k, ty) in pow2.iter().enumerate().skip(1) {
+            let width = 1 << (k - 1);
+            let word =
+                |bits: &[bool]| Value::from_bits_and_type(&mut bits.iter().cloned(), ty).unwrap();
+            let zero = word(&vec![false; width]);
+            let max = word(&vec![true; width]);
+            let mut one_bits = vec![false; width];
+            one_bits[width - 1] = true;
+            let one = word(&one_bits);
+
+            assert!(zero.is_zero(ty), "width {}", width);
+            assert!(!zero.is_max(ty), "width {}", width);
+            assert_eq!(zero.popcount(ty), Ok(0));
+            assert!(max.is_max(ty), "width {}", width);
+            assert!(!max.is_zero(ty), "width {}", width);
+            assert_eq!(max.popcount(ty), Ok(width));
+            assert!(!one.is_zero(ty), "width {}", width);
+            assert_eq!(one.is_max(ty), width == 1);
+            assert_eq!(one.popcount(ty), Ok(1));
+        }
+        let (word8, word32) = (&pow2[4], &pow2[6]);
+        assert_eq!(Value::u8(0xa5).popcount(word8), Ok(4));
+        assert_eq!(Value::u32(0x8000_0001).popcount(word32), Ok(2));
+        // Values not of the given type are neither zero nor maximal, and
+        // have no popcount
+        assert!(!Value::u8(0).is_zero(word32));
+        assert!(!Value::u8(0xff).is_max(word32));
+        assert_eq!(Value::u8(0).popcount(word32), Err(ValueError::TypeMismatch));
+
+        // Unit is zero; products of zeros are zero; other sums never are
+        let unit = Arc::new(FinalType::unit());
+        assert!(Value::Unit.is_zero(&unit));
+        assert!(Value::Unit.is_max(&unit));
+        let pair = FinalType::prod(word8.clone(), unit.clone());
+        assert!(Value::prod(Value::u8(0), Value::Unit).is_zero(&pair));
+        assert!(Value::prod(Value::u8(0xff), Value::Unit).is_max(&pair));
+        assert!(!Value::prod(Value::u8(1), Value::Unit).is_zero(&pair));
+        let option = FinalType::sum(unit.clone(), word8.clone());
+        assert!(!Value::sum_l(Value::Unit).is_zero(&option));
+        assert!(!Value::sum_r(Value::u8(0)).is_zero(&option));
+        assert_eq!(Value::sum_r(Value::u8(0x03)).popcount(&option), Ok(3));
+        // Padding bits are never set
+        let padded = FinalType::sum(unit, word8.clone());
+        assert_eq!(Value::sum_l(Value::Unit).popcount(&padded), Ok(0));
+    }
+}
@@ -1,8 +1,10 @@
 //FIXME: Remove this later
 #![allow(dead_code)]
 
+use std::collections::HashMap;
 use std::{cell::RefCell, cmp, fmt, mem, rc::Rc, sync::Arc};
 
+use crate::cmr::DISCONNECT_CMR_BITS;
 use crate::extension;
 use crate::extension::Jet as ExtNode;
 use crate::Error;
@@ -37,21 +39,21 @@ pub struct FinalType {
 }
 
 impl FinalType {
-    const fn unit() -> Self {
+    pub(crate) const fn unit() -> Self {
         Self {
             ty: FinalTypeInner::Unit,
             bit_width: 0,
         }
     }
 
-    fn sum(a: Arc<Self>, b: Arc<Self>) -> Self {
+    pub(crate) fn sum(a: Arc<Self>, b: Arc<Self>) -> Self {
         Self {
             ty: FinalTypeInner::Sum(a.clone(), b.clone()),
             bit_width: 1 + cmp::max(a.bit_width, b.bit_width),
         }
     }
 
-    fn prod(a: Arc<Self>, b: Arc<Self>) -> Self {
+    pub(crate) fn prod(a: Arc<Self>, b: Arc<Self>) -> Self {
         Self {
             ty: FinalTypeInner::Product(a.clone(), b.clone()),
             bit_width: a.bit_width + b.bit_width,
@@ -59,6 +61,31 @@ impl FinalType {
     }
 }
 
+/// The type with the given name, in the notation of `extension::TypeName`
+pub(crate) fn final_type_from_name(name: &extension::TypeName) -> Arc<FinalType> {
+    fn from_name<I: Iterator<Item = u8>>(n: &mut I, pow2s: &[Arc<FinalType>]) -> Arc<FinalType> {
+        match n.next() {
+            Some(b'1') => pow2s[0].clone(),
+            Some(b'2') => pow2s[1].clone(),
+            Some(b'c') => pow2s[4].clone(),
+            Some(b'i') => pow2s[6].clone(),
+            Some(b'l') => pow2s[7].clone(),
+            Some(b'h') => pow2s[9].clone(),
+            Some(b'+') => {
+                let left = from_name(&mut *n, pow2s);
+                Arc::new(FinalType::sum(left, from_name(&mut *n, pow2s)))
+            }
+            Some(b'*') => {
+                let left = from_name(&mut *n, pow2s);
+                Arc::new(FinalType::prod(left, from_name(&mut *n, pow2s)))
+            }
+            Some(x) => panic!("Do not understand byte {} in type name", x as char),
+            None => panic!("unexpected end of string parsing type"),
+        }
+    }
+    from_name(&mut name.0.iter().cloned(), &pow2_types())
+}
+
 pub(crate) fn pow2_types() -> [Arc<FinalType>; 11] {
     let word0 = Arc::new(FinalType::unit());
     let word1 = Arc::new(FinalType::sum(Arc::clone(&word0), Arc::clone(&word0)));
@@ -116,6 +143,34 @@ impl FinalType {
         self.bit_width
     }
 
+    /// Whether this is a word type: `2`, or the product of two equal
+    /// word types
+    pub fn is_word(&self) -> bool {
+        match self.ty {
+            FinalTypeInner::Sum(ref a, ref b) => {
+                a.ty == FinalTypeInner::Unit && b.ty == FinalTypeInner::Unit
+            }
+            FinalTypeInner::Product(ref a, ref b) => a == b && a.is_word(),
+            FinalTypeInner::Unit => false,
+        }
+    }
+
+    /// Number of padding bits between the tag of a sum of this type and
+    /// a value of its left or right arm, in that order, or `None` if this
+    /// is not a sum type
+    ///
+    /// Computed from the widths of the arms alone, so either may be zero
+    /// without any subtraction overflowing.
+    pub fn sum_padding(&self) -> Option<(usize, usize)> {
+        match self.ty {
+            FinalTypeInner::Sum(ref a, ref b) => {
+                let width = cmp::max(a.bit_width, b.bit_width);
+                Some((width - a.bit_width, width - b.bit_width))
+            }
+            _ => None,
+        }
+    }
+
     fn from_var(var: RcVar) -> Result<Arc<FinalType>, Error> {
         let var = find_root(var);
         let mut var_borr = var.borrow_mut();
@@ -361,6 +416,46 @@ fn unify(mut alpha: RcVar, mut beta: RcVar) -> Result<(), Error> {
     Ok(())
 }
 
+/// The type which a variable is bound to, if it is already fully
+/// determined, without finalizing it as `FinalType::from_var` does
+///
+/// `memo` caches the result for each representative variable; variables
+/// which are part of a cycle, and so will fail the occurs check, are not
+/// determined.
+fn determined_type(
+    var: &RcVar,
+    memo: &mut HashMap<*const RefCell<UnificationVar>, Option<Arc<FinalType>>>,
+) -> Option<Arc<FinalType>> {
+    let var = find_root(var.clone());
+    let key = &*var as *const RefCell<UnificationVar>;
+    if let Some(ty) = memo.get(&key) {
+        return ty.clone();
+    }
+    memo.insert(key, None);
+
+    let bound = var.borrow().var.clone();
+    let ty = match bound {
+        Variable::Free => None,
+        Variable::Bound(Type::Unit, _) => Some(Arc::new(FinalType::unit())),
+        Variable::Bound(Type::Sum(a, b), _) => {
+            match (determined_type(&a, memo), determined_type(&b, memo)) {
+                (Some(a), Some(b)) => Some(Arc::new(FinalType::sum(a, b))),
+                _ => None,
+            }
+        }
+        Variable::Bound(Type::Product(a, b), _) => {
+            match (determined_type(&a, memo), determined_type(&b, memo)) {
+                (Some(a), Some(b)) => Some(Arc::new(FinalType::prod(a, b))),
+                _ => None,
+            }
+        }
+        Variable::EqualTo(..) => unreachable!(),
+        Variable::Finalized(ty) => Some(ty),
+    };
+    memo.insert(key, ty.clone());
+    ty
+}
+
 #[derive(Clone)]
 struct UnificationArrow {
     source: Rc<RefCell<UnificationVar>>,
@@ -383,6 +478,7 @@ fn type_from_name<I: Iterator<Item = u8>>(n: &mut I, pow2s: &[RcVar]) -> Type {
             let unit = Type::Unit.into_rcvar();
             Type::Sum(unit.clone(), unit)
         }
+        Some(b'c') => Type::Product(pow2s[2].clone(), pow2s[2].clone()),
         Some(b'i') => Type::Product(pow2s[4].clone(), pow2s[4].clone()),
         Some(b'l') => Type::Product(pow2s[5].clone(), pow2s[5].clone()),
         Some(b'h') => Type::Product(pow2s[7].clone(), pow2s[7].clone()),
@@ -399,35 +495,54 @@ fn type_from_name<I: Iterator<Item = u8>>(n: &mut I, pow2s: &[RcVar]) -> Type {
     }
 }
 
-/// Attach types to all nodes in a program
-pub fn type_check<Witness, Ext: extension::Jet>(
-    program: UnTypedProg<Witness, Ext>,
-) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
-    let vec_nodes = program.0;
-    if vec_nodes.is_empty() {
-        return Ok(vec![]);
+/// Unification state for a program whose nodes are added one at a time,
+/// in order. Each node may only reference nodes which were added before it.
+pub(crate) struct TypeInference {
+    /// `pow2s[i]` = 2^(2^i)
+    pow2s: [RcVar; 9],
+    /// Source and target unification variables of every node so far
+    rcs: Vec<Rc<UnificationArrow>>,
+    /// Whether each node so far is `Hidden`, which `Case` needs to know
+    hidden: Vec<bool>,
+}
+
+impl TypeInference {
+    /// Create a new inference state with no nodes
+    pub(crate) fn new() -> TypeInference {
+        let two_0 = Type::Unit.into_rcvar();
+        let two_1 = Type::Sum(two_0.clone(), two_0).into_rcvar();
+        let two_2 = Type::Product(two_1.clone(), two_1.clone()).into_rcvar();
+        let two_4 = Type::Product(two_2.clone(), two_2.clone()).into_rcvar();
+        let two_8 = Type::Product(two_4.clone(), two_4.clone()).into_rcvar();
+        let two_16 = Type::Product(two_8.clone(), two_8.clone()).into_rcvar();
+        let two_32 = Type::Product(two_16.clone(), two_16.clone()).into_rcvar();
+        let two_64 = Type::Product(two_32.clone(), two_32.clone()).into_rcvar();
+        let two_128 = Type::Product(two_64.clone(), two_64.clone()).into_rcvar();
+        let two_256 = Type::Product(two_128.clone(), two_128.clone()).into_rcvar();
+
+        TypeInference {
+            pow2s: [
+                two_1, two_2, two_4, two_8, two_16, two_32, two_64, two_128, two_256,
+            ],
+            rcs: vec![],
+            hidden: vec![],
+        }
     }
 
-    let two_0 = Type::Unit.into_rcvar();
-    let two_1 = Type::Sum(two_0.clone(), two_0).into_rcvar();
-    let two_2 = Type::Product(two_1.clone(), two_1.clone()).into_rcvar();
-    let two_4 = Type::Product(two_2.clone(), two_2.clone()).into_rcvar();
-    let two_8 = Type::Product(two_4.clone(), two_4.clone()).into_rcvar();
-    let two_16 = Type::Product(two_8.clone(), two_8.clone()).into_rcvar();
-    let two_32 = Type::Product(two_16.clone(), two_16.clone()).into_rcvar();
-    let two_64 = Type::Product(two_32.clone(), two_32.clone()).into_rcvar();
-    let two_128 = Type::Product(two_64.clone(), two_64.clone()).into_rcvar();
-    let two_256 = Type::Product(two_128.clone(), two_128.clone()).into_rcvar();
-    // pow2s[i] = 2^(2^i)
-    let pow2s = [
-        two_1, two_2, two_4, two_8, two_16, two_32, two_64, two_128, two_256,
-    ];
-
-    let mut rcs = Vec::<Rc<UnificationArrow>>::with_capacity(vec_nodes.len());
-    let mut finals = Vec::<TypedNode<Witness, Ext>>::with_capacity(vec_nodes.len());
+    /// Number of nodes which have been added so far
+    pub(crate) fn len(&self) -> usize {
+        self.rcs.len()
+    }
 
-    // Compute most general unifier for all types in the DAG
-    for (idx, program_node) in vec_nodes.iter().enumerate() {
+    /// Add the next node of the program, unifying its types with those of
+    /// the nodes it refers to
+    pub(crate) fn add_node<Witness, Ext: extension::Jet>(
+        &mut self,
+        program_node: &Term<Witness, Ext>,
+    ) -> Result<(), Error> {
+        let idx = self.rcs.len();
+        let rcs = &self.rcs;
+        let pow2s = &self.pow2s;
         let node = UnificationArrow {
             source: Rc::new(RefCell::new(UnificationVar::free())),
             target: Rc::new(RefCell::new(UnificationVar::free())),
@@ -490,16 +605,14 @@ pub fn type_check<Witness, Ext: extension::Jet>(
 
                 let source_ty = Type::Product(sum12_var, var3.clone());
                 bind(&node.source, source_ty)?;
-                if let Term::Hidden(..) = vec_nodes[i] {
-                } else {
+                if !self.hidden[i] {
                     bind(
                         &find_root(rcs[i].source.clone()),
                         Type::Product(var1.clone(), var3.clone()),
                     )?;
                     unify(node.target.clone(), rcs[i].target.clone())?;
                 }
-                if let Term::Hidden(..) = vec_nodes[j] {
-                } else {
+                if !self.hidden[j] {
                     bind(
                         &find_root(rcs[j].source.clone()),
                         Type::Product(var2.clone(), var3.clone()),
@@ -525,9 +638,14 @@ pub fn type_check<Witness, Ext: extension::Jet>(
                 let var_c = Rc::new(RefCell::new(UnificationVar::free()));
                 let var_d = Rc::new(RefCell::new(UnificationVar::free()));
 
-                let s_source = Type::Product(pow2s[8].clone(), var_a.clone()).into_rcvar();
+                // `pow2s[i]` has width 2^i
+                let cmr_ty = pow2s[DISCONNECT_CMR_BITS.trailing_zeros() as usize].clone();
+                let s_source = Type::Product(cmr_ty, var_a.clone()).into_rcvar();
                 let s_target = Type::Product(var_b.clone(), var_c.clone()).into_rcvar();
-                unify(rcs[i].source.clone(), s_source)?;
+                unify(rcs[i].source.clone(), s_source).map_err(|e| match e {
+                    Error::TypeCheck => Error::DisconnectShape(idx),
+                    e => e,
+                })?;
                 unify(rcs[i].target.clone(), s_target)?;
 
                 let node_target = Type::Product(var_b, var_d.clone()).into_rcvar();
@@ -567,18 +685,201 @@ pub fn type_check<Witness, Ext: extension::Jet>(
             Term::Fail(..) => unimplemented!("Cannot typecheck a program with `Fail` in it"),
         };
 
-        rcs.push(Rc::new(node));
+        self.rcs.push(Rc::new(node));
+        self.hidden.push(match program_node {
+            Term::Hidden(..) => true,
+            _ => false,
+        });
+        Ok(())
     }
 
-    // Finalize, setting all unconstrained types to `Unit` and doing the
-    // occurs check. (All the magic happens inside `FinalType::from_var`.)
-    for (idx, node) in vec_nodes.into_iter().enumerate() {
-        finals.push(TypedNode {
-            node: node,
-            source_ty: FinalType::from_var(rcs[idx].source.clone())?,
-            target_ty: FinalType::from_var(rcs[idx].target.clone())?,
-        });
+    /// Finalize, setting all unconstrained types to `Unit` and doing the
+    /// occurs check. (All the magic happens inside `FinalType::from_var`.)
+    ///
+    /// The given nodes must be the ones which were added, in order.
+    pub(crate) fn finalize<Witness, Ext>(
+        self,
+        vec_nodes: Vec<Term<Witness, Ext>>,
+    ) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
+        assert_eq!(vec_nodes.len(), self.rcs.len());
+
+        let mut finals = Vec::<TypedNode<Witness, Ext>>::with_capacity(vec_nodes.len());
+        for (idx, node) in vec_nodes.into_iter().enumerate() {
+            finals.push(TypedNode {
+                node: node,
+                source_ty: FinalType::from_var(self.rcs[idx].source.clone())?,
+                target_ty: FinalType::from_var(self.rcs[idx].target.clone())?,
+            });
+        }
+
+        Ok(finals)
+    }
+}
+
+/// Type inference for a program whose nodes are added one at a time, in
+/// order, so that a node which does not typecheck is reported as soon as
+/// it is added rather than once the whole program is known
+pub struct Typechecker<Witness, Ext> {
+    inference: TypeInference,
+    nodes: Vec<Term<Witness, Ext>>,
+}
+
+impl<Witness, Ext: extension::Jet> Default for Typechecker<Witness, Ext> {
+    fn default() -> Self {
+        Typechecker::new()
+    }
+}
+
+impl<Witness, Ext: extension::Jet> Typechecker<Witness, Ext> {
+    /// Create a typechecker with no nodes
+    pub fn new() -> Self {
+        Typechecker {
+            inference: TypeInference::new(),
+            nodes: vec![],
+        }
+    }
+
+    /// Number of nodes which have been added so far
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no nodes have been added yet
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add the next node, unifying its types with those of the earlier
+    /// nodes it refers to
+    ///
+    /// If this fails, the node is not added and the earlier nodes keep
+    /// their types, so another node may be tried in its place. Recursive
+    /// types are only detected by `solve`.
+    pub fn add_node(&mut self, node: Term<Witness, Ext>) -> Result<(), Error> {
+        let idx = self.nodes.len();
+        let (left, right) = node.child_offsets();
+        if left
+            .into_iter()
+            .chain(right)
+            .any(|off| off == 0 || off > idx)
+        {
+            return Err(Error::BadIndex);
+        }
+
+        if let Err(e) = self.inference.add_node(&node) {
+            // Unification may have bound variables of earlier nodes before
+            // failing, so start again from the nodes which were accepted
+            let mut inference = TypeInference::new();
+            for node in &self.nodes {
+                inference.add_node(node).expect("node was accepted before");
+            }
+            self.inference = inference;
+            return Err(e);
+        }
+        self.nodes.push(node);
+        Ok(())
     }
 
-    Ok(finals)
+    /// The source and target types of the node at `index`, if both are
+    /// already fully determined by the nodes added so far
+    ///
+    /// Unlike `solve`, this does not set undetermined types to unit, as
+    /// later nodes may still constrain them.
+    pub fn node_type(&self, index: usize) -> Option<(Arc<FinalType>, Arc<FinalType>)> {
+        let arrow = self.inference.rcs.get(index)?;
+        let mut memo = HashMap::new();
+        let source = determined_type(&arrow.source, &mut memo)?;
+        let target = determined_type(&arrow.target, &mut memo)?;
+        Some((source, target))
+    }
+
+    /// Finalize the types of all nodes, setting any which are still
+    /// undetermined to unit
+    pub fn solve(self) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
+        self.inference.finalize(self.nodes)
+    }
+}
+
+/// Attach types to all nodes in a program
+pub fn type_check<Witness, Ext: extension::Jet>(
+    program: UnTypedProg<Witness, Ext>,
+) -> Result<Vec<TypedNode<Witness, Ext>>, Error> {
+    let vec_nodes = program.0;
+    if vec_nodes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Compute most general unifier for all types in the DAG
+    let mut inference = TypeInference::new();
+    for program_node in &vec_nodes {
+        inference.add_node(program_node)?;
+    }
+
+    inference.finalize(vec_nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::dummy::DummyNode;
+    use crate::extension::jets::JetsNode;
+
+    #[test]
+    fn typechecker() {
+        let pow2 = pow2_types();
+        let mut tc = Typechecker::<(), DummyNode>::new();
+        tc.add_node(Term::Unit).unwrap();
+        tc.add_node(Term::Jet(JetsNode::Adder32)).unwrap();
+        let adder_target = Arc::new(FinalType::prod(pow2[1].clone(), pow2[6].clone()));
+        assert_eq!(
+            tc.node_type(1),
+            Some((pow2[7].clone(), adder_target.clone()))
+        );
+        // The source of `unit` is not known yet
+        assert_eq!(tc.node_type(0), None);
+        assert_eq!(tc.node_type(2), None);
+
+        // `case unit adder32` fails only after binding the source of `unit`
+        assert_matches!(tc.add_node(Term::Case(2, 1)), Err(Error::TypeCheck));
+        assert_matches!(tc.add_node(Term::Take(3)), Err(Error::BadIndex));
+        assert_eq!(tc.len(), 2);
+
+        // Neither failure affected the types of the nodes which were added
+        tc.add_node(Term::Take(2)).unwrap();
+        let nodes = vec![Term::Unit, Term::Jet(JetsNode::Adder32), Term::Take(2)];
+        let expected = type_check(UnTypedProg(nodes)).unwrap();
+        let typed = tc.solve().unwrap();
+        assert_eq!(typed, expected);
+        assert_eq!(typed[0].source_ty, pow2[0]);
+        assert_eq!(typed[1].target_ty, adder_target);
+    }
+
+    #[test]
+    fn disconnect_shape() {
+        // disconnect adder32 unit: adder32 takes 64 bits, not a CMR
+        let nodes = vec![
+            Term::Jet(JetsNode::Adder32),
+            Term::Unit,
+            Term::Disconnect(2, 1),
+        ];
+        let prog = UnTypedProg::<(), DummyNode>(nodes.clone());
+        assert_matches!(type_check(prog), Err(Error::DisconnectShape(2)));
+
+        let mut tc = Typechecker::<(), DummyNode>::new();
+        tc.add_node(nodes[0].clone()).unwrap();
+        tc.add_node(nodes[1].clone()).unwrap();
+        assert_matches!(
+            tc.add_node(nodes[2].clone()),
+            Err(Error::DisconnectShape(2))
+        );
+
+        // sha256 takes a 256-bit CMR paired with another 256 bits
+        let nodes = vec![
+            Term::Jet(JetsNode::Sha256),
+            Term::Unit,
+            Term::Disconnect(2, 1),
+        ];
+        let typed = type_check(UnTypedProg::<(), DummyNode>(nodes)).unwrap();
+        assert_eq!(typed[2].source_ty.bit_width(), 256);
+    }
 }
@@ -145,7 +145,7 @@ impl BitWrite for Vec<bool> {
     }
 }
 
-impl<'a, B: BitWrite> BitWrite for &'a mut B {
+impl<B: BitWrite> BitWrite for &mut B {
     fn write_bit(&mut self, b: bool) -> io::Result<()> {
         (**self).write_bit(b)
     }
@@ -262,7 +262,13 @@ pub fn encode_node_no_witness<T, W: BitWrite, Ext: extension::Jet>(
         Term::Drop(i) => Ok(writer.write_u8(7, 5)? + encode_natural(i, &mut *writer)?),
         Term::Iden => writer.write_u8(8, 5),
         Term::Unit => writer.write_u8(9, 5),
-        Term::Fail(..) => unimplemented!(),
+        Term::Fail(hl, hr) => {
+            let mut len = writer.write_u8(10, 5)?;
+            for byte in hl.iter().chain(hr.iter()) {
+                len += writer.write_u8(*byte, 8)?;
+            }
+            Ok(len)
+        }
         Term::Hidden(cmr) => {
             let mut len = writer.write_u8(6, 4)?;
             for byte in &cmr[..] {
@@ -276,15 +282,38 @@ pub fn encode_node_no_witness<T, W: BitWrite, Ext: extension::Jet>(
     }
 }
 
-pub fn decode_program_no_witness<I: Iterator<Item = u8>, Ext: extension::Jet>(
-    iter: &mut BitIter<I>,
-) -> Result<UnTypedProg<(), Ext>, Error> {
+/// Length, in bits, of the encoding of a node by `encode_node_no_witness`
+pub fn node_bit_len<T, Ext: extension::Jet>(node: &Term<T, Ext>) -> usize {
+    match *node {
+        Term::Comp(i, j) | Term::Case(i, j) | Term::Pair(i, j) | Term::Disconnect(i, j) => {
+            5 + natural_bit_len(i) + natural_bit_len(j)
+        }
+        Term::InjL(i) | Term::InjR(i) | Term::Take(i) | Term::Drop(i) => 5 + natural_bit_len(i),
+        Term::Iden | Term::Unit => 5,
+        Term::Fail(..) => 5 + 512,
+        Term::Hidden(..) => 4 + 256,
+        Term::Witness(..) => 4,
+        Term::Ext(ref b) => b.encoded_bit_len(),
+        Term::Jet(ref j) => j.encoded_bit_len(),
+    }
+}
+
+/// Decode the number of nodes which prefixes an encoded program, rejecting
+/// programs which are too large to be allocated
+pub fn decode_program_len<I: Iterator<Item = u8>>(iter: &mut BitIter<I>) -> Result<usize, Error> {
     let prog_len = decode_natural(&mut *iter, None)?;
 
     // FIXME make this a reasonable limit
     if prog_len > 1_000_000 {
         return Err(Error::TooManyNodes(prog_len));
     }
+    Ok(prog_len)
+}
+
+pub fn decode_program_no_witness<I: Iterator<Item = u8>, Ext: extension::Jet>(
+    iter: &mut BitIter<I>,
+) -> Result<UnTypedProg<(), Ext>, Error> {
+    let prog_len = decode_program_len(&mut *iter)?;
 
     let mut program = Vec::with_capacity(prog_len);
     for i in 0..prog_len {
@@ -316,6 +345,18 @@ pub fn encode_natural<W: BitWrite>(n: usize, writer: &mut W) -> io::Result<usize
     }
 }
 
+/// Length, in bits, of the encoding of a natural number by `encode_natural`
+pub fn natural_bit_len(n: usize) -> usize {
+    assert_ne!(n, 0); // Cannot encode zero
+    let len = 8 * mem::size_of::<usize>() - n.leading_zeros() as usize - 1;
+
+    if len == 0 {
+        1
+    } else {
+        1 + natural_bit_len(len) + len
+    }
+}
+
 /// Decode a natural number according to section 7.2.1
 /// of the Simplicity whitepaper.
 /// Optionally provide a bound for the value being decoded.
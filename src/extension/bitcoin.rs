@@ -18,11 +18,12 @@
 //! blockchain
 //!
 
-use bitcoin_hashes::{sha256, Hash, HashEngine};
+use bitcoin::consensus::Encodable;
+use bitcoin_hashes::{sha256, sha256d, Hash, HashEngine};
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::{fmt, io};
 
-use super::TypeName;
+use super::{JetFailed, TypeName};
 use crate::bititer::BitIter;
 use crate::cmr::Cmr;
 use crate::encode;
@@ -33,12 +34,89 @@ use crate::Error;
 /// Transaction environment for Bitcoin Simplicity programs
 pub struct TxEnv {
     tx: bitcoin::Transaction,
+    /// Index of the input being spent
+    ix: u32,
+    /// Outputs spent by the inputs of the transaction, in order
+    spent_outputs: Vec<bitcoin::TxOut>,
 }
 
 impl TxEnv {
     /// Constructor from a transaction
     pub fn from_tx(tx: bitcoin::Transaction) -> TxEnv {
-        TxEnv { tx: tx }
+        TxEnv::from_spent_outputs(tx, 0, vec![])
+    }
+
+    /// Constructor from a transaction, the index of the input being
+    /// spent, and the outputs spent by each of its inputs
+    pub fn from_spent_outputs(
+        tx: bitcoin::Transaction,
+        ix: u32,
+        spent_outputs: Vec<bitcoin::TxOut>,
+    ) -> TxEnv {
+        TxEnv {
+            tx: tx,
+            ix: ix,
+            spent_outputs: spent_outputs,
+        }
+    }
+
+    /// The BIP-143 signature hash of the input being spent, with the
+    /// script of the output it spends as the script code, or `None` if
+    /// the transaction has no input, or no spent output, at the index
+    /// being spent
+    pub fn bip_143_sighash(&self, hash_type: u8) -> Option<sha256d::Hash> {
+        const SIGHASH_NONE: u8 = 0x02;
+        const SIGHASH_SINGLE: u8 = 0x03;
+        const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+        let ix = self.ix as usize;
+        let input = self.tx.input.get(ix)?;
+        let spent = self.spent_outputs.get(ix)?;
+        let base_type = hash_type & 0x1f;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        let all_outputs = base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE;
+
+        let mut hash_prevouts = sha256d::Hash::default();
+        let mut hash_sequence = sha256d::Hash::default();
+        let mut hash_outputs = sha256d::Hash::default();
+        if !anyone_can_pay {
+            let mut eng = sha256d::Hash::engine();
+            for input in &self.tx.input {
+                input.previous_output.consensus_encode(&mut eng).unwrap();
+            }
+            hash_prevouts = sha256d::Hash::from_engine(eng);
+        }
+        if !anyone_can_pay && all_outputs {
+            let mut eng = sha256d::Hash::engine();
+            for input in &self.tx.input {
+                eng.write_u32::<LittleEndian>(input.sequence).unwrap();
+            }
+            hash_sequence = sha256d::Hash::from_engine(eng);
+        }
+        if all_outputs {
+            let mut eng = sha256d::Hash::engine();
+            for output in &self.tx.output {
+                output.consensus_encode(&mut eng).unwrap();
+            }
+            hash_outputs = sha256d::Hash::from_engine(eng);
+        } else if base_type == SIGHASH_SINGLE && ix < self.tx.output.len() {
+            let mut eng = sha256d::Hash::engine();
+            self.tx.output[ix].consensus_encode(&mut eng).unwrap();
+            hash_outputs = sha256d::Hash::from_engine(eng);
+        }
+
+        let mut eng = sha256d::Hash::engine();
+        eng.write_u32::<LittleEndian>(self.tx.version).unwrap();
+        eng.input(&hash_prevouts[..]);
+        eng.input(&hash_sequence[..]);
+        input.previous_output.consensus_encode(&mut eng).unwrap();
+        spent.script_pubkey.consensus_encode(&mut eng).unwrap();
+        eng.write_u64::<LittleEndian>(spent.value).unwrap();
+        eng.write_u32::<LittleEndian>(input.sequence).unwrap();
+        eng.input(&hash_outputs[..]);
+        eng.write_u32::<LittleEndian>(self.tx.lock_time).unwrap();
+        eng.write_u32::<LittleEndian>(hash_type as u32).unwrap();
+        Some(sha256d::Hash::from_engine(eng))
     }
 }
 
@@ -234,8 +312,7 @@ impl extension::Jet for BtcNode {
         }
     }
 
-    fn exec(&self, mac: &mut exec::BitMachine, txenv: &Self::TxEnv) {
-        // FIXME finish this
+    fn exec(&self, mac: &mut exec::BitMachine, txenv: &Self::TxEnv) -> Result<(), JetFailed> {
         match *self {
             BtcNode::InputsHash => {
                 let mut eng = sha256::Hash::engine();
@@ -269,7 +346,141 @@ impl extension::Jet for BtcNode {
             BtcNode::Version => {
                 mac.write_u32(txenv.tx.version);
             }
-            ref b => unimplemented!("bitcoin {}", b),
+            _ => return Err(JetFailed::Unsupported),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::BitWrite;
+    use crate::extension::Jet;
+    use crate::testing::{assert_jet, assert_jet_fails};
+    use crate::Value;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hash_types::SigHash;
+    use bitcoin::util::bip143::SighashComponents;
+    use bitcoin_hashes::hex::FromHex;
+
+    /// The native P2WPKH example of BIP-143, spending its second input
+    fn bip143_env() -> TxEnv {
+        let tx: bitcoin::Transaction = deserialize(
+            &Vec::<u8>::from_hex(
+                "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f000000\
+                 0000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+                 00ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093\
+                 510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        // The script code of the P2WPKH input, standing in for its script
+        let pk = bitcoin::PublicKey::from_slice(
+            &Vec::<u8>::from_hex(
+                "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let script_code = bitcoin::Address::p2pkh(&pk, bitcoin::Network::Bitcoin).script_pubkey();
+        let spent_outputs = vec![
+            bitcoin::TxOut {
+                value: 625_000_000,
+                script_pubkey: bitcoin::Script::new(),
+            },
+            bitcoin::TxOut {
+                value: 600_000_000,
+                script_pubkey: script_code,
+            },
+        ];
+        TxEnv::from_spent_outputs(tx, 1, spent_outputs)
+    }
+
+    #[test]
+    fn bip143_sighash() {
+        let env = bip143_env();
+        let spent = &env.spent_outputs[1];
+        let expected = SighashComponents::new(&env.tx).sighash_all(
+            &env.tx.input[1],
+            &spent.script_pubkey,
+            spent.value,
+        );
+        assert_eq!(
+            expected,
+            SigHash::from_slice(
+                &Vec::<u8>::from_hex(
+                    "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+                )
+                .unwrap()
+            )
+            .unwrap()
+        );
+        assert_eq!(env.bip_143_sighash(0x01).unwrap()[..], expected[..]);
+
+        // Every other hash type commits to something different
+        let hashes: Vec<_> = [0x01, 0x02, 0x03, 0x81, 0x82, 0x83]
+            .iter()
+            .map(|&hash_type| env.bip_143_sighash(hash_type).unwrap())
+            .collect();
+        for (i, hash) in hashes.iter().enumerate() {
+            assert!(!hashes[..i].contains(hash));
+        }
+
+        // The input being spent and the output it spends must both exist
+        let tx = env.tx.clone();
+        assert_eq!(TxEnv::from_tx(tx.clone()).bip_143_sighash(0x01), None);
+        let spent_outputs = env.spent_outputs.clone();
+        assert_eq!(
+            TxEnv::from_spent_outputs(tx, 2, spent_outputs).bip_143_sighash(0x01),
+            None
+        );
+
+        // SIGHASH_SINGLE without a corresponding output commits to no outputs
+        let mut env = env;
+        env.tx.output.truncate(1);
+        let (single, all) = (env.bip_143_sighash(0x03), env.bip_143_sighash(0x01));
+        env.tx.output[0].value += 1;
+        assert_eq!(env.bip_143_sighash(0x03), single);
+        assert_ne!(env.bip_143_sighash(0x01), all);
+    }
+
+    #[test]
+    fn version_and_lock_time() {
+        let env = TxEnv::from_tx(bitcoin::Transaction {
+            version: 0x1234_5678,
+            lock_time: 650_000,
+            input: vec![],
+            output: vec![],
+        });
+        assert_jet!(BtcNode::Version, Value::Unit, Value::u32(0x1234_5678), env);
+        assert_jet!(BtcNode::LockTime, Value::Unit, Value::u32(650_000), env);
+    }
+
+    #[test]
+    fn unsupported_jets() {
+        let env = TxEnv::default();
+        assert_jet_fails!(BtcNode::NumInputs, Value::Unit, env);
+        assert_jet_fails!(BtcNode::InputValue, Value::u32(0), env);
+    }
+
+    #[test]
+    fn encode_decode() {
+        for &(node, len) in &[
+            (BtcNode::Version, 7),
+            (BtcNode::OutputScriptHash, 6),
+            (BtcNode::ScriptCMR, 6),
+        ] {
+            let mut w = encode::BitWriter::new(vec![]);
+            assert_eq!(node.encode(&mut w).unwrap(), len);
+            w.flush_all().unwrap();
+            let bytes = w.into_inner();
+            // Skip the two bits which mark the node as an extension
+            let mut iter = BitIter::from(bytes.into_iter());
+            iter.next();
+            iter.next();
+            assert_eq!(BtcNode::decode(&mut iter).unwrap(), node);
         }
     }
 }
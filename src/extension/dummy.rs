@@ -19,7 +19,7 @@
 
 use std::{fmt, io};
 
-use super::TypeName;
+use super::{JetFailed, TypeName};
 use crate::bititer::BitIter;
 use crate::cmr;
 use crate::encode;
@@ -28,6 +28,7 @@ use crate::extension;
 use crate::Error;
 
 /// Dummy transaction environment
+#[derive(Default)]
 pub struct TxEnv;
 
 /// Dummy extension provides no combinators and cannot be constructed
@@ -57,7 +58,7 @@ impl extension::Jet for DummyNode {
         match *self {}
     }
 
-    fn exec(&self, _: &mut exec::BitMachine, _: &Self::TxEnv) {
+    fn exec(&self, _: &mut exec::BitMachine, _: &Self::TxEnv) -> Result<(), JetFailed> {
         match *self {}
     }
 }
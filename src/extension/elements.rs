@@ -20,7 +20,10 @@
 
 use std::{fmt, io};
 
-use super::TypeName;
+use elements::bitcoin::hashes::{sha256, Hash};
+use elements::{confidential, AssetId};
+
+use super::{JetFailed, TypeName};
 use crate::bititer::BitIter;
 use crate::cmr::Cmr;
 use crate::encode;
@@ -30,13 +33,13 @@ use crate::Error;
 
 /// Transaction environment for Bitcoin Simplicity programs
 pub struct TxEnv {
-    _tx: elements::Transaction,
+    tx: elements::Transaction,
 }
 
 impl TxEnv {
     /// Constructor from a transaction
     pub fn from_tx(tx: elements::Transaction) -> TxEnv {
-        TxEnv { _tx: tx }
+        TxEnv { tx: tx }
     }
 }
 
@@ -427,8 +430,349 @@ impl extension::Jet for ElementsNode {
         }
     }
 
-    fn exec(&self, _mac: &mut exec::BitMachine, _txenv: &Self::TxEnv) {
-        // FIXME finish this
-        unimplemented!()
+    fn exec(&self, mac: &mut exec::BitMachine, txenv: &Self::TxEnv) -> Result<(), JetFailed> {
+        match *self {
+            ElementsNode::OutputAsset => {
+                let vout = mac.read_u32() as usize;
+                match txenv.tx.output.get(vout).map(|out| out.asset) {
+                    None | Some(confidential::Asset::Null) => {
+                        mac.write_bit(false);
+                        mac.write_padding(258);
+                    }
+                    Some(asset) => {
+                        mac.write_bit(true);
+                        write_asset(mac, asset);
+                    }
+                }
+            }
+            ElementsNode::OutputAmount => {
+                let vout = mac.read_u32() as usize;
+                match txenv.tx.output.get(vout).map(|out| out.value) {
+                    None | Some(confidential::Value::Null) => {
+                        mac.write_bit(false);
+                        mac.write_padding(258);
+                    }
+                    Some(value) => {
+                        mac.write_bit(true);
+                        write_amount(mac, value);
+                    }
+                }
+            }
+            ElementsNode::NumOutputs => mac.write_u32(txenv.tx.output.len() as u32),
+            ElementsNode::Fee => {
+                let asset = mac.read_32bytes();
+                mac.write_u64(txenv.fee(|id| id[..] == asset[..]));
+            }
+            _ => return Err(JetFailed::Unsupported),
+        }
+        Ok(())
+    }
+}
+
+impl TxEnv {
+    /// Sum of the explicit amounts of all explicit fee outputs, across all
+    /// assets, saturating at `u64::MAX`
+    pub fn total_fee(&self) -> u64 {
+        self.fee(|_| true)
+    }
+
+    /// The amount of output `vout` if it is a fee output with explicit
+    /// asset and amount, and zero otherwise
+    pub fn output_explicit_fee(&self, vout: usize) -> u64 {
+        match self.tx.output.get(vout) {
+            Some(out) => explicit_fee(out).map_or(0, |(_, value)| value),
+            None => 0,
+        }
+    }
+
+    /// The ID of the asset issued by input `vin`, if it exists and has
+    /// an issuance
+    pub fn issuance_asset(&self, vin: usize) -> Option<[u8; 32]> {
+        self.tx.input.get(vin).and_then(issuance_asset)
+    }
+
+    /// The ID of the reissuance token created by input `vin`, if it exists
+    /// and is a new issuance which issues tokens
+    pub fn issuance_token(&self, vin: usize) -> Option<[u8; 32]> {
+        self.tx.input.get(vin).and_then(issuance_token)
+    }
+
+    /// Sum of the explicit amounts of the explicit fee outputs whose asset
+    /// ID satisfies `asset`, saturating at `u64::MAX`
+    fn fee<F: Fn(&[u8]) -> bool>(&self, asset: F) -> u64 {
+        self.tx
+            .output
+            .iter()
+            .filter_map(explicit_fee)
+            .filter(|&(id, _)| asset(&id[..]))
+            .fold(0u64, |sum, (_, value)| sum.saturating_add(value))
+    }
+}
+
+/// The asset ID and amount of an output, if it is a fee output with
+/// explicit asset and amount
+fn explicit_fee(out: &elements::TxOut) -> Option<([u8; 32], u64)> {
+    match (out.asset, out.value) {
+        (confidential::Asset::Explicit(id), confidential::Value::Explicit(value))
+            if out.is_fee() =>
+        {
+            Some((id.into_inner(), value))
+        }
+        _ => None,
+    }
+}
+
+/// The entropy of the issuance attached to an input, if any
+fn issuance_entropy(input: &elements::TxIn) -> Option<sha256::Midstate> {
+    if !input.has_issuance() {
+        return None;
+    }
+    let issuance = &input.asset_issuance;
+    if issuance.asset_blinding_nonce == [0; 32] {
+        // A new issuance commits to the outpoint it spends
+        let contract = sha256::Hash::from_inner(issuance.asset_entropy);
+        Some(AssetId::generate_asset_entropy(
+            input.previous_output,
+            contract,
+        ))
+    } else {
+        Some(sha256::Midstate::from_inner(issuance.asset_entropy))
+    }
+}
+
+/// The ID of the asset issued by an input, if any
+fn issuance_asset(input: &elements::TxIn) -> Option<[u8; 32]> {
+    issuance_entropy(input).map(|entropy| AssetId::from_entropy(entropy).into_inner().into_inner())
+}
+
+/// The ID of the reissuance token created by an input, if any; only new
+/// issuances which issue tokens create them
+fn issuance_token(input: &elements::TxIn) -> Option<[u8; 32]> {
+    let issuance = &input.asset_issuance;
+    if issuance.asset_blinding_nonce != [0; 32]
+        || issuance.inflation_keys == confidential::Value::Null
+    {
+        return None;
+    }
+    let entropy = issuance_entropy(input)?;
+    // The token ID depends on whether the issued amount is blinded
+    let token = match issuance.amount {
+        confidential::Value::Confidential(..) => {
+            AssetId::reissuance_token_from_entropy(entropy, true)
+        }
+        _ => AssetId::reissuance_token_from_entropy(entropy, false),
+    };
+    Some(token.into_inner().into_inner())
+}
+
+/// Write a possibly confidential asset, which must not be null, as a value
+/// of type `+*2hh`: a commitment as the parity of its y-coordinate and its
+/// x-coordinate, or an explicit asset ID
+fn write_asset(mac: &mut exec::BitMachine, asset: confidential::Asset) {
+    match asset {
+        confidential::Asset::Null => panic!("writing a null asset"),
+        confidential::Asset::Explicit(id) => {
+            mac.write_bit(true);
+            mac.write_padding(1);
+            mac.write_bytes(&id[..]);
+        }
+        confidential::Asset::Confidential(prefix, x) => {
+            mac.write_bit(false);
+            mac.write_bit(prefix & 1 == 1);
+            mac.write_bytes(&x);
+        }
+    }
+}
+
+/// Write a possibly confidential amount, which must not be null, as a
+/// value of type `+*2hl`: a commitment as the parity of its y-coordinate
+/// and its x-coordinate, or an explicit amount
+fn write_amount(mac: &mut exec::BitMachine, value: confidential::Value) {
+    match value {
+        confidential::Value::Null => panic!("writing a null amount"),
+        confidential::Value::Explicit(value) => {
+            mac.write_bit(true);
+            mac.write_padding(193);
+            mac.write_u64(value);
+        }
+        confidential::Value::Confidential(prefix, x) => {
+            mac.write_bit(false);
+            mac.write_bit(prefix & 1 == 1);
+            mac.write_bytes(&x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::BitWrite;
+    use crate::extension::Jet;
+    use crate::testing::{assert_jet, assert_jet_fails};
+    use crate::Value;
+
+    /// A 256-bit word given as bytes
+    fn word256(bytes: &[u8]) -> Value {
+        let bits: Vec<bool> = BitIter::from(bytes.iter().cloned()).collect();
+        Value::from_padded_bits(&bits, &crate::core::types::pow2_types()[9]).unwrap()
+    }
+
+    fn fee_output(asset: [u8; 32], value: u64) -> elements::TxOut {
+        elements::TxOut {
+            asset: confidential::Asset::Explicit(
+                elements::bitcoin::hashes::sha256d::Hash::from_inner(asset),
+            ),
+            value: confidential::Value::Explicit(value),
+            ..Default::default()
+        }
+    }
+
+    fn mock_tx() -> TxEnv {
+        let mut payment = fee_output([1; 32], 9_999);
+        payment.script_pubkey = elements::bitcoin::Script::from(vec![0x51]);
+        let mut blinded_fee = fee_output([1; 32], 0);
+        blinded_fee.value = confidential::Value::Confidential(0x09, [7; 32]);
+
+        TxEnv::from_tx(elements::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![
+                fee_output([1; 32], 1_000),
+                payment,
+                fee_output([2; 32], 250),
+                blinded_fee,
+                fee_output([1; 32], 500),
+            ],
+        })
+    }
+
+    #[test]
+    fn fees() {
+        let env = mock_tx();
+        assert_eq!(env.total_fee(), 1_750);
+        assert_eq!(TxEnv::default().total_fee(), 0);
+
+        let fee = |asset: [u8; 32], amount| {
+            assert_jet!(ElementsNode::Fee, word256(&asset), Value::u64(amount), env)
+        };
+        fee([1; 32], 1_500);
+        fee([2; 32], 250);
+        fee([3; 32], 0);
+
+        assert_eq!(env.output_explicit_fee(0), 1_000);
+        assert_eq!(env.output_explicit_fee(2), 250);
+        // Not a fee output, a confidential fee, and no output at all
+        assert_eq!(env.output_explicit_fee(1), 0);
+        assert_eq!(env.output_explicit_fee(3), 0);
+        assert_eq!(env.output_explicit_fee(5), 0);
+    }
+
+    #[test]
+    fn assets_and_amounts() {
+        let env = mock_tx();
+        let just = |value| Value::sum_r(value);
+        let nothing = Value::sum_l(Value::Unit);
+
+        let asset = |vout, expected| {
+            assert_jet!(ElementsNode::OutputAsset, Value::u32(vout), expected, env)
+        };
+        asset(2, just(Value::sum_r(word256(&[2; 32]))));
+        asset(5, nothing.clone());
+
+        let amount = |vout, expected| {
+            assert_jet!(ElementsNode::OutputAmount, Value::u32(vout), expected, env)
+        };
+        amount(2, just(Value::sum_r(Value::u64(250))));
+        amount(
+            3,
+            just(Value::sum_l(Value::prod(Value::u1(1), word256(&[7; 32])))),
+        );
+        amount(5, nothing);
+    }
+
+    #[test]
+    fn issuance() {
+        let input = |blinding_nonce: [u8; 32], inflation_keys| elements::TxIn {
+            previous_output: elements::OutPoint::default(),
+            is_pegin: false,
+            has_issuance: true,
+            script_sig: elements::bitcoin::Script::new(),
+            sequence: 0xffff_ffff,
+            asset_issuance: elements::AssetIssuance {
+                asset_blinding_nonce: blinding_nonce,
+                asset_entropy: [5; 32],
+                amount: confidential::Value::Explicit(100),
+                inflation_keys: inflation_keys,
+            },
+            witness: Default::default(),
+        };
+        let mut no_issuance = input([0; 32], confidential::Value::Null);
+        no_issuance.has_issuance = false;
+        let env = TxEnv::from_tx(elements::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                input([0; 32], confidential::Value::Explicit(1)),
+                input([9; 32], confidential::Value::Null),
+                no_issuance,
+            ],
+            output: vec![],
+        });
+
+        let entropy = AssetId::generate_asset_entropy(
+            elements::OutPoint::default(),
+            sha256::Hash::from_inner([5; 32]),
+        );
+        let id = |id: AssetId| Some(id.into_inner().into_inner());
+        assert_eq!(env.issuance_asset(0), id(AssetId::from_entropy(entropy)));
+        // A reissuance gives its entropy directly
+        assert_eq!(
+            env.issuance_asset(1),
+            id(AssetId::from_entropy(sha256::Midstate::from_inner([5; 32])))
+        );
+        assert_eq!(env.issuance_asset(2), None);
+        assert_eq!(env.issuance_asset(3), None);
+
+        assert_eq!(
+            env.issuance_token(0),
+            id(AssetId::reissuance_token_from_entropy(entropy, false))
+        );
+        assert_eq!(env.issuance_token(1), None);
+        assert_eq!(env.issuance_token(2), None);
+    }
+
+    #[test]
+    fn unsupported_jets() {
+        assert_jet_fails!(ElementsNode::Version, Value::Unit, mock_tx());
+        assert_jet_fails!(ElementsNode::InputAsset, Value::u32(0), mock_tx());
+    }
+
+    #[test]
+    fn encode_decode() {
+        for &jet in &[
+            ElementsNode::Version,
+            ElementsNode::OutputAsset,
+            ElementsNode::NumOutputs,
+            ElementsNode::Fee,
+        ] {
+            let mut bytes = vec![];
+            let mut w = encode::BitWriter::new(&mut bytes);
+            let len = jet.encode(&mut w).unwrap();
+            w.flush_all().unwrap();
+            assert_eq!(len, jet.encoded_bit_len());
+
+            // Skip the two bits which mark an extension node
+            let mut iter = BitIter::from(bytes.into_iter());
+            iter.nth(1);
+            assert_eq!(ElementsNode::decode(&mut iter).unwrap(), jet);
+        }
+
+        // `fee` has the last 5-bit code
+        let mut bytes = vec![];
+        let mut w = encode::BitWriter::new(&mut bytes);
+        assert_eq!(ElementsNode::Fee.encode(&mut w).unwrap(), 7);
+        w.flush_all().unwrap();
+        assert_eq!(bytes, [0b1011_1110]);
     }
 }
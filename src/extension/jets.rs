@@ -18,9 +18,9 @@
 //! blockchain
 //!
 
-use std::{fmt, io};
+use std::{fmt, io, str};
 
-use super::TypeName;
+use super::{JetFailed, TypeName};
 use crate::bitcoin_hashes::{sha256, Hash, HashEngine};
 use crate::bititer::BitIter;
 use crate::cmr::Cmr;
@@ -29,6 +29,9 @@ use crate::exec;
 use crate::extension;
 use crate::Error;
 
+#[cfg(feature = "secp")]
+pub mod secp;
+
 /// Set of new Simplicity nodes enabled by the Bitcoin extension
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum JetsNode {
@@ -45,6 +48,18 @@ pub enum JetsNode {
     Sha256,
     LessThanV32, // less than verify for u32
     EqV32,
+    /// Add two secp256k1 points
+    SecpPointAdd,
+    /// Multiply the secp256k1 generator by a scalar
+    SecpGeneratorMul,
+    /// Parse an x-only public key into a secp256k1 point
+    SecpParseXOnly,
+    /// Append a public key to a MuSig2 context
+    Musig2AddPubkey,
+    /// Compute the aggregate public key of a MuSig2 context
+    Musig2Finalize,
+    /// Verify a signature against the aggregate key of a MuSig2 context
+    Musig2AggVerify,
 }
 
 impl fmt::Display for JetsNode {
@@ -62,10 +77,44 @@ impl fmt::Display for JetsNode {
             JetsNode::Sha256 => "sha256",
             JetsNode::LessThanV32 => "le32",
             JetsNode::EqV32 => "eqv32",
+            JetsNode::SecpPointAdd => "secppointadd",
+            JetsNode::SecpGeneratorMul => "secpgeneratormul",
+            JetsNode::SecpParseXOnly => "secpparsexonly",
+            JetsNode::Musig2AddPubkey => "musig2addpubkey",
+            JetsNode::Musig2Finalize => "musig2finalize",
+            JetsNode::Musig2AggVerify => "musig2aggverify",
         })
     }
 }
 
+impl str::FromStr for JetsNode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "adder32" => Ok(JetsNode::Adder32),
+            "fulladder32" => Ok(JetsNode::FullAdder32),
+            "subtractor32" => Ok(JetsNode::Subtractor32),
+            "fullsubtractor32" => Ok(JetsNode::FullSubtractor32),
+            "multiplier32" => Ok(JetsNode::Multiplier32),
+            "fullmultiplier32" => Ok(JetsNode::FullMultiplier32),
+            "sha256hashblock" => Ok(JetsNode::Sha256HashBlock),
+            "schnorrassert" => Ok(JetsNode::SchnorrAssert),
+            "eqv256" => Ok(JetsNode::EqV256),
+            "sha256" => Ok(JetsNode::Sha256),
+            "le32" => Ok(JetsNode::LessThanV32),
+            "eqv32" => Ok(JetsNode::EqV32),
+            "secppointadd" => Ok(JetsNode::SecpPointAdd),
+            "secpgeneratormul" => Ok(JetsNode::SecpGeneratorMul),
+            "secpparsexonly" => Ok(JetsNode::SecpParseXOnly),
+            "musig2addpubkey" => Ok(JetsNode::Musig2AddPubkey),
+            "musig2finalize" => Ok(JetsNode::Musig2Finalize),
+            "musig2aggverify" => Ok(JetsNode::Musig2AggVerify),
+            _ => Err(Error::ParseError("unknown jet name")),
+        }
+    }
+}
+
 impl extension::Jet for JetsNode {
     type TxEnv = ();
     /// Name of the source type for this node
@@ -83,6 +132,12 @@ impl extension::Jet for JetsNode {
             JetsNode::Sha256 => TypeName(b"*hh"),
             JetsNode::LessThanV32 => TypeName(b"l"),
             JetsNode::EqV32 => TypeName(b"l"),
+            JetsNode::SecpPointAdd => TypeName(b"**hh*hh"),
+            JetsNode::SecpGeneratorMul => TypeName(b"h"),
+            JetsNode::SecpParseXOnly => TypeName(b"h"),
+            JetsNode::Musig2AddPubkey => TypeName(b"**l***2h*2h**2h*2h*2h"),
+            JetsNode::Musig2Finalize => TypeName(b"*l***2h*2h**2h*2h"),
+            JetsNode::Musig2AggVerify => TypeName(b"**l***2h*2h**2h*2h**hhh"),
         }
     }
 
@@ -101,6 +156,12 @@ impl extension::Jet for JetsNode {
             JetsNode::Sha256 => TypeName(b"h"),
             JetsNode::LessThanV32 => TypeName(b"1"),
             JetsNode::EqV32 => TypeName(b"1"),
+            JetsNode::SecpPointAdd => TypeName(b"+1*hh"),
+            JetsNode::SecpGeneratorMul => TypeName(b"+1*hh"),
+            JetsNode::SecpParseXOnly => TypeName(b"+1*hh"),
+            JetsNode::Musig2AddPubkey => TypeName(b"+1*l***2h*2h**2h*2h"),
+            JetsNode::Musig2Finalize => TypeName(b"+1h"),
+            JetsNode::Musig2AggVerify => TypeName(b"2"),
         }
     }
 
@@ -168,6 +229,36 @@ impl extension::Jet for JetsNode {
                 0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
                 0x31, 0xba, 0xec, 0x9f, //only last `a` changed to `f` from sha2 block cmr
             ])),
+            JetsNode::SecpPointAdd => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa0, //only last `9a` changed to `a0` from sha2 block cmr
+            ])),
+            JetsNode::SecpGeneratorMul => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa1, //only last `9a` changed to `a1` from sha2 block cmr
+            ])),
+            JetsNode::SecpParseXOnly => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa2, //only last `9a` changed to `a2` from sha2 block cmr
+            ])),
+            JetsNode::Musig2AddPubkey => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa3, //only last `9a` changed to `a3` from sha2 block cmr
+            ])),
+            JetsNode::Musig2Finalize => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa4, //only last `9a` changed to `a4` from sha2 block cmr
+            ])),
+            JetsNode::Musig2AggVerify => cmr.update_1(Cmr::from([
+                0xee, 0xae, 0x47, 0xe2, 0xf7, 0x87, 0x6c, 0x3b, 0x9c, 0xbc, 0xd4, 0x04, 0xa3, 0x38,
+                0xb0, 0x89, 0xfd, 0xea, 0xdf, 0x1b, 0x9b, 0xb3, 0x82, 0xec, 0x6e, 0x69, 0x71, 0x9d,
+                0x31, 0xba, 0xec, 0xa5, //only last `9a` changed to `a5` from sha2 block cmr
+            ])),
         }
     }
 
@@ -186,6 +277,12 @@ impl extension::Jet for JetsNode {
             JetsNode::Sha256 => w.write_u8(15 * 16 + 2, 8),
             JetsNode::LessThanV32 => w.write_u8(15 * 16 + 3, 8),
             JetsNode::EqV32 => w.write_u8(15 * 16 + 4, 8),
+            JetsNode::SecpPointAdd => w.write_u8(15 * 16 + 5, 8),
+            JetsNode::SecpGeneratorMul => w.write_u8(15 * 16 + 6, 8),
+            JetsNode::SecpParseXOnly => w.write_u8(15 * 16 + 7, 8),
+            JetsNode::Musig2AddPubkey => w.write_u8(15 * 16 + 8, 8),
+            JetsNode::Musig2Finalize => w.write_u8(15 * 16 + 9, 8),
+            JetsNode::Musig2AggVerify => w.write_u8(15 * 16 + 10, 8),
         }
     }
 
@@ -230,6 +327,12 @@ impl extension::Jet for JetsNode {
                         2 => Ok(JetsNode::Sha256),
                         3 => Ok(JetsNode::LessThanV32),
                         4 => Ok(JetsNode::EqV32),
+                        5 => Ok(JetsNode::SecpPointAdd),
+                        6 => Ok(JetsNode::SecpGeneratorMul),
+                        7 => Ok(JetsNode::SecpParseXOnly),
+                        8 => Ok(JetsNode::Musig2AddPubkey),
+                        9 => Ok(JetsNode::Musig2Finalize),
+                        10 => Ok(JetsNode::Musig2AggVerify),
                         _ => Err(Error::ParseError("bad jet")),
                     }
                 }
@@ -239,7 +342,37 @@ impl extension::Jet for JetsNode {
         }
     }
 
-    fn exec(&self, mac: &mut exec::BitMachine, _tx_env: &Self::TxEnv) {
+    fn partial_eval(&self, known_bits: &[Option<bool>]) -> Option<Vec<Option<bool>>> {
+        if known_bits.len() != self.source_type().bit_width() {
+            return None;
+        }
+        // The `n`th 32-bit word of the input, if all its bits are known
+        let word = |n: usize| {
+            known_bits[32 * n..32 * (n + 1)]
+                .iter()
+                .try_fold(0u32, |acc, bit| bit.map(|bit| acc << 1 | bit as u32))
+        };
+        let carry = || known_bits[64].map(|bit| bit as u32);
+
+        let out = match *self {
+            JetsNode::Adder32 => add_sub_bits(u32::overflowing_add, word(0), word(1), Some(0)),
+            JetsNode::FullAdder32 => add_sub_bits(u32::overflowing_add, word(0), word(1), carry()),
+            JetsNode::Subtractor32 => add_sub_bits(u32::overflowing_sub, word(0), word(1), Some(0)),
+            JetsNode::FullSubtractor32 => {
+                add_sub_bits(u32::overflowing_sub, word(0), word(1), carry())
+            }
+            // A product is zero if either factor is, whatever the other
+            JetsNode::Multiplier32 => match (word(0), word(1)) {
+                (Some(0), _) | (_, Some(0)) => known_word(0, 64).collect(),
+                (Some(a), Some(b)) => known_word(a as u64 * b as u64, 64).collect(),
+                _ => vec![None; 64],
+            },
+            _ => vec![None; self.target_type().bit_width()],
+        };
+        Some(out)
+    }
+
+    fn exec(&self, mac: &mut exec::BitMachine, _tx_env: &Self::TxEnv) -> Result<(), JetFailed> {
         match *self {
             JetsNode::Adder32 => {
                 let a = mac.read_u32();
@@ -287,25 +420,25 @@ impl extension::Jet for JetsNode {
             }
             JetsNode::Sha256HashBlock => {
                 let hash = mac.read_32bytes();
-                let block = mac.read_bytes(64);
+                let mut block = [0; 64];
+                mac.read_bytes_into(&mut block);
                 let sha2_midstate = sha256::Midstate::from_inner(hash);
                 let mut engine = sha256::HashEngine::from_midstate(sha2_midstate, 0);
                 engine.input(&block);
-                let h = sha256::Hash::from_engine(engine).into_inner();
+                // The compression function alone, without SHA-256 padding
+                let h = engine.midstate().into_inner();
                 mac.write_bytes(&h);
             }
             JetsNode::SchnorrAssert => {
                 let _pubkey = mac.read_32bytes();
-                let _sig = mac.read_bytes(64);
+                let mut _sig = [0; 64];
+                mac.read_bytes_into(&mut _sig);
                 //Check the signature here later
             }
             JetsNode::EqV256 => {
-                let a = mac.read_32bytes();
-                let b = mac.read_32bytes();
-
-                // FIXME:
-                // Get Error here instead of assert
-                assert!(a == b);
+                if !mac.read_equal_halves(256) {
+                    return Err(JetFailed::AssertionFailed);
+                }
             }
             JetsNode::Sha256 => {
                 let data = mac.read_32bytes();
@@ -316,17 +449,334 @@ impl extension::Jet for JetsNode {
             JetsNode::LessThanV32 => {
                 let a = mac.read_u32();
                 let b = mac.read_u32();
-
-                // FIXME: error
-                assert!(a < b);
+                if a >= b {
+                    return Err(JetFailed::AssertionFailed);
+                }
             }
             JetsNode::EqV32 => {
                 let a = mac.read_u32();
                 let b = mac.read_u32();
-
-                // FIXME: error
-                assert!(a == b);
+                if a != b {
+                    return Err(JetFailed::AssertionFailed);
+                }
             }
+            #[cfg(feature = "secp")]
+            JetsNode::SecpPointAdd => secp::point_add(mac),
+            #[cfg(feature = "secp")]
+            JetsNode::SecpGeneratorMul => secp::generator_mul(mac),
+            #[cfg(feature = "secp")]
+            JetsNode::SecpParseXOnly => secp::parse_xonly(mac),
+            #[cfg(feature = "secp")]
+            JetsNode::Musig2AddPubkey => secp::musig2_add_pubkey(mac),
+            #[cfg(feature = "secp")]
+            JetsNode::Musig2Finalize => secp::musig2_finalize(mac),
+            #[cfg(feature = "secp")]
+            JetsNode::Musig2AggVerify => secp::musig2_agg_verify(mac),
+            // The secp256k1 jets are always decoded, so that the encoding
+            // does not depend on the features enabled, but can only be
+            // executed with the `secp` feature
+            #[cfg(not(feature = "secp"))]
+            JetsNode::SecpPointAdd
+            | JetsNode::SecpGeneratorMul
+            | JetsNode::SecpParseXOnly
+            | JetsNode::Musig2AddPubkey
+            | JetsNode::Musig2Finalize
+            | JetsNode::Musig2AggVerify => return Err(JetFailed::Unsupported),
+        }
+        Ok(())
+    }
+}
+
+/// Output bits of an adder or subtractor jet: a carry bit, then a 32-bit
+/// word, known if all the operands are
+fn add_sub_bits(
+    op: fn(u32, u32) -> (u32, bool),
+    a: Option<u32>,
+    b: Option<u32>,
+    carry: Option<u32>,
+) -> Vec<Option<bool>> {
+    match (a, b, carry) {
+        (Some(a), Some(b), Some(carry)) => {
+            let (res, overflow_1) = op(a, b);
+            let (res, overflow_2) = op(res, carry);
+            known_word((overflow_1 || overflow_2) as u64, 1)
+                .chain(known_word(res as u64, 32))
+                .collect()
+        }
+        _ => vec![None; 33],
+    }
+}
+
+/// The low `width` bits of a word, most significant first, as known bits
+fn known_word(word: u64, width: usize) -> impl Iterator<Item = Option<bool>> {
+    (0..width).rev().map(move |i| Some(word >> i & 1 == 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_hashes::hex::FromHex;
+    use crate::core::types;
+    use crate::extension::dummy::{DummyNode, TxEnv};
+    use crate::extension::Jet;
+    use crate::testing::{self, assert_jet, assert_jet_fails};
+    use crate::{Program, Term, UnTypedProg, Value};
+
+    /// A 256-bit word given as hex
+    fn word256(hex: &str) -> Value {
+        let bytes = Vec::<u8>::from_hex(hex).unwrap();
+        let bits: Vec<bool> = BitIter::from(bytes.into_iter()).collect();
+        Value::from_padded_bits(&bits, &types::pow2_types()[9]).unwrap()
+    }
+
+    fn pair32(a: u32, b: u32) -> Value {
+        Value::prod(Value::u32(a), Value::u32(b))
+    }
+
+    /// A carry bit and a 32-bit word, the output of an adder or subtractor
+    fn carry_word(carry: u8, word: u32) -> Value {
+        Value::prod(Value::u1(carry), Value::u32(word))
+    }
+
+    fn known(bits: &[bool]) -> Vec<Option<bool>> {
+        bits.iter().cloned().map(Some).collect()
+    }
+
+    #[test]
+    fn partial_eval() {
+        let bits: Vec<bool> = known_word(0xdead_beef_0bad_f00d, 64)
+            .chain(Some(Some(true)))
+            .map(Option::unwrap)
+            .collect();
+        let jets = [
+            (JetsNode::Adder32, 64),
+            (JetsNode::FullAdder32, 65),
+            (JetsNode::Subtractor32, 64),
+            (JetsNode::FullSubtractor32, 65),
+            (JetsNode::Multiplier32, 64),
+        ];
+        for &(jet, width) in &jets {
+            let input = &bits[..width];
+            // Fully known inputs give the jet's output
+            assert_eq!(
+                jet.partial_eval(&known(input)),
+                testing::exec_jet_bits(jet, input).map(|output| known(&output)),
+                "{}",
+                jet,
+            );
+            // An unknown bit anywhere makes the whole output unknown
+            let mut partial = known(input);
+            partial[40] = None;
+            let out = jet.partial_eval(&partial).unwrap();
+            assert_eq!(out.len(), jet.target_type().bit_width());
+            assert!(out.iter().all(Option::is_none), "{}", jet);
+            // Input of the wrong width
+            assert_eq!(jet.partial_eval(&partial[1..]), None);
         }
+
+        // Multiplying by zero gives zero, whatever the other factor
+        let mut input = vec![None; 32];
+        input.extend(known(&[false; 32]));
+        assert_eq!(
+            JetsNode::Multiplier32.partial_eval(&input),
+            Some(vec![Some(false); 64])
+        );
+
+        // Other jets know nothing about their output
+        assert_eq!(
+            JetsNode::Sha256.partial_eval(&known(&[false; 512])),
+            Some(vec![None; 256])
+        );
+        assert_eq!(
+            JetsNode::EqV32.partial_eval(&known(&bits[..64])),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn arithmetic_jets() {
+        assert_jet!(JetsNode::Adder32, pair32(2, 3), carry_word(0, 5));
+        assert_jet!(JetsNode::Adder32, pair32(0xffff_ffff, 1), carry_word(1, 0));
+        assert_jet!(
+            JetsNode::FullAdder32,
+            Value::prod(pair32(1, 2), Value::u1(1)),
+            carry_word(0, 4)
+        );
+        assert_jet!(
+            JetsNode::FullAdder32,
+            Value::prod(pair32(0xffff_fffe, 1), Value::u1(1)),
+            carry_word(1, 0)
+        );
+        assert_jet!(JetsNode::Subtractor32, pair32(5, 3), carry_word(0, 2));
+        assert_jet!(
+            JetsNode::Subtractor32,
+            pair32(0, 1),
+            carry_word(1, 0xffff_ffff)
+        );
+        assert_jet!(
+            JetsNode::FullSubtractor32,
+            Value::prod(pair32(5, 3), Value::u1(1)),
+            carry_word(0, 1)
+        );
+        assert_jet!(
+            JetsNode::FullSubtractor32,
+            Value::prod(pair32(0, 0), Value::u1(1)),
+            carry_word(1, 0xffff_ffff)
+        );
+        assert_jet!(
+            JetsNode::Multiplier32,
+            pair32(0x1_0000, 0x1_0000),
+            Value::u64(0x1_0000_0000)
+        );
+        assert_jet!(
+            JetsNode::Multiplier32,
+            pair32(0xffff_ffff, 0xffff_ffff),
+            Value::u64(0xffff_fffe_0000_0001)
+        );
+        // The largest product plus both addends still fits in 64 bits
+        assert_jet!(
+            JetsNode::FullMultiplier32,
+            Value::prod(
+                pair32(0xffff_ffff, 0xffff_ffff),
+                pair32(0xffff_ffff, 0xffff_ffff)
+            ),
+            Value::u64(0xffff_ffff_ffff_ffff)
+        );
+    }
+
+    #[test]
+    fn hash_jets() {
+        // The SHA-256 initial value and the padded block of "abc"
+        let iv = word256("6a09e667bb67ae853c6ef372a54ff53a510e527f9b05688c1f83d9ab5be0cd19");
+        let block = Value::prod(
+            word256("6162638000000000000000000000000000000000000000000000000000000000"),
+            word256("0000000000000000000000000000000000000000000000000000000000000018"),
+        );
+        assert_jet!(
+            JetsNode::Sha256HashBlock,
+            Value::prod(iv, block),
+            word256("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+
+        // Only the first half of the input is hashed
+        let zero = word256(&"00".repeat(32));
+        assert_jet!(
+            JetsNode::Sha256,
+            Value::prod(zero.clone(), zero),
+            word256("66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925")
+        );
+    }
+
+    #[test]
+    fn sha256_hash_block_midstate() {
+        use crate::bitcoin_hashes::{sha256, Hash};
+
+        // sha256hashblock gives the midstate after compressing one block,
+        // so hashing a 64-byte message takes a second block for the padding
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+        let message: Vec<u8> = (0..64).collect();
+        let mut padding = [0u8; 64];
+        padding[0] = 0x80;
+        padding[62] = 0x02;
+        let block =
+            |bytes: &[u8]| Value::prod(word256(&hex(&bytes[..32])), word256(&hex(&bytes[32..])));
+
+        let iv = word256("6a09e667bb67ae853c6ef372a54ff53a510e527f9b05688c1f83d9ab5be0cd19");
+        let midstate =
+            testing::exec_jet(JetsNode::Sha256HashBlock, &Value::prod(iv, block(&message)))
+                .unwrap();
+        let digest = sha256::Hash::hash(&message).into_inner();
+        assert_ne!(midstate, word256(&hex(&digest)));
+        assert_jet!(
+            JetsNode::Sha256HashBlock,
+            Value::prod(midstate, block(&padding)),
+            word256(&hex(&digest))
+        );
+    }
+
+    #[test]
+    fn secp_jet_encoding() {
+        // The secp256k1 jets decode the same way whatever the features
+        let jets = [
+            (JetsNode::SecpPointAdd, 0xf5),
+            (JetsNode::SecpGeneratorMul, 0xf6),
+            (JetsNode::SecpParseXOnly, 0xf7),
+            (JetsNode::Musig2AddPubkey, 0xf8),
+            (JetsNode::Musig2Finalize, 0xf9),
+            (JetsNode::Musig2AggVerify, 0xfa),
+        ];
+        for &(jet, code) in &jets {
+            let mut w = encode::BitWriter::new(vec![]);
+            assert_eq!(jet.encode(&mut w).unwrap(), 8);
+            encode::BitWrite::flush_all(&mut w).unwrap();
+            assert_eq!(w.into_inner(), vec![code]);
+
+            let mut iter = BitIter::from(vec![code].into_iter());
+            iter.read_bits_be(2).unwrap();
+            assert_eq!(JetsNode::decode(&mut iter).unwrap(), jet);
+        }
+
+        // Without the `secp` feature they cannot be executed
+        #[cfg(not(feature = "secp"))]
+        for &(jet, _) in &jets {
+            let prog = Program::<DummyNode>::from_untyped_nodes(
+                UnTypedProg(vec![Term::Jet(jet)]),
+                &mut BitIter::from(vec![0x00].into_iter()),
+            )
+            .unwrap();
+            let input = Value::from_padded_bits(
+                &vec![false; prog.root_node().source_ty.bit_width()],
+                &prog.root_node().source_ty,
+            )
+            .unwrap();
+            let mut mac = exec::BitMachine::for_program(&prog);
+            assert_eq!(
+                mac.exec_with_input(&prog, &input, &TxEnv),
+                Err(exec::ExecError::JetFailed(
+                    prog.id(0),
+                    JetFailed::Unsupported
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn verify_jets() {
+        let a = word256(&"ab".repeat(32));
+        let b = word256(&"cd".repeat(32));
+        assert_jet!(
+            JetsNode::EqV256,
+            Value::prod(a.clone(), a.clone()),
+            Value::Unit
+        );
+        assert_jet_fails!(JetsNode::EqV256, Value::prod(a.clone(), b.clone()));
+
+        assert_jet!(JetsNode::LessThanV32, pair32(1, 2), Value::Unit);
+        assert_jet_fails!(JetsNode::LessThanV32, pair32(2, 2));
+        assert_jet_fails!(JetsNode::LessThanV32, pair32(2, 1));
+        assert_jet!(JetsNode::EqV32, pair32(7, 7), Value::Unit);
+        assert_jet_fails!(JetsNode::EqV32, pair32(7, 8));
+
+        // A failed assertion is reported as an error at the jet's node
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Jet(JetsNode::EqV32)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let mut mac = exec::BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &pair32(7, 8), &TxEnv),
+            Err(exec::ExecError::JetFailed(
+                prog.id(0),
+                JetFailed::AssertionFailed
+            ))
+        );
+
+        // Signatures are not checked yet
+        assert_jet!(
+            JetsNode::SchnorrAssert,
+            Value::prod(a, Value::prod(b.clone(), b)),
+            Value::Unit
+        );
     }
 }
@@ -0,0 +1,543 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # secp256k1 Jets
+//!
+//! Point and scalar arithmetic on the secp256k1 curve, for programs which
+//! derive or tweak keys. Points are represented as a pair of 256-bit
+//! big-endian field elements `(x, y)`, and each jet outputs `1 + point`,
+//! taking the left branch when the result is not a valid point (e.g. the
+//! point at infinity).
+//!
+//! The MuSig2 jets work on a context holding up to `MUSIG2_MAX_SIGNERS`
+//! compressed public keys, each represented as a parity bit and an x
+//! coordinate, preceded by a 64-bit count of the keys in use. The number
+//! of slots is part of the jets' types, so it is fixed for all programs.
+//! Key aggregation follows BIP-327 and signatures are verified against
+//! the aggregate key following BIP-340, with hashes reduced modulo the
+//! group order wherever they are used as scalars.
+//!
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{All, PublicKey, Secp256k1, SecretKey};
+
+use crate::exec;
+
+thread_local! {
+    /// Context shared by all secp256k1 jets run on a thread, since
+    /// creating one is much slower than any jet
+    static SECP: Secp256k1<All> = Secp256k1::new();
+}
+
+/// Order of the secp256k1 group, big-endian
+const ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// Number of public key slots in a MuSig2 context
+pub const MUSIG2_MAX_SIGNERS: usize = 4;
+
+/// Width in bits of a MuSig2 context
+const MUSIG2_CONTEXT_WIDTH: usize = 64 + MUSIG2_MAX_SIGNERS * 257;
+
+/// Read an `(x, y)` point from the active read frame
+fn read_point(mac: &mut exec::BitMachine) -> Option<PublicKey> {
+    let mut ser = [0x04; 65];
+    ser[1..33].copy_from_slice(&mac.read_32bytes());
+    ser[33..65].copy_from_slice(&mac.read_32bytes());
+    PublicKey::from_slice(&ser).ok()
+}
+
+/// Write an optional point to the active write frame
+fn write_point(mac: &mut exec::BitMachine, point: Option<PublicKey>) {
+    match point {
+        Some(point) => {
+            mac.write_bit(true);
+            mac.write_bytes(&point.serialize_uncompressed()[1..]);
+        }
+        None => {
+            mac.write_bit(false);
+            mac.write_padding(512);
+        }
+    }
+}
+
+/// `(point, point) → 1 + point`: add two points
+pub(crate) fn point_add(mac: &mut exec::BitMachine) {
+    let a = read_point(mac);
+    let b = read_point(mac);
+    let sum = match (a, b) {
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+        _ => None,
+    };
+    write_point(mac, sum);
+}
+
+/// `2^256 → 1 + point`: multiply the generator by a scalar, which must
+/// be nonzero and less than the group order
+pub(crate) fn generator_mul(mac: &mut exec::BitMachine) {
+    let scalar = mac.read_32bytes();
+    write_point(mac, mul_generator(&scalar));
+}
+
+/// `2^256 → 1 + point`: lift an x-only public key to the point with that
+/// x coordinate and an even y coordinate
+pub(crate) fn parse_xonly(mac: &mut exec::BitMachine) {
+    let mut ser = [0x02; 33];
+    ser[1..].copy_from_slice(&mac.read_32bytes());
+    write_point(mac, PublicKey::from_slice(&ser).ok());
+}
+
+/// BIP-340 tagged hash of the concatenation of some byte strings
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    for d in data {
+        engine.input(d);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Reduce a 256-bit big-endian number modulo the group order. Since the
+/// order is more than half of 2^256, one subtraction is always enough.
+fn reduce_scalar(scalar: &[u8; 32]) -> [u8; 32] {
+    let mut ret = *scalar;
+    if ret[..] >= ORDER[..] {
+        let mut borrow = 0;
+        for i in (0..32).rev() {
+            let diff = i16::from(ret[i]) - i16::from(ORDER[i]) - borrow;
+            borrow = (diff < 0) as i16;
+            ret[i] = (diff + 256 * borrow) as u8;
+        }
+    }
+    ret
+}
+
+/// Sum of two points, where `None` stands for the point at infinity
+fn add_points(a: Option<PublicKey>, b: Option<PublicKey>) -> Option<PublicKey> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Product of a point and a scalar, reduced modulo the group order, where
+/// `None` stands for the point at infinity
+fn mul_point(point: &PublicKey, scalar: &[u8; 32]) -> Option<PublicKey> {
+    let mut ret = *point;
+    // Tweaking fails only if the reduced scalar is zero
+    SECP.with(|secp| ret.mul_assign(secp, &reduce_scalar(scalar)))
+        .ok()?;
+    Some(ret)
+}
+
+/// Product of the generator and a scalar less than the group order, where
+/// `None` stands for the point at infinity
+fn mul_generator(scalar: &[u8; 32]) -> Option<PublicKey> {
+    let sk = SecretKey::from_slice(scalar).ok()?;
+    Some(SECP.with(|secp| PublicKey::from_secret_key(secp, &sk)))
+}
+
+/// Flip the parity of the y coordinate of a point
+fn negate(point: &PublicKey) -> PublicKey {
+    let mut ser = point.serialize();
+    ser[0] ^= 1;
+    PublicKey::from_slice(&ser).expect("negation of a valid point")
+}
+
+/// Read a MuSig2 context, returning the serialized keys in use, or `None`
+/// if the count is out of range
+fn read_context(mac: &mut exec::BitMachine) -> Option<Vec<[u8; 33]>> {
+    let count = mac.read_u64();
+    let mut keys = Vec::with_capacity(MUSIG2_MAX_SIGNERS);
+    for _ in 0..MUSIG2_MAX_SIGNERS {
+        let mut key = [0; 33];
+        key[0] = 0x02 + mac.read_bit() as u8;
+        key[1..].copy_from_slice(&mac.read_32bytes());
+        keys.push(key);
+    }
+    if count > MUSIG2_MAX_SIGNERS as u64 {
+        return None;
+    }
+    keys.truncate(count as usize);
+    Some(keys)
+}
+
+/// BIP-327 key aggregation, returning the aggregate point, or `None` if
+/// any key is invalid or the aggregate is the point at infinity
+fn key_agg(keys: &[[u8; 33]]) -> Option<PublicKey> {
+    let list: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+    let list_hash = tagged_hash("KeyAgg list", &list);
+    let second = keys.iter().find(|k| **k != keys[0]);
+
+    let mut agg = None;
+    for key in keys {
+        let point = PublicKey::from_slice(key).ok()?;
+        let term = if Some(key) == second {
+            Some(point)
+        } else {
+            mul_point(
+                &point,
+                &tagged_hash("KeyAgg coefficient", &[&list_hash, key]),
+            )
+        };
+        agg = add_points(agg, term);
+    }
+    agg
+}
+
+/// BIP-340 verification of a signature against an x-only public key
+fn verify_bip340(pk_x: &[u8; 32], sig: &[u8], msg: &[u8; 32]) -> bool {
+    let verify = || -> Option<bool> {
+        let mut pk = [0x02; 33];
+        pk[1..].copy_from_slice(pk_x);
+        let p = PublicKey::from_slice(&pk).ok()?;
+        let mut r = [0; 32];
+        let mut s = [0; 32];
+        r.copy_from_slice(&sig[..32]);
+        s.copy_from_slice(&sig[32..]);
+        if s >= ORDER {
+            return Some(false);
+        }
+
+        // R = sG - eP, which must not be the point at infinity
+        let e = tagged_hash("BIP0340/challenge", &[&r, pk_x, msg]);
+        let r_point = add_points(mul_generator(&s), mul_point(&negate(&p), &e))?.serialize();
+        Some(r_point[0] == 0x02 && r_point[1..] == r)
+    };
+    verify().unwrap_or(false)
+}
+
+/// `ctx × pubkey → 1 + ctx`: append a public key to a MuSig2 context,
+/// failing if the context is full
+pub(crate) fn musig2_add_pubkey(mac: &mut exec::BitMachine) {
+    let keys = read_context(mac);
+    let mut key = [0; 33];
+    key[0] = 0x02 + mac.read_bit() as u8;
+    key[1..].copy_from_slice(&mac.read_32bytes());
+
+    match keys {
+        Some(mut keys) if keys.len() < MUSIG2_MAX_SIGNERS => {
+            keys.push(key);
+            mac.write_bit(true);
+            mac.write_u64(keys.len() as u64);
+            for i in 0..MUSIG2_MAX_SIGNERS {
+                let key = keys.get(i).cloned().unwrap_or([0x02; 33]);
+                mac.write_bit(key[0] == 0x03);
+                mac.write_bytes(&key[1..]);
+            }
+        }
+        _ => {
+            mac.write_bit(false);
+            mac.write_padding(MUSIG2_CONTEXT_WIDTH);
+        }
+    }
+}
+
+/// `ctx → 1 + 2^256`: compute the x-only aggregate public key of the keys
+/// in a MuSig2 context, failing if there are none or any is invalid
+pub(crate) fn musig2_finalize(mac: &mut exec::BitMachine) {
+    let agg = read_context(mac)
+        .filter(|keys| !keys.is_empty())
+        .and_then(|keys| key_agg(&keys));
+    match agg {
+        Some(agg) => {
+            mac.write_bit(true);
+            mac.write_bytes(&agg.serialize()[1..]);
+        }
+        None => {
+            mac.write_bit(false);
+            mac.write_padding(256);
+        }
+    }
+}
+
+/// `ctx × (sig × msg) → 2`: verify a signature by the aggregate key of the
+/// keys in a MuSig2 context
+pub(crate) fn musig2_agg_verify(mac: &mut exec::BitMachine) {
+    let keys = read_context(mac);
+    let mut sig = [0; 64];
+    mac.read_bytes_into(&mut sig);
+    let msg = mac.read_32bytes();
+
+    let valid = keys
+        .filter(|keys| !keys.is_empty())
+        .and_then(|keys| key_agg(&keys))
+        .map(|agg| {
+            let mut agg_x = [0; 32];
+            agg_x.copy_from_slice(&agg.serialize()[1..]);
+            verify_bip340(&agg_x, &sig, &msg)
+        })
+        .unwrap_or(false);
+    mac.write_bit(valid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::extension::jets::JetsNode;
+    use crate::testing;
+
+    const G_X: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const G_Y: &str = "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+    const G2_X: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const G2_Y: &str = "1ae168fea63dc339a3c58419466ceaeef7f632653266d0e1236431a950cfe52a";
+
+    fn hex_bytes(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn to_bits(bytes: &[u8]) -> Vec<bool> {
+        BitIter::from(bytes.iter().cloned()).collect()
+    }
+
+    fn to_bytes(bits: &[bool]) -> Vec<u8> {
+        bits.chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &b| acc * 2 + b as u8))
+            .collect()
+    }
+
+    /// Run a single jet on the given input bytes, returning the output
+    /// bytes, or `None` if the jet took the left branch of its output
+    fn run_jet(jet: JetsNode, input: &[u8]) -> Option<Vec<u8>> {
+        let bits = testing::exec_jet_bits(jet, &to_bits(input)).unwrap();
+        if bits[0] {
+            Some(to_bytes(&bits[1..]))
+        } else {
+            None
+        }
+    }
+
+    /// Encode a MuSig2 context holding the given compressed keys
+    fn context_bits(keys: &[Vec<u8>]) -> Vec<bool> {
+        let mut bits = to_bits(&(keys.len() as u64).to_be_bytes());
+        for i in 0..MUSIG2_MAX_SIGNERS {
+            let key = keys.get(i).cloned().unwrap_or_else(|| vec![0x02; 33]);
+            bits.push(key[0] == 0x03);
+            bits.extend(to_bits(&key[1..]));
+        }
+        bits
+    }
+
+    #[test]
+    fn generator_doubling() {
+        let g = [hex_bytes(G_X), hex_bytes(G_Y)].concat();
+        let g2 = [hex_bytes(G2_X), hex_bytes(G2_Y)].concat();
+
+        let mut one = [0; 32];
+        one[31] = 1;
+        assert_eq!(run_jet(JetsNode::SecpGeneratorMul, &one), Some(g.clone()));
+        let mut two = [0; 32];
+        two[31] = 2;
+        assert_eq!(run_jet(JetsNode::SecpGeneratorMul, &two), Some(g2.clone()));
+        assert_eq!(run_jet(JetsNode::SecpGeneratorMul, &[0; 32]), None);
+
+        let g_plus_g = [&g[..], &g[..]].concat();
+        assert_eq!(run_jet(JetsNode::SecpPointAdd, &g_plus_g), Some(g2));
+        // G + -G is the point at infinity
+        let mut neg_g = g.clone();
+        neg_g[32..].copy_from_slice(&hex_bytes(
+            "b7c52588d95c3b9aa25b0403f1eef75702e84bb7597aabe663b82f6f04ef2777",
+        ));
+        assert_eq!(
+            run_jet(JetsNode::SecpPointAdd, &[&g[..], &neg_g[..]].concat()),
+            None
+        );
+
+        assert_eq!(run_jet(JetsNode::SecpParseXOnly, &hex_bytes(G_X)), Some(g));
+        // x = 5 is not on the curve
+        let mut five = [0; 32];
+        five[31] = 5;
+        assert_eq!(run_jet(JetsNode::SecpParseXOnly, &five), None);
+    }
+
+    #[test]
+    fn scalar_reduction() {
+        let mut scalar = ORDER;
+        assert_eq!(reduce_scalar(&scalar), [0; 32]);
+        scalar[31] += 2;
+        let mut two = [0; 32];
+        two[31] = 2;
+        assert_eq!(reduce_scalar(&scalar), two);
+        assert_eq!(reduce_scalar(&two), two);
+        // 2^256 - 1 - n, which needs a borrow through most bytes
+        let expected =
+            hex_bytes("000000000000000000000000000000014551231950b75fc4402da1732fc9bebe");
+        assert_eq!(reduce_scalar(&[0xff; 32])[..], expected[..]);
+
+        // Multiplying by n + 2 is multiplying by 2, and by n gives the
+        // point at infinity
+        let g = mul_generator(&{
+            let mut one = [0; 32];
+            one[31] = 1;
+            one
+        })
+        .unwrap();
+        assert_eq!(mul_point(&g, &scalar), mul_generator(&two));
+        assert_eq!(mul_point(&g, &ORDER), None);
+        assert_eq!(add_points(Some(g), Some(negate(&g))), None);
+        assert_eq!(add_points(None, Some(g)), Some(g));
+    }
+
+    #[test]
+    fn musig2_key_agg() {
+        // BIP-327 key aggregation test vectors
+        let x1 = hex_bytes("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+        let x2 = hex_bytes("03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659");
+        let x3 = hex_bytes("023590a94e768f8e1815c2f24b4d80a8e3149316c3518ce7b7ad338368d038ca66");
+        let vectors = [
+            (
+                vec![x1.clone(), x2.clone(), x3.clone()],
+                "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c",
+            ),
+            (
+                vec![x3.clone(), x2.clone(), x1.clone()],
+                "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b",
+            ),
+            (
+                vec![x1.clone(), x1.clone(), x1.clone()],
+                "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935",
+            ),
+            (
+                vec![x1.clone(), x1.clone(), x2.clone(), x2.clone()],
+                "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e",
+            ),
+        ];
+        for (keys, expected) in &vectors {
+            let output =
+                testing::exec_jet_bits(JetsNode::Musig2Finalize, &context_bits(keys)).unwrap();
+            assert!(output[0]);
+            assert_eq!(to_bytes(&output[1..]), hex_bytes(expected));
+        }
+
+        // Build the first context key by key
+        let mut ctx = context_bits(&[]);
+        for key in &vectors[0].0 {
+            let mut input = ctx.clone();
+            input.push(key[0] == 0x03);
+            input.extend(to_bits(&key[1..]));
+            let output = testing::exec_jet_bits(JetsNode::Musig2AddPubkey, &input).unwrap();
+            assert!(output[0]);
+            ctx = output[1..].to_vec();
+        }
+        assert_eq!(ctx, context_bits(&vectors[0].0));
+
+        // A full context takes no more keys, and an empty one has no
+        // aggregate key
+        let mut input = context_bits(&vectors[3].0);
+        input.push(false);
+        input.extend(to_bits(&x3[1..]));
+        assert!(!testing::exec_jet_bits(JetsNode::Musig2AddPubkey, &input).unwrap()[0]);
+        assert!(!testing::exec_jet_bits(JetsNode::Musig2Finalize, &context_bits(&[])).unwrap()[0]);
+    }
+
+    #[test]
+    fn bip340_vectors() {
+        let mut pk = [0; 32];
+        let mut msg = [0; 32];
+        pk.copy_from_slice(&hex_bytes(
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+        ));
+        let sig = hex_bytes(concat!(
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca8215",
+            "25f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+        ));
+        assert!(verify_bip340(&pk, &sig, &msg));
+
+        pk.copy_from_slice(&hex_bytes(
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+        ));
+        msg.copy_from_slice(&hex_bytes(
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+        ));
+        let sig = hex_bytes(concat!(
+            "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de3341",
+            "8906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+        ));
+        assert!(verify_bip340(&pk, &sig, &msg));
+        msg[0] ^= 1;
+        assert!(!verify_bip340(&pk, &sig, &msg));
+    }
+
+    #[test]
+    fn musig2_agg_verify() {
+        let secp = Secp256k1::new();
+        let scalar = |n: u8| {
+            let mut ret = [0; 32];
+            ret[31] = n;
+            SecretKey::from_slice(&ret).unwrap()
+        };
+
+        // Find three signers whose aggregate key has even y, so that the
+        // aggregate secret key needs no negation
+        let (keys, agg_sk, agg_pk) = (1..)
+            .map(|n| {
+                let sks: Vec<SecretKey> = (n..n + 3).map(scalar).collect();
+                let keys: Vec<[u8; 33]> = sks
+                    .iter()
+                    .map(|sk| PublicKey::from_secret_key(&secp, sk).serialize())
+                    .collect();
+                let list: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+                let list_hash = tagged_hash("KeyAgg list", &list);
+                let mut agg_sk = sks[1];
+                for &i in &[0, 2] {
+                    let mut sk = sks[i];
+                    sk.mul_assign(&tagged_hash("KeyAgg coefficient", &[&list_hash, &keys[i]]))
+                        .unwrap();
+                    agg_sk.add_assign(&sk[..]).unwrap();
+                }
+                let agg_pk = PublicKey::from_secret_key(&secp, &agg_sk).serialize();
+                (keys, agg_sk, agg_pk)
+            })
+            .find(|&(_, _, agg_pk)| agg_pk[0] == 0x02)
+            .unwrap();
+        assert_eq!(key_agg(&keys).unwrap().serialize(), agg_pk);
+
+        // Sign with a nonce whose point has even y
+        let msg = [0xab; 32];
+        let (k, r) = (1..)
+            .map(|n| {
+                (
+                    scalar(n),
+                    PublicKey::from_secret_key(&secp, &scalar(n)).serialize(),
+                )
+            })
+            .find(|&(_, r)| r[0] == 0x02)
+            .unwrap();
+        let e = tagged_hash("BIP0340/challenge", &[&r[1..], &agg_pk[1..], &msg]);
+        let mut s = agg_sk;
+        s.mul_assign(&e).unwrap();
+        s.add_assign(&k[..]).unwrap();
+        let sig = [&r[1..], &s[..]].concat();
+
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        let signed = |keys: &[Vec<u8>], msg: &[u8]| {
+            let mut input = context_bits(keys);
+            input.extend(to_bits(&sig));
+            input.extend(to_bits(msg));
+            testing::exec_jet_bits(JetsNode::Musig2AggVerify, &input).unwrap()
+        };
+        assert_eq!(signed(&keys, &msg), vec![true]);
+        assert_eq!(signed(&keys, &[0xac; 32]), vec![false]);
+        assert_eq!(signed(&keys[..2], &msg), vec![false]);
+    }
+}
@@ -24,7 +24,7 @@ pub mod dummy;
 pub mod elements;
 pub mod jets;
 
-use std::{fmt, io};
+use std::{cmp, error, fmt, io};
 
 use crate::bititer::BitIter;
 use crate::cmr::Cmr;
@@ -35,6 +35,27 @@ use crate::Error;
 #[cfg(not(feature = "bitcoin"))]
 pub use self::dummy as bitcoin;
 
+/// Ways in which executing a jet can fail
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum JetFailed {
+    /// The jet's input does not satisfy the condition the jet asserts
+    AssertionFailed,
+    /// The jet cannot be executed by this build of the library, e.g.
+    /// because it needs a feature which is not enabled
+    Unsupported,
+}
+
+impl fmt::Display for JetFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JetFailed::AssertionFailed => f.write_str("Jet assertion failed"),
+            JetFailed::Unsupported => f.write_str("Jet is not supported"),
+        }
+    }
+}
+
+impl error::Error for JetFailed {}
+
 /// Types used by Bitcoin/Elements primitives and hardcoded jets
 ///
 /// The type inference engine does not have direct access to Bitcoin/Elements
@@ -50,6 +71,7 @@ pub use self::dummy as bitcoin;
 /// |------|--------------|
 /// | `1`  | unit         |
 /// | `2`  | single bit   |
+/// | `c`  | 8-bit word   |
 /// | `i`  | 32-bit word  |
 /// | `l`  | 64-bit word  |
 /// | `h`  | 256-bit word |
@@ -57,6 +79,27 @@ pub use self::dummy as bitcoin;
 #[derive(Clone)]
 pub struct TypeName(pub &'static [u8]);
 
+impl TypeName {
+    /// Width, in bits, of values of the named type in the Bit Machine
+    pub fn bit_width(&self) -> usize {
+        fn width<I: Iterator<Item = u8>>(n: &mut I) -> usize {
+            match n.next() {
+                Some(b'1') => 0,
+                Some(b'2') => 1,
+                Some(b'c') => 8,
+                Some(b'i') => 32,
+                Some(b'l') => 64,
+                Some(b'h') => 256,
+                Some(b'+') => 1 + cmp::max(width(n), width(n)),
+                Some(b'*') => width(n) + width(n),
+                Some(x) => panic!("Do not understand byte {} in type name", x as char),
+                None => panic!("unexpected end of string parsing type"),
+            }
+        }
+        width(&mut self.clone())
+    }
+}
+
 impl Iterator for TypeName {
     type Item = u8;
     fn next(&mut self) -> Option<u8> {
@@ -81,9 +124,32 @@ pub trait Jet: Sized + fmt::Display {
     /// Encode a node into a bit writer
     fn encode<W: encode::BitWrite>(&self, w: &mut W) -> io::Result<usize>;
 
-    /// Execute the node in a Bit Machine; assuming the surrounding
-    /// program has typechecked, this cannot fail
-    fn exec(&self, mac: &mut exec::BitMachine, txenv: &Self::TxEnv);
+    /// Length, in bits, of the encoding of this node
+    fn encoded_bit_len(&self) -> usize {
+        self.encode(&mut encode::BitWriter::new(io::sink()))
+            .expect("writing to a sink never fails")
+    }
+
+    /// Execute the node in a Bit Machine
+    ///
+    /// Assuming the surrounding program has typechecked, this fails only
+    /// if the jet asserts something about its input which does not hold,
+    /// or if it cannot be executed at all.
+    fn exec(&self, mac: &mut exec::BitMachine, txenv: &Self::TxEnv) -> Result<(), JetFailed>;
+
+    /// Evaluate the node on an input of which only some bits are known,
+    /// as they are laid out in a Bit Machine frame, giving whichever bits
+    /// of the output are determined by them
+    ///
+    /// Returns `None` if `known_bits` is not as wide as the source type.
+    /// The default implementation knows nothing about the output.
+    fn partial_eval(&self, known_bits: &[Option<bool>]) -> Option<Vec<Option<bool>>> {
+        if known_bits.len() == self.source_type().bit_width() {
+            Some(vec![None; self.target_type().bit_width()])
+        } else {
+            None
+        }
+    }
 
     /// Return the CMR of the node
     fn cmr(&self) -> Cmr;
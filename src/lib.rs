@@ -16,24 +16,48 @@
 
 extern crate bitcoin_hashes;
 extern crate byteorder;
+#[cfg(feature = "log")]
+extern crate log;
 extern crate miniscript;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "secp")]
+extern crate secp256k1;
+#[cfg(feature = "test_vectors")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "test_vectors")]
+extern crate toml;
 
 #[macro_use]
 mod macros;
 
+#[cfg(any(test, feature = "bench_util"))]
+pub mod bench_util;
 pub mod bit_machine;
 pub mod bititer;
+pub mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod cmr;
 pub mod core;
 pub mod encode;
 pub mod extension;
+#[cfg(feature = "log")]
+mod logging;
 
 // FIXME: Extend to elements mode.
 #[cfg(feature = "bitcoin")]
 pub mod policy;
 pub mod program;
+pub mod programs;
+#[cfg(feature = "test_vectors")]
+pub mod test_vectors;
+pub mod testing;
+pub mod text;
 
-use std::fmt;
+use std::{error, fmt, io, sync::Arc};
 
 pub use crate::bit_machine::exec;
 pub use crate::core::term::Term;
@@ -48,6 +72,12 @@ use miniscript::{DummyKey, MiniscriptKey};
 pub enum Error {
     /// Unable to unify types in a DAG
     TypeCheck,
+    /// The left child of the `disconnect` node at the given index does
+    /// not take a 256-bit CMR, paired with its other input, as input
+    DisconnectShape(usize),
+    /// The types of the node at the given index do not agree with those
+    /// of its children
+    NodeType(usize),
     /// A recursive type was inferred, violating the "occurs check" of the
     /// type inference engine
     OccursCheck,
@@ -56,9 +86,19 @@ pub enum Error {
     /// Number exceeded 32 bits
     NaturalOverflow,
     /// Non-'case' nodes may not have hidden children
-    NonCaseHiddenChild,
-    /// 'case' nodes may have at most one hidden child
-    CaseMultipleHiddenChildren,
+    NonCaseHiddenChild {
+        /// Index of the hidden node
+        node: usize,
+        /// Index of the node's parent
+        parent: usize,
+        /// Name of the parent's combinator
+        parent_term: &'static str,
+    },
+    /// 'case' nodes may have at most one hidden child, given the index
+    /// of the 'case' node
+    CaseMultipleHiddenChildren(usize),
+    /// The root of a program may not be hidden
+    HiddenRoot,
     /// Bitstream ended early
     EndOfStream,
     /// Tried to allocate too many nodes in a program
@@ -67,12 +107,60 @@ pub enum Error {
     ParseError(&'static str),
     /// Miniscript Error
     MiniscriptError(miniscript::Error),
+    /// I/O error from an underlying reader
+    Io(io::Error),
+    /// Program did not have the expected CMR
+    CmrMismatch,
+    /// The value of the witness node at the given index is not of the
+    /// node's target type
+    WitnessType {
+        /// Index of the witness node
+        node: usize,
+        /// Target type of the node
+        expected: Arc<core::types::FinalType>,
+        /// Value of the node
+        found: Value,
+    },
+    /// A node reference was used with a program other than the one it
+    /// was taken from
+    ForeignNodeId(program::NodeId),
+    /// Error setting up or running the Bit Machine
+    Exec(exec::ExecError),
+    /// Error converting between values and their bit encodings
+    Value(core::term::ValueError),
+    /// Error parsing a program in the surface syntax
+    Text(Box<text::ParseError>),
+    /// Error parsing or typing a program in the text format
+    Annotate(Box<text::AnnotateError>),
+    /// Error removing hidden nodes from a program
+    Strip(program::StripError),
+    /// Error enumerating the execution paths of a program
+    Path(program::PathError),
+    /// A program is not total
+    Totality(program::TotalityError),
+    /// Error replacing a node of a program
+    Replace(program::ReplaceError),
+    /// Error comparing the costs of two programs
+    CostCompare(program::CostCompareError),
+    /// Error decoding a value from compact JSON
+    #[cfg(feature = "json")]
+    Json(core::json::JsonError),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::TypeCheck => f.write_str("Unable to unify types in a DAG"),
+            Error::DisconnectShape(idx) => write!(
+                f,
+                "Left child of disconnect node {} does not take a 256-bit CMR as input",
+                idx
+            ),
+            Error::NodeType(idx) => write!(
+                f,
+                "Types of node {} do not agree with those of its children",
+                idx
+            ),
             Error::OccursCheck => f.write_str(
                 "A recursive type was inferred, violating the of the type inference engine",
             ),
@@ -80,18 +168,106 @@ impl fmt::Display for Error {
                 f.write_str("Node made a back-reference past the beginning of the program")
             }
             Error::NaturalOverflow => f.write_str("Number exceeded 32 bits"),
-            Error::NonCaseHiddenChild => {
-                f.write_str("Non-'case' nodes may not have hidden children")
-            }
-            Error::CaseMultipleHiddenChildren => {
-                f.write_str("'case' nodes may have at most one hidden child")
+            Error::NonCaseHiddenChild {
+                node,
+                parent,
+                parent_term,
+            } => write!(
+                f,
+                "Hidden node {} is a child of non-'case' node {} ({})",
+                node, parent, parent_term
+            ),
+            Error::CaseMultipleHiddenChildren(idx) => {
+                write!(f, "'case' node {} has two hidden children", idx)
             }
+            Error::HiddenRoot => f.write_str("The root of the program is hidden"),
             Error::EndOfStream => f.write_str("Bitstream ended early"),
             Error::TooManyNodes(k) => {
                 write!(f, "Tried to allocate too many nodes in a program: {}", k)
             }
             Error::ParseError(s) => write!(f, "Unrecognized node {}", s),
             Error::MiniscriptError(ref e) => fmt::Display::fmt(e, f),
+            Error::Io(ref e) => fmt::Display::fmt(e, f),
+            Error::CmrMismatch => f.write_str("Program CMR does not match commitment"),
+            Error::WitnessType {
+                node,
+                ref expected,
+                ref found,
+            } => write!(
+                f,
+                "Witness node {} has value {}, which is not of type {}",
+                node, found, expected
+            ),
+            Error::ForeignNodeId(id) => {
+                write!(f, "Node {} is from a different program", id)
+            }
+            Error::Exec(ref e) => fmt::Display::fmt(e, f),
+            Error::Value(ref e) => fmt::Display::fmt(e, f),
+            Error::Text(ref e) => fmt::Display::fmt(e, f),
+            Error::Annotate(ref e) => fmt::Display::fmt(e, f),
+            Error::Strip(ref e) => fmt::Display::fmt(e, f),
+            Error::Path(ref e) => fmt::Display::fmt(e, f),
+            Error::Totality(ref e) => fmt::Display::fmt(e, f),
+            Error::Replace(ref e) => fmt::Display::fmt(e, f),
+            Error::CostCompare(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "json")]
+            Error::Json(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // The wrapping variants display as the error they wrap, so they
+        // continue the chain from that error's source rather than repeating
+        // its message
+        match *self {
+            Error::MiniscriptError(ref e) => error::Error::source(e),
+            Error::Io(ref e) => error::Error::source(e),
+            Error::Exec(ref e) => error::Error::source(e),
+            Error::Value(ref e) => error::Error::source(e),
+            Error::Text(ref e) => error::Error::source(&**e),
+            Error::Annotate(ref e) => error::Error::source(&**e),
+            Error::Strip(ref e) => error::Error::source(e),
+            Error::Path(ref e) => error::Error::source(e),
+            Error::Totality(ref e) => error::Error::source(e),
+            Error::Replace(ref e) => error::Error::source(e),
+            Error::CostCompare(ref e) => error::Error::source(e),
+            #[cfg(feature = "json")]
+            Error::Json(ref e) => error::Error::source(e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Whether this error means that a program, or a witness value in it,
+    /// is ill-typed
+    pub fn is_type_error(&self) -> bool {
+        match *self {
+            Error::TypeCheck
+            | Error::OccursCheck
+            | Error::DisconnectShape(..)
+            | Error::NodeType(..)
+            | Error::WitnessType { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this error means that a program could not be decoded from
+    /// its bit encoding
+    pub fn is_decode_error(&self) -> bool {
+        match *self {
+            Error::BadIndex
+            | Error::NaturalOverflow
+            | Error::NonCaseHiddenChild { .. }
+            | Error::CaseMultipleHiddenChildren(..)
+            | Error::HiddenRoot
+            | Error::EndOfStream
+            | Error::TooManyNodes(..)
+            | Error::ParseError(..)
+            | Error::Io(..) => true,
+            _ => false,
         }
     }
 }
@@ -103,6 +279,84 @@ impl From<miniscript::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<exec::ExecError> for Error {
+    fn from(e: exec::ExecError) -> Error {
+        Error::Exec(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<core::term::ValueError> for Error {
+    fn from(e: core::term::ValueError) -> Error {
+        Error::Value(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<text::ParseError> for Error {
+    fn from(e: text::ParseError) -> Error {
+        Error::Text(Box::new(e))
+    }
+}
+
+#[doc(hidden)]
+impl From<text::AnnotateError> for Error {
+    fn from(e: text::AnnotateError) -> Error {
+        Error::Annotate(Box::new(e))
+    }
+}
+
+#[doc(hidden)]
+impl From<program::StripError> for Error {
+    fn from(e: program::StripError) -> Error {
+        Error::Strip(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<program::PathError> for Error {
+    fn from(e: program::PathError) -> Error {
+        Error::Path(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<program::TotalityError> for Error {
+    fn from(e: program::TotalityError) -> Error {
+        Error::Totality(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<program::ReplaceError> for Error {
+    fn from(e: program::ReplaceError) -> Error {
+        Error::Replace(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<program::CostCompareError> for Error {
+    fn from(e: program::CostCompareError) -> Error {
+        Error::CostCompare(e)
+    }
+}
+
+#[cfg(feature = "json")]
+#[doc(hidden)]
+impl From<core::json::JsonError> for Error {
+    fn from(e: core::json::JsonError) -> Error {
+        Error::Json(e)
+    }
+}
+
 /// Trait describing public key types which can be converted to bitcoin pubkeys
 pub trait PubkeyKey32: MiniscriptKey {
     /// Converts an object to a public key
@@ -124,3 +378,93 @@ impl PubkeyKey32 for DummyKey {
         DummyKey
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bititer::BitIter;
+    use crate::extension::dummy::{DummyNode, TxEnv};
+    use crate::program::PathError;
+    use crate::text::ParseError;
+
+    /// Parse and run a program, with every error converted by `?`
+    fn run(src: &str) -> Result<Value, Error> {
+        let prog =
+            text::parse_program::<DummyNode, _>(src, &mut BitIter::from(vec![0x00].into_iter()))?;
+        let mut mac = exec::BitMachine::for_program(&prog);
+        let output = mac.exec_with_input(&prog, &Value::Unit, &TxEnv)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn display_stability() {
+        assert_eq!(Error::EndOfStream.to_string(), "Bitstream ended early");
+        assert_eq!(
+            Error::from(exec::ExecError::TooMuchMemory {
+                required: 100,
+                limit: 64,
+            })
+            .to_string(),
+            "Program requires 100 bytes of memory, exceeding the limit of 64"
+        );
+        assert_eq!(
+            ParseError::Syntax {
+                line: 3,
+                column: 7,
+                reason: "unexpected token",
+            }
+            .to_string(),
+            "line 3, column 7: unexpected token"
+        );
+        assert_eq!(
+            Error::from(PathError::TooManyPaths { limit: 8 }).to_string(),
+            "Program has more than 8 execution paths"
+        );
+    }
+
+    /// Messages of an error and of each error in its source chain
+    fn chain(err: &dyn error::Error) -> Vec<String> {
+        let mut chain = vec![];
+        let mut next = Some(err);
+        while let Some(e) = next {
+            chain.push(e.to_string());
+            next = e.source();
+        }
+        chain
+    }
+
+    #[test]
+    fn source_chain() {
+        let err = Error::from(ParseError::Finalize(Error::EndOfStream));
+        assert_eq!(chain(&err), vec!["finalizing: Bitstream ended early"]);
+
+        let err = Error::from(io::Error::new(
+            io::ErrorKind::Other,
+            Error::from(exec::ExecError::MissingInput),
+        ));
+        assert_eq!(chain(&err), vec![exec::ExecError::MissingInput.to_string()]);
+    }
+
+    #[test]
+    fn classification() {
+        assert!(Error::TypeCheck.is_type_error());
+        assert!(!Error::TypeCheck.is_decode_error());
+        assert!(Error::EndOfStream.is_decode_error());
+        assert!(!Error::EndOfStream.is_type_error());
+        let err = Error::from(PathError::TooManyPaths { limit: 8 });
+        assert!(!err.is_type_error());
+        assert!(!err.is_decode_error());
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(run("main := const 0x2a").unwrap(), Value::u8(0x2a));
+        match run("main := const") {
+            Err(Error::Text(e)) => match *e {
+                ParseError::Syntax { line: 1, .. } => {}
+                ref e => panic!("unexpected error {}", e),
+            },
+            ref x => panic!("unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Logging
+//!
+//! Helpers for the `log` feature. The `log` crate has no spans, so a span
+//! is a pair of trace events, one when it is entered and one when it is
+//! left, both with the span's name as the `span` field.
+//!
+
+/// Guard for a span, which logs leaving it when dropped, so that early
+/// returns are covered too
+pub(crate) struct Span {
+    name: &'static str,
+}
+
+impl Span {
+    /// Log entering the span with the given name
+    pub(crate) fn enter(name: &'static str) -> Span {
+        log::trace!(span = name; "enter {}", name);
+        Span { name: name }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        log::trace!(span = self.name; "exit {}", self.name);
+    }
+}
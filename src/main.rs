@@ -2725,7 +2725,7 @@ fn main() {
         use std::fs;
 
         let bytes =
-            fs::read(&format!("fuzz/simplicityC_test_inputs/test_{}", i)).expect("opening file");
+            fs::read(format!("fuzz/simplicityC_test_inputs/test_{}", i)).expect("opening file");
         let mut bits: simplicity::bititer::BitIter<_> = bytes.into_iter().into();
         match simplicity::program::Program::<simplicity::extension::dummy::DummyNode>::decode(
             &mut bits,
@@ -2778,7 +2778,8 @@ fn main() {
     mac.input(&Value::prod(
         Value::u2(3),
         Value::prod(Value::u16(1), Value::u16(0)),
-    ));
+    ))
+    .unwrap();
     println!(
         " input: {}",
         Value::prod(Value::prod(Value::u2(3), Value::u16(1)), Value::u16(0))
@@ -19,11 +19,18 @@
 //! data.
 //!
 
-use std::{cmp, fmt, sync::Arc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{cmp, fmt, ops, sync::Arc};
 
 use crate::bititer::BitIter;
 use crate::cmr::{self, Cmr};
 use crate::core::types;
+use crate::encode::{BitWrite, BitWriter};
+use crate::exec;
+use crate::extension::jets::JetsNode;
 use crate::extension::Jet as ExtNode;
 use crate::{encode, extension};
 use crate::{Error, Term, Value};
@@ -44,11 +51,207 @@ pub struct ProgramNode<Ext> {
     /// Target type for this node
     pub target_ty: Arc<types::FinalType>,
     /// Upper bound on the number of cells required in the Bit
-    /// Machine by this node
+    /// Machine by this node, not counting its input and output frames.
+    /// This is attained along the most expensive branch of each `case`.
     pub extra_cells_bound: usize,
-    /// Upper bound on the number of cells required in the Bit
-    /// Machine by this node
+    /// Upper bound on the number of frames on either frame stack of
+    /// the Bit Machine required by this node, not counting its input and
+    /// output frames. This is attained along the most expensive branch
+    /// of each `case`.
     pub frame_count_bound: usize,
+    /// Upper bound on the number of entries on the call stack of
+    /// `BitMachine::exec` while executing this node, on top of any
+    /// already pending when it starts
+    pub call_stack_bound: usize,
+    /// Absolute indices of its children, resolved from the offsets in
+    /// `node` when the program was built
+    children: (Option<usize>, Option<usize>),
+}
+
+impl<Ext> ProgramNode<Ext> {
+    /// Absolute indices of the left and right children of this node
+    ///
+    /// These are resolved from the relative offsets in `node`, which were
+    /// checked against `index`, when the program was built, and are not
+    /// updated if `node` is edited afterwards.
+    pub fn children(&self) -> (Option<usize>, Option<usize>) {
+        self.children
+    }
+
+    /// Absolute index of the left child, or only child, of this node
+    ///
+    /// Panics if the node has no children.
+    pub fn left(&self) -> usize {
+        self.children().0.expect("node has a left child")
+    }
+
+    /// Absolute index of the right child of this node
+    ///
+    /// Panics if the node has fewer than two children.
+    pub fn right(&self) -> usize {
+        self.children().1.expect("node has a right child")
+    }
+
+    /// The children and input layout of a `case` node, or `None` if
+    /// this is not a `case` node whose input is a sum paired with
+    /// another value
+    pub fn case_parts(&self) -> Option<CaseParts> {
+        match (&self.node, &self.source_ty.ty) {
+            (Term::Case(s, t), types::FinalTypeInner::Product(sum, _)) => match sum.ty {
+                types::FinalTypeInner::Sum(ref a, ref b) => Some(CaseParts {
+                    s_offset: *s,
+                    t_offset: *t,
+                    sum_ty_left_width: a.bit_width(),
+                    sum_ty_right_width: b.bit_width(),
+                    max_branch_width: cmp::max(a.bit_width(), b.bit_width()),
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Number of padding bits written after the tag by an `injl` or
+    /// `injr` node, or `None` if this is not an injection into a sum
+    pub fn inj_padding(&self) -> Option<usize> {
+        let (left, right) = self.target_ty.sum_padding()?;
+        match self.node {
+            Term::InjL(..) => Some(left),
+            Term::InjR(..) => Some(right),
+            _ => None,
+        }
+    }
+
+    /// Number of input bits skipped by a `drop` node, or `None` if this
+    /// is not a `drop` node whose input is a product
+    pub fn drop_width(&self) -> Option<usize> {
+        match (&self.node, &self.source_ty.ty) {
+            (Term::Drop(..), types::FinalTypeInner::Product(a, _)) => Some(a.bit_width()),
+            _ => None,
+        }
+    }
+}
+
+/// The children of a `case` node, and the layout of the sum at the
+/// start of its input
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CaseParts {
+    /// Offset back from the node to the child run on a left value
+    pub s_offset: usize,
+    /// Offset back from the node to the child run on a right value
+    pub t_offset: usize,
+    /// Width of the left type of the sum
+    pub sum_ty_left_width: usize,
+    /// Width of the right type of the sum
+    pub sum_ty_right_width: usize,
+    /// Width of the wider of the two types, which is the width of the
+    /// sum less its tag
+    pub max_branch_width: usize,
+}
+
+impl CaseParts {
+    /// Number of padding bits between the tag and a left value, or `None`
+    /// if the left width exceeds `max_branch_width`, which is never the
+    /// case for parts given by `ProgramNode::case_parts`
+    pub fn left_padding(&self) -> Option<usize> {
+        self.max_branch_width.checked_sub(self.sum_ty_left_width)
+    }
+
+    /// Number of padding bits between the tag and a right value, or
+    /// `None` if the right width exceeds `max_branch_width`, which is
+    /// never the case for parts given by `ProgramNode::case_parts`
+    pub fn right_padding(&self) -> Option<usize> {
+        self.max_branch_width.checked_sub(self.sum_ty_right_width)
+    }
+}
+
+/// Allocation or deallocation of a Bit Machine frame, as simulated by
+/// `Program::compute_worst_case_frame_sequence`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameEvent {
+    /// A new frame of the given number of bits was allocated
+    Push(usize),
+    /// The most recently allocated frame was freed
+    Pop,
+}
+
+/// Work left to do by `FrameEvents`
+enum FrameWork {
+    /// Expand the events of executing the node with the given index
+    Node(usize),
+    /// Yield an event
+    Event(FrameEvent),
+}
+
+/// Iterator over the worst case frame events of a program, which keeps
+/// its own stack of work rather than recursing, so that deep programs do
+/// not overflow the call stack
+struct FrameEvents<'a, Ext> {
+    program: &'a Program<Ext>,
+    /// Work left to do, with the next item on top
+    stack: Vec<FrameWork>,
+}
+
+impl<'a, Ext> Iterator for FrameEvents<'a, Ext> {
+    type Item = FrameEvent;
+
+    fn next(&mut self) -> Option<FrameEvent> {
+        loop {
+            let idx = match self.stack.pop()? {
+                FrameWork::Event(event) => return Some(event),
+                FrameWork::Node(idx) => idx,
+            };
+            let nodes = &self.program.nodes;
+            let node = &nodes[idx];
+            // Work is pushed in reverse, so that it is done in order
+            match node.node {
+                Term::Iden
+                | Term::Unit
+                | Term::Witness(..)
+                | Term::Fail(..)
+                | Term::Hidden(..)
+                | Term::Ext(..)
+                | Term::Jet(..) => {}
+                Term::InjL(..) | Term::InjR(..) | Term::Take(..) | Term::Drop(..) => {
+                    self.stack.push(FrameWork::Node(node.left()))
+                }
+                Term::Pair(..) => {
+                    self.stack.push(FrameWork::Node(node.right()));
+                    self.stack.push(FrameWork::Node(node.left()));
+                }
+                Term::Comp(..) => {
+                    let s = &nodes[node.left()];
+                    self.stack.push(FrameWork::Event(FrameEvent::Pop));
+                    self.stack.push(FrameWork::Node(node.right()));
+                    self.stack.push(FrameWork::Node(node.left()));
+                    return Some(FrameEvent::Push(s.target_ty.bit_width()));
+                }
+                Term::Case(..) => {
+                    let (s, t) = (&nodes[node.left()], &nodes[node.right()]);
+                    let cost = |n: &ProgramNode<Ext>| (n.extra_cells_bound, n.frame_count_bound);
+                    let branch = match (&s.node, &t.node) {
+                        (_, Term::Hidden(..)) => s,
+                        (Term::Hidden(..), _) => t,
+                        _ if cost(t) > cost(s) => t,
+                        _ => s,
+                    };
+                    self.stack.push(FrameWork::Node(branch.index));
+                }
+                Term::Disconnect(..) => {
+                    // The output and input frames of `s`, with the input
+                    // frame freed as soon as `s` is done
+                    let s = &nodes[node.left()];
+                    self.stack.push(FrameWork::Event(FrameEvent::Pop));
+                    self.stack.push(FrameWork::Node(node.right()));
+                    self.stack.push(FrameWork::Event(FrameEvent::Pop));
+                    self.stack.push(FrameWork::Node(node.left()));
+                    self.stack
+                        .push(FrameWork::Event(FrameEvent::Push(s.source_ty.bit_width())));
+                    return Some(FrameEvent::Push(s.target_ty.bit_width()));
+                }
+            }
+        }
+    }
 }
 
 impl<Ext: fmt::Display> fmt::Display for ProgramNode<Ext> {
@@ -75,347 +278,5213 @@ impl<Ext: fmt::Display> fmt::Display for ProgramNode<Ext> {
     }
 }
 
+/// Set of node kinds present in a program which need special handling
+/// by the Bit Machine; programs with none of them run on a faster path
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ProgramFeatures(u8);
+
+impl ProgramFeatures {
+    /// The program contains `witness` nodes
+    pub const WITNESS: ProgramFeatures = ProgramFeatures(1);
+    /// The program contains `disconnect` nodes
+    pub const DISCONNECT: ProgramFeatures = ProgramFeatures(2);
+    /// The program contains extension nodes
+    pub const EXT: ProgramFeatures = ProgramFeatures(4);
+
+    /// The empty set of features
+    pub fn empty() -> ProgramFeatures {
+        ProgramFeatures(0)
+    }
+
+    /// Whether no features are set
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all features of `other` are set
+    pub fn contains(self, other: ProgramFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features of a single node
+    fn of_node<W, Ext>(node: &Term<W, Ext>) -> ProgramFeatures {
+        match *node {
+            Term::Witness(..) => ProgramFeatures::WITNESS,
+            Term::Disconnect(..) => ProgramFeatures::DISCONNECT,
+            Term::Ext(..) => ProgramFeatures::EXT,
+            _ => ProgramFeatures::empty(),
+        }
+    }
+
+    /// The features of a list of nodes
+    fn of_nodes<Ext>(nodes: &[ProgramNode<Ext>]) -> ProgramFeatures {
+        nodes.iter().fold(ProgramFeatures::empty(), |acc, node| {
+            acc | ProgramFeatures::of_node(&node.node)
+        })
+    }
+}
+
+impl ops::BitOr for ProgramFeatures {
+    type Output = ProgramFeatures;
+
+    fn bitor(self, other: ProgramFeatures) -> ProgramFeatures {
+        ProgramFeatures(self.0 | other.0)
+    }
+}
+
+impl ops::BitOrAssign for ProgramFeatures {
+    fn bitor_assign(&mut self, other: ProgramFeatures) {
+        self.0 |= other.0;
+    }
+}
+
 /// A fully parsed, witnesses-included Simplicity program
 #[derive(Debug)]
 pub struct Program<Ext> {
     /// The list of nodes in the program
     pub nodes: Vec<ProgramNode<Ext>>,
+    /// Tag identifying this program, shared by the `NodeId`s taken from it
+    pub(crate) tag: usize,
+    /// Kinds of node present in the program, found when it is built
+    features: ProgramFeatures,
 }
 
-impl<Ext: extension::Jet> Program<Ext> {
-    /// Obtain the node representing the root of the program DAG
-    pub fn root_node(&self) -> &ProgramNode<Ext> {
-        &self.nodes[self.nodes.len() - 1]
+/// A fresh tag for a newly built program
+fn next_tag() -> usize {
+    static NEXT_TAG: AtomicUsize = AtomicUsize::new(0);
+    NEXT_TAG.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Reference to a node of a particular program
+///
+/// Unlike a bare index, a `NodeId` is only valid for the program it was
+/// taken from, and using it with any other program is an error, since
+/// the same index may refer to an unrelated node there.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NodeId {
+    pub(crate) index: usize,
+    pub(crate) tag: usize,
+}
+
+impl NodeId {
+    /// Index of the node in its program's list of nodes
+    pub fn index(self) -> usize {
+        self.index
     }
+}
 
-    /// Decode a program from a stream of bits
-    pub fn decode<I: Iterator<Item = u8>>(iter: &mut BitIter<I>) -> Result<Program<Ext>, Error> {
-        // Decode a bunch of untyped, witness-less nodes
-        let nodes = encode::decode_program_no_witness(&mut *iter)?;
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.index, f)
+    }
+}
+
+/// Error returned by `Program::strip_hidden_nodes`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StripError {
+    /// A hidden node is reachable from the root, so removing it would
+    /// change the program
+    ReachableHiddenNode {
+        /// The hidden node
+        node: NodeId,
+        /// CMR of the hidden node
+        cmr: [u8; 32],
+    },
+}
 
-        Program::<Ext>::from_untyped_nodes(nodes, iter)
+impl fmt::Display for StripError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StripError::ReachableHiddenNode { node, cmr } => write!(
+                f,
+                "Hidden node {} with CMR {} is reachable from the root",
+                node,
+                Cmr::from(cmr),
+            ),
+        }
     }
+}
 
-    /// Decode a program from a stream of bits
-    pub fn from_untyped_nodes<I: Iterator<Item = u8>>(
-        nodes: UnTypedProg<(), Ext>,
-        iter: &mut BitIter<I>,
-    ) -> Result<Program<Ext>, Error> {
-        // Do type-checking
-        let typed_nodes = types::type_check(nodes)?;
+impl error::Error for StripError {}
 
-        // Parse witnesses, if available
-        // FIXME actually only read as much as wit_len
-        let _wit_len = match iter.next() {
-            Some(false) => 0,
-            Some(true) => encode::decode_natural(&mut *iter, None)?,
-            None => return Err(Error::EndOfStream),
-        };
+/// How a path enumerated by `Program::enumerate_paths` ends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathOutcome {
+    /// The program produces an output
+    Output,
+    /// Execution reaches the given hidden node
+    Hidden(NodeId),
+    /// Execution reaches the given `fail` node
+    Fail(NodeId),
+}
 
-        let typed_nodes = typed_nodes
-            .into_iter()
-            .map::<Result<_, Error>, _>(|node| {
-                Ok(types::TypedNode {
-                    node: match node.node {
-                        // really, Rust???
-                        Term::Iden => Term::Iden,
-                        Term::Unit => Term::Unit,
-                        Term::InjL(i) => Term::InjL(i),
-                        Term::InjR(i) => Term::InjR(i),
-                        Term::Take(i) => Term::Take(i),
-                        Term::Drop(i) => Term::Drop(i),
-                        Term::Comp(i, j) => Term::Comp(i, j),
-                        Term::Case(i, j) => Term::Case(i, j),
-                        Term::Pair(i, j) => Term::Pair(i, j),
-                        Term::Disconnect(i, j) => Term::Disconnect(i, j),
-                        Term::Witness(()) => Term::Witness(Value::from_bits_and_type(
-                            &mut iter.by_ref(),
-                            &node.target_ty,
-                        )?),
-                        Term::Fail(x, y) => Term::Fail(x, y),
-                        Term::Hidden(x) => Term::Hidden(x),
-                        Term::Ext(e) => Term::Ext(e),
-                        Term::Jet(j) => Term::Jet(j),
-                    },
-                    source_ty: node.source_ty,
-                    target_ty: node.target_ty,
-                })
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+/// A path through a program, as enumerated by `Program::enumerate_paths`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PathResult {
+    /// Each `case` node reached, in order, with whether it takes its
+    /// right branch
+    pub branches: Vec<(NodeId, bool)>,
+    /// How the path ends
+    pub outcome: PathOutcome,
+    /// Type of the program's output, which is the same for every path
+    pub output_ty: Arc<types::FinalType>,
+}
 
-        // Compute cached data and return
-        let mut ret = Vec::<ProgramNode<Ext>>::with_capacity(typed_nodes.len());
-        for (index, node) in typed_nodes.into_iter().enumerate() {
-            let final_node = ProgramNode {
-                index: index,
-                cmr: compute_cmr(&ret, &node.node, index),
-                extra_cells_bound: compute_extra_cells_bound(
-                    &ret,
-                    &node.node,
-                    index,
-                    node.target_ty.bit_width(),
-                ),
-                frame_count_bound: compute_frame_count_bound(&ret, &node.node, index),
-                node: node.node,
-                source_ty: node.source_ty,
-                target_ty: node.target_ty,
-            };
-            ret.push(final_node);
+/// Error returned by `Program::enumerate_paths`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathError {
+    /// Some node has more paths through it than the given limit
+    TooManyPaths {
+        /// The limit
+        limit: usize,
+    },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::TooManyPaths { limit } => {
+                write!(f, "Program has more than {} execution paths", limit)
+            }
         }
+    }
+}
+
+impl error::Error for PathError {}
 
-        Ok(Program { nodes: ret })
+/// Error returned by `Program::check_total_symbolic`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TotalityError {
+    /// Some choice of witness values reaches the given `fail` or hidden
+    /// nodes, in order of index
+    Partial(Vec<NodeId>),
+    /// The exploration was abandoned
+    Paths(PathError),
+}
+
+impl From<PathError> for TotalityError {
+    fn from(e: PathError) -> TotalityError {
+        TotalityError::Paths(e)
     }
+}
 
-    /// Print out the program in a graphviz-parseable format
-    pub fn graph_print(&self) {
-        for node in &self.nodes {
-            println!(
-                "{} [label=\"{}\\n{}\\n{} → {}\"];",
-                node.index,
-                match node.node {
-                    Term::Iden => "iden",
-                    Term::Unit => "unit",
-                    Term::InjL(..) => "injl",
-                    Term::InjR(..) => "injr",
-                    Term::Take(..) => "take",
-                    Term::Drop(..) => "drop",
-                    Term::Comp(..) => "comp",
-                    Term::Case(..) => "case",
-                    Term::Pair(..) => "pair",
-                    Term::Disconnect(..) => "disconnect",
-                    Term::Witness(..) => "witness",
-                    Term::Hidden(..) => "hidden",
-                    Term::Fail(..) => "fail",
-                    Term::Ext(..) => "[ext]", // FIXME `ext` and `jet` should passthrough
-                    Term::Jet(..) => "[jet]",
-                },
-                node.index,
-                node.source_ty,
-                node.target_ty,
-            );
-            match node.node {
-                Term::Iden
-                | Term::Unit
-                | Term::Witness(..)
-                | Term::Hidden(..)
-                | Term::Fail(..)
-                | Term::Ext(..)
-                | Term::Jet(..) => {}
-                Term::InjL(i) | Term::InjR(i) | Term::Take(i) | Term::Drop(i) => {
-                    println!("  {} -> {};", node.index, node.index - i);
-                }
-                Term::Comp(i, j) | Term::Case(i, j) | Term::Pair(i, j) | Term::Disconnect(i, j) => {
-                    println!("  {} -> {} [color=red];", node.index, node.index - i);
-                    println!("  {} -> {} [color=blue];", node.index, node.index - j);
+impl fmt::Display for TotalityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TotalityError::Partial(ref nodes) => {
+                f.write_str("Program may fail, reaching node")?;
+                for (n, node) in nodes.iter().enumerate() {
+                    let sep = if n == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, node)?;
                 }
+                Ok(())
             }
+            TotalityError::Paths(ref e) => fmt::Display::fmt(e, f),
         }
     }
 }
 
-fn compute_cmr<Ext: extension::Jet>(
-    program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
-    idx: usize,
-) -> Cmr {
-    match *node {
-        Term::Iden => cmr::tag::iden(),
-        Term::Unit => cmr::tag::unit(),
-        Term::InjL(i) => cmr::tag::injl().update_1(program[idx - i].cmr),
-        Term::InjR(i) => cmr::tag::injr().update_1(program[idx - i].cmr),
-        Term::Take(i) => cmr::tag::take().update_1(program[idx - i].cmr),
-        Term::Drop(i) => cmr::tag::drop().update_1(program[idx - i].cmr),
-        Term::Comp(i, j) => cmr::tag::comp().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Case(i, j) => cmr::tag::case().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Pair(i, j) => cmr::tag::pair().update(program[idx - i].cmr, program[idx - j].cmr),
-        Term::Disconnect(i, _) => cmr::tag::disconnect().update_1(program[idx - i].cmr),
-        Term::Witness(..) => cmr::tag::witness(),
-        Term::Fail(..) => unimplemented!(),
-        Term::Hidden(cmr) => cmr,
-        Term::Ext(ref b) => b.cmr(),
-        Term::Jet(ref j) => j.cmr(),
+impl error::Error for TotalityError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            TotalityError::Partial(..) => None,
+            TotalityError::Paths(ref e) => error::Error::source(e),
+        }
     }
 }
 
-fn compute_extra_cells_bound<Ext: extension::Jet>(
-    program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
-    idx: usize,
-    witness_target_width: usize,
-) -> usize {
-    match *node {
-        Term::Iden => 0,
-        Term::Unit => 0,
-        Term::InjL(i) => program[idx - i].extra_cells_bound,
-        Term::InjR(i) => program[idx - i].extra_cells_bound,
-        Term::Take(i) => program[idx - i].extra_cells_bound,
-        Term::Drop(i) => program[idx - i].extra_cells_bound,
-        Term::Comp(i, j) => {
-            program[idx - i].target_ty.bit_width()
-                + cmp::max(
-                    program[idx - i].extra_cells_bound,
-                    program[idx - j].extra_cells_bound,
-                )
+/// A value in `Program::check_total_symbolic`, any part of which may be
+/// unknown
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum SymValue {
+    Unknown,
+    Unit,
+    SumL(Arc<SymValue>),
+    SumR(Arc<SymValue>),
+    Prod(Arc<SymValue>, Arc<SymValue>),
+}
+
+impl SymValue {
+    /// The product of two values, which is unknown if both are
+    fn prod(a: SymValue, b: SymValue) -> SymValue {
+        match (a, b) {
+            (SymValue::Unknown, SymValue::Unknown) => SymValue::Unknown,
+            (a, b) => SymValue::Prod(Arc::new(a), Arc::new(b)),
         }
-        Term::Case(i, j) => cmp::max(
-            program[idx - i].extra_cells_bound,
-            program[idx - j].extra_cells_bound,
-        ),
-        Term::Pair(i, j) => cmp::max(
-            program[idx - i].extra_cells_bound,
-            program[idx - j].extra_cells_bound,
-        ),
-        Term::Disconnect(i, j) => {
-            program[idx - i].source_ty.bit_width()
-                + program[idx - i].target_ty.bit_width()
-                + cmp::max(
-                    program[idx - i].extra_cells_bound,
-                    program[idx - j].extra_cells_bound,
-                )
+    }
+
+    /// The two halves of a product, which are unknown if it is
+    fn halves(&self) -> (SymValue, SymValue) {
+        match *self {
+            SymValue::Prod(ref a, ref b) => ((**a).clone(), (**b).clone()),
+            _ => (SymValue::Unknown, SymValue::Unknown),
+        }
+    }
+}
+
+/// The possible results of running a node in `Program::check_total_symbolic`:
+/// an output, or the index of a `fail` or hidden node which was reached
+type SymOutcomes = Vec<Result<SymValue, usize>>;
+
+/// Error returned by `Program::replace_node`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReplaceError {
+    /// There is no node at the given index
+    InvalidIndex(usize),
+    /// The node at the given index is not reachable from the root, so
+    /// replacing it would not change the program
+    UnreachableNode(usize),
+    /// The replacement's source and target types are not those of the
+    /// node it replaces
+    TypeMismatch {
+        /// Source and target types of the node
+        expected: (Arc<types::FinalType>, Arc<types::FinalType>),
+        /// Source and target types of the replacement
+        found: (Arc<types::FinalType>, Arc<types::FinalType>),
+    },
+}
+
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplaceError::InvalidIndex(idx) => write!(f, "Program has no node {}", idx),
+            ReplaceError::UnreachableNode(idx) => {
+                write!(f, "Node {} is not reachable from the root", idx)
+            }
+            ReplaceError::TypeMismatch {
+                ref expected,
+                ref found,
+            } => write!(
+                f,
+                "Replacement of type {} → {} does not match node of type {} → {}",
+                found.0, found.1, expected.0, expected.1
+            ),
+        }
+    }
+}
+
+impl error::Error for ReplaceError {}
+
+/// One of a pair of differing nodes reported by `Program::diff`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DiffNode {
+    /// Index of the node in its program
+    pub index: usize,
+    /// The node's combinator, with its witness value, jet or hidden CMR
+    pub term: String,
+    /// Source type for this node
+    pub source_ty: Arc<types::FinalType>,
+    /// Target type for this node
+    pub target_ty: Arc<types::FinalType>,
+    /// Its Commitment Merkle Root
+    pub cmr: Cmr,
+}
+
+impl fmt::Display for DiffNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {} → {} (cmr {})",
+            self.index, self.term, self.source_ty, self.target_ty, self.cmr
+        )
+    }
+}
+
+/// A pair of nodes at which two programs first differ along some path
+/// from their roots
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NodeDiff {
+    /// Child taken at each step from the roots to the nodes: `false` for
+    /// the left, or only, child of a node and `true` for its right child
+    pub path: Vec<bool>,
+    /// The node in the left program
+    pub left: DiffNode,
+    /// The node in the right program
+    pub right: DiffNode,
+}
+
+/// Structural differences between two programs, as computed by
+/// `Program::diff`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProgramDiff {
+    /// The first differing nodes along each path, in depth-first order
+    pub differences: Vec<NodeDiff>,
+    /// Number of nodes of the left program with no identical node,
+    /// including descendants, in the right program
+    pub only_left: usize,
+    /// Number of nodes of the right program with no identical node,
+    /// including descendants, in the left program
+    pub only_right: usize,
+    /// Number of nodes of the left program with an identical node in
+    /// the right program
+    pub common: usize,
+}
+
+impl ProgramDiff {
+    /// Whether the two programs are identical
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty() && self.only_left == 0 && self.only_right == 0
+    }
+}
+
+impl fmt::Display for ProgramDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} nodes only in left, {} only in right, {} in common",
+            self.only_left, self.only_right, self.common
+        )?;
+        for diff in &self.differences {
+            f.write_str("at root")?;
+            for &right in &diff.path {
+                f.write_str(if right { ".1" } else { ".0" })?;
+            }
+            writeln!(f, ":\n  - {}\n  + {}", diff.left, diff.right)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node's combinator, with its witness value, jet or hidden CMR but
+/// without its children
+fn diff_label<Ext: fmt::Display>(node: &ProgramNode<Ext>) -> String {
+    match node.node {
+        Term::Witness(ref value) => format!("{} {}", node.node.name(), value),
+        Term::Hidden(ref cmr) => format!("{} {}", node.node.name(), cmr),
+        Term::Fail(..) => format!("{} {}", node.node.name(), node.cmr),
+        _ => node.node.name(),
+    }
+}
+
+/// Key identifying a node by its label and types and the classes of its
+/// children, as used by `Program::diff`
+type DiffKey = (String, String, String, Option<usize>, Option<usize>);
+
+/// Suspicious structure in a program, as found by `Program::lint`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LintWarning {
+    /// A branch of a `case` node can never be taken, as the node's input
+    /// is always built with the same constant injection
+    DeadBranch {
+        /// The `case` node
+        node: NodeId,
+        /// Whether the dead branch is the right branch
+        right: bool,
+    },
+    /// The value of a witness node never affects the output, as it is
+    /// always dropped before it is read
+    UnusedWitness(NodeId),
+    /// A node other than the root is not a child of any node
+    UnreferencedNode(NodeId),
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LintWarning::DeadBranch { node, right } => write!(
+                f,
+                "{} branch of case node {} is never taken",
+                if right { "right" } else { "left" },
+                node
+            ),
+            LintWarning::UnusedWitness(node) => write!(
+                f,
+                "value of witness node {} is dropped without being read",
+                node
+            ),
+            LintWarning::UnreferencedNode(node) => {
+                write!(f, "node {} is not used by any other node", node)
+            }
+        }
+    }
+}
+
+/// Which parts of a node's input, or output, are read, as tracked by
+/// `Program::lint`
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Usage {
+    /// None of it
+    Unused,
+    /// Parts of each half of a product
+    Pair(Box<Usage>, Box<Usage>),
+    /// Possibly all of it
+    All,
+}
+
+impl Usage {
+    /// Usage of a product whose halves have the given usages
+    fn pair(left: Usage, right: Usage) -> Usage {
+        match (left, right) {
+            (Usage::Unused, Usage::Unused) => Usage::Unused,
+            (Usage::All, Usage::All) => Usage::All,
+            (left, right) => Usage::Pair(Box::new(left), Box::new(right)),
+        }
+    }
+
+    /// Usages of the two halves of a product with this usage
+    fn halves(&self) -> (Usage, Usage) {
+        match *self {
+            Usage::Unused => (Usage::Unused, Usage::Unused),
+            Usage::Pair(ref left, ref right) => ((**left).clone(), (**right).clone()),
+            Usage::All => (Usage::All, Usage::All),
+        }
+    }
+
+    /// Parts read by either of two usages
+    fn join(&self, other: &Usage) -> Usage {
+        match (self, other) {
+            (Usage::Unused, x) | (x, Usage::Unused) => x.clone(),
+            (Usage::Pair(a, b), Usage::Pair(c, d)) => Usage::pair(a.join(c), b.join(d)),
+            _ => Usage::All,
+        }
+    }
+}
+
+/// Cost of executing a program on a set of inputs, as reported by
+/// `Program::cost_compare`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ExecCost {
+    /// Number of nodes executed
+    pub steps: u64,
+    /// Number of jets, and extension nodes, executed
+    pub jets: u64,
+    /// Cost of execution, as in `exec::ResourceUsage::cost_consumed`
+    pub cost: u64,
+}
+
+/// Costs of executing two programs on the same inputs, as computed by
+/// `Program::cost_compare`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CostComparison {
+    /// Cost of the program `cost_compare` was called on
+    pub left: ExecCost,
+    /// Cost of the program it was compared with
+    pub right: ExecCost,
+}
+
+impl CostComparison {
+    /// How the cost of the left program compares to that of the right:
+    /// `Less` if the left program is cheaper
+    pub fn ordering(&self) -> cmp::Ordering {
+        self.left.cost.cmp(&self.right.cost)
+    }
+}
+
+impl fmt::Display for CostComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(name, cost) in &[("left", self.left), ("right", self.right)] {
+            writeln!(
+                f,
+                "{}: cost {}, {} steps, {} jets",
+                name, cost.cost, cost.steps, cost.jets
+            )?;
+        }
+        match self.ordering() {
+            cmp::Ordering::Less => f.write_str("left is cheaper"),
+            cmp::Ordering::Equal => f.write_str("both cost the same"),
+            cmp::Ordering::Greater => f.write_str("right is cheaper"),
+        }
+    }
+}
+
+/// Error returned by `Program::cost_compare`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CostCompareError {
+    /// The programs have different source or target types
+    TypeMismatch,
+    /// Executing a program on an input failed
+    Exec {
+        /// Index of the input
+        input: usize,
+        /// Whether it was the right program which failed
+        right: bool,
+        /// The error
+        error: exec::ExecError,
+    },
+    /// The programs gave different outputs for an input
+    OutputMismatch {
+        /// Index of the input
+        input: usize,
+    },
+}
+
+impl fmt::Display for CostCompareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CostCompareError::TypeMismatch => {
+                f.write_str("Programs have different source or target types")
+            }
+            CostCompareError::Exec {
+                input,
+                right,
+                ref error,
+            } => write!(
+                f,
+                "{} program failed on input {}: {}",
+                if right { "Right" } else { "Left" },
+                input,
+                error
+            ),
+            CostCompareError::OutputMismatch { input } => {
+                write!(f, "Programs give different outputs for input {}", input)
+            }
+        }
+    }
+}
+
+impl error::Error for CostCompareError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            CostCompareError::Exec { error: ref e, .. } => error::Error::source(e),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the indices of the nodes which executing a program
+/// visits, as returned by `Program::iter_exec_order`
+pub struct ExecOrder<'a, Ext: extension::Jet> {
+    program: &'a Program<Ext>,
+    txenv: &'a Ext::TxEnv,
+    /// Machine running the program, until it finishes or fails
+    mac: Option<exec::BitMachine>,
+}
+
+impl<'a, Ext: extension::Jet> Iterator for ExecOrder<'a, Ext> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let next = self
+            .mac
+            .as_mut()?
+            .run_to_next_node(self.program, self.txenv);
+        match next {
+            Ok(Some(idx)) => Some(idx),
+            Ok(None) | Err(_) => {
+                self.mac = None;
+                None
+            }
+        }
+    }
+}
+
+/// Number of nodes of each kind in a program, as computed by
+/// `Program::term_histogram`
+///
+/// Kinds are named as by `Term::name`, so jets and extension nodes are
+/// named `jet_<name>` and `ext_<name>`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TermHistogram {
+    /// Number of nodes of each kind in the program's DAG, where shared
+    /// nodes are counted once
+    pub dag: BTreeMap<String, usize>,
+    /// Number of nodes of each kind in the program's expression tree,
+    /// where shared nodes are counted once for every use, saturating at
+    /// `u64::MAX`
+    pub tree: BTreeMap<String, u64>,
+    /// Number of bits of the program's encoding taken by nodes of each
+    /// kind, including the values of witness nodes but not the lengths
+    /// of the program and of its witness data
+    pub bits: BTreeMap<String, usize>,
+}
+
+impl TermHistogram {
+    /// The DAG counts as a comma-separated list of `kind:count`, most
+    /// common kinds first and ties broken by name, e.g.
+    /// `comp:41,case:7,jet_sha256hashblock:3`
+    pub fn fingerprint(&self) -> String {
+        let mut counts: Vec<_> = self.dag.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let counts: Vec<_> = counts
+            .into_iter()
+            .map(|(kind, count)| format!("{}:{}", kind, count))
+            .collect();
+        counts.join(",")
+    }
+}
+
+impl fmt::Display for TermHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (kind, count) in &self.dag {
+            writeln!(
+                f,
+                "{}: {} in DAG, {} in tree, {} bits",
+                kind,
+                count,
+                self.tree.get(kind).copied().unwrap_or(0),
+                self.bits.get(kind).copied().unwrap_or(0),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A histogram of sizes drawn as a bar chart, one line per size, e.g.
+/// from `Program::compute_type_size_histogram`
+///
+/// Collect `(size, count)` pairs into a chart to display them. Bars are
+/// drawn with Unicode block characters in eighths of a cell, scaled so
+/// that the largest count is `BarChart::WIDTH` cells wide.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BarChart(pub Vec<(usize, usize)>);
+
+impl BarChart {
+    /// Width, in cells, of the longest bar
+    pub const WIDTH: usize = 40;
+}
+
+impl core::iter::FromIterator<(usize, usize)> for BarChart {
+    fn from_iter<I: IntoIterator<Item = (usize, usize)>>(iter: I) -> Self {
+        BarChart(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for BarChart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const EIGHTHS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+        let max = self.0.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        let label_width = self
+            .0
+            .iter()
+            .map(|&(size, _)| size.to_string().len())
+            .max()
+            .unwrap_or(0);
+        for &(size, count) in &self.0 {
+            // Length in eighths of a cell, rounded up so that no nonzero
+            // count is drawn as an empty bar
+            let eighths = match count {
+                0 => 0,
+                _ => (count * BarChart::WIDTH * 8 + max - 1) / max,
+            };
+            let mut bar = "█".repeat(eighths / 8);
+            if eighths % 8 > 0 {
+                bar.push(EIGHTHS[eighths % 8 - 1]);
+            }
+            writeln!(f, "{:>w$} │{} {}", size, bar, count, w = label_width)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node of a `FlatProgram`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlatNode<Ext> {
+    /// The underlying node, whose child references are absolute indices
+    /// into the program rather than offsets back from this node
+    pub term: Term<Value, Ext>,
+    /// Source type for this node
+    pub source_ty: Arc<types::FinalType>,
+    /// Target type for this node
+    pub target_ty: Arc<types::FinalType>,
+}
+
+impl<Ext> FlatNode<Ext> {
+    /// Absolute indices of the left and right children of this node
+    pub fn children(&self) -> (Option<usize>, Option<usize>) {
+        self.term.child_offsets()
+    }
+}
+
+/// A program in a flattened form for analysis, in which nodes refer
+/// to their children by absolute index and carry only their types
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlatProgram<Ext> {
+    /// The list of nodes in the program, root last
+    pub nodes: Vec<FlatNode<Ext>>,
+}
+
+impl<Ext: extension::Jet + Clone> FlatProgram<Ext> {
+    /// Convert back into an executable program, recomputing the cached
+    /// data of each node
+    ///
+    /// Fails with `Error::BadIndex` if a node refers to a child which does
+    /// not come before it, `Error::NodeType` if the types of a node do not
+    /// agree with those of its children, and `Error::WitnessType` if a
+    /// witness value is not of the target type of its node.
+    pub fn to_program(&self) -> Result<Program<Ext>, Error> {
+        let mut typed_nodes = Vec::with_capacity(self.nodes.len());
+        for (index, node) in self.nodes.iter().enumerate() {
+            let (left, right) = node.children();
+            if left.into_iter().chain(right).any(|child| child >= index) {
+                return Err(Error::BadIndex);
+            }
+            typed_nodes.push(types::TypedNode {
+                node: node.term.clone().map_children(|child| index - child),
+                source_ty: node.source_ty.clone(),
+                target_ty: node.target_ty.clone(),
+            });
+        }
+        check_hidden_nodes(&typed_nodes)?;
+        check_node_types(&typed_nodes)?;
+        check_witnesses(&typed_nodes)?;
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+}
+
+/// Locations, in an encoded program, of the nodes which are referenced
+/// by other nodes
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SharingMap {
+    /// Map from the index of each referenced node to the bit position,
+    /// relative to the start of the program, where its encoding begins
+    pub node_to_position: HashMap<usize, usize>,
+}
+
+impl SharingMap {
+    /// Build a sharing map from a list of nodes and the positions at
+    /// which every node was encoded
+    fn new<'a, W: 'a, Ext: 'a, I>(nodes: I, positions: Vec<usize>) -> SharingMap
+    where
+        I: Iterator<Item = &'a Term<W, Ext>>,
+    {
+        let mut node_to_position = HashMap::new();
+        for (idx, node) in nodes.enumerate() {
+            let (left, right) = node.child_offsets();
+            for offset in left.into_iter().chain(right) {
+                node_to_position.insert(idx - offset, positions[idx - offset]);
+            }
+        }
+        SharingMap { node_to_position }
+    }
+}
+
+/// The part of a program's encoding which a bit belongs to, as given by
+/// `Program::explain_offset`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OffsetInfo {
+    /// The number of nodes, at the start of the encoding
+    ProgramLength,
+    /// The encoding of a node
+    Node {
+        /// The node
+        node: NodeId,
+        /// Bits of the node's encoding
+        range: ops::Range<usize>,
+    },
+    /// The start of the witness data, which gives its length
+    WitnessLength,
+    /// The value of a witness node
+    Witness {
+        /// The witness node
+        node: NodeId,
+        /// Bits of the node's value
+        range: ops::Range<usize>,
+    },
+}
+
+impl<Ext: extension::Jet> Program<Ext> {
+    /// Obtain the node representing the root of the program DAG
+    pub fn root_node(&self) -> &ProgramNode<Ext> {
+        &self.nodes[self.nodes.len() - 1]
+    }
+
+    /// Kinds of node present in the program which need special handling
+    /// by the Bit Machine
+    ///
+    /// These are found when the program is built, and are not updated if
+    /// `nodes` is edited afterwards.
+    pub fn features(&self) -> ProgramFeatures {
+        self.features
+    }
+
+    /// Reference to the node with the given index, if there is one
+    pub fn node_id(&self, index: usize) -> Option<NodeId> {
+        if index < self.nodes.len() {
+            Some(self.id(index))
+        } else {
+            None
+        }
+    }
+
+    /// References to every node of the program, in order
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (0..self.nodes.len()).map(move |index| self.id(index))
+    }
+
+    /// Index and node of each node without children, in order: every
+    /// `unit`, `iden`, `witness`, `fail` and `hidden` node, and every jet
+    pub fn leaf_nodes(&self) -> impl Iterator<Item = (usize, &ProgramNode<Ext>)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(_, node)| node.children().0.is_none())
+    }
+
+    /// Index and node of each node with at least one child, in order
+    pub fn internal_nodes(&self) -> impl Iterator<Item = (usize, &ProgramNode<Ext>)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(_, node)| node.children().0.is_some())
+    }
+
+    /// Index and node of each node with two children, in order: every
+    /// `comp`, `case`, `pair` and `disconnect` node
+    pub fn binary_nodes(&self) -> impl Iterator<Item = (usize, &ProgramNode<Ext>)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(_, node)| node.children().1.is_some())
+    }
+
+    /// Reference to the node with the given index, which must exist
+    pub(crate) fn id(&self, index: usize) -> NodeId {
+        NodeId {
+            index: index,
+            tag: self.tag,
+        }
+    }
+
+    /// Look up a node, failing with `Error::ForeignNodeId` if `id` was
+    /// taken from another program
+    pub fn node(&self, id: NodeId) -> Result<&ProgramNode<Ext>, Error> {
+        if id.tag != self.tag {
+            return Err(Error::ForeignNodeId(id));
+        }
+        self.nodes.get(id.index).ok_or(Error::BadIndex)
+    }
+
+    /// Decode a program from a stream of bits
+    pub fn decode<I: Iterator<Item = u8>>(iter: &mut BitIter<I>) -> Result<Program<Ext>, Error> {
+        #[cfg(feature = "log")]
+        let _span = crate::logging::Span::enter("decode");
+        // Decode a bunch of untyped, witness-less nodes
+        let result = encode::decode_program_no_witness(&mut *iter)
+            .and_then(|nodes| Program::<Ext>::from_untyped_nodes(nodes, iter));
+
+        #[cfg(feature = "log")]
+        match result {
+            Ok(ref prog) => log::debug!(nodes = prog.nodes.len(); "decoded program"),
+            Err(ref e) => log::debug!(error:% = e; "failed to decode program"),
+        }
+        result
+    }
+
+    /// Encode the program, followed by its witness data, into a stream
+    /// of bits. Returns the number of bits written.
+    pub fn encode<W: BitWrite>(&self, w: &mut W) -> io::Result<usize> {
+        self.encode_tracking(w, |_, _| {})
+    }
+
+    /// Encode the program, calling `track` with the index and starting
+    /// bit position of each node as it is written
+    fn encode_tracking<W: BitWrite, F: FnMut(usize, usize)>(
+        &self,
+        w: &mut W,
+        mut track: F,
+    ) -> io::Result<usize> {
+        let start = w.n_written();
+        encode::encode_natural(self.nodes.len(), &mut *w)?;
+        for node in &self.nodes {
+            track(node.index, w.n_written() - start);
+            encode::encode_node_no_witness(&node.node, &mut *w)?;
+        }
+
+        let witness_bits = self.nodes.iter().filter_map(|node| match node.node {
+            Term::Witness(ref value) => Some(value.iter_bits()),
+            _ => None,
+        });
+        let wit_len = witness_bits.clone().flatten().count();
+        if wit_len == 0 {
+            w.write_bit(false)?;
+        } else {
+            w.write_bit(true)?;
+            encode::encode_natural(wit_len, &mut *w)?;
+            for bit in witness_bits.flatten() {
+                w.write_bit(bit)?;
+            }
+        }
+        Ok(w.n_written() - start)
+    }
+
+    /// Length, in bits, of the encoding which `encode` would write,
+    /// computed without encoding the program
+    pub fn encoded_bit_len(&self) -> usize {
+        let nodes_len: usize = self
+            .nodes
+            .iter()
+            .map(|node| encode::node_bit_len(&node.node))
+            .sum();
+        let wit_len: usize = self
+            .nodes
+            .iter()
+            .map(|node| match node.node {
+                Term::Witness(ref value) => value.len(),
+                _ => 0,
+            })
+            .sum();
+        let wit_section_len = if wit_len == 0 {
+            1
+        } else {
+            1 + encode::natural_bit_len(wit_len) + wit_len
+        };
+        encode::natural_bit_len(self.nodes.len()) + nodes_len + wit_section_len
+    }
+
+    /// Serialize the program, with its witness data, into a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with_sharing().0
+    }
+
+    /// Serialize the program, also returning the bit position at which
+    /// each node that is referenced by another node was written
+    pub fn serialize_with_sharing(&self) -> (Vec<u8>, SharingMap) {
+        let mut positions = vec![0; self.nodes.len()];
+        let mut w = BitWriter::new(vec![]);
+        self.encode_tracking(&mut w, |idx, pos| positions[idx] = pos)
+            .and_then(|_| w.flush_all())
+            .expect("writing to a vector never fails");
+
+        let sharing = SharingMap::new(self.nodes.iter().map(|node| &node.node), positions);
+        (w.into_inner(), sharing)
+    }
+
+    /// Serialize the program, also returning the range of bits encoding
+    /// each node, in order, followed by the range of bits of the value of
+    /// each witness node
+    ///
+    /// Together with the program length at the start and the witness
+    /// length before the first value, the ranges cover every bit of the
+    /// encoding, which is followed by padding to a whole byte.
+    pub fn encode_with_offsets(&self) -> (Vec<u8>, Vec<(NodeId, ops::Range<usize>)>) {
+        (self.serialize(), self.encoding_offsets())
+    }
+
+    /// The part of the program's encoding which the bit at position
+    /// `bit` belongs to, or `None` if `bit` is past the end of it
+    ///
+    /// This is useful to find which node a decoding error is in.
+    pub fn explain_offset(&self, bit: usize) -> Option<OffsetInfo> {
+        if bit >= self.encoded_bit_len() {
+            return None;
+        }
+        let offsets = self.encoding_offsets();
+        let (nodes, witnesses) = offsets.split_at(self.nodes.len());
+        if let Some((node, range)) = nodes.iter().find(|(_, range)| range.contains(&bit)) {
+            return Some(OffsetInfo::Node {
+                node: *node,
+                range: range.clone(),
+            });
+        }
+        if let Some((node, range)) = witnesses.iter().find(|(_, range)| range.contains(&bit)) {
+            return Some(OffsetInfo::Witness {
+                node: *node,
+                range: range.clone(),
+            });
+        }
+        match nodes.first() {
+            Some((_, range)) if bit < range.start => Some(OffsetInfo::ProgramLength),
+            _ => Some(OffsetInfo::WitnessLength),
+        }
+    }
+
+    /// Bit ranges of the encoding of each node, then of each witness
+    /// value, as written by `encode`
+    fn encoding_offsets(&self) -> Vec<(NodeId, ops::Range<usize>)> {
+        let mut offsets = Vec::with_capacity(self.nodes.len());
+        let mut pos = encode::natural_bit_len(self.nodes.len());
+        for node in &self.nodes {
+            let end = pos + encode::node_bit_len(&node.node);
+            offsets.push((self.id(node.index), pos..end));
+            pos = end;
+        }
+
+        let witnesses: Vec<_> = self
+            .nodes
+            .iter()
+            .filter_map(|node| match node.node {
+                Term::Witness(ref value) => Some((node.index, value.len())),
+                _ => None,
+            })
+            .collect();
+        let wit_len: usize = witnesses.iter().map(|&(_, len)| len).sum();
+        pos += 1;
+        if wit_len > 0 {
+            pos += encode::natural_bit_len(wit_len);
+        }
+        for (idx, len) in witnesses {
+            offsets.push((self.id(idx), pos..pos + len));
+            pos += len;
+        }
+        offsets
+    }
+
+    /// Deserialize a program, with its witness data, from a byte slice
+    pub fn deserialize(bytes: &[u8]) -> Result<Program<Ext>, Error> {
+        Program::decode(&mut BitIter::from(bytes.iter().cloned()))
+    }
+
+    /// Deserialize a program, also returning the bit position at which
+    /// each node that is referenced by another node was read. For a
+    /// program produced by `serialize_with_sharing` the two maps agree.
+    pub fn deserialize_with_tracking(bytes: &[u8]) -> Result<(Program<Ext>, SharingMap), Error> {
+        let mut iter = BitIter::from(bytes.iter().cloned());
+        let prog_len = encode::decode_program_len(&mut iter)?;
+
+        let mut nodes = Vec::with_capacity(prog_len);
+        let mut positions = Vec::with_capacity(prog_len);
+        for i in 0..prog_len {
+            positions.push(iter.n_total_read());
+            nodes.push(encode::decode_node_no_witness(i, &mut iter)?);
+        }
+
+        let sharing = SharingMap::new(nodes.iter(), positions);
+        let program = Program::from_untyped_nodes(UnTypedProg(nodes), &mut iter)?;
+        Ok((program, sharing))
+    }
+
+    /// Decode a program from a stream of bits
+    pub fn from_untyped_nodes<I: Iterator<Item = u8>>(
+        nodes: UnTypedProg<(), Ext>,
+        iter: &mut BitIter<I>,
+    ) -> Result<Program<Ext>, Error> {
+        #[cfg(feature = "log")]
+        let _span = crate::logging::Span::enter("finalize");
+        // Do type-checking
+        let typed_nodes = types::type_check(nodes);
+        #[cfg(feature = "log")]
+        if let Err(ref e) = typed_nodes {
+            log::debug!(error:% = e; "type inference failed");
+        }
+        let typed_nodes = typed_nodes?;
+
+        Program::from_typed_nodes(typed_nodes, iter)
+    }
+
+    /// Attach witnesses, read from a stream of bits, to a list of typed
+    /// nodes and compute the cached data for each node
+    fn from_typed_nodes<I: Iterator<Item = u8>>(
+        typed_nodes: Vec<types::TypedNode<(), Ext>>,
+        iter: &mut BitIter<I>,
+    ) -> Result<Program<Ext>, Error> {
+        check_hidden_nodes(&typed_nodes)?;
+
+        // Parse witnesses, if available
+        // FIXME actually only read as much as wit_len
+        let _wit_len = match iter.next() {
+            Some(false) => 0,
+            Some(true) => encode::decode_natural(&mut *iter, None)?,
+            None => return Err(Error::EndOfStream),
+        };
+
+        let typed_nodes = typed_nodes
+            .into_iter()
+            .map::<Result<_, Error>, _>(|node| {
+                Ok(types::TypedNode {
+                    node: match node.node {
+                        // really, Rust???
+                        Term::Iden => Term::Iden,
+                        Term::Unit => Term::Unit,
+                        Term::InjL(i) => Term::InjL(i),
+                        Term::InjR(i) => Term::InjR(i),
+                        Term::Take(i) => Term::Take(i),
+                        Term::Drop(i) => Term::Drop(i),
+                        Term::Comp(i, j) => Term::Comp(i, j),
+                        Term::Case(i, j) => Term::Case(i, j),
+                        Term::Pair(i, j) => Term::Pair(i, j),
+                        Term::Disconnect(i, j) => Term::Disconnect(i, j),
+                        Term::Witness(()) => Term::Witness(Value::decode_witness(
+                            &mut iter.by_ref(),
+                            &node.target_ty,
+                        )?),
+                        Term::Fail(x, y) => Term::Fail(x, y),
+                        Term::Hidden(x) => Term::Hidden(x),
+                        Term::Ext(e) => Term::Ext(e),
+                        Term::Jet(j) => Term::Jet(j),
+                    },
+                    source_ty: node.source_ty,
+                    target_ty: node.target_ty,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+
+    /// Compute the cached data for each of a list of typed nodes with
+    /// their witnesses attached
+    fn from_witnessed_nodes(typed_nodes: Vec<types::TypedNode<Value, Ext>>) -> Program<Ext> {
+        let mut ret = Vec::<ProgramNode<Ext>>::with_capacity(typed_nodes.len());
+        for (index, node) in typed_nodes.into_iter().enumerate() {
+            // Offsets were checked against `index` when typechecking
+            let (left, right) = node.node.child_offsets();
+            let final_node = ProgramNode {
+                index: index,
+                children: (left.map(|i| index - i), right.map(|j| index - j)),
+                cmr: compute_cmr(&ret, &node.node, index),
+                extra_cells_bound: compute_extra_cells_bound(
+                    &ret,
+                    &node.node,
+                    index,
+                    node.target_ty.bit_width(),
+                ),
+                frame_count_bound: compute_frame_count_bound(&ret, &node.node, index),
+                call_stack_bound: compute_call_stack_bound(&ret, &node.node, index),
+                node: node.node,
+                source_ty: node.source_ty,
+                target_ty: node.target_ty,
+            };
+            ret.push(final_node);
+        }
+
+        Program {
+            features: ProgramFeatures::of_nodes(&ret),
+            nodes: ret,
+            tag: next_tag(),
+        }
+    }
+
+    /// Convert the program into a flattened form for analysis
+    pub fn to_flat(&self) -> FlatProgram<Ext>
+    where
+        Ext: Clone,
+    {
+        FlatProgram {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| FlatNode {
+                    term: node.node.clone().map_children(|offset| node.index - offset),
+                    source_ty: node.source_ty.clone(),
+                    target_ty: node.target_ty.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Execute the program on each of the given inputs, reusing a single
+    /// Bit Machine, which is reset between runs
+    pub fn apply_batch(
+        &self,
+        inputs: &[Value],
+        txenv: &Ext::TxEnv,
+    ) -> Vec<Result<Value, exec::ExecError>> {
+        exec::BitMachine::for_program(self).exec_batch(self, txenv, inputs)
+    }
+
+    /// Execute the program on each of the given inputs, sharding them
+    /// across one Bit Machine per thread
+    #[cfg(feature = "rayon")]
+    pub fn apply_batch_parallel(
+        &self,
+        inputs: &[Value],
+        txenv: &Ext::TxEnv,
+    ) -> Vec<Result<Value, exec::ExecError>>
+    where
+        Ext: Sync,
+        Ext::TxEnv: Sync,
+    {
+        exec::BitMachine::exec_batch_par(self, txenv, inputs)
+    }
+
+    /// Execute this program and `other` on each of the given inputs,
+    /// each with its transaction environment, and total the cost of each
+    ///
+    /// The programs must have the same source and target types, and give
+    /// the same output for every input.
+    pub fn cost_compare(
+        &self,
+        other: &Program<Ext>,
+        inputs: &[(Value, Ext::TxEnv)],
+    ) -> Result<CostComparison, CostCompareError> {
+        let (root, other_root) = (self.root_node(), other.root_node());
+        if root.source_ty != other_root.source_ty || root.target_ty != other_root.target_ty {
+            return Err(CostCompareError::TypeMismatch);
+        }
+
+        let mut comparison = CostComparison {
+            left: ExecCost::default(),
+            right: ExecCost::default(),
+        };
+        for (i, (input, txenv)) in inputs.iter().enumerate() {
+            let mut outputs = vec![];
+            for &(program, right) in &[(self, false), (other, true)] {
+                let mut mac = exec::BitMachine::for_program(program);
+                let output = mac.exec_with_input(program, input, txenv).map_err(|e| {
+                    CostCompareError::Exec {
+                        input: i,
+                        right: right,
+                        error: e,
+                    }
+                })?;
+                let stats = mac.stats();
+                let cost = if right {
+                    &mut comparison.right
+                } else {
+                    &mut comparison.left
+                };
+                cost.steps += stats.steps;
+                cost.jets += stats.jets;
+                cost.cost += stats.steps;
+                outputs.push(output);
+            }
+            if outputs[0] != outputs[1] {
+                return Err(CostCompareError::OutputMismatch { input: i });
+            }
+        }
+        Ok(comparison)
+    }
+
+    /// Indices of the nodes which executing the program on the given
+    /// input visits, in the order in which they are dispatched, following
+    /// the branches taken by each `case`
+    ///
+    /// The program is run one node at a time as the iterator is advanced,
+    /// on a Bit Machine of at most `max_bytes` bytes. If execution fails,
+    /// the order ends with the node at which it failed.
+    pub fn iter_exec_order<'a>(
+        &'a self,
+        input: &Value,
+        txenv: &'a Ext::TxEnv,
+        max_bytes: usize,
+    ) -> Result<ExecOrder<'a, Ext>, exec::ExecError> {
+        if !input.is_of_type(&self.root_node().source_ty) {
+            return Err(exec::ExecError::InputTypeMismatch);
+        }
+        let mut mac = exec::BitMachine::try_for_program(self, max_bytes)?;
+        mac.input(input)?;
+        Ok(ExecOrder {
+            program: self,
+            txenv: txenv,
+            mac: Some(mac),
+        })
+    }
+
+    /// Execute the program on the given input and check that the frame
+    /// operations and nodes visited match a previously recorded trace
+    /// event for event, reporting the first divergence
+    ///
+    /// If `input` does not have the program's source type, nothing is
+    /// executed, and the trace diverges at its first event.
+    pub fn verify_trace(
+        &self,
+        input: &Value,
+        txenv: &Ext::TxEnv,
+        expected: &[exec::TraceEvent],
+    ) -> Result<(), exec::TraceMismatch> {
+        if !input.is_of_type(&self.root_node().source_ty) {
+            return Err(exec::TraceMismatch {
+                index: 0,
+                expected: expected.first().cloned(),
+                found: None,
+            });
+        }
+        let mut mac = exec::BitMachine::for_program(self);
+        mac.input(input).expect("input has the source type");
+        let (_, trace) = mac.exec_traced(self, txenv);
+        exec::TraceMismatch::compare(expected, &trace)
+    }
+
+    /// Execute the program on the given input until it reaches its first
+    /// witness node, and return the type of the witness value needed there
+    ///
+    /// Returns `None` if the program finishes, or fails, without reaching
+    /// a witness node, or if `input` does not have the program's source
+    /// type.
+    pub fn next_witness_type(
+        &self,
+        input: &Value,
+        txenv: &Ext::TxEnv,
+    ) -> Option<Arc<types::FinalType>> {
+        let mut mac = exec::BitMachine::for_program(self);
+        match mac.exec_until_witness(self, input, txenv) {
+            Ok(Some(id)) => Some(self.nodes[id.index].target_ty.clone()),
+            _ => None,
+        }
+    }
+
+    /// Generate the source of a self-contained Rust test module, for use
+    /// from outside this crate, which decodes this program, runs it on
+    /// `input` and checks that it outputs `expected_output`
+    ///
+    /// `ext_path` is the path by which the module names `Ext`, e.g.
+    /// `simplicity::extension::bitcoin::BtcNode`.
+    pub fn to_test_fixture(&self, ext_path: &str, input: &Value, expected_output: &Value) -> String
+    where
+        Ext::TxEnv: Default,
+    {
+        self.to_test_fixture_batch(ext_path, &[(input.clone(), expected_output.clone())])
+    }
+
+    /// Generate the source of a test module, as in `to_test_fixture`,
+    /// which checks several pairs of input and expected output
+    pub fn to_test_fixture_batch(&self, ext_path: &str, cases: &[(Value, Value)]) -> String
+    where
+        Ext::TxEnv: Default,
+    {
+        fn hex_array(bytes: &[u8]) -> String {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+            format!("[{}]", bytes.join(", "))
+        }
+        fn value_bytes(value: &Value) -> Vec<u8> {
+            let mut bits: Vec<bool> = value.iter_bits().collect();
+            bits.resize((bits.len() + 7) / 8 * 8, false);
+            crate::core::bitvec_to_bytevec(bits)
+        }
+
+        let mut ret = String::new();
+        ret.push_str("#[cfg(test)]\nmod fixture {\n");
+        ret.push_str("    use simplicity::bititer::BitIter;\n");
+        ret.push_str("    use simplicity::exec::BitMachine;\n");
+        ret.push_str("    use simplicity::extension::Jet;\n");
+        ret.push_str("    use simplicity::{Program, Value};\n\n");
+        ret.push_str(&format!("    type Ext = {};\n\n", ext_path));
+        ret.push_str("    #[test]\n    fn test_prog() {\n");
+        ret.push_str(&format!(
+            "        let prog = Program::<Ext>::deserialize(&{}).unwrap();\n",
+            hex_array(&self.serialize()),
+        ));
+        ret.push_str("        let root = prog.root_node();\n");
+        ret.push_str("        let cases: &[(&[u8], &[u8])] = &[\n");
+        for (input, output) in cases {
+            ret.push_str(&format!(
+                "            (&{}, &{}),\n",
+                hex_array(&value_bytes(input)),
+                hex_array(&value_bytes(output)),
+            ));
+        }
+        ret.push_str("        ];\n");
+        ret.push_str("        for (input, output) in cases {\n");
+        ret.push_str("            let mut input = BitIter::from(input.iter().cloned());\n");
+        ret.push_str("            let input = Value::from_bits_and_type(&mut input, &root.source_ty).unwrap();\n");
+        ret.push_str("            let mut output = BitIter::from(output.iter().cloned());\n");
+        ret.push_str("            let output = Value::from_bits_and_type(&mut output, &root.target_ty).unwrap();\n");
+        ret.push_str("            let mut mac = BitMachine::for_program(&prog);\n");
+        ret.push_str("            mac.input(&input).unwrap();\n");
+        ret.push_str("            let txenv = <Ext as Jet>::TxEnv::default();\n");
+        ret.push_str("            assert_eq!(mac.exec(&prog, &txenv), output);\n");
+        ret.push_str("        }\n    }\n}\n");
+        ret
+    }
+
+    /// For each node, whether `target` is reachable from it
+    fn reaches(&self, target: usize) -> Vec<bool> {
+        let mut ret = vec![false; self.nodes.len()];
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let (left, right) = node.children();
+            ret[idx] = idx == target || left.into_iter().chain(right).any(|child| ret[child]);
+        }
+        ret
+    }
+
+    /// Indices of the children of node `idx` from which `target` is reachable
+    fn children_reaching(&self, idx: usize, reaches: &[bool]) -> Vec<usize> {
+        let (left, right) = self.nodes[idx].children();
+        left.into_iter()
+            .chain(right)
+            .filter(|&child| reaches[child])
+            .collect()
+    }
+
+    /// Whether each node is reachable from the root
+    fn reachable(&self) -> Vec<bool> {
+        let mut ret = vec![false; self.nodes.len()];
+        if let Some(root) = ret.last_mut() {
+            *root = true;
+        }
+        for (idx, node) in self.nodes.iter().enumerate().rev() {
+            if ret[idx] {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    ret[child] = true;
+                }
+            }
+        }
+        ret
+    }
+
+    /// Remove all hidden nodes, failing if any of them is reachable from
+    /// the root
+    ///
+    /// Unreachable nodes which refer to a removed hidden node, directly
+    /// or indirectly, are removed too. The remaining nodes keep their
+    /// cached data, which does not depend on the removed nodes.
+    pub fn strip_hidden_nodes(&self) -> Result<Program<Ext>, StripError>
+    where
+        Ext: Clone,
+    {
+        let reachable = self.reachable();
+        for node in &self.nodes {
+            if let Term::Hidden(..) = node.node {
+                if reachable[node.index] {
+                    return Err(StripError::ReachableHiddenNode {
+                        node: self.id(node.index),
+                        cmr: node.cmr.into_inner(),
+                    });
+                }
+            }
+        }
+
+        // New index of each node which is kept
+        let mut new_index = Vec::<Option<usize>>::with_capacity(self.nodes.len());
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let (left, right) = node.children();
+            let keep = if let Term::Hidden(..) = node.node {
+                false
+            } else {
+                left.into_iter()
+                    .chain(right)
+                    .all(|child| new_index[child].is_some())
+            };
+            if !keep {
+                new_index.push(None);
+                continue;
+            }
+
+            let index = nodes.len();
+            new_index.push(Some(index));
+            let new_child = |child: usize| new_index[child].expect("child is kept");
+            nodes.push(ProgramNode {
+                node: node
+                    .node
+                    .clone()
+                    .map_children(|off| index - new_child(node.index - off)),
+                index: index,
+                children: (left.map(new_child), right.map(new_child)),
+                ..node.clone()
+            });
+        }
+
+        Ok(Program {
+            features: ProgramFeatures::of_nodes(&nodes),
+            nodes: nodes,
+            tag: next_tag(),
+        })
+    }
+
+    /// Replace every hidden node by a `fail` node, so that executing it
+    /// fails with an error rather than panicking
+    ///
+    /// Each `fail` node keeps the CMR of the hidden node it replaces as
+    /// its cached CMR, and as its entropy, so the CMR of every other node
+    /// is unchanged. So is the index of every node, so `NodeId`s of this
+    /// program may be used with the new one.
+    pub fn hidden_to_fail(&self) -> Program<Ext>
+    where
+        Ext: Clone,
+    {
+        Program {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| match node.node {
+                    Term::Hidden(cmr) => ProgramNode {
+                        node: Term::Fail(cmr.into_inner(), [0; 32]),
+                        ..node.clone()
+                    },
+                    _ => node.clone(),
+                })
+                .collect(),
+            tag: self.tag,
+            features: self.features,
+        }
+    }
+
+    /// Replace every witness value by the all-zero value of its type,
+    /// i.e. the one whose sums are all left values, so that the program
+    /// can be shared, e.g. in a bug report, without its secrets
+    ///
+    /// Witness values do not contribute to CMRs or bounds, so every node
+    /// keeps its CMR, types and bounds, and its index, so `NodeId`s of
+    /// this program may be used with the new one. The new program will
+    /// usually fail, or reach a hidden node, if executed.
+    pub fn anonymize_witnesses(&self) -> Program<Ext>
+    where
+        Ext: Clone,
+    {
+        Program {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| match node.node {
+                    Term::Witness(..) => ProgramNode {
+                        node: Term::Witness(zero_value(&node.target_ty)),
+                        ..node.clone()
+                    },
+                    _ => node.clone(),
+                })
+                .collect(),
+            tag: self.tag,
+            features: self.features,
+        }
+    }
+
+    /// Replace every witness value as by `anonymize_witnesses`, and every
+    /// hidden CMR by 32 bytes given by `random`, which should come from
+    /// a cryptographic random number generator
+    ///
+    /// Hidden CMRs are committed to by their ancestors, so unlike with
+    /// `anonymize_witnesses` the CMRs of the hidden nodes' ancestors,
+    /// including the root, change. Types, bounds and node indices do not.
+    pub fn anonymize_hidden<F>(&self, mut random: F) -> Program<Ext>
+    where
+        Ext: Clone,
+        F: FnMut() -> [u8; 32],
+    {
+        let typed_nodes = self
+            .anonymize_witnesses()
+            .nodes
+            .into_iter()
+            .map(|node| types::TypedNode {
+                node: match node.node {
+                    Term::Hidden(..) => Term::Hidden(Cmr::from(random())),
+                    term => term,
+                },
+                source_ty: node.source_ty,
+                target_ty: node.target_ty,
+            })
+            .collect();
+        Program::from_witnessed_nodes(typed_nodes)
+    }
+
+    /// Replace every subexpression whose CMR is a key of `jet_cmrs` by
+    /// the corresponding jet, and drop any nodes which are then unreachable
+    ///
+    /// A subexpression is only replaced if the jet has the same CMR as it,
+    /// i.e. is a jet for that expression, and exactly its source and
+    /// target types, so the CMR of every remaining node is unchanged.
+    pub fn remove_jetted_subprograms(&self, jet_cmrs: &HashMap<Cmr, JetsNode>) -> Program<Ext>
+    where
+        Ext: Clone,
+    {
+        let jets: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                jet_cmrs.get(&node.cmr).filter(|jet| match node.node {
+                    Term::Jet(..) => false,
+                    _ => {
+                        jet.cmr() == node.cmr
+                            && *types::final_type_from_name(&jet.source_type()) == *node.source_ty
+                            && *types::final_type_from_name(&jet.target_type()) == *node.target_ty
+                    }
+                })
+            })
+            .collect();
+
+        // Nodes reachable from the root without passing through a jet
+        let mut keep = vec![false; self.nodes.len()];
+        if let Some(root) = keep.last_mut() {
+            *root = true;
+        }
+        for (idx, node) in self.nodes.iter().enumerate().rev() {
+            if keep[idx] && jets[idx].is_none() {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    keep[child] = true;
+                }
+            }
+        }
+
+        let mut new_index = vec![0; self.nodes.len()];
+        let mut typed_nodes = vec![];
+        for node in self.nodes.iter().filter(|node| keep[node.index]) {
+            let index = typed_nodes.len();
+            new_index[node.index] = index;
+            let term = match jets[node.index] {
+                Some(jet) => Term::Jet(*jet),
+                None => node
+                    .node
+                    .clone()
+                    .map_children(|off| index - new_index[node.index - off]),
+            };
+            typed_nodes.push(types::TypedNode {
+                node: term,
+                source_ty: node.source_ty.clone(),
+                target_ty: node.target_ty.clone(),
+            });
+        }
+
+        Program::from_witnessed_nodes(typed_nodes)
+    }
+
+    /// The frames allocated and freed by executing the program, not
+    /// counting its input and output frames, in the worst case
+    ///
+    /// At each `case` node the branch with the larger `extra_cells_bound`
+    /// is taken, or with the larger `frame_count_bound` if those are
+    /// equal, and never a hidden one. The sequence follows every path
+    /// through the DAG, so may be exponentially longer than the program.
+    pub fn compute_worst_case_frame_sequence(&self) -> Vec<FrameEvent> {
+        self.frame_events().collect()
+    }
+
+    /// Iterator over the events of `compute_worst_case_frame_sequence`
+    fn frame_events(&self) -> FrameEvents<'_, Ext> {
+        FrameEvents {
+            program: self,
+            stack: match self.nodes.len() {
+                0 => vec![],
+                n => vec![FrameWork::Node(n - 1)],
+            },
+        }
+    }
+
+    /// The largest number of bits allocated at once by the frames of
+    /// `compute_worst_case_frame_sequence`
+    pub fn compute_frame_watermark(&self) -> usize {
+        let mut frames = vec![];
+        let (mut current, mut watermark) = (0, 0);
+        for event in self.frame_events() {
+            match event {
+                FrameEvent::Push(size) => {
+                    frames.push(size);
+                    current += size;
+                    watermark = cmp::max(watermark, current);
+                }
+                FrameEvent::Pop => current -= frames.pop().expect("pops match pushes"),
+            }
+        }
+        watermark
+    }
+
+    /// Number of nodes with each source and target type width, where
+    /// each node is counted once for its source and once for its target
+    ///
+    /// Collect the histogram into a `BarChart` to display it.
+    pub fn compute_type_size_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for node in &self.nodes {
+            for ty in &[&node.source_ty, &node.target_ty] {
+                *histogram.entry(ty.bit_width()).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Number of frames of each size, in bits, allocated by
+    /// `compute_worst_case_frame_sequence`, in increasing order of size
+    ///
+    /// Collect the distribution into a `BarChart` to display it.
+    pub fn compute_frame_size_distribution(&self) -> Vec<(usize, usize)> {
+        let mut distribution = BTreeMap::new();
+        for event in self.frame_events() {
+            if let FrameEvent::Push(size) = event {
+                *distribution.entry(size).or_insert(0) += 1;
+            }
+        }
+        distribution.into_iter().collect()
+    }
+
+    /// Number of bytes of data which a Bit Machine needs to execute the
+    /// program: its input and output frames plus the static bound on
+    /// the cells used by other frames
+    pub fn required_data_bytes(&self) -> usize {
+        let root = self.root_node();
+        let io_width = root.source_ty.bit_width() + root.target_ty.bit_width();
+        (io_width + root.extra_cells_bound + 7) / 8
+    }
+
+    /// Number of frames which a Bit Machine needs room for to execute
+    /// the program, counting both frame stacks
+    pub fn required_frame_capacity(&self) -> usize {
+        // +1 for input or output on each stack
+        2 * (self.root_node().frame_count_bound + 1)
+    }
+
+    /// Enumerate every combination of branches taken at `case` nodes
+    /// during execution, failing if there are more than `max_paths`
+    ///
+    /// Values are not tracked, so some paths may be impossible, for
+    /// example if a `case` node always receives a constant. A path ends
+    /// early if it reaches a hidden or `fail` node; jets and witnesses
+    /// are assumed to succeed. A `case` node which is reached more than
+    /// once on a path makes a separate choice each time.
+    pub fn enumerate_paths(&self, max_paths: usize) -> Result<Vec<PathResult>, PathError> {
+        let root = match self.nodes.last() {
+            Some(root) => root,
+            None => return Ok(vec![]),
+        };
+        let paths = self.paths_from_root(max_paths)?;
+        Ok(paths
+            .into_iter()
+            .map(|(branches, stop)| PathResult {
+                branches: branches
+                    .into_iter()
+                    .map(|(idx, right)| (self.id(idx), right))
+                    .collect(),
+                outcome: stop.unwrap_or(PathOutcome::Output),
+                output_ty: root.target_ty.clone(),
+            })
+            .collect())
+    }
+
+    /// Paths through the root, each with the `case` choices made on it
+    /// and the node at which it stops early, if any
+    ///
+    /// The paths through each node reachable from the root are found
+    /// in turn, children before their parents, so that deep programs
+    /// do not overflow the stack.
+    #[allow(clippy::type_complexity)]
+    fn paths_from_root(
+        &self,
+        max_paths: usize,
+    ) -> Result<Vec<(Vec<(usize, bool)>, Option<PathOutcome>)>, PathError> {
+        let root = self.nodes.len() - 1;
+        let mut reachable = vec![false; self.nodes.len()];
+        reachable[root] = true;
+        for (idx, node) in self.nodes.iter().enumerate().rev() {
+            if reachable[idx] {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    reachable[child] = true;
+                }
+            }
+        }
+
+        let mut memo: Vec<Vec<(Vec<(usize, bool)>, Option<PathOutcome>)>> =
+            vec![vec![]; self.nodes.len()];
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if !reachable[idx] {
+                continue;
+            }
+            let paths = match node.node {
+                Term::Iden | Term::Unit | Term::Witness(..) | Term::Ext(..) | Term::Jet(..) => {
+                    vec![(vec![], None)]
+                }
+                Term::Hidden(..) => vec![(vec![], Some(PathOutcome::Hidden(self.id(idx))))],
+                Term::Fail(..) => vec![(vec![], Some(PathOutcome::Fail(self.id(idx))))],
+                Term::InjL(..) | Term::InjR(..) | Term::Take(..) | Term::Drop(..) => {
+                    memo[node.left()].clone()
+                }
+                Term::Case(..) => {
+                    let mut paths = vec![];
+                    for &(child, right) in &[(node.left(), false), (node.right(), true)] {
+                        for (branches, stop) in &memo[child] {
+                            let mut branches = branches.clone();
+                            branches.insert(0, (idx, right));
+                            paths.push((branches, *stop));
+                        }
+                    }
+                    paths
+                }
+                // The left child runs first, then the right one unless the
+                // left one stopped
+                Term::Comp(..) | Term::Pair(..) | Term::Disconnect(..) => {
+                    let rights = &memo[node.right()];
+                    let mut paths = vec![];
+                    for (branches, stop) in &memo[node.left()] {
+                        if stop.is_some() {
+                            paths.push((branches.clone(), *stop));
+                            continue;
+                        }
+                        for (right_branches, right_stop) in rights {
+                            let mut joined = branches.clone();
+                            joined.extend_from_slice(right_branches);
+                            paths.push((joined, *right_stop));
+                            if paths.len() > max_paths {
+                                return Err(PathError::TooManyPaths { limit: max_paths });
+                            }
+                        }
+                    }
+                    paths
+                }
+            };
+            if paths.len() > max_paths {
+                return Err(PathError::TooManyPaths { limit: max_paths });
+            }
+            memo[idx] = paths;
+        }
+        Ok(memo.swap_remove(root))
+    }
+
+    /// Check that no choice of witness values makes the program reach a
+    /// `fail` or hidden node
+    ///
+    /// Values are tracked symbolically, with witnesses, the program's
+    /// input and the outputs of jets unknown. A `case` node whose tag is
+    /// unknown has both of its branches explored, while one whose tag
+    /// is known, e.g. an assertion on a constant, has only the branch
+    /// taken explored. A witness node reached more than once may be
+    /// given a different value each time, so some failures found may
+    /// be impossible. Fails with `PathError::TooManyPaths` if some node
+    /// has more than `max_paths` distinct outcomes for a single input.
+    pub fn check_total_symbolic(&self, max_paths: usize) -> Result<(), TotalityError> {
+        let root = match self.nodes.last() {
+            Some(root) => root,
+            None => return Ok(()),
+        };
+        let mut memo = HashMap::new();
+        let outcomes = self.sym_outcomes(root.index, SymValue::Unknown, max_paths, &mut memo)?;
+        let mut stops: Vec<_> = outcomes.into_iter().filter_map(Result::err).collect();
+        if stops.is_empty() {
+            return Ok(());
+        }
+        stops.sort_unstable();
+        stops.dedup();
+        Err(TotalityError::Partial(
+            stops.into_iter().map(|idx| self.id(idx)).collect(),
+        ))
+    }
+
+    /// The distinct outcomes of running node `idx` on `input`
+    ///
+    /// Nodes are run from an explicit stack rather than recursively, so
+    /// that deep programs do not overflow the call stack: each node is
+    /// run once the outcomes of every child it runs are known.
+    fn sym_outcomes(
+        &self,
+        idx: usize,
+        input: SymValue,
+        max_paths: usize,
+        memo: &mut HashMap<(usize, SymValue), SymOutcomes>,
+    ) -> Result<SymOutcomes, PathError> {
+        let key = (idx, input);
+        let mut stack = vec![key.clone()];
+        while let Some(top) = stack.last() {
+            if memo.contains_key(top) {
+                stack.pop();
+                continue;
+            }
+            let (idx, input) = top.clone();
+            match self.sym_step(idx, &input, max_paths, memo)? {
+                Ok(outcomes) => {
+                    stack.pop();
+                    memo.insert((idx, input), outcomes);
+                }
+                Err(missing) => stack.extend(missing),
+            }
+        }
+        Ok(memo[&key].clone())
+    }
+
+    /// The distinct outcomes of running node `idx` on `input`, or the
+    /// children and inputs whose outcomes are needed first
+    #[allow(clippy::type_complexity)]
+    fn sym_step(
+        &self,
+        idx: usize,
+        input: &SymValue,
+        max_paths: usize,
+        memo: &HashMap<(usize, SymValue), SymOutcomes>,
+    ) -> Result<Result<SymOutcomes, Vec<(usize, SymValue)>>, PathError> {
+        let node = &self.nodes[idx];
+        let input = input.clone();
+        let mut outcomes = vec![];
+        let mut push = |outcome| {
+            if !outcomes.contains(&outcome) {
+                outcomes.push(outcome);
+            }
+            match outcomes.len() > max_paths {
+                true => Err(PathError::TooManyPaths { limit: max_paths }),
+                false => Ok(()),
+            }
+        };
+        let mut missing = vec![];
+        // The outcomes of running `child` on `value`, if already known
+        let mut known = |child: usize, value: SymValue| {
+            let key = (child, value);
+            let outcomes = memo.get(&key).cloned();
+            if outcomes.is_none() {
+                missing.push(key);
+            }
+            outcomes.unwrap_or_default()
+        };
+        match node.node {
+            Term::Iden => push(Ok(input))?,
+            Term::Unit => push(Ok(SymValue::Unit))?,
+            Term::Witness(..) | Term::Ext(..) | Term::Jet(..) => push(Ok(SymValue::Unknown))?,
+            Term::Hidden(..) | Term::Fail(..) => push(Err(idx))?,
+            Term::InjL(..) | Term::InjR(..) => {
+                for outcome in known(node.left(), input) {
+                    push(outcome.map(|value| match node.node {
+                        Term::InjL(..) => SymValue::SumL(Arc::new(value)),
+                        _ => SymValue::SumR(Arc::new(value)),
+                    }))?;
+                }
+            }
+            Term::Take(..) | Term::Drop(..) => {
+                let (a, b) = input.halves();
+                let half = match node.node {
+                    Term::Take(..) => a,
+                    _ => b,
+                };
+                for outcome in known(node.left(), half) {
+                    push(outcome)?;
+                }
+            }
+            Term::Comp(..) => {
+                for outcome in known(node.left(), input) {
+                    match outcome {
+                        Ok(value) => {
+                            for outcome in known(node.right(), value) {
+                                push(outcome)?;
+                            }
+                        }
+                        Err(stop) => push(Err(stop))?,
+                    }
+                }
+            }
+            // The left child runs first, then the right one unless the
+            // left one stopped
+            Term::Pair(..) => {
+                let rights = known(node.right(), input.clone());
+                for outcome in known(node.left(), input) {
+                    match outcome {
+                        Ok(a) => {
+                            for right in &rights {
+                                push(right.clone().map(|b| SymValue::prod(a.clone(), b)))?;
+                            }
+                        }
+                        Err(stop) => push(Err(stop))?,
+                    }
+                }
+            }
+            Term::Case(..) => {
+                let (tag, c) = input.halves();
+                // The branches taken, with the values they take
+                let (left, right) = match tag {
+                    SymValue::SumL(a) => (Some((*a).clone()), None),
+                    SymValue::SumR(b) => (None, Some((*b).clone())),
+                    _ => (Some(SymValue::Unknown), Some(SymValue::Unknown)),
+                };
+                let arms = left
+                    .map(|a| (node.left(), a))
+                    .into_iter()
+                    .chain(right.map(|b| (node.right(), b)));
+                for (child, arm) in arms {
+                    let arm_input = SymValue::prod(arm, c.clone());
+                    for outcome in known(child, arm_input) {
+                        push(outcome)?;
+                    }
+                }
+            }
+            // The left child takes the right child's CMR, which is not
+            // tracked, paired with the input
+            Term::Disconnect(..) => {
+                let s_input = SymValue::prod(SymValue::Unknown, input);
+                for outcome in known(node.left(), s_input) {
+                    match outcome {
+                        Ok(value) => {
+                            let (b, c) = value.halves();
+                            for outcome in known(node.right(), c) {
+                                push(outcome.map(|d| SymValue::prod(b.clone(), d)))?;
+                            }
+                        }
+                        Err(stop) => push(Err(stop))?,
+                    }
+                }
+            }
+        }
+        if missing.is_empty() {
+            Ok(Ok(outcomes))
+        } else {
+            Ok(Err(missing))
+        }
+    }
+
+    /// Extract the nodes reachable from `root` as a standalone program
+    /// whose root is `root`
+    ///
+    /// The nodes keep their types and their relative order, while their
+    /// offsets and cached data are recomputed for the new program. Fails
+    /// with `Error::ForeignNodeId` if `root` is not from this program.
+    pub fn subprogram(&self, root: NodeId) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone,
+    {
+        let root = self.node(root)?.index;
+        let mut keep = vec![false; root + 1];
+        keep[root] = true;
+        for (idx, node) in self.nodes[..=root].iter().enumerate().rev() {
+            if keep[idx] {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    keep[child] = true;
+                }
+            }
+        }
+
+        let mut new_index = vec![0; root + 1];
+        let mut typed_nodes = vec![];
+        for node in self.nodes[..=root].iter().filter(|node| keep[node.index]) {
+            let index = typed_nodes.len();
+            new_index[node.index] = index;
+            typed_nodes.push(types::TypedNode {
+                node: node
+                    .node
+                    .clone()
+                    .map_children(|off| index - new_index[node.index - off]),
+                source_ty: node.source_ty.clone(),
+                target_ty: node.target_ty.clone(),
+            });
+        }
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+
+    /// Instantiate a program template, replacing the node at each index
+    /// given in `holes` by the paired program, and type check the result
+    ///
+    /// Nodes which are not replaced stay shared as they are in the
+    /// template, while nodes which are only reachable through replaced
+    /// nodes are dropped. Fails with `Error::ForeignNodeId` if a node is
+    /// not from the template, or `Error::BadIndex` if a node is given
+    /// twice or a replacement has no nodes.
+    pub fn from_template(
+        template: &Program<Ext>,
+        holes: &[(NodeId, &Program<Ext>)],
+    ) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone,
+    {
+        let mut replacements = vec![None; template.nodes.len()];
+        for &(id, replacement) in holes {
+            let idx = template.node(id)?.index;
+            match replacements.get_mut(idx) {
+                Some(slot @ None) if !replacement.nodes.is_empty() => *slot = Some(replacement),
+                _ => return Err(Error::BadIndex),
+            }
+        }
+
+        // Nodes reachable from the root without passing through a hole
+        let mut keep = vec![false; template.nodes.len()];
+        if let Some(root) = keep.last_mut() {
+            *root = true;
+        }
+        for (idx, node) in template.nodes.iter().enumerate().rev() {
+            if keep[idx] && replacements[idx].is_none() {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    keep[child] = true;
+                }
+            }
+        }
+
+        let mut new_index = vec![0; template.nodes.len()];
+        let mut nodes = vec![];
+        for node in template.nodes.iter().filter(|node| keep[node.index]) {
+            match replacements[node.index] {
+                Some(replacement) => {
+                    nodes.extend(replacement.nodes.iter().map(|node| node.node.clone()))
+                }
+                None => {
+                    let index = nodes.len();
+                    nodes.push(
+                        node.node
+                            .clone()
+                            .map_children(|off| index - new_index[node.index - off]),
+                    );
+                }
+            }
+            new_index[node.index] = nodes.len() - 1;
+        }
+
+        let typed_nodes = types::type_check(UnTypedProg(nodes))?;
+        check_hidden_nodes(&typed_nodes)?;
+        check_witnesses(&typed_nodes)?;
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+
+    /// Replace the node at `index` by the program `replacement`, which
+    /// must have the same source and target types
+    ///
+    /// Unlike with `from_template`, no types are inferred again, so every
+    /// other node keeps its types. Every reference to the node refers to
+    /// the replacement's root instead.
+    ///
+    /// Only nodes which are reachable from the new root are kept. Nodes
+    /// which were only reachable through the replaced node are dropped,
+    /// as are nodes which were not reachable at all, so other nodes may
+    /// have new indices in the result. Fails with
+    /// `ReplaceError::UnreachableNode` if the node itself is not
+    /// reachable, since replacing it would have no effect.
+    pub fn replace_node(
+        &self,
+        index: usize,
+        replacement: Program<Ext>,
+    ) -> Result<Program<Ext>, ReplaceError>
+    where
+        Ext: Clone,
+    {
+        let node = self
+            .nodes
+            .get(index)
+            .ok_or(ReplaceError::InvalidIndex(index))?;
+        if !self.reachable()[index] {
+            return Err(ReplaceError::UnreachableNode(index));
+        }
+        let root = replacement.root_node();
+        if root.source_ty != node.source_ty || root.target_ty != node.target_ty {
+            return Err(ReplaceError::TypeMismatch {
+                expected: (node.source_ty.clone(), node.target_ty.clone()),
+                found: (root.source_ty.clone(), root.target_ty.clone()),
+            });
+        }
+
+        // Nodes reachable from the root without passing through the node
+        let mut keep = vec![false; self.nodes.len()];
+        if let Some(root) = keep.last_mut() {
+            *root = true;
+        }
+        for (idx, node) in self.nodes.iter().enumerate().rev() {
+            if keep[idx] && idx != index {
+                let (left, right) = node.children();
+                for child in left.into_iter().chain(right) {
+                    keep[child] = true;
+                }
+            }
+        }
+
+        let mut new_index = vec![0; self.nodes.len()];
+        let mut typed_nodes = vec![];
+        for node in self.nodes.iter().filter(|node| keep[node.index]) {
+            if node.index == index {
+                typed_nodes.extend(replacement.nodes.iter().map(|node| types::TypedNode {
+                    node: node.node.clone(),
+                    source_ty: node.source_ty.clone(),
+                    target_ty: node.target_ty.clone(),
+                }));
+            } else {
+                let new = typed_nodes.len();
+                typed_nodes.push(types::TypedNode {
+                    node: node
+                        .node
+                        .clone()
+                        .map_children(|off| new - new_index[node.index - off]),
+                    source_ty: node.source_ty.clone(),
+                    target_ty: node.target_ty.clone(),
+                });
+            }
+            new_index[node.index] = typed_nodes.len() - 1;
+        }
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+
+    /// Specialize a program whose input is a pair by fixing the first
+    /// component of its input, giving a program whose input is the
+    /// second component alone
+    ///
+    /// The new program composes the original one with `pair c iden`,
+    /// where `c` is a constant expression for `fixed_prefix`. Fails with
+    /// `Error::TypeCheck` if the input of the program is not a pair whose
+    /// first component has the type of `fixed_prefix`.
+    pub fn partial_apply(&self, fixed_prefix: &Value) -> Result<Program<Ext>, Error>
+    where
+        Ext: Clone,
+    {
+        match self.root_node().source_ty.ty {
+            types::FinalTypeInner::Product(ref a, _) if fixed_prefix.is_of_type(a) => {}
+            _ => return Err(Error::TypeCheck),
+        }
+
+        let mut nodes: Vec<_> = self.nodes.iter().map(|node| node.node.clone()).collect();
+        let root = nodes.len() - 1;
+        let mut consts = HashMap::new();
+        let prefix = push_const(&mut nodes, &mut consts, fixed_prefix);
+        nodes.push(Term::Iden);
+        let idx = nodes.len();
+        nodes.push(Term::Pair(idx - prefix, 1));
+        nodes.push(Term::Comp(1, idx + 1 - root));
+
+        let typed_nodes = types::type_check(UnTypedProg(nodes))?;
+        check_hidden_nodes(&typed_nodes)?;
+        check_witnesses(&typed_nodes)?;
+        Ok(Program::from_witnessed_nodes(typed_nodes))
+    }
+
+    /// Return the nodes on a path from the root to node `target`, root
+    /// first and `target` last, or `None` if `target` is not reachable
+    /// from the root. If the node is shared, there may be several such
+    /// paths, and an arbitrary one is returned.
+    pub fn path_to_node(&self, target: NodeId) -> Result<Option<Vec<NodeId>>, Error> {
+        let target = self.node(target)?.index;
+        let reaches = self.reaches(target);
+        let mut idx = self.nodes.len() - 1;
+        if !reaches[idx] {
+            return Ok(None);
+        }
+
+        let mut path = vec![self.id(idx)];
+        while idx != target {
+            idx = self.children_reaching(idx, &reaches)[0];
+            path.push(self.id(idx));
+        }
+        Ok(Some(path))
+    }
+
+    /// Return every path from the root to node `target`, in the format
+    /// of `path_to_node`. The number of paths may be exponential in the
+    /// size of the program.
+    pub fn all_paths_to_node(&self, target: NodeId) -> Result<Vec<Vec<NodeId>>, Error> {
+        let target = self.node(target)?.index;
+        let reaches = self.reaches(target);
+        let root = self.nodes.len() - 1;
+        if !reaches[root] {
+            return Ok(vec![]);
+        }
+
+        let mut ret = vec![];
+        let mut stack = vec![vec![root]];
+        while let Some(path) = stack.pop() {
+            let idx = *path.last().unwrap();
+            if idx == target {
+                ret.push(path.into_iter().map(|idx| self.id(idx)).collect());
+                continue;
+            }
+            for child in self.children_reaching(idx, &reaches).into_iter().rev() {
+                let mut next = path.clone();
+                next.push(child);
+                stack.push(next);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Describe an execution error along with the node at which it
+    /// occurred, if any: the nodes on a path to it from the root, the
+    /// node itself and its children, each with its types
+    pub fn explain_error(&self, err: &exec::ExecError) -> String
+    where
+        Ext: fmt::Display,
+    {
+        let mut ret = format!("{}\n", err);
+        let target = match err.node() {
+            Some(id) => id,
+            None => return ret,
+        };
+        let path = match self.path_to_node(target) {
+            Ok(Some(path)) => path,
+            Ok(None) => return ret,
+            Err(e) => {
+                ret.push_str(&format!("{}\n", e));
+                return ret;
+            }
+        };
+
+        ret.push_str("path from the root:\n");
+        for id in &path[..path.len() - 1] {
+            ret.push_str(&format!("    {}\n", self.nodes[id.index]));
+        }
+        ret.push_str(&format!(
+            "failing node:\n    {}\n",
+            self.nodes[target.index]
+        ));
+        let (left, right) = self.nodes[target.index].children();
+        if left.is_some() {
+            ret.push_str("children:\n");
+            for child in left.into_iter().chain(right) {
+                ret.push_str(&format!("    {}\n", self.nodes[child]));
+            }
+        }
+        ret
+    }
+
+    /// Compare the program with another, top-down from the roots
+    ///
+    /// Subtrees which are identical in both programs, witness values
+    /// included, are skipped. Along each path, the first nodes which
+    /// differ in their combinator, witness value, jet or types, or which
+    /// have no differing children to descend into, are reported. A pair
+    /// of nodes reached by several paths is reported only once.
+    pub fn diff(&self, other: &Program<Ext>) -> ProgramDiff
+    where
+        Ext: fmt::Display,
+    {
+        let mut classes = HashMap::new();
+        let left_classes = self.diff_classes(&mut classes);
+        let right_classes = other.diff_classes(&mut classes);
+        let in_left: HashSet<_> = left_classes.iter().collect();
+        let in_right: HashSet<_> = right_classes.iter().collect();
+        let common = left_classes.iter().filter(|c| in_right.contains(c)).count();
+        let only_right = right_classes
+            .iter()
+            .filter(|c| !in_left.contains(c))
+            .count();
+
+        let mut differences = vec![];
+        let mut seen = HashSet::new();
+        // An empty program has no root to compare, only nodes to count
+        let mut stack = vec![];
+        if !self.nodes.is_empty() && !other.nodes.is_empty() {
+            stack.push((self.nodes.len() - 1, other.nodes.len() - 1, vec![]));
+        }
+        while let Some((l, r, path)) = stack.pop() {
+            if left_classes[l] == right_classes[r] || !seen.insert((l, r)) {
+                continue;
+            }
+            let (left, right) = (&self.nodes[l], &other.nodes[r]);
+            let same_node = diff_label(left) == diff_label(right)
+                && left.source_ty == right.source_ty
+                && left.target_ty == right.target_ty;
+            let (left_l, left_r) = left.children();
+            let (right_l, right_r) = right.children();
+            let differing: Vec<_> = left_l
+                .into_iter()
+                .zip(right_l)
+                .map(|(lc, rc)| (lc, rc, false))
+                .chain(
+                    left_r
+                        .into_iter()
+                        .zip(right_r)
+                        .map(|(lc, rc)| (lc, rc, true)),
+                )
+                .filter(|&(lc, rc, _)| left_classes[lc] != right_classes[rc])
+                .collect();
+
+            if same_node && !differing.is_empty() {
+                // Pushed in reverse, so that left children come first
+                for (lc, rc, is_right) in differing.into_iter().rev() {
+                    let mut child_path = path.clone();
+                    child_path.push(is_right);
+                    stack.push((lc, rc, child_path));
+                }
+            } else {
+                differences.push(NodeDiff {
+                    path: path,
+                    left: self.diff_node(l),
+                    right: other.diff_node(r),
+                });
+            }
+        }
+
+        ProgramDiff {
+            differences: differences,
+            only_left: left_classes.len() - common,
+            only_right: only_right,
+            common: common,
+        }
+    }
+
+    /// Assign each node a class shared by identical nodes, descendants
+    /// included, of every program given the same `classes`
+    fn diff_classes(&self, classes: &mut HashMap<DiffKey, usize>) -> Vec<usize>
+    where
+        Ext: fmt::Display,
+    {
+        let mut ret: Vec<usize> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let (left, right) = node.children();
+            let key = (
+                diff_label(node),
+                node.source_ty.to_string(),
+                node.target_ty.to_string(),
+                left.map(|i| ret[i]),
+                right.map(|j| ret[j]),
+            );
+            let next = classes.len();
+            ret.push(*classes.entry(key).or_insert(next));
+        }
+        ret
+    }
+
+    /// The node with the given index, as reported by `diff`
+    fn diff_node(&self, index: usize) -> DiffNode
+    where
+        Ext: fmt::Display,
+    {
+        let node = &self.nodes[index];
+        DiffNode {
+            index: index,
+            term: diff_label(node),
+            source_ty: Arc::clone(&node.source_ty),
+            target_ty: Arc::clone(&node.target_ty),
+            cmr: node.cmr,
+        }
+    }
+
+    /// Look for suspicious structure in the program: `case` branches
+    /// which can never be taken, witness values which never affect the
+    /// output, and nodes which are not part of the program's expression
+    ///
+    /// The checks are conservative: a branch is only reported dead when
+    /// every use of its `case` node pairs a constant injection with the
+    /// rest of its input, and a witness value is only reported unused
+    /// when it is dropped by a `take`, `drop` or `unit` along every path.
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let root = self.nodes.len() - 1;
+        let mut parents = vec![vec![]; self.nodes.len()];
+        for node in &self.nodes {
+            let (left, right) = node.children();
+            for child in left.into_iter().chain(right) {
+                parents[child].push(node.index);
+            }
+        }
+
+        // Parts of its input which each node reads, bottom-up
+        let mut reads: Vec<Usage> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let usage = match node.node {
+                Term::Unit | Term::Witness(..) | Term::Hidden(..) | Term::Fail(..) => Usage::Unused,
+                Term::InjL(..) | Term::InjR(..) => reads[node.left()].clone(),
+                Term::Take(..) => Usage::pair(reads[node.left()].clone(), Usage::Unused),
+                Term::Drop(..) => Usage::pair(Usage::Unused, reads[node.left()].clone()),
+                Term::Comp(..) => match reads[node.right()] {
+                    Usage::Unused => Usage::Unused,
+                    _ => reads[node.left()].clone(),
+                },
+                Term::Pair(..) => reads[node.left()].join(&reads[node.right()]),
+                Term::Iden
+                | Term::Case(..)
+                | Term::Disconnect(..)
+                | Term::Ext(..)
+                | Term::Jet(..) => Usage::All,
+            };
+            reads.push(usage);
+        }
+
+        // Parts of its output which are read for each node, top-down
+        let mut demand = vec![Usage::Unused; self.nodes.len()];
+        demand[root] = Usage::All;
+        for node in self.nodes.iter().rev() {
+            let out = demand[node.index].clone();
+            let (left, right) = match node.node {
+                Term::Comp(..) if out == Usage::Unused => (Usage::Unused, Usage::Unused),
+                Term::Comp(..) => (reads[node.right()].clone(), out),
+                Term::Pair(..) => out.halves(),
+                Term::Take(..) | Term::Drop(..) | Term::Case(..) => (out.clone(), out),
+                _ if out == Usage::Unused => (Usage::Unused, Usage::Unused),
+                _ => (Usage::All, Usage::All),
+            };
+            let (l, r) = node.children();
+            if let Some(l) = l {
+                demand[l] = demand[l].join(&left);
+            }
+            if let Some(r) = r {
+                demand[r] = demand[r].join(&right);
+            }
+        }
+
+        let mut warnings = vec![];
+        for node in &self.nodes {
+            let id = self.id(node.index);
+            if node.index != root && parents[node.index].is_empty() {
+                warnings.push(LintWarning::UnreferencedNode(id));
+                continue;
+            }
+            match node.node {
+                Term::Witness(..)
+                    if node.target_ty.bit_width() > 0 && demand[node.index] == Usage::Unused =>
+                {
+                    warnings.push(LintWarning::UnusedWitness(id));
+                }
+                Term::Case(..) => {
+                    let tags: Option<Vec<bool>> = parents[node.index]
+                        .iter()
+                        .map(|&p| self.constant_case_tag(p, node.index))
+                        .collect();
+                    if let Some(tags) = tags.filter(|tags| !tags.is_empty()) {
+                        if tags.iter().all(|&tag| tag == tags[0]) {
+                            let dead = if tags[0] { node.left() } else { node.right() };
+                            match self.nodes[dead].node {
+                                Term::Hidden(..) => {}
+                                _ => warnings.push(LintWarning::DeadBranch {
+                                    node: id,
+                                    right: !tags[0],
+                                }),
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        warnings
+    }
+
+    /// The tag of the sum in the input of the `case` node `case`, if its
+    /// parent `parent` always builds it with the same constant injection
+    fn constant_case_tag(&self, parent: usize, case: usize) -> Option<bool> {
+        let parent = &self.nodes[parent];
+        match parent.node {
+            Term::Comp(..) if parent.right() == case && parent.left() != case => {
+                let pair = &self.nodes[parent.left()];
+                match pair.node {
+                    Term::Pair(..) => {
+                        // The injection may be the last step of a composition
+                        let mut inj = &self.nodes[pair.left()];
+                        while let Term::Comp(..) = inj.node {
+                            inj = &self.nodes[inj.right()];
+                        }
+                        match inj.node {
+                            Term::InjL(..) => Some(false),
+                            Term::InjR(..) => Some(true),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Count the nodes of each kind in the program, both as shared in its
+    /// DAG and as unrolled into its expression tree, along with the size
+    /// of their encoding as given by `encode_with_offsets`
+    pub fn term_histogram(&self) -> TermHistogram
+    where
+        Ext: fmt::Display,
+    {
+        // Number of times each node occurs in the tree, from the root down
+        let mut uses = vec![0u64; self.nodes.len()];
+        uses[self.nodes.len() - 1] = 1;
+        for node in self.nodes.iter().rev() {
+            let (left, right) = node.children();
+            for child in left.into_iter().chain(right) {
+                uses[child] = uses[child].saturating_add(uses[node.index]);
+            }
+        }
+
+        let mut histogram = TermHistogram::default();
+        for node in &self.nodes {
+            let kind = node.node.name();
+            *histogram.dag.entry(kind.clone()).or_insert(0) += 1;
+            histogram.bits.entry(kind.clone()).or_insert(0);
+            let tree = histogram.tree.entry(kind).or_insert(0);
+            *tree = tree.saturating_add(uses[node.index]);
+        }
+        for (id, range) in self.encoding_offsets() {
+            let kind = self.nodes[id.index()].node.name();
+            *histogram.bits.get_mut(&kind).expect("kind counted") += range.len();
+        }
+        histogram
+    }
+
+    /// Print out the program in a graphviz-parseable format
+    pub fn graph_print(&self) {
+        for node in &self.nodes {
+            println!(
+                "{} [label=\"{}\\n{}\\n{} → {}\"];",
+                node.index,
+                match node.node {
+                    Term::Iden => "iden",
+                    Term::Unit => "unit",
+                    Term::InjL(..) => "injl",
+                    Term::InjR(..) => "injr",
+                    Term::Take(..) => "take",
+                    Term::Drop(..) => "drop",
+                    Term::Comp(..) => "comp",
+                    Term::Case(..) => "case",
+                    Term::Pair(..) => "pair",
+                    Term::Disconnect(..) => "disconnect",
+                    Term::Witness(..) => "witness",
+                    Term::Hidden(..) => "hidden",
+                    Term::Fail(..) => "fail",
+                    Term::Ext(..) => "[ext]", // FIXME `ext` and `jet` should passthrough
+                    Term::Jet(..) => "[jet]",
+                },
+                node.index,
+                node.source_ty,
+                node.target_ty,
+            );
+            match node.node {
+                Term::Iden
+                | Term::Unit
+                | Term::Witness(..)
+                | Term::Hidden(..)
+                | Term::Fail(..)
+                | Term::Ext(..)
+                | Term::Jet(..) => {}
+                Term::InjL(..) | Term::InjR(..) | Term::Take(..) | Term::Drop(..) => {
+                    println!("  {} -> {};", node.index, node.left());
+                }
+                Term::Comp(..) | Term::Case(..) | Term::Pair(..) | Term::Disconnect(..) => {
+                    println!("  {} -> {} [color=red];", node.index, node.left());
+                    println!("  {} -> {} [color=blue];", node.index, node.right());
+                }
+            }
+        }
+    }
+
+    /// Render the program in the surface syntax read by
+    /// `text::parse_program`, giving a program with the same CMR
+    ///
+    /// Nodes used more than once, and witness nodes, are given their own
+    /// definitions, with the value of each witness in a comment; the
+    /// parser reads witness values separately. Nodes which are not
+    /// reachable from the root are left out. Programs with `fail` or
+    /// extension nodes are rendered, but cannot be parsed back.
+    pub fn to_source(&self) -> String
+    where
+        Ext: fmt::Display,
+    {
+        let reachable = self.reachable();
+        let mut parents = vec![0usize; self.nodes.len()];
+        for node in self.nodes.iter().filter(|node| reachable[node.index]) {
+            let (left, right) = node.children();
+            for child in left.into_iter().chain(right) {
+                parents[child] += 1;
+            }
+        }
+        let root = self.nodes.len().saturating_sub(1);
+        let names: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| match node.node {
+                _ if node.index == root => Some("main".to_owned()),
+                Term::Witness(..) => Some(format!("w{}", node.index)),
+                _ if parents[node.index] > 1 => Some(format!("n{}", node.index)),
+                _ => None,
+            })
+            .collect();
+
+        let mut ret = String::new();
+        for node in self.nodes.iter().filter(|node| reachable[node.index]) {
+            if let Some(ref name) = names[node.index] {
+                ret.push_str(&format!(
+                    "{} := {}",
+                    name,
+                    self.source_expr(node.index, &names)
+                ));
+                if let Term::Witness(ref value) = node.node {
+                    ret.push_str(&format!("  -- {}", bits_literal(value.iter_bits())));
+                }
+                ret.push('\n');
+            }
+        }
+        ret
+    }
+
+    /// The expression for node `idx` in the surface syntax, referring to
+    /// its children by name where they have one
+    fn source_expr(&self, idx: usize, names: &[Option<String>]) -> String
+    where
+        Ext: fmt::Display,
+    {
+        let node = &self.nodes[idx];
+        let arg = |child: usize| match names[child] {
+            Some(ref name) => name.clone(),
+            None => match (&self.nodes[child].node, self.nodes[child].children()) {
+                (&Term::Hidden(..), _) => format!("({})", self.source_expr(child, names)),
+                (_, (None, None)) => self.source_expr(child, names),
+                _ => format!("({})", self.source_expr(child, names)),
+            },
+        };
+        let name = node.node.name();
+        match (node.node.child_offsets(), &node.node) {
+            ((Some(_), Some(_)), _) => {
+                format!("{} {} {}", name, arg(node.left()), arg(node.right()))
+            }
+            ((Some(_), None), _) => format!("{} {}", name, arg(node.left())),
+            (_, Term::Hidden(cmr)) => format!("{} 0x{}", name, cmr),
+            _ => name,
+        }
+    }
+}
+
+/// Buffered bytewise iterator over an `io::Read`, which stops at the first
+/// I/O error and remembers it, so that it can be wrapped in a `BitIter`
+struct ReadBytes<R: io::Read> {
+    bytes: io::Bytes<io::BufReader<R>>,
+    error: Option<io::Error>,
+}
+
+impl<R: io::Read> ReadBytes<R> {
+    /// Replace a decoding error with the I/O error that caused it, if any
+    fn map_error(&mut self, e: Error) -> Error {
+        match self.error.take() {
+            Some(io_err) => Error::Io(io_err),
+            None => e,
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for ReadBytes<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        match self.bytes.next()? {
+            Ok(byte) => Some(byte),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// A node yielded by a `ProgramParser`. It has been unified with all
+/// nodes before it, but its types are not final until the whole program
+/// has been parsed.
+#[derive(Debug)]
+pub struct ParsedNode<'a, Ext> {
+    /// Its index within the total program
+    pub index: usize,
+    /// The underlying node, without any witness data
+    pub node: &'a Term<(), Ext>,
+}
+
+/// Streaming program decoder, which reads a program from an `io::Read`
+/// one node at a time, doing type inference as it goes
+pub struct ProgramParser<R: io::Read, Ext> {
+    iter: BitIter<ReadBytes<R>>,
+    prog_len: Option<usize>,
+    nodes: Vec<Term<(), Ext>>,
+    inference: types::TypeInference,
+}
+
+impl<R: io::Read, Ext: extension::Jet> ProgramParser<R, Ext> {
+    /// Create a new parser. Nothing is read until the first call to
+    /// `next_node` or `collect`.
+    pub fn new(reader: R) -> ProgramParser<R, Ext> {
+        ProgramParser {
+            iter: BitIter::new(ReadBytes {
+                bytes: io::BufReader::new(reader).bytes(),
+                error: None,
+            }),
+            prog_len: None,
+            nodes: vec![],
+            inference: types::TypeInference::new(),
+        }
+    }
+
+    /// Read and unify the next node of the program, returning `None` once
+    /// all nodes have been read. Witness data is not read by this method.
+    pub fn next_node(&mut self) -> Result<Option<ParsedNode<'_, Ext>>, Error> {
+        let prog_len = match self.prog_len {
+            Some(n) => n,
+            None => {
+                let n = encode::decode_program_len(&mut self.iter)
+                    .map_err(|e| self.iter.get_mut().map_error(e))?;
+                self.prog_len = Some(n);
+                n
+            }
+        };
+
+        let index = self.inference.len();
+        if index == prog_len {
+            return Ok(None);
+        }
+
+        let node = encode::decode_node_no_witness(index, &mut self.iter)
+            .map_err(|e| self.iter.get_mut().map_error(e))?;
+        self.inference.add_node(&node)?;
+        self.nodes.push(node);
+        Ok(Some(ParsedNode {
+            index: index,
+            node: &self.nodes[index],
+        }))
+    }
+
+    /// Read all remaining nodes, as well as the witness data, and produce
+    /// the complete program
+    pub fn collect(mut self) -> Result<Program<Ext>, Error> {
+        while self.next_node()?.is_some() {}
+
+        let mut iter = self.iter;
+        let typed_nodes = self.inference.finalize(self.nodes)?;
+        Program::from_typed_nodes(typed_nodes, &mut iter).map_err(|e| iter.get_mut().map_error(e))
+    }
+}
+
+/// Programs are serialized as their canonical encoding, as hex in
+/// human-readable formats and as bytes otherwise
+#[cfg(feature = "serde")]
+impl<Ext: extension::Jet> serde::Serialize for Program<Ext> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use bitcoin_hashes::hex::ToHex;
+
+        let bytes = Program::serialize(self);
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&bytes.to_hex())
+        } else {
+            serializer.serialize_bytes(&bytes)
+        }
+    }
+}
+
+/// Programs are decoded with the usual decoder, and rejected unless
+/// their encoding is canonical, i.e. re-encoding them gives the same
+/// bytes, with no trailing data or nonzero padding
+#[cfg(feature = "serde")]
+impl<'de, Ext: extension::Jet> serde::Deserialize<'de> for Program<Ext> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use bitcoin_hashes::hex::FromHex;
+        use serde::de::{self, Visitor};
+        use std::marker::PhantomData;
+
+        struct ProgramVisitor<Ext>(PhantomData<Ext>);
+
+        impl<'de, Ext: extension::Jet> Visitor<'de> for ProgramVisitor<Ext> {
+            type Value = Program<Ext>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an encoded Simplicity program")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Program<Ext>, E> {
+                let program = Program::deserialize(bytes).map_err(E::custom)?;
+                if Program::serialize(&program) != bytes {
+                    return Err(E::custom("program encoding is not canonical"));
+                }
+                Ok(program)
+            }
+
+            fn visit_str<E: de::Error>(self, hex: &str) -> Result<Program<Ext>, E> {
+                let bytes = Vec::<u8>::from_hex(hex).map_err(E::custom)?;
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(ProgramVisitor(PhantomData))
+        } else {
+            deserializer.deserialize_bytes(ProgramVisitor(PhantomData))
+        }
+    }
+}
+
+/// Append nodes computing the constant `value` from any input, sharing
+/// those already appended for equal values, and return the index of
+/// the last
+fn push_const<'a, Ext>(
+    nodes: &mut Vec<Term<Value, Ext>>,
+    consts: &mut HashMap<&'a Value, usize>,
+    value: &'a Value,
+) -> usize {
+    if let Some(&idx) = consts.get(value) {
+        return idx;
+    }
+    let term = match *value {
+        Value::Unit => Term::Unit,
+        Value::SumL(ref v) => {
+            let v = push_const(nodes, consts, v);
+            Term::InjL(nodes.len() - v)
+        }
+        Value::SumR(ref v) => {
+            let v = push_const(nodes, consts, v);
+            Term::InjR(nodes.len() - v)
+        }
+        Value::Prod(ref a, ref b) => {
+            let a = push_const(nodes, consts, a);
+            let b = push_const(nodes, consts, b);
+            Term::Pair(nodes.len() - a, nodes.len() - b)
+        }
+    };
+    nodes.push(term);
+    consts.insert(value, nodes.len() - 1);
+    nodes.len() - 1
+}
+
+/// A `0x` literal for a sequence of bits if it is a whole number of hex
+/// digits, or a `0b` literal otherwise
+fn bits_literal<I: Iterator<Item = bool>>(bits: I) -> String {
+    let bits: Vec<bool> = bits.collect();
+    if bits.is_empty() || bits.len() % 4 != 0 {
+        let digits: String = bits
+            .iter()
+            .map(|&bit| if bit { '1' } else { '0' })
+            .collect();
+        return format!("0b{}", digits);
+    }
+    let digits: String = bits
+        .chunks(4)
+        .map(|chunk| {
+            let digit = chunk.iter().fold(0, |acc, &bit| acc << 1 | bit as u32);
+            std::char::from_digit(digit, 16).expect("digit is less than 16")
+        })
+        .collect();
+    format!("0x{}", digits)
+}
+
+/// Check that the value of every witness node is of the node's target
+/// type, since the Bit Machine writes it at that type's width
+fn check_witnesses<Ext>(typed_nodes: &[types::TypedNode<Value, Ext>]) -> Result<(), Error> {
+    for (index, node) in typed_nodes.iter().enumerate() {
+        if let Term::Witness(ref value) = node.node {
+            if !value.is_of_type(&node.target_ty) {
+                return Err(Error::WitnessType {
+                    node: index,
+                    expected: node.target_ty.clone(),
+                    found: value.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the types of every node agree with those of its children,
+/// as given by the typing rule of its combinator. Hidden children are
+/// not checked, since nothing determines their types.
+fn check_node_types<W, Ext: extension::Jet>(
+    typed_nodes: &[types::TypedNode<W, Ext>],
+) -> Result<(), Error> {
+    use types::FinalType;
+    use types::FinalTypeInner::{Product, Sum, Unit};
+
+    let word256 = types::pow2_types()[9].clone();
+    for (index, node) in typed_nodes.iter().enumerate() {
+        let child = |offset: usize| &typed_nodes[index - offset];
+        // Whether the child at `offset` has the given types
+        let has_types = |offset: usize, source: &FinalType, target: &FinalType| {
+            let child = child(offset);
+            match child.node {
+                Term::Hidden(..) => true,
+                _ => *child.source_ty == *source && *child.target_ty == *target,
+            }
+        };
+        let prod = |a: &Arc<FinalType>, b: &Arc<FinalType>| FinalType::prod(a.clone(), b.clone());
+        let (a, b) = (&*node.source_ty, &*node.target_ty);
+
+        let ok = match node.node {
+            Term::Iden => a == b,
+            Term::Unit => b.ty == Unit,
+            Term::InjL(i) => match b.ty {
+                Sum(ref l, _) => has_types(i, a, l),
+                _ => false,
+            },
+            Term::InjR(i) => match b.ty {
+                Sum(_, ref r) => has_types(i, a, r),
+                _ => false,
+            },
+            Term::Take(i) => match a.ty {
+                Product(ref l, _) => has_types(i, l, b),
+                _ => false,
+            },
+            Term::Drop(i) => match a.ty {
+                Product(_, ref r) => has_types(i, r, b),
+                _ => false,
+            },
+            Term::Comp(i, j) => {
+                let mid = &child(i).target_ty;
+                has_types(i, a, mid) && has_types(j, mid, b)
+            }
+            Term::Case(i, j) => match a.ty {
+                Product(ref sum, ref c) => match sum.ty {
+                    Sum(ref l, ref r) => {
+                        has_types(i, &prod(l, c), b) && has_types(j, &prod(r, c), b)
+                    }
+                    _ => false,
+                },
+                _ => false,
+            },
+            Term::Pair(i, j) => match b.ty {
+                Product(ref l, ref r) => has_types(i, a, l) && has_types(j, a, r),
+                _ => false,
+            },
+            Term::Disconnect(i, j) => match (&b.ty, &child(i).target_ty.ty) {
+                (Product(ref x, ref d), Product(_, ref c)) => {
+                    has_types(i, &prod(&word256, &node.source_ty), &prod(x, c))
+                        && has_types(j, c, d)
+                }
+                _ => false,
+            },
+            Term::Ext(ref e) => {
+                *a == *types::final_type_from_name(&e.source_type())
+                    && *b == *types::final_type_from_name(&e.target_type())
+            }
+            Term::Jet(ref j) => {
+                *a == *types::final_type_from_name(&j.source_type())
+                    && *b == *types::final_type_from_name(&j.target_type())
+            }
+            Term::Witness(..) | Term::Hidden(..) | Term::Fail(..) => true,
+        };
+        if !ok {
+            return Err(Error::NodeType(index));
+        }
+    }
+    Ok(())
+}
+
+/// Check that every hidden node is one branch of a `case` node, whose
+/// other branch is not hidden
+fn check_hidden_nodes<W, Ext>(typed_nodes: &[types::TypedNode<W, Ext>]) -> Result<(), Error> {
+    if let Some(&Term::Hidden(..)) = typed_nodes.last().map(|node| &node.node) {
+        return Err(Error::HiddenRoot);
+    }
+    for (index, node) in typed_nodes.iter().enumerate() {
+        let is_hidden = |offset: Option<usize>| match offset {
+            Some(offset) => match typed_nodes[index - offset].node {
+                Term::Hidden(..) => true,
+                _ => false,
+            },
+            None => false,
+        };
+        let (left, right) = node.node.child_offsets();
+        let parent_term = match node.node {
+            Term::Case(..) if is_hidden(left) && is_hidden(right) => {
+                return Err(Error::CaseMultipleHiddenChildren(index))
+            }
+            Term::Case(..) => continue,
+            Term::InjL(..) => "injl",
+            Term::InjR(..) => "injr",
+            Term::Take(..) => "take",
+            Term::Drop(..) => "drop",
+            Term::Comp(..) => "comp",
+            Term::Pair(..) => "pair",
+            Term::Disconnect(..) => "disconnect",
+            _ => continue,
+        };
+        for offset in left.into_iter().chain(right) {
+            if is_hidden(Some(offset)) {
+                return Err(Error::NonCaseHiddenChild {
+                    node: index - offset,
+                    parent: index,
+                    parent_term: parent_term,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The value of the given type whose sums are all left values, and so
+/// whose encoding is all zero bits
+fn zero_value(ty: &types::FinalType) -> Value {
+    Value::from_bits_and_type(&mut core::iter::repeat(false), ty)
+        .expect("an endless stream of bits")
+}
+
+fn compute_cmr<Ext: extension::Jet>(
+    program: &[ProgramNode<Ext>],
+    node: &Term<Value, Ext>,
+    idx: usize,
+) -> Cmr {
+    match *node {
+        Term::Iden => cmr::tag::iden(),
+        Term::Unit => cmr::tag::unit(),
+        Term::InjL(i) => cmr::tag::injl().update_1(program[idx - i].cmr),
+        Term::InjR(i) => cmr::tag::injr().update_1(program[idx - i].cmr),
+        Term::Take(i) => cmr::tag::take().update_1(program[idx - i].cmr),
+        Term::Drop(i) => cmr::tag::drop().update_1(program[idx - i].cmr),
+        Term::Comp(i, j) => cmr::tag::comp().update(program[idx - i].cmr, program[idx - j].cmr),
+        Term::Case(i, j) => cmr::tag::case().update(program[idx - i].cmr, program[idx - j].cmr),
+        Term::Pair(i, j) => cmr::tag::pair().update(program[idx - i].cmr, program[idx - j].cmr),
+        Term::Disconnect(i, _) => cmr::tag::disconnect().update_1(program[idx - i].cmr),
+        Term::Witness(..) => cmr::tag::witness(),
+        Term::Fail(..) => unimplemented!(),
+        Term::Hidden(cmr) => cmr,
+        Term::Ext(ref b) => b.cmr(),
+        Term::Jet(ref j) => j.cmr(),
+    }
+}
+
+fn compute_extra_cells_bound<Ext: extension::Jet>(
+    program: &[ProgramNode<Ext>],
+    node: &Term<Value, Ext>,
+    idx: usize,
+    witness_target_width: usize,
+) -> usize {
+    match *node {
+        Term::Iden => 0,
+        Term::Unit => 0,
+        Term::InjL(i) => program[idx - i].extra_cells_bound,
+        Term::InjR(i) => program[idx - i].extra_cells_bound,
+        Term::Take(i) => program[idx - i].extra_cells_bound,
+        Term::Drop(i) => program[idx - i].extra_cells_bound,
+        Term::Comp(i, j) => {
+            program[idx - i].target_ty.bit_width()
+                + cmp::max(
+                    program[idx - i].extra_cells_bound,
+                    program[idx - j].extra_cells_bound,
+                )
+        }
+        // Only one branch runs, and the padding of the scrutinee is skipped
+        // by moving the read cursor, which allocates nothing
+        Term::Case(i, j) => cmp::max(
+            program[idx - i].extra_cells_bound,
+            program[idx - j].extra_cells_bound,
+        ),
+        Term::Pair(i, j) => cmp::max(
+            program[idx - i].extra_cells_bound,
+            program[idx - j].extra_cells_bound,
+        ),
+        // The frame holding `i`'s input is freed before `j` runs, but the
+        // one holding `i`'s output lives until `j` is done
+        Term::Disconnect(i, j) => {
+            program[idx - i].target_ty.bit_width()
+                + cmp::max(
+                    program[idx - i].source_ty.bit_width() + program[idx - i].extra_cells_bound,
+                    program[idx - j].extra_cells_bound,
+                )
+        }
+        Term::Witness(..) => witness_target_width,
+        Term::Fail(..) => unimplemented!(),
+        Term::Hidden(..) => 0,
+        Term::Ext(..) => 0, // FIXME should fallthrough
+        Term::Jet(..) => 0,
+    }
+}
+
+fn compute_frame_count_bound<Ext: extension::Jet>(
+    program: &[ProgramNode<Ext>],
+    node: &Term<Value, Ext>,
+    idx: usize,
+) -> usize {
+    match *node {
+        Term::Iden => 0,
+        Term::Unit => 0,
+        Term::InjL(i) => program[idx - i].frame_count_bound,
+        Term::InjR(i) => program[idx - i].frame_count_bound,
+        Term::Take(i) => program[idx - i].frame_count_bound,
+        Term::Drop(i) => program[idx - i].frame_count_bound,
+        Term::Comp(i, j) => {
+            1 + cmp::max(
+                program[idx - i].frame_count_bound,
+                program[idx - j].frame_count_bound,
+            )
+        }
+        // Only one branch runs
+        Term::Case(i, j) => cmp::max(
+            program[idx - i].frame_count_bound,
+            program[idx - j].frame_count_bound,
+        ),
+        Term::Pair(i, j) => cmp::max(
+            program[idx - i].frame_count_bound,
+            program[idx - j].frame_count_bound,
+        ),
+        // Both new frames are briefly on the write stack together; after
+        // that, each child runs with only one extra frame on each stack
+        Term::Disconnect(i, j) => {
+            1 + cmp::max(
+                1,
+                cmp::max(
+                    program[idx - i].frame_count_bound,
+                    program[idx - j].frame_count_bound,
+                ),
+            )
+        }
+        Term::Witness(..) => 0,
+        Term::Fail(..) => unimplemented!(),
+        Term::Hidden(..) => 0,
+        Term::Ext(..) => 0, // FIXME should fallthrough
+        Term::Jet(..) => 0,
+    }
+}
+
+/// This mirrors the entries which `exec` pushes for each combinator: a
+/// child runs on top of whatever entries its parent still has pending.
+fn compute_call_stack_bound<Ext: extension::Jet>(
+    program: &[ProgramNode<Ext>],
+    node: &Term<Value, Ext>,
+    idx: usize,
+) -> usize {
+    match *node {
+        Term::Iden
+        | Term::Unit
+        | Term::Witness(..)
+        | Term::Fail(..)
+        | Term::Hidden(..)
+        | Term::Ext(..)
+        | Term::Jet(..) => 0,
+        // goto(i)
+        Term::InjL(i) | Term::InjR(i) | Term::Take(i) => {
+            cmp::max(1, program[idx - i].call_stack_bound)
+        }
+        // back, goto(i)
+        Term::Drop(i) => cmp::max(2, 1 + program[idx - i].call_stack_bound),
+        // back, goto(i) or goto(j)
+        Term::Case(i, j) => cmp::max(
+            2,
+            1 + cmp::max(
+                program[idx - i].call_stack_bound,
+                program[idx - j].call_stack_bound,
+            ),
+        ),
+        // goto(j), goto(i)
+        Term::Pair(i, j) => cmp::max(
+            2,
+            cmp::max(
+                1 + program[idx - i].call_stack_bound,
+                program[idx - j].call_stack_bound,
+            ),
+        ),
+        // drop, goto(j), move, goto(i)
+        Term::Comp(i, j) => cmp::max(
+            4,
+            cmp::max(
+                3 + program[idx - i].call_stack_bound,
+                1 + program[idx - j].call_stack_bound,
+            ),
+        ),
+        // drop, goto(j), copy, move, drop, goto(i)
+        Term::Disconnect(i, j) => cmp::max(
+            6,
+            cmp::max(
+                5 + program[idx - i].call_stack_bound,
+                1 + program[idx - j].call_stack_bound,
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bititer::BitIter;
+    use crate::extension::{
+        dummy::{DummyNode, TxEnv},
+        jets::JetsNode,
+    };
+    use crate::Term;
+
+    #[test]
+    fn simple_unit_prog() {
+        // vec![0 0 1 0 0 1 0 0] = vec![0x24]
+        // prog_len = 1 :vec![0 1 0 0 1 0 0]
+        // non a extension or jets node : vec![1 0 0 1 0 0]
+        // code = 2 [1 0 ] : vec![0 1 0 0]
+        // subcode = 1 [0 1]:  vec![0 0] => Parsed unit node.
+        // witness len = 0 vec![0]
+        let prog = vec![0x24];
+        let prog = Program::<DummyNode>::decode(&mut BitIter::from(prog.into_iter()))
+            .expect("decoding program");
+
+        assert_eq!(prog.nodes.len(), 1);
+        assert_eq!(prog.nodes[0].node, Term::Unit);
+        // Checked against C implementation
+        assert_eq!(
+            prog.nodes[0].cmr.to_string(),
+            "d723083cff3c75e29f296707ecf2750338f100591c86e0c71717f807ff3cf69d",
+        );
+    }
+
+    #[test]
+    fn injl_unit_prog() {
+        // 100 01001 00100 0
+        // 1000 1001 0010 0000
+        let prog = vec![0x89, 0x20];
+        let prog = Program::<DummyNode>::decode(&mut BitIter::from(prog.into_iter()))
+            .expect("decoding program");
+
+        prog.graph_print();
+        assert_eq!(prog.nodes.len(), 2);
+        assert_eq!(prog.nodes[0].node, Term::Unit);
+        assert_eq!(prog.nodes[1].node, Term::InjL(1));
+
+        // Checked against C implementation
+        assert_eq!(
+            prog.nodes[0].cmr.to_string(),
+            "d723083cff3c75e29f296707ecf2750338f100591c86e0c71717f807ff3cf69d",
+        );
+        // Checked against C implementation
+        assert_eq!(
+            prog.nodes[1].cmr.to_string(),
+            "7a4ebcbd3be89bb9dfd901fdbeff16cfa80aa36363785b14615cbdd3f0ae1f0a"
+        );
+    }
+
+    #[test]
+    fn stream_parse() {
+        let bytes = [0x89, 0x20];
+        let mut parser = ProgramParser::<_, DummyNode>::new(&bytes[..]);
+
+        let node = parser.next_node().unwrap().unwrap();
+        assert_eq!(node.index, 0);
+        assert_eq!(*node.node, Term::Unit);
+        let node = parser.next_node().unwrap().unwrap();
+        assert_eq!(node.index, 1);
+        assert_eq!(*node.node, Term::InjL(1));
+        assert!(parser.next_node().unwrap().is_none());
+
+        let streamed = parser.collect().expect("collecting program");
+        let decoded = Program::<DummyNode>::decode(&mut BitIter::from(bytes.iter().cloned()))
+            .expect("decoding program");
+        assert_eq!(streamed.nodes, decoded.nodes);
+
+        // Truncated stream
+        let parser = ProgramParser::<_, DummyNode>::new(&bytes[..1]);
+        assert_matches!(parser.collect(), Err(Error::EndOfStream));
+    }
+
+    #[test]
+    fn stream_parse_io_error() {
+        struct BrokenReader;
+        impl io::Read for BrokenReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken reader"))
+            }
+        }
+
+        let mut parser = ProgramParser::<_, DummyNode>::new(BrokenReader);
+        assert_matches!(parser.next_node(), Err(Error::Io(..)));
+    }
+
+    #[test]
+    fn serialize_sharing() {
+        let prog: Vec<Term<(), DummyNode>> = vec![Term::Unit, Term::InjL(1), Term::Pair(1, 2)];
+        let prog = Program::from_untyped_nodes(
+            UnTypedProg(prog),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+
+        let (bytes, sharing) = prog.serialize_with_sharing();
+        // 3 nodes: 101, unit at bit 3, injl at bit 8, root is not referenced
+        let mut expected = HashMap::new();
+        expected.insert(0, 3);
+        expected.insert(1, 8);
+        assert_eq!(sharing.node_to_position, expected);
+
+        let (decoded, tracked) = Program::<DummyNode>::deserialize_with_tracking(&bytes).unwrap();
+        assert_eq!(decoded.nodes, prog.nodes);
+        assert_eq!(tracked, sharing);
+    }
+
+    #[test]
+    fn serialize_witness_roundtrip() {
+        let prog: Vec<Term<(), DummyNode>> = vec![
+            Term::Unit,
+            Term::InjR(1),
+            Term::Witness(()),
+            Term::Case(2, 1),
+            Term::Witness(()),
+            Term::Comp(1, 2),
+        ];
+        let prog = Program::from_untyped_nodes(
+            UnTypedProg(prog),
+            &mut BitIter::from(vec![0x80].into_iter()),
+        )
+        .unwrap();
+
+        let bytes = prog.serialize();
+        let decoded = Program::<DummyNode>::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.nodes, prog.nodes);
+        assert_eq!(decoded.serialize(), bytes);
+    }
+
+    #[test]
+    fn anonymize() {
+        // witness &&& witness >>> adder32 >>> case hidden (drop iden),
+        // with witnesses whose sum carries into the right arm
+        let secret = [0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01];
+        let mut w = BitWriter::new(vec![]);
+        w.write_bit(true).unwrap();
+        encode::encode_natural(64, &mut w).unwrap();
+        for byte in &secret {
+            w.write_u8(*byte, 8).unwrap();
+        }
+        w.flush_all().unwrap();
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Witness(()),
+                Term::Witness(()),
+                Term::Pair(2, 1),
+                Term::Jet(JetsNode::Adder32),
+                Term::Comp(2, 1),
+                Term::Hidden(Cmr::from([0x11; 32])),
+                Term::Iden,
+                Term::Drop(1),
+                Term::Case(3, 1),
+                Term::Comp(5, 1),
+            ]),
+            &mut BitIter::from(w.into_inner().into_iter()),
+        )
+        .unwrap();
+        let mut mac = exec::BitMachine::for_program(&prog);
+        assert_eq!(mac.exec(&prog, &TxEnv), Value::u32(0));
+
+        let anon = prog.anonymize_witnesses();
+        for (node, anon_node) in prog.nodes.iter().zip(&anon.nodes) {
+            assert_eq!(anon_node.cmr, node.cmr);
+            assert_eq!(anon_node.target_ty, node.target_ty);
+            assert_eq!(anon_node.extra_cells_bound, node.extra_cells_bound);
+            match anon_node.node {
+                Term::Witness(ref value) => assert_eq!(*value, Value::u32(0)),
+                ref term => assert_eq!(*term, node.node),
+            }
+        }
+        assert_eq!(anon.node_id(0), prog.node_id(0));
+
+        // The encoding decodes to the same program, with only zero bits
+        // left of the witnesses
+        let bytes = anon.serialize();
+        assert_ne!(bytes, prog.serialize());
+        let decoded = Program::<DummyNode>::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.nodes, anon.nodes);
+        let witness_bits = prog.encoded_bit_len() - 64;
+        let mut bits = BitIter::from(bytes.into_iter());
+        for _ in 0..witness_bits {
+            bits.next();
+        }
+        assert!(bits.take(64).all(|bit| !bit));
+
+        let mut count = 0;
+        let anon = prog.anonymize_hidden(|| {
+            count += 1;
+            [count; 32]
+        });
+        assert_eq!(count, 1);
+        assert_eq!(anon.nodes[5].node, Term::Hidden(Cmr::from([1; 32])));
+        assert_eq!(anon.nodes[5].cmr, Cmr::from([1; 32]));
+        assert_ne!(anon.root_node().cmr, prog.root_node().cmr);
+        // Other than the hidden node's ancestors, CMRs are unchanged
+        for idx in (0..5).chain(6..8) {
+            assert_eq!(anon.nodes[idx].cmr, prog.nodes[idx].cmr);
+        }
+        assert_eq!(
+            anon.root_node().extra_cells_bound,
+            prog.root_node().extra_cells_bound
+        );
+        assert_eq!(anon.nodes[0].node, Term::Witness(Value::u32(0)));
+        let decoded = Program::<DummyNode>::deserialize(&anon.serialize()).unwrap();
+        assert_eq!(decoded.nodes, anon.nodes);
+    }
+
+    #[test]
+    fn hidden_placement() {
+        let hidden = |byte| Term::Hidden(Cmr::from([byte; 32]));
+        let cases: Vec<(Vec<Term<(), DummyNode>>, Error)> = vec![
+            (vec![hidden(1)], Error::HiddenRoot),
+            (
+                vec![hidden(1), Term::Unit, Term::Comp(2, 1)],
+                Error::NonCaseHiddenChild {
+                    node: 0,
+                    parent: 2,
+                    parent_term: "comp",
+                },
+            ),
+            (
+                vec![Term::Unit, hidden(1), Term::Pair(2, 1)],
+                Error::NonCaseHiddenChild {
+                    node: 1,
+                    parent: 2,
+                    parent_term: "pair",
+                },
+            ),
+            (
+                vec![hidden(1), Term::InjL(1)],
+                Error::NonCaseHiddenChild {
+                    node: 0,
+                    parent: 1,
+                    parent_term: "injl",
+                },
+            ),
+            (
+                vec![hidden(1), hidden(2), Term::Case(2, 1)],
+                Error::CaseMultipleHiddenChildren(2),
+            ),
+        ];
+        for (nodes, expected) in cases {
+            // Built directly
+            let result = Program::<DummyNode>::from_untyped_nodes(
+                UnTypedProg(nodes.clone()),
+                &mut BitIter::from(vec![0x00].into_iter()),
+            );
+            assert_eq!(result.unwrap_err().to_string(), expected.to_string());
+
+            // Decoded from an encoding, with no witness data
+            let mut w = BitWriter::new(vec![]);
+            encode::encode_natural(nodes.len(), &mut w).unwrap();
+            for node in &nodes {
+                encode::encode_node_no_witness(node, &mut w).unwrap();
+            }
+            w.write_bit(false).unwrap();
+            w.flush_all().unwrap();
+            let result = Program::<DummyNode>::deserialize(&w.into_inner());
+            assert_eq!(result.unwrap_err().to_string(), expected.to_string());
+        }
+
+        // A single hidden branch of a case is fine
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![hidden(1), Term::Unit, Term::Case(2, 1)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let decoded = Program::<DummyNode>::deserialize(&prog.serialize()).unwrap();
+        assert_eq!(decoded.nodes, prog.nodes);
+    }
+
+    #[test]
+    fn encoding_offsets() {
+        // adder32 applied to a pair of 32-bit witnesses
+        let mut w = BitWriter::new(vec![]);
+        w.write_bit(true).unwrap();
+        encode::encode_natural(64, &mut w).unwrap();
+        for byte in &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0] {
+            w.write_u8(*byte, 8).unwrap();
+        }
+        w.flush_all().unwrap();
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Witness(()),
+                Term::Witness(()),
+                Term::Pair(2, 1),
+                Term::Jet(JetsNode::Adder32),
+                Term::Comp(2, 1),
+            ]),
+            &mut BitIter::from(w.into_inner().into_iter()),
+        )
+        .unwrap();
+
+        let (bytes, offsets) = prog.encode_with_offsets();
+        assert_eq!(bytes, prog.serialize());
+        let ids: Vec<_> = offsets.iter().map(|&(id, _)| id.index()).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 0, 1]);
+        // Contiguous after the program length, then after the witness length
+        let (nodes, witnesses) = offsets.split_at(5);
+        assert_eq!(nodes[0].1.start, encode::natural_bit_len(5));
+        for pair in nodes.windows(2).chain(witnesses.windows(2)) {
+            assert_eq!(pair[0].1.end, pair[1].1.start);
+        }
+        assert_eq!(
+            witnesses[0].1.start,
+            nodes[4].1.end + 1 + encode::natural_bit_len(64)
+        );
+        assert_eq!(witnesses[1].1.end, prog.encoded_bit_len());
+        assert_eq!((prog.encoded_bit_len() + 7) / 8, bytes.len());
+
+        assert_eq!(prog.explain_offset(0), Some(OffsetInfo::ProgramLength));
+        for (id, range) in nodes {
+            let info = Some(OffsetInfo::Node {
+                node: *id,
+                range: range.clone(),
+            });
+            assert_eq!(prog.explain_offset(range.start), info);
+            assert_eq!(prog.explain_offset(range.end - 1), info);
+        }
+        assert_eq!(
+            prog.explain_offset(nodes[4].1.end),
+            Some(OffsetInfo::WitnessLength)
+        );
+        assert_eq!(
+            prog.explain_offset(witnesses[1].1.start),
+            Some(OffsetInfo::Witness {
+                node: prog.id(1),
+                range: witnesses[1].1.clone(),
+            })
+        );
+        assert_eq!(prog.explain_offset(prog.encoded_bit_len()), None);
+
+        // Corrupting any bit of the root's encoding so that decoding
+        // fails, the position of the failure maps back to the root. Other
+        // corruptions give a different program, which may not typecheck.
+        let (prog, _) = crate::bench_util::deep_comp_chain(3);
+        let (bytes, offsets) = prog.encode_with_offsets();
+        let (root, range) = offsets[4].clone();
+        let root_info = Some(OffsetInfo::Node {
+            node: root,
+            range: range.clone(),
+        });
+        let mut decode_failures = 0;
+        for bit in range {
+            assert_eq!(prog.explain_offset(bit), root_info);
+            let mut corrupt = bytes.clone();
+            corrupt[bit / 8] ^= 0x80 >> (bit % 8);
+
+            // Decode node by node to find the position of the failure
+            let mut iter = BitIter::from(corrupt.iter().cloned());
+            let len = encode::decode_program_len(&mut iter).unwrap();
+            let mut failed_at = None;
+            for i in 0..len {
+                let start = iter.n_total_read();
+                if encode::decode_node_no_witness::<_, DummyNode>(i, &mut iter).is_err() {
+                    failed_at = Some(start);
+                    break;
+                }
+            }
+            match failed_at {
+                Some(start) => {
+                    decode_failures += 1;
+                    assert!(Program::<DummyNode>::deserialize(&corrupt).is_err());
+                    assert_eq!(prog.explain_offset(start), root_info);
+                }
+                None => assert_matches!(
+                    Program::<DummyNode>::deserialize(&corrupt),
+                    Ok(..) | Err(Error::TypeCheck)
+                ),
+            }
+        }
+        assert!(decode_failures > 0);
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push)]
+    fn encode_prog() {
+        let mut prog: Vec<Term<(), DummyNode>> = vec![];
+
+        prog.push(Term::Jet(JetsNode::Adder32));
+        // prog.push(Node::Case(0, 1));
+
+        let prog = Program::from_untyped_nodes(
+            UnTypedProg(prog),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        prog.graph_print();
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push)]
+    fn witness_and() {
+        let mut prog: Vec<Term<(), DummyNode>> = vec![];
+
+        prog.push(Term::Unit);
+        prog.push(Term::InjR(1));
+        prog.push(Term::Witness(()));
+        prog.push(Term::Case(2, 1));
+        prog.push(Term::Witness(()));
+        prog.push(Term::Comp(1, 2));
+
+        let prog = Program::from_untyped_nodes(
+            UnTypedProg(prog),
+            &mut BitIter::from(vec![0x80].into_iter()),
+        )
+        .unwrap();
+        prog.graph_print();
+
+        let mut mac = exec::BitMachine::for_program(&prog);
+        // mac.input(&Value::prod(Value::u1(0), Value::Unit)).unwrap();
+        let output = mac.exec(&prog, &TxEnv);
+
+        println!("{}", output);
+    }
+
+    #[test]
+    fn paths_to_node() {
+        // 0: iden, 1: unit, 2: injl(1), 3: injr(1), 4: pair(2, 3)
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Unit,
+                Term::InjL(1),
+                Term::InjR(2),
+                Term::Pair(2, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+
+        let indices = |path: Vec<NodeId>| path.into_iter().map(NodeId::index).collect::<Vec<_>>();
+        let path = |idx| {
+            prog.path_to_node(prog.node_id(idx).unwrap())
+                .unwrap()
+                .map(indices)
+        };
+        let all_paths = |idx| {
+            prog.all_paths_to_node(prog.node_id(idx).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(indices)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(path(4), Some(vec![4]));
+        assert_eq!(path(3), Some(vec![4, 3]));
+        assert_eq!(path(1), Some(vec![4, 2, 1]));
+        // Unreachable and out of range
+        assert_eq!(path(0), None);
+        assert_eq!(prog.node_id(5), None);
+
+        assert_eq!(all_paths(1), vec![vec![4, 2, 1], vec![4, 3, 1]]);
+        assert_eq!(all_paths(2), vec![vec![4, 2]]);
+        assert!(all_paths(0).is_empty());
+    }
+
+    #[test]
+    fn apply_batch() {
+        let (prog, _) = crate::bench_util::deep_comp_chain(4);
+        let mut inputs: Vec<Value> = (0..20u32)
+            .map(|i| Value::prod(Value::u32(i.wrapping_mul(0x9e37_79b9)), Value::u32(!i)))
+            .collect();
+        inputs.push(Value::u8(0));
+
+        let expected: Vec<_> = inputs
+            .iter()
+            .map(|input| exec::BitMachine::for_program(&prog).exec_with_input(&prog, input, &TxEnv))
+            .collect();
+        assert_eq!(
+            expected.last(),
+            Some(&Err(exec::ExecError::InputTypeMismatch))
+        );
+        assert_eq!(prog.apply_batch(&inputs, &TxEnv), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(prog.apply_batch_parallel(&inputs, &TxEnv), expected);
+        assert!(prog.apply_batch(&[], &TxEnv).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_roundtrip() {
+        use bitcoin_hashes::hex::ToHex;
+
+        let (prog, _) = crate::bench_util::witness_hash_block();
+        let bytes = prog.serialize();
+
+        // Hex in human-readable formats
+        let json = serde_json::to_string(&prog).unwrap();
+        assert_eq!(json, format!("\"{}\"", bytes.to_hex()));
+        let decoded: Program<DummyNode> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.nodes, prog.nodes);
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+
+        // Bytes in binary formats
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(&prog, &mut cbor).unwrap();
+        assert!(cbor.ends_with(&bytes));
+        let decoded: Program<DummyNode> = ciborium::de::from_reader(&cbor[..]).unwrap();
+        assert_eq!(decoded.nodes, prog.nodes);
+        let mut again = vec![];
+        ciborium::ser::into_writer(&decoded, &mut again).unwrap();
+        assert_eq!(again, cbor);
+
+        // Trailing data and nonzero padding are not canonical
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let json = format!("\"{}\"", trailing.to_hex());
+        assert!(serde_json::from_str::<Program<DummyNode>>(&json).is_err());
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(&ciborium::value::Value::Bytes(trailing), &mut cbor).unwrap();
+        assert!(ciborium::de::from_reader::<Program<DummyNode>, _>(&cbor[..]).is_err());
+
+        // injr unit, which decodes with its last padding bit set too
+        assert!(serde_json::from_str::<Program<DummyNode>>("\"8928\"").is_ok());
+        assert!(Program::<DummyNode>::deserialize(&[0x89, 0x29]).is_ok());
+        assert!(serde_json::from_str::<Program<DummyNode>>("\"8929\"").is_err());
+        // Neither valid hex nor a valid program
+        assert!(serde_json::from_str::<Program<DummyNode>>("\"89zz\"").is_err());
+        assert!(serde_json::from_str::<Program<DummyNode>>("\"89\"").is_err());
+    }
+
+    #[test]
+    fn node_layout() {
+        // drop (case (injl (take iden)) (injr (take iden))), on input
+        // 2^8 × ((2^32 + 2^8) × 1)
+        let pow2 = types::pow2_types();
+        let (a, b) = (pow2[6].clone(), pow2[4].clone());
+        let unit = pow2[0].clone();
+        let sum = Arc::new(types::FinalType::sum(a.clone(), b.clone()));
+        let prod = |x: &Arc<types::FinalType>, y: &Arc<types::FinalType>| {
+            Arc::new(types::FinalType::prod(x.clone(), y.clone()))
+        };
+        let node = |term, source_ty, target_ty| FlatNode {
+            term: term,
+            source_ty: source_ty,
+            target_ty: target_ty,
+        };
+        let prog = FlatProgram::<DummyNode> {
+            nodes: vec![
+                node(Term::Iden, a.clone(), a.clone()),
+                node(Term::Take(0), prod(&a, &unit), a.clone()),
+                node(Term::InjL(1), prod(&a, &unit), sum.clone()),
+                node(Term::Iden, b.clone(), b.clone()),
+                node(Term::Take(3), prod(&b, &unit), b.clone()),
+                node(Term::InjR(4), prod(&b, &unit), sum.clone()),
+                node(Term::Case(2, 5), prod(&sum, &unit), sum.clone()),
+                node(Term::Drop(6), prod(&b, &prod(&sum, &unit)), sum.clone()),
+            ],
         }
-        Term::Witness(..) => witness_target_width,
-        Term::Fail(..) => unimplemented!(),
-        Term::Hidden(..) => 0,
-        Term::Ext(..) => 0, // FIXME should fallthrough
-        Term::Jet(..) => 0,
+        .to_program()
+        .unwrap();
+
+        assert_eq!(
+            prog.nodes[6].case_parts(),
+            Some(CaseParts {
+                s_offset: 4,
+                t_offset: 1,
+                sum_ty_left_width: 32,
+                sum_ty_right_width: 8,
+                max_branch_width: 32,
+            })
+        );
+        let parts = prog.nodes[6].case_parts().unwrap();
+        assert_eq!(
+            (parts.left_padding(), parts.right_padding()),
+            (Some(0), Some(24))
+        );
+        // Parts built by hand may be inconsistent
+        let parts = CaseParts {
+            max_branch_width: 8,
+            ..parts
+        };
+        assert_eq!(
+            (parts.left_padding(), parts.right_padding()),
+            (None, Some(0))
+        );
+        assert_eq!(prog.nodes[2].inj_padding(), Some(0));
+        assert_eq!(prog.nodes[5].inj_padding(), Some(24));
+        assert_eq!(prog.nodes[7].drop_width(), Some(8));
+        // Other kinds of node
+        assert_eq!(prog.nodes[1].case_parts(), None);
+        assert_eq!(prog.nodes[6].inj_padding(), None);
+        assert_eq!(prog.nodes[1].drop_width(), None);
+
+        let input = Value::prod(
+            Value::u8(1),
+            Value::prod(Value::sum_r(Value::u8(0xa5)), Value::Unit),
+        );
+        let mut mac = exec::BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &input, &TxEnv),
+            Ok(Value::sum_r(Value::u8(0xa5)))
+        );
+    }
+
+    #[test]
+    fn foreign_node_id() {
+        let decode = || Program::<DummyNode>::deserialize(&[0x89, 0x28]).unwrap();
+        let (prog, other) = (decode(), decode());
+        assert_eq!(prog.node_ids().count(), 2);
+        let root = prog.node_ids().last().unwrap();
+        assert_eq!(root.index(), 1);
+        assert_eq!(prog.node(root).unwrap().index, 1);
+
+        // The same index of an identical program is still a different node
+        assert_ne!(other.node_id(1), Some(root));
+        assert_matches!(other.node(root), Err(Error::ForeignNodeId(id)) if id == root);
+        assert_matches!(other.path_to_node(root), Err(Error::ForeignNodeId(..)));
+        assert_matches!(other.all_paths_to_node(root), Err(Error::ForeignNodeId(..)));
+        assert_matches!(
+            Program::from_template(&other, &[(root, &prog)]),
+            Err(Error::ForeignNodeId(..))
+        );
+        // Ids survive `hidden_to_fail`, which keeps every node's index
+        assert!(prog.hidden_to_fail().node(root).is_ok());
+    }
+
+    #[test]
+    fn verify_trace() {
+        use crate::exec::{TraceEvent, TraceMismatch};
+
+        // comp (injl unit) iden
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Unit,
+                Term::InjL(1),
+                Term::Iden,
+                Term::Comp(2, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let trace = vec![
+            TraceEvent::NewFrame(1),
+            TraceEvent::Exec(3),
+            TraceEvent::NewFrame(1),
+            TraceEvent::Exec(1),
+            TraceEvent::Exec(0),
+            TraceEvent::MoveFrame,
+            TraceEvent::Exec(2),
+            TraceEvent::DropFrame,
+        ];
+        assert_eq!(prog.verify_trace(&Value::Unit, &TxEnv, &trace), Ok(()));
+
+        let mut altered = trace.clone();
+        altered[5] = TraceEvent::DropFrame;
+        assert_eq!(
+            prog.verify_trace(&Value::Unit, &TxEnv, &altered),
+            Err(TraceMismatch {
+                index: 5,
+                expected: Some(TraceEvent::DropFrame),
+                found: Some(TraceEvent::MoveFrame),
+            }),
+        );
+        assert_eq!(
+            prog.verify_trace(&Value::Unit, &TxEnv, &trace[..7]),
+            Err(TraceMismatch {
+                index: 7,
+                expected: None,
+                found: Some(TraceEvent::DropFrame),
+            }),
+        );
+
+        // A mistyped input is not executed
+        assert_eq!(
+            prog.verify_trace(&Value::u1(0), &TxEnv, &trace),
+            Err(TraceMismatch {
+                index: 0,
+                expected: Some(TraceEvent::NewFrame(1)),
+                found: None,
+            }),
+        );
+    }
+
+    #[test]
+    fn absolute_children() {
+        let deep = crate::bench_util::deep_comp_chain(3).0;
+        let shallow = || deep.subprogram(deep.node_id(3).unwrap()).unwrap();
+        let progs = vec![
+            deep.replace_node(3, shallow()).unwrap(),
+            shallow(),
+            deep,
+            crate::bench_util::case_ladder(4).0,
+            crate::bench_util::witness_hash_block().0,
+        ];
+        for prog in &progs {
+            for node in &prog.nodes {
+                let (left, right) = node.node.child_offsets();
+                assert_eq!(
+                    node.children(),
+                    (left.map(|i| node.index - i), right.map(|j| node.index - j)),
+                );
+            }
+
+            // Encoding still emits relative offsets
+            let decoded = Program::<DummyNode>::deserialize(&prog.serialize()).unwrap();
+            assert_eq!(decoded.nodes, prog.nodes);
+        }
+    }
+
+    #[test]
+    fn encoded_bit_len() {
+        use crate::bench_util;
+
+        let mut progs = vec![
+            bench_util::deep_comp_chain(300).0,
+            bench_util::wide_pair_tree(4).0,
+            bench_util::sha256_chain(3).0,
+            bench_util::case_ladder(20).0,
+            bench_util::witness_hash_block().0,
+        ];
+        progs.push(
+            Program::from_untyped_nodes(
+                UnTypedProg(vec![
+                    Term::Unit,
+                    Term::InjR(1),
+                    Term::Witness(()),
+                    Term::Case(2, 1),
+                    Term::Witness(()),
+                    Term::Comp(1, 2),
+                ]),
+                &mut BitIter::from(vec![0x80].into_iter()),
+            )
+            .unwrap(),
+        );
+
+        // The text corpus, with witnesses zeroed
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_vectors/text");
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() == Some("simpl".as_ref()) {
+                let src = std::fs::read_to_string(&path).unwrap();
+                let mut witness = BitIter::from(vec![0x00; 16].into_iter());
+                progs.push(crate::text::parse_program(&src, &mut witness).unwrap());
+            }
+        }
+        // ... and with hidden nodes replaced by `fail` nodes
+        let failing: Vec<_> = progs.iter().map(Program::hidden_to_fail).collect();
+        assert!(failing
+            .iter()
+            .any(|prog| prog.nodes.iter().any(|node| match node.node {
+                Term::Fail(..) => true,
+                _ => false,
+            })));
+        progs.extend(failing);
+
+        for prog in &progs {
+            let mut w = BitWriter::new(io::sink());
+            assert_eq!(prog.encoded_bit_len(), prog.encode(&mut w).unwrap());
+
+            // Node offsets agree with the lengths
+            let (bytes, offsets) = prog.encode_with_offsets();
+            assert_eq!((prog.encoded_bit_len() + 7) / 8, bytes.len());
+            for &(id, ref range) in &offsets[..prog.nodes.len()] {
+                let node = &prog.nodes[id.index()].node;
+                assert_eq!(range.len(), encode::node_bit_len(node));
+                assert_eq!(
+                    prog.explain_offset(range.end - 1),
+                    Some(OffsetInfo::Node {
+                        node: id,
+                        range: range.clone()
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn next_witness_type() {
+        // adder32 applied to a pair of 32-bit witnesses
+        let nodes = vec![
+            Term::Witness(()),
+            Term::Witness(()),
+            Term::Pair(2, 1),
+            Term::Jet(JetsNode::Adder32),
+            Term::Comp(2, 1),
+        ];
+        let mut w = BitWriter::new(vec![]);
+        w.write_bit(true).unwrap();
+        encode::encode_natural(64, &mut w).unwrap();
+        for byte in &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0] {
+            w.write_u8(*byte, 8).unwrap();
+        }
+        w.flush_all().unwrap();
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(nodes),
+            &mut BitIter::from(w.into_inner().into_iter()),
+        )
+        .unwrap();
+
+        let ty = prog.next_witness_type(&Value::Unit, &TxEnv).unwrap();
+        assert_eq!(ty.to_string(), "2^32");
+        assert!(Value::u32(0).is_of_type(&ty));
+        // The input must have the program's source type
+        assert_eq!(prog.next_witness_type(&Value::u1(0), &TxEnv), None);
+
+        let (hash_block, input) = crate::bench_util::witness_hash_block();
+        let ty = hash_block.next_witness_type(&input, &TxEnv).unwrap();
+        assert_eq!(ty.to_string(), "2^256");
+        let (no_witness, input) = crate::bench_util::deep_comp_chain(3);
+        assert_eq!(no_witness.next_witness_type(&input, &TxEnv), None);
+
+        // Stopping early leaves the machine usable after a reset
+        let mut mac = exec::BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_until_witness(&prog, &Value::Unit, &TxEnv),
+            Ok(prog.node_id(0))
+        );
+        mac.reset();
+        let output = mac.exec_with_input(&prog, &Value::Unit, &TxEnv).unwrap();
+        assert_eq!(output, Value::prod(Value::u1(0), Value::u32(0xacf1_3568)));
+    }
+
+    #[test]
+    fn remove_jetted_subprograms() {
+        // multiplier32 followed by `pair e f`, where `e` has the type of
+        // adder32, and `f` has an output of the same width but a
+        // different type
+        let mut prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Jet(JetsNode::Multiplier32),
+                Term::Unit,
+                Term::InjL(1),
+                Term::Iden,
+                Term::Take(1),
+                Term::Pair(3, 1),
+                Term::Pair(2, 4),
+                Term::Pair(2, 1),
+                Term::Comp(8, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.nodes[5].target_ty.bit_width(), 33);
+        assert_eq!(prog.nodes[6].target_ty.bit_width(), 33);
+
+        // Nothing to replace
+        let unchanged = prog.remove_jetted_subprograms(&HashMap::new());
+        assert_eq!(unchanged.nodes, prog.nodes);
+
+        // A jet only replaces an expression with its own CMR
+        let mut jet_cmrs = HashMap::new();
+        jet_cmrs.insert(prog.nodes[5].cmr, JetsNode::Adder32);
+        let unchanged = prog.remove_jetted_subprograms(&jet_cmrs);
+        assert_eq!(unchanged.nodes, prog.nodes);
+
+        // Let `e` and `f` stand in for expansions of adder32
+        let adder32_cmr = JetsNode::Adder32.cmr();
+        prog.nodes[5].cmr = adder32_cmr;
+        prog.nodes[6].cmr = adder32_cmr;
+        jet_cmrs.insert(adder32_cmr, JetsNode::Adder32);
+        let jetted = prog.remove_jetted_subprograms(&jet_cmrs);
+
+        let terms: Vec<_> = jetted.nodes.iter().map(|node| node.node.clone()).collect();
+        let mut expected: Vec<_> = prog.nodes.iter().map(|node| node.node.clone()).collect();
+        expected[5] = Term::Jet(JetsNode::Adder32);
+        assert_eq!(terms, expected);
+        assert_eq!(jetted.nodes[5].cmr, adder32_cmr);
+        assert_eq!(jetted.root_node().target_ty, prog.root_node().target_ty);
+
+        let input = Value::u64(0x0000_0003_0000_0005);
+        let mut mac = exec::BitMachine::for_program(&jetted);
+        let adder32 = Value::prod(Value::u1(0), Value::u32(15));
+        let f = Value::prod(Value::u32(0), Value::sum_l(Value::Unit));
+        assert_eq!(
+            mac.exec_with_input(&jetted, &input, &TxEnv),
+            Ok(Value::prod(adder32, f))
+        );
+    }
+
+    #[test]
+    fn partial_apply() {
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Jet(JetsNode::Adder32)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let applied = prog.partial_apply(&Value::u32(0x8000_0001)).unwrap();
+        assert_eq!(applied.root_node().source_ty.bit_width(), 32);
+        assert_eq!(applied.root_node().target_ty, prog.root_node().target_ty);
+
+        for &b in &[0, 1, 0x7fff_ffff, 0xffff_ffff] {
+            let full = Value::prod(Value::u32(0x8000_0001), Value::u32(b));
+            let mut mac = exec::BitMachine::for_program(&prog);
+            let expected = mac.exec_with_input(&prog, &full, &TxEnv).unwrap();
+            let mut mac = exec::BitMachine::for_program(&applied);
+            assert_eq!(
+                mac.exec_with_input(&applied, &Value::u32(b), &TxEnv),
+                Ok(expected)
+            );
+        }
+
+        // The prefix must have the type of the first component of the input
+        assert_matches!(prog.partial_apply(&Value::u8(1)), Err(Error::TypeCheck));
+        let unit = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Unit]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_matches!(unit.partial_apply(&Value::Unit), Err(Error::TypeCheck));
+    }
+
+    #[test]
+    fn worst_case_frame_sequence() {
+        // Without case nodes the sequence is that of any execution
+        let (prog, input) = crate::bench_util::deep_comp_chain(3);
+        let mut mac = exec::BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let (_, trace) = mac.exec_traced(&prog, &TxEnv);
+        let traced: Vec<_> = trace
+            .into_iter()
+            .filter_map(|event| match event {
+                exec::TraceEvent::NewFrame(size) => Some(FrameEvent::Push(size)),
+                exec::TraceEvent::DropFrame => Some(FrameEvent::Pop),
+                _ => None,
+            })
+            .collect();
+        let events = prog.compute_worst_case_frame_sequence();
+        // The first frame traced is the output frame
+        assert_eq!(traced[0], FrameEvent::Push(33));
+        assert_eq!(events, traced[1..]);
+        assert_eq!(prog.compute_frame_watermark(), 3 * 64);
+        assert_eq!(
+            prog.compute_frame_watermark(),
+            prog.root_node().extra_cells_bound
+        );
+
+        // The right branch of the case allocates more
+        let prog = crate::text::parse_program::<DummyNode, _>(
+            "add := comp (pair iden iden) jet_adder32\n\
+             main := case (drop add) (drop (comp (comp iden iden) add))",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        assert_eq!(
+            prog.compute_worst_case_frame_sequence(),
+            vec![
+                FrameEvent::Push(32),
+                FrameEvent::Push(32),
+                FrameEvent::Pop,
+                FrameEvent::Push(64),
+                FrameEvent::Pop,
+                FrameEvent::Pop,
+            ]
+        );
+        assert_eq!(prog.compute_frame_watermark(), 96);
+        assert_eq!(
+            prog.compute_frame_watermark(),
+            prog.root_node().extra_cells_bound
+        );
+
+        let (ladder, _) = crate::bench_util::case_ladder(4);
+        assert!(ladder.compute_worst_case_frame_sequence().is_empty());
+        assert_eq!(ladder.compute_frame_watermark(), 0);
+
+        // Too deep to walk recursively on a test thread's stack
+        let (deep, _) = crate::bench_util::deep_comp_chain(50_000);
+        assert_eq!(
+            deep.compute_frame_watermark(),
+            deep.root_node().extra_cells_bound
+        );
+        assert_eq!(deep.compute_frame_size_distribution(), vec![(64, 50_000)]);
+    }
+
+    #[test]
+    fn size_histograms() {
+        let prog = crate::text::parse_program::<DummyNode, _>(
+            "add := comp (pair iden iden) jet_adder32\n\
+             main := case (drop add) (drop (comp (comp iden iden) add))",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        // Every node has a source and a target: the case takes a bit and
+        // a word, and everything under the drops works on words
+        let histogram = prog.compute_type_size_histogram();
+        assert_eq!(histogram.values().sum::<usize>(), 2 * prog.nodes.len());
+        assert_eq!(
+            histogram.iter().map(|(&w, &n)| (w, n)).collect::<Vec<_>>(),
+            vec![(32, 15), (33, 7), (64, 2)]
+        );
+        let chart: BarChart = histogram.into_iter().collect();
+        assert_eq!(
+            chart.to_string(),
+            "32 │████████████████████████████████████████ 15\n\
+             33 │██████████████████▊ 7\n\
+             64 │█████▍ 2\n"
+        );
+
+        // Only the frames of the worst case branch are counted
+        let distribution = prog.compute_frame_size_distribution();
+        assert_eq!(distribution, vec![(32, 2), (64, 1)]);
+        let chart: BarChart = distribution.into_iter().collect();
+        assert_eq!(
+            chart.to_string(),
+            "32 │████████████████████████████████████████ 2\n\
+             64 │████████████████████ 1\n"
+        );
+
+        assert_eq!(BarChart::default().to_string(), "");
+        assert_eq!(BarChart(vec![(0, 0)]).to_string(), "0 │ 0\n");
+    }
+
+    #[test]
+    fn to_source() {
+        let (prog, _) = crate::bench_util::witness_hash_block();
+        assert_eq!(
+            prog.to_source(),
+            "w0 := witness  -- 0x000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\n\
+             w1 := witness  -- 0x202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f\n\
+             w2 := witness  -- 0x404142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f\n\
+             main := comp (pair w0 (pair w1 w2)) jet_sha256hashblock\n"
+        );
+        let (prog, _) = crate::bench_util::sha256_chain(2);
+        assert_eq!(
+            prog.to_source(),
+            "n3 := pair jet_sha256hashblock (drop iden)\n\
+             main := comp n3 (comp n3 (take iden))\n"
+        );
+
+        // Parsing the source gives back the same CMR
+        let hidden = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Unit,
+                Term::Hidden(Cmr::from([0xab; 32])),
+                Term::Case(2, 1),
+                Term::Comp(4, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let programs = vec![
+            crate::bench_util::deep_comp_chain(4).0,
+            crate::bench_util::wide_pair_tree(4).0,
+            crate::bench_util::sha256_chain(3).0,
+            crate::bench_util::witness_hash_block().0,
+            crate::bench_util::case_ladder(5).0,
+            hidden,
+        ];
+        for prog in programs {
+            let source = prog.to_source();
+            // Witness values are not read from the source, so any will do
+            let parsed = crate::text::parse_program::<DummyNode, _>(
+                &source,
+                &mut BitIter::from(vec![0x00; 128].into_iter()),
+            )
+            .unwrap_or_else(|e| panic!("{}\n{}", source, e));
+            assert_eq!(parsed.root_node().cmr, prog.root_node().cmr, "{}", source);
+        }
+    }
+
+    #[test]
+    fn enumerate_paths() {
+        let parse = |src| {
+            crate::text::parse_program::<DummyNode, _>(
+                src,
+                &mut BitIter::from(vec![0x00].into_iter()),
+            )
+            .unwrap()
+        };
+        let prog =
+            parse("main := pair (case (drop unit) (drop unit)) (case (drop iden) (drop iden))");
+        let cases: Vec<_> = prog
+            .nodes
+            .iter()
+            .filter(|node| match node.node {
+                Term::Case(..) => true,
+                _ => false,
+            })
+            .map(|node| prog.node_id(node.index).unwrap())
+            .collect();
+        let (first, second) = (cases[0], cases[1]);
+        let paths = prog.enumerate_paths(4).unwrap();
+        let branches: Vec<_> = paths.iter().map(|path| path.branches.clone()).collect();
+        assert_eq!(
+            branches,
+            vec![
+                vec![(first, false), (second, false)],
+                vec![(first, false), (second, true)],
+                vec![(first, true), (second, false)],
+                vec![(first, true), (second, true)],
+            ]
+        );
+        assert!(paths.iter().all(|path| path.outcome == PathOutcome::Output
+            && path.output_ty == prog.root_node().target_ty));
+        assert_eq!(
+            prog.enumerate_paths(3),
+            Err(PathError::TooManyPaths { limit: 3 })
+        );
+
+        // A path ends at a hidden node, without running what follows
+        let prog = parse(&format!(
+            "main := comp (pair (case (injl unit) (hidden 0x{})) unit) (case unit unit)",
+            "ab".repeat(32)
+        ));
+        let hidden = prog
+            .nodes
+            .iter()
+            .find(|node| match node.node {
+                Term::Hidden(..) => true,
+                _ => false,
+            })
+            .map(|node| prog.node_id(node.index).unwrap())
+            .unwrap();
+        let paths = prog.enumerate_paths(10).unwrap();
+        let outcomes: Vec<_> = paths
+            .iter()
+            .map(|path| (path.branches.len(), path.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (2, PathOutcome::Output),
+                (2, PathOutcome::Output),
+                (1, PathOutcome::Hidden(hidden)),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_total_symbolic() {
+        // Witnesses are a single bit
+        let parse = |src: &str| {
+            let mut w = BitWriter::new(vec![]);
+            w.write_bit(true).unwrap();
+            encode::encode_natural(1, &mut w).unwrap();
+            w.write_bit(true).unwrap();
+            w.flush_all().unwrap();
+            crate::text::parse_program::<DummyNode, _>(
+                src,
+                &mut BitIter::from(w.into_inner().into_iter()),
+            )
+            .unwrap()
+        };
+        let hidden = format!("hidden 0x{}", "ab".repeat(32));
+        let find_hidden = |prog: &Program<DummyNode>| {
+            prog.nodes
+                .iter()
+                .find(|node| match node.node {
+                    Term::Hidden(..) => true,
+                    _ => false,
+                })
+                .map(|node| prog.id(node.index))
+                .unwrap()
+        };
+
+        // Either branch of the witness case is fine, and the assertion
+        // on a constant never reaches its hidden branch
+        let prog = parse(&format!(
+            "main := pair (comp (pair witness unit) (case (drop unit) (drop unit))) \
+             (comp (pair (injr unit) unit) (case ({}) unit))",
+            hidden
+        ));
+        assert_eq!(prog.check_total_symbolic(10), Ok(()));
+        // Unlike `enumerate_paths`, which does not track values
+        let paths = prog.enumerate_paths(10).unwrap();
+        assert!(paths.iter().any(|path| path.outcome != PathOutcome::Output));
+
+        // The hidden branch is taken on a right witness
+        let prog = parse(&format!(
+            "main := comp (pair witness unit) (case unit ({}))",
+            hidden
+        ));
+        let node = find_hidden(&prog);
+        assert_eq!(
+            prog.check_total_symbolic(10),
+            Err(TotalityError::Partial(vec![node]))
+        );
+        assert_eq!(
+            prog.hidden_to_fail().check_total_symbolic(10),
+            Err(TotalityError::Partial(vec![node]))
+        );
+        assert_eq!(
+            prog.check_total_symbolic(10).unwrap_err().to_string(),
+            format!("Program may fail, reaching node {}", node)
+        );
+
+        // Four distinct outputs, one for each pair of witness bits
+        let prog = parse(
+            "bit := comp (pair witness unit) (case (drop (injl unit)) (drop (injr unit)))\n\
+             main := pair bit bit",
+        );
+        assert_eq!(prog.check_total_symbolic(4), Ok(()));
+        assert_eq!(
+            prog.check_total_symbolic(3),
+            Err(TotalityError::Paths(PathError::TooManyPaths { limit: 3 }))
+        );
+    }
+
+    #[test]
+    fn deep_path_checks() {
+        // Too deep to walk recursively on a test thread's stack
+        let (prog, _) = crate::bench_util::deep_comp_chain(50_000);
+        let paths = prog.enumerate_paths(1).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].outcome, PathOutcome::Output);
+        assert_eq!(prog.check_total_symbolic(1), Ok(()));
+    }
+
+    #[test]
+    fn subprogram() {
+        // 0: iden, 1: adder32, 2: comp(0, 1), 3: comp(0, 2), 4: comp(0, 3)
+        let (prog, input) = crate::bench_util::deep_comp_chain(3);
+        let id = |idx| prog.node_id(idx).unwrap();
+
+        let whole = prog.subprogram(prog.node_id(4).unwrap()).unwrap();
+        assert_eq!(whole.nodes, prog.nodes);
+        assert_eq!(whole.serialize(), prog.serialize());
+
+        let sub = prog.subprogram(id(2)).unwrap();
+        assert_eq!(sub.nodes.len(), 3);
+        assert_eq!(sub.root_node().cmr, prog.nodes[2].cmr);
+        let jet = prog.subprogram(id(1)).unwrap();
+        assert_eq!(jet.nodes.len(), 1);
+        assert_eq!(jet.root_node().node, Term::Jet(JetsNode::Adder32));
+
+        let expected = exec::BitMachine::for_program(&jet).exec_with_input(&jet, &input, &TxEnv);
+        assert_eq!(
+            expected,
+            Ok(Value::prod(Value::u1(0), Value::u32(0xacf1_3568)))
+        );
+        let mut mac = exec::BitMachine::for_program(&sub);
+        assert_eq!(mac.exec_with_input(&sub, &input, &TxEnv), expected);
+
+        // Nodes not reachable from the new root are dropped
+        let unreachable = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Iden, Term::Unit, Term::InjL(1)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let root = unreachable.node_id(2).unwrap();
+        let stripped = unreachable.subprogram(root).unwrap();
+        assert_eq!(stripped.nodes.len(), 2);
+        assert_eq!(stripped.root_node().cmr, unreachable.root_node().cmr);
+
+        assert_matches!(prog.subprogram(root), Err(Error::ForeignNodeId(..)));
+    }
+
+    #[test]
+    fn replace_node() {
+        // adder32 applied to a pair of 32-bit witnesses, 2 and 3
+        let nodes = vec![
+            Term::Witness(()),
+            Term::Witness(()),
+            Term::Pair(2, 1),
+            Term::Jet(JetsNode::Adder32),
+            Term::Comp(2, 1),
+        ];
+        let mut w = BitWriter::new(vec![]);
+        w.write_bit(true).unwrap();
+        encode::encode_natural(64, &mut w).unwrap();
+        for byte in &[0, 0, 0, 2, 0, 0, 0, 3] {
+            w.write_u8(*byte, 8).unwrap();
+        }
+        w.flush_all().unwrap();
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(nodes),
+            &mut BitIter::from(w.into_inner().into_iter()),
+        )
+        .unwrap();
+        let constant = |src: &str| {
+            crate::text::parse_program::<DummyNode, _>(
+                src,
+                &mut BitIter::from(vec![0x00].into_iter()),
+            )
+            .unwrap()
+        };
+        let run =
+            |prog: &Program<DummyNode>| exec::BitMachine::for_program(prog).exec(prog, &TxEnv);
+        assert_eq!(run(&prog), Value::prod(Value::u1(0), Value::u32(5)));
+
+        // The first witness is replaced by a constant, computed by a
+        // sub-program of several nodes
+        let five = constant("main := const 0x00000005");
+        let five_len = five.nodes.len();
+        let replaced = prog.replace_node(0, five).unwrap();
+        assert_eq!(replaced.nodes.len(), prog.nodes.len() + five_len - 1);
+        assert_ne!(replaced.root_node().cmr, prog.root_node().cmr);
+        assert_eq!(run(&replaced), Value::prod(Value::u1(0), Value::u32(8)));
+        // The other witness keeps its value
+        assert_eq!(replaced.nodes[five_len].node, Term::Witness(Value::u32(3)));
+        let decoded = Program::<DummyNode>::deserialize(&replaced.serialize()).unwrap();
+        assert_eq!(decoded.nodes, replaced.nodes);
+
+        // Replacing the root drops every other node
+        let root = constant("main := pair (const 0b0) (const 0x00000001)");
+        let replaced = prog.replace_node(4, root).unwrap();
+        assert_eq!(run(&replaced), Value::prod(Value::u1(0), Value::u32(1)));
+        assert!(!replaced.nodes.iter().any(|node| match node.node {
+            Term::Witness(..) | Term::Jet(..) => true,
+            _ => false,
+        }));
+
+        let byte = constant("main := const 0x05");
+        assert_eq!(
+            prog.replace_node(0, byte).unwrap_err(),
+            ReplaceError::TypeMismatch {
+                expected: (
+                    prog.nodes[0].source_ty.clone(),
+                    prog.nodes[0].target_ty.clone()
+                ),
+                found: (
+                    Arc::new(types::FinalType::unit()),
+                    types::pow2_types()[4].clone()
+                ),
+            }
+        );
+        let err = prog.replace_node(5, constant("main := unit")).unwrap_err();
+        assert_eq!(err, ReplaceError::InvalidIndex(5));
+        assert_eq!(err.to_string(), "Program has no node 5");
+
+        // An unreachable node is not replaced, and the unreachable node
+        // is dropped by replacing a reachable one
+        let unreachable = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Iden, Term::Unit]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let err = unreachable
+            .replace_node(0, constant("main := unit"))
+            .unwrap_err();
+        assert_eq!(err, ReplaceError::UnreachableNode(0));
+        assert_eq!(err.to_string(), "Node 0 is not reachable from the root");
+        let replaced = unreachable
+            .replace_node(1, constant("main := unit"))
+            .unwrap();
+        assert_eq!(replaced.nodes.len(), 1);
     }
-}
 
-fn compute_frame_count_bound<Ext: extension::Jet>(
-    program: &[ProgramNode<Ext>],
-    node: &Term<Value, Ext>,
-    idx: usize,
-) -> usize {
-    match *node {
-        Term::Iden => 0,
-        Term::Unit => 0,
-        Term::InjL(i) => program[idx - i].frame_count_bound,
-        Term::InjR(i) => program[idx - i].frame_count_bound,
-        Term::Take(i) => program[idx - i].frame_count_bound,
-        Term::Drop(i) => program[idx - i].frame_count_bound,
-        Term::Comp(i, j) => {
-            1 + cmp::max(
-                program[idx - i].frame_count_bound,
-                program[idx - j].frame_count_bound,
+    #[test]
+    fn from_template() {
+        let prog = |nodes| {
+            Program::<DummyNode>::from_untyped_nodes(
+                UnTypedProg(nodes),
+                &mut BitIter::from(vec![0x00].into_iter()),
             )
+            .unwrap()
+        };
+        // comp (pair iden iden) unit, with the unit as a hole for a
+        // function of a pair
+        let template = prog(vec![
+            Term::Iden,
+            Term::Pair(1, 1),
+            Term::Unit,
+            Term::Comp(2, 1),
+        ]);
+        let hole = |idx| template.node_id(idx).unwrap();
+
+        let adder = prog(vec![Term::Jet(JetsNode::Adder32)]);
+        let doubler = Program::from_template(&template, &[(hole(2), &adder)]).unwrap();
+        assert_eq!(doubler.nodes.len(), 4);
+        assert_eq!(doubler.root_node().source_ty.bit_width(), 32);
+        let mut mac = exec::BitMachine::for_program(&doubler);
+        assert_eq!(
+            mac.exec_with_input(&doubler, &Value::u32(21), &TxEnv),
+            Ok(Value::prod(Value::u1(0), Value::u32(42)))
+        );
+
+        // The shared `iden` stays shared
+        let take = prog(vec![Term::Iden, Term::Take(1)]);
+        let first = Program::from_template(&template, &[(hole(2), &take)]).unwrap();
+        assert_eq!(first.nodes.len(), 5);
+        assert_eq!(first.nodes[1].children(), (Some(0), Some(0)));
+        assert_eq!(first.root_node().children(), (Some(1), Some(3)));
+
+        // Replacing the root drops the rest of the template
+        let replaced = Program::from_template(&template, &[(hole(3), &adder)]).unwrap();
+        assert_eq!(replaced.nodes, adder.nodes);
+
+        let hash = prog(vec![Term::Jet(JetsNode::Sha256HashBlock)]);
+        assert_matches!(
+            Program::from_template(&template, &[(hole(2), &hash)]),
+            Err(Error::TypeCheck)
+        );
+        assert_matches!(
+            Program::from_template(&template, &[(hole(2), &adder), (hole(2), &take)]),
+            Err(Error::BadIndex)
+        );
+    }
+
+    #[test]
+    fn witness_type() {
+        let pow2 = types::pow2_types();
+        let witness = |value| FlatProgram::<DummyNode> {
+            nodes: vec![FlatNode {
+                term: Term::Witness(value),
+                source_ty: pow2[0].clone(),
+                target_ty: pow2[6].clone(),
+            }],
+        };
+
+        let prog = witness(Value::u32(0x1234_5678)).to_program().unwrap();
+        let mut mac = exec::BitMachine::for_program(&prog);
+        assert_eq!(mac.exec(&prog, &TxEnv), Value::u32(0x1234_5678));
+
+        // A 31-bit value in a 32-bit witness slot
+        let short = Value::prod(
+            Value::u16(0x1234),
+            Value::prod(
+                Value::u8(0x56),
+                Value::prod(Value::u4(7), Value::prod(Value::u2(3), Value::u1(1))),
+            ),
+        );
+        match witness(short.clone()).to_program() {
+            Err(Error::WitnessType {
+                node,
+                expected,
+                found,
+            }) => {
+                assert_eq!(node, 0);
+                assert_eq!(expected, pow2[6]);
+                assert_eq!(found, short);
+            }
+            _ => panic!("expected a witness type error"),
         }
-        Term::Case(i, j) => cmp::max(
-            program[idx - i].frame_count_bound,
-            program[idx - j].frame_count_bound,
-        ),
-        Term::Pair(i, j) => cmp::max(
-            program[idx - i].frame_count_bound,
-            program[idx - j].frame_count_bound,
-        ),
-        Term::Disconnect(i, j) => {
-            2 + cmp::max(
-                program[idx - i].frame_count_bound,
-                program[idx - j].frame_count_bound,
-            )
+    }
+
+    #[test]
+    fn to_flat() {
+        let progs = vec![
+            crate::bench_util::case_ladder(4),
+            crate::bench_util::witness_hash_block(),
+        ];
+        for (prog, input) in &progs {
+            let flat = prog.to_flat();
+            for (node, flat_node) in prog.nodes.iter().zip(&flat.nodes) {
+                let (left, right) = node.node.child_offsets();
+                assert_eq!(
+                    flat_node.children(),
+                    (left.map(|i| node.index - i), right.map(|j| node.index - j)),
+                );
+            }
+
+            let round_trip = flat.to_program().unwrap();
+            assert_eq!(round_trip.nodes, prog.nodes);
+            assert_eq!(round_trip.features(), prog.features());
+
+            let mut mac = exec::BitMachine::for_program(&round_trip);
+            mac.input(input).unwrap();
+            let mut expected_mac = exec::BitMachine::for_program(prog);
+            expected_mac.input(input).unwrap();
+            assert_eq!(
+                mac.exec(&round_trip, &TxEnv),
+                expected_mac.exec(prog, &TxEnv)
+            );
         }
-        Term::Witness(..) => 0,
-        Term::Fail(..) => unimplemented!(),
-        Term::Hidden(..) => 0,
-        Term::Ext(..) => 0, // FIXME should fallthrough
-        Term::Jet(..) => 0,
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::exec;
+    #[test]
+    fn to_program_checks() {
+        use crate::bench_util;
 
-    use crate::bititer::BitIter;
-    use crate::extension::{
-        dummy::{DummyNode, TxEnv},
-        jets::JetsNode,
-    };
-    use crate::Term;
+        // Well-typed programs using every combinator are accepted
+        let disconnect = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Take(1),
+                Term::Iden,
+                Term::Unit,
+                Term::Comp(2, 1),
+                Term::Disconnect(4, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let hidden = crate::text::parse_program::<DummyNode, _>(
+            "main := comp (pair (injr unit) unit) (case unit (hidden 0x0000000000000000000000000000000000000000000000000000000000000000))",
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        let progs = vec![
+            bench_util::deep_comp_chain(3).0,
+            bench_util::wide_pair_tree(3).0,
+            bench_util::sha256_chain(2).0,
+            bench_util::case_ladder(4).0,
+            bench_util::witness_hash_block().0,
+            disconnect,
+            hidden,
+        ];
+        for prog in &progs {
+            assert_eq!(prog.to_flat().to_program().unwrap().nodes, prog.nodes);
+        }
+
+        // Children must come before their parents
+        let flat = progs[3].to_flat();
+        let root = flat.nodes.len() - 1;
+        for &child in &[root, root + 1] {
+            let mut bad = flat.clone();
+            bad.nodes[root].term = bad.nodes[root].term.clone().map_children(|_| child);
+            assert_matches!(bad.to_program(), Err(Error::BadIndex));
+        }
+
+        // Types must agree with those of the children
+        let pow2 = types::pow2_types();
+        let mut bad = flat.clone();
+        bad.nodes[root].target_ty = pow2[1].clone();
+        assert_matches!(bad.to_program(), Err(Error::NodeType(idx)) if idx == root);
+
+        let mut bad = progs[2].to_flat();
+        let jet = bad
+            .nodes
+            .iter()
+            .position(|node| match node.term {
+                Term::Jet(..) => true,
+                _ => false,
+            })
+            .unwrap();
+        bad.nodes[jet].source_ty = pow2[0].clone();
+        assert_matches!(bad.to_program(), Err(Error::NodeType(idx)) if idx == jet);
+    }
 
     #[test]
-    fn simple_unit_prog() {
-        // vec![0 0 1 0 0 1 0 0] = vec![0x24]
-        // prog_len = 1 :vec![0 1 0 0 1 0 0]
-        // non a extension or jets node : vec![1 0 0 1 0 0]
-        // code = 2 [1 0 ] : vec![0 1 0 0]
-        // subcode = 1 [0 1]:  vec![0 0] => Parsed unit node.
-        // witness len = 0 vec![0]
-        let prog = vec![0x24];
-        let prog = Program::<DummyNode>::decode(&mut BitIter::from(prog.into_iter()))
-            .expect("decoding program");
+    fn nodes_by_arity() {
+        let indices = |iter: &mut dyn Iterator<Item = (usize, &ProgramNode<DummyNode>)>| {
+            iter.map(|(i, _)| i).collect::<Vec<_>>()
+        };
+
+        let prog = crate::bench_util::witness_hash_block().0;
+        assert_eq!(indices(&mut prog.leaf_nodes()), vec![0, 1, 2, 5]);
+        assert_eq!(indices(&mut prog.internal_nodes()), vec![3, 4, 6]);
+        assert_eq!(indices(&mut prog.binary_nodes()), vec![3, 4, 6]);
+
+        let prog = crate::bench_util::case_ladder(2).0;
+        assert_eq!(indices(&mut prog.leaf_nodes()), vec![0]);
+        assert_eq!(indices(&mut prog.internal_nodes()), vec![1, 2, 3, 4]);
+        assert_eq!(indices(&mut prog.binary_nodes()), vec![2, 4]);
+        for (i, node) in prog.binary_nodes() {
+            assert_eq!(node.index, i);
+            assert_eq!(node.node, Term::Case(1, 1));
+        }
+    }
+
+    #[test]
+    fn iter_exec_order() {
+        use crate::exec::TraceEvent;
+
+        const MAX_BYTES: usize = 1 << 20;
+
+        let (prog, input) = crate::bench_util::case_ladder(3);
+        let mut mac = exec::BitMachine::for_program(&prog);
+        mac.input(&input).unwrap();
+        let (_, trace) = mac.exec_traced(&prog, &TxEnv);
+        let dispatched: Vec<_> = trace
+            .into_iter()
+            .filter_map(|event| match event {
+                TraceEvent::Exec(idx) => Some(idx),
+                _ => None,
+            })
+            .collect();
+        let order = prog.iter_exec_order(&input, &TxEnv, MAX_BYTES).unwrap();
+        assert_eq!(order.collect::<Vec<_>>(), dispatched);
+        // Each case is visited, then its only child by either branch
+        assert_eq!(dispatched, vec![6, 5, 4, 3, 2, 1, 0]);
+
+        // Nodes are yielded as the program runs, starting from the root
+        let mut order = prog.iter_exec_order(&input, &TxEnv, MAX_BYTES).unwrap();
+        assert_eq!(order.next(), Some(6));
+        assert_eq!(order.next(), Some(5));
 
-        assert_eq!(prog.nodes.len(), 1);
-        assert_eq!(prog.nodes[0].node, Term::Unit);
-        // Checked against C implementation
         assert_eq!(
-            prog.nodes[0].cmr.to_string(),
-            "d723083cff3c75e29f296707ecf2750338f100591c86e0c71717f807ff3cf69d",
+            prog.iter_exec_order(&Value::Unit, &TxEnv, MAX_BYTES).err(),
+            Some(exec::ExecError::InputTypeMismatch)
+        );
+        match prog.iter_exec_order(&input, &TxEnv, 0) {
+            Err(exec::ExecError::TooMuchMemory { limit: 0, .. }) => {}
+            _ => panic!("expected memory limit to be enforced"),
+        }
+
+        // Only the branch taken by the case is visited
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Unit,
+                Term::InjL(1),
+                Term::InjR(2),
+                Term::Case(2, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
+        for &(right, ref expected) in &[(false, vec![3, 1, 0]), (true, vec![3, 2, 0])] {
+            let branch = if right {
+                Value::sum_r(Value::Unit)
+            } else {
+                Value::sum_l(Value::Unit)
+            };
+            let input = Value::prod(branch, Value::Unit);
+            let order = prog.iter_exec_order(&input, &TxEnv, MAX_BYTES).unwrap();
+            assert_eq!(&order.collect::<Vec<_>>(), expected);
+        }
+
+        // Execution stops at the fail node, in the right branch
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Unit,
+                Term::Hidden(Cmr::from([0; 32])),
+                Term::Case(2, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap()
+        .hidden_to_fail();
+        let input = Value::prod(Value::sum_r(Value::Unit), Value::Unit);
+        let order = prog.iter_exec_order(&input, &TxEnv, MAX_BYTES).unwrap();
+        assert_eq!(order.collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[test]
+    fn diff() {
+        // The witness is a single bit
+        let parse = |src: &str, witness: bool| {
+            let mut w = BitWriter::new(vec![]);
+            w.write_bit(true).unwrap();
+            encode::encode_natural(1, &mut w).unwrap();
+            w.write_bit(witness).unwrap();
+            w.flush_all().unwrap();
+            crate::text::parse_program::<DummyNode, _>(
+                src,
+                &mut BitIter::from(w.into_inner().into_iter()),
+            )
+            .unwrap()
+        };
+        let src = "main := comp (pair witness unit) (case (drop (injl unit)) (drop (injr unit)))";
+        let prog = parse(src, false);
+
+        let same = prog.diff(&parse(src, false));
+        assert!(same.is_empty());
+        assert_eq!((same.only_left, same.only_right), (0, 0));
+        assert_eq!(same.common, prog.nodes.len());
+
+        let witness = prog.diff(&parse(src, true));
+        let paths: Vec<_> = witness.differences.iter().map(|d| &d.path[..]).collect();
+        assert_eq!(paths, vec![&[false, false][..]]);
+        let diff = &witness.differences[0];
+        assert_eq!(diff.left.term, "witness 0");
+        assert_eq!(diff.right.term, "witness 1");
+        // The witness value is not committed to
+        assert_eq!(diff.left.cmr, diff.right.cmr);
+        // The witness, the pair and the root differ
+        assert_eq!((witness.only_left, witness.only_right), (3, 3));
+
+        let swapped = prog.diff(&parse(
+            "main := comp (pair witness unit) (case (drop (injr unit)) (drop (injl unit)))",
+            false,
+        ));
+        let paths: Vec<_> = swapped.differences.iter().map(|d| &d.path[..]).collect();
+        assert_eq!(
+            paths,
+            vec![&[true, false, false][..], &[true, true, false][..]]
+        );
+        assert_eq!(swapped.differences[0].left.term, "injl");
+        assert_eq!(swapped.differences[0].right.term, "injr");
+        assert_ne!(
+            swapped.differences[0].left.cmr,
+            swapped.differences[0].right.cmr
+        );
+        // Only the case and the root differ
+        assert_eq!((swapped.only_left, swapped.only_right), (2, 2));
+
+        // An empty program has no root, but its counts still differ
+        let empty = Program::<DummyNode> {
+            nodes: vec![],
+            tag: next_tag(),
+            features: ProgramFeatures::empty(),
+        };
+        assert!(empty.diff(&empty).is_empty());
+        let added = empty.diff(&prog);
+        assert!(!added.is_empty());
+        assert!(added.differences.is_empty());
+        assert_eq!((added.only_left, added.only_right), (0, prog.nodes.len()));
+        let removed = prog.diff(&empty);
+        assert_eq!(
+            (removed.only_left, removed.only_right),
+            (prog.nodes.len(), 0)
         );
+
+        let rendered = swapped.to_string();
+        assert!(rendered.starts_with("2 nodes only in left, 2 only in right"));
+        assert!(rendered.contains("at root.1.0.0:\n  - ["));
+        assert!(rendered.contains("at root.1.1.0:\n"));
     }
 
     #[test]
-    fn injl_unit_prog() {
-        // 100 01001 00100 0
-        // 1000 1001 0010 0000
-        let prog = vec![0x89, 0x20];
-        let prog = Program::<DummyNode>::decode(&mut BitIter::from(prog.into_iter()))
-            .expect("decoding program");
+    fn cost_compare() {
+        let (bloated, input) = crate::bench_util::deep_comp_chain(10);
+        let (minimal, _) = crate::bench_util::deep_comp_chain(0);
+        let inputs = vec![
+            (input, TxEnv),
+            (Value::prod(Value::u32(1), Value::u32(2)), TxEnv),
+        ];
 
-        prog.graph_print();
-        assert_eq!(prog.nodes.len(), 2);
-        assert_eq!(prog.nodes[0].node, Term::Unit);
-        assert_eq!(prog.nodes[1].node, Term::InjL(1));
+        let comparison = bloated.cost_compare(&minimal, &inputs).unwrap();
+        // Each `comp` executes itself and an `iden` on top of the adder
+        assert_eq!(
+            comparison.left,
+            ExecCost {
+                steps: 42,
+                jets: 2,
+                cost: 42,
+            }
+        );
+        assert_eq!(
+            comparison.right,
+            ExecCost {
+                steps: 2,
+                jets: 2,
+                cost: 2,
+            }
+        );
+        assert_eq!(comparison.ordering(), cmp::Ordering::Greater);
+        assert!(comparison.to_string().ends_with("right is cheaper"));
+        assert_eq!(
+            minimal.cost_compare(&bloated, &inputs).unwrap().ordering(),
+            cmp::Ordering::Less
+        );
 
-        // Checked against C implementation
+        let (ladder, _) = crate::bench_util::case_ladder(3);
         assert_eq!(
-            prog.nodes[0].cmr.to_string(),
-            "d723083cff3c75e29f296707ecf2750338f100591c86e0c71717f807ff3cf69d",
+            bloated.cost_compare(&ladder, &[]),
+            Err(CostCompareError::TypeMismatch)
         );
-        // Checked against C implementation
+        let subtractor = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Jet(JetsNode::Subtractor32)]),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .unwrap();
         assert_eq!(
-            prog.nodes[1].cmr.to_string(),
-            "7a4ebcbd3be89bb9dfd901fdbeff16cfa80aa36363785b14615cbdd3f0ae1f0a"
+            minimal.cost_compare(&subtractor, &inputs),
+            Err(CostCompareError::OutputMismatch { input: 0 })
         );
     }
 
     #[test]
-    fn encode_prog() {
-        let mut prog: Vec<Term<(), DummyNode>> = vec![];
+    fn term_histogram() {
+        let counts = |counts: &[(&str, u64)]| -> BTreeMap<String, u64> {
+            counts.iter().map(|&(k, n)| (k.to_owned(), n)).collect()
+        };
 
-        prog.push(Term::Jet(JetsNode::Adder32));
-        // prog.push(Node::Case(0, 1));
+        let (prog, _) = crate::bench_util::deep_comp_chain(3);
+        let histogram = prog.term_histogram();
+        assert_eq!(
+            histogram.tree,
+            counts(&[("comp", 3), ("iden", 3), ("jet_adder32", 1)])
+        );
+        // The fingerprint gives the DAG counts
+        assert_eq!(histogram.fingerprint(), "comp:3,iden:1,jet_adder32:1");
+        // Every bit but the program length and empty witness data is
+        // attributed to a kind of node
+        let bits: usize = histogram.bits.values().sum();
+        assert_eq!(
+            bits + encode::natural_bit_len(prog.nodes.len()) + 1,
+            prog.encoded_bit_len()
+        );
+        assert_eq!(
+            histogram.to_string(),
+            "comp: 3 in DAG, 3 in tree, 30 bits\n\
+             iden: 1 in DAG, 3 in tree, 5 bits\n\
+             jet_adder32: 1 in DAG, 1 in tree, 6 bits\n"
+        );
+        // The fields are public, so a kind may be missing from some of them
+        let mut partial = TermHistogram::default();
+        partial.dag.insert("unit".to_owned(), 2);
+        assert_eq!(partial.to_string(), "unit: 2 in DAG, 0 in tree, 0 bits\n");
 
-        let prog = Program::from_untyped_nodes(
-            UnTypedProg(prog),
+        // Each level of pairs is a single node used twice by the next
+        // A witness node's value is attributed to it
+        let (prog, _) = crate::bench_util::shared_witness(1);
+        assert_eq!(
+            prog.term_histogram().bits["witness"],
+            encode::node_bit_len(&prog.nodes[0].node) + 256
+        );
+
+        let (prog, _) = crate::bench_util::wide_pair_tree(20);
+        let histogram = prog.term_histogram();
+        assert_eq!(histogram.fingerprint(), "pair:20,jet_adder32:1");
+        assert_eq!(
+            histogram.tree,
+            counts(&[("jet_adder32", 1 << 20), ("pair", (1 << 20) - 1)])
+        );
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&histogram).unwrap();
+            let decoded: TermHistogram = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, histogram);
+        }
+    }
+
+    #[test]
+    fn lint() {
+        // Witnesses are a single bit
+        let parse = |src: &str| {
+            let mut w = BitWriter::new(vec![]);
+            w.write_bit(true).unwrap();
+            encode::encode_natural(1, &mut w).unwrap();
+            w.write_bit(true).unwrap();
+            w.flush_all().unwrap();
+            crate::text::parse_program::<DummyNode, _>(
+                src,
+                &mut BitIter::from(w.into_inner().into_iter()),
+            )
+            .unwrap()
+        };
+        // The case reads the witness, and may take either branch
+        let clean =
+            parse("main := comp (pair witness unit) (case (drop (injl unit)) (drop (injr unit)))");
+        assert_eq!(clean.lint(), vec![]);
+
+        // The case always takes its right branch
+        let prog = parse(
+            "main := comp (pair (injr unit) unit) (case (drop (injl unit)) (drop (injr unit)))",
+        );
+        let case = prog.root_node().right();
+        let warnings = prog.lint();
+        assert_eq!(
+            warnings,
+            vec![LintWarning::DeadBranch {
+                node: prog.id(case),
+                right: false,
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            format!("left branch of case node {} is never taken", case)
+        );
+
+        // (witness >>> iden) &&& iden >>> drop iden >>> adder32, where the
+        // shared `iden` gives the witness the type of the adder's input
+        let mut w = BitWriter::new(vec![]);
+        w.write_bit(true).unwrap();
+        encode::encode_natural(64, &mut w).unwrap();
+        for _ in 0..8 {
+            w.write_u8(0, 8).unwrap();
+        }
+        w.flush_all().unwrap();
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Witness(()),
+                Term::Comp(1, 2),
+                Term::Pair(1, 3),
+                Term::Drop(4),
+                Term::Comp(2, 1),
+                Term::Jet(JetsNode::Adder32),
+                Term::Comp(2, 1),
+            ]),
+            &mut BitIter::from(w.into_inner().into_iter()),
+        )
+        .unwrap();
+        assert_eq!(prog.lint(), vec![LintWarning::UnusedWitness(prog.id(1))]);
+
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![Term::Unit, Term::Iden, Term::Unit]),
             &mut BitIter::from(vec![0x00].into_iter()),
         )
         .unwrap();
-        prog.graph_print();
+        assert_eq!(
+            prog.lint(),
+            vec![
+                LintWarning::UnreferencedNode(prog.id(0)),
+                LintWarning::UnreferencedNode(prog.id(1)),
+            ]
+        );
     }
 
     #[test]
-    fn witness_and() {
-        let mut prog: Vec<Term<(), DummyNode>> = vec![];
+    fn strip_hidden_nodes() {
+        let hidden = Cmr::from([0xab; 32]);
+        let decode = |nodes| {
+            Program::<DummyNode>::from_untyped_nodes(
+                UnTypedProg(nodes),
+                &mut BitIter::from(vec![0x00].into_iter()),
+            )
+            .unwrap()
+        };
 
-        prog.push(Term::Unit);
-        prog.push(Term::InjR(1));
-        prog.push(Term::Witness(()));
-        prog.push(Term::Case(2, 1));
-        prog.push(Term::Witness(()));
-        prog.push(Term::Comp(1, 2));
+        // injl unit, with an unreachable `case unit hidden` left over
+        let prog = decode(vec![
+            Term::Unit,
+            Term::Hidden(hidden),
+            Term::Case(2, 1),
+            Term::Unit,
+            Term::InjL(1),
+        ]);
+        let stripped = prog.strip_hidden_nodes().unwrap();
+        let terms: Vec<_> = stripped.nodes.iter().map(|node| &node.node).collect();
+        assert_eq!(terms, vec![&Term::Unit, &Term::Unit, &Term::InjL(1)]);
+        assert_eq!(stripped.root_node().cmr, prog.root_node().cmr);
+        assert_eq!(stripped.root_node().children(), (Some(1), None));
 
-        let prog = Program::from_untyped_nodes(
-            UnTypedProg(prog),
-            &mut BitIter::from(vec![0x80].into_iter()),
+        // case unit hidden
+        let prog = decode(vec![Term::Unit, Term::Hidden(hidden), Term::Case(2, 1)]);
+        assert_eq!(
+            prog.strip_hidden_nodes().err(),
+            Some(StripError::ReachableHiddenNode {
+                node: prog.node_id(1).unwrap(),
+                cmr: hidden.into_inner(),
+            })
+        );
+
+        // Taking the hidden branch fails cleanly once it is a `fail` node
+        let failing = prog.hidden_to_fail();
+        assert_eq!(failing.root_node().cmr, prog.root_node().cmr);
+        let run = |input| {
+            let input = Value::prod(input, Value::Unit);
+            let mut mac = exec::BitMachine::for_program(&failing);
+            mac.exec_with_input(&failing, &input, &TxEnv)
+        };
+        assert_eq!(run(Value::sum_l(Value::Unit)), Ok(Value::Unit));
+        assert_eq!(
+            run(Value::sum_r(Value::Unit)),
+            Err(exec::ExecError::FailNode(prog.node_id(1).unwrap()))
+        );
+    }
+
+    #[test]
+    fn explain_error() {
+        // comp iden (case unit hidden), taking the hidden branch once it
+        // is a `fail` node
+        let prog = Program::<DummyNode>::from_untyped_nodes(
+            UnTypedProg(vec![
+                Term::Iden,
+                Term::Unit,
+                Term::Hidden(Cmr::from([0xab; 32])),
+                Term::Case(2, 1),
+                Term::Comp(4, 1),
+            ]),
+            &mut BitIter::from(vec![0x00].into_iter()),
         )
         .unwrap();
-        prog.graph_print();
+        let failing = prog.hidden_to_fail();
+        let input = Value::prod(Value::sum_r(Value::Unit), Value::Unit);
+        let mut mac = exec::BitMachine::for_program(&failing);
+        let err = mac.exec_with_input(&failing, &input, &TxEnv).unwrap_err();
+        assert_eq!(err, exec::ExecError::FailNode(prog.node_id(2).unwrap()));
 
-        let mut mac = exec::BitMachine::for_program(&prog);
-        // mac.input(&Value::prod(Value::u1(0), Value::Unit));
-        let output = mac.exec(&prog, &TxEnv);
+        let explained = prog.explain_error(&err);
+        assert!(explained.starts_with("Execution reached fail node 2\n"));
+        assert!(explained.contains(&format!(
+            "path from the root:\n    {}\n    {}\n",
+            prog.nodes[4], prog.nodes[3]
+        )));
+        assert!(explained.contains("    [3] case(2, 1): "));
+        assert!(explained.contains(&format!("failing node:\n    {}\n", prog.nodes[2])));
+        assert!(explained.contains("    [2] hidden: "));
+        assert!(!explained.contains("children:"));
 
-        println!("{}", output);
+        // A node from a different program is not looked up
+        let other = Program::<DummyNode>::deserialize(&prog.serialize()).unwrap();
+        assert_eq!(
+            other.explain_error(&err),
+            "Execution reached fail node 2\nNode 2 is from a different program\n"
+        );
+
+        // Errors which do not come from a node are only described
+        assert_eq!(
+            prog.explain_error(&exec::ExecError::MissingInput),
+            "Program takes an input, but none was given\n"
+        );
+    }
+
+    #[test]
+    fn to_test_fixture() {
+        // injr unit
+        let prog = Program::<DummyNode>::deserialize(&[0x89, 0x28]).unwrap();
+        let ext = "simplicity::extension::dummy::DummyNode";
+        let fixture = prog.to_test_fixture(ext, &Value::Unit, &Value::sum_r(Value::Unit));
+        assert!(fixture.contains("type Ext = simplicity::extension::dummy::DummyNode;"));
+        assert!(fixture.contains("Program::<Ext>::deserialize(&[0x89, 0x28])"));
+        assert!(fixture.contains("            (&[], &[0x80]),\n        ];"));
+
+        let cases = [
+            (Value::Unit, Value::sum_r(Value::Unit)),
+            (Value::Unit, Value::sum_r(Value::Unit)),
+        ];
+        let fixture = prog.to_test_fixture_batch(ext, &cases);
+        assert_eq!(fixture.matches("(&[], &[0x80])").count(), 2);
     }
 }
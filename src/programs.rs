@@ -0,0 +1,260 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Programs
+//!
+//! Builders for complete Simplicity programs, which can be run as they
+//! are or composed into larger programs.
+//!
+
+use std::collections::HashMap;
+
+use crate::bititer::BitIter;
+use crate::extension::{self, jets::JetsNode};
+use crate::{Program, Term, UnTypedProg};
+
+/// Initial SHA-256 midstate
+const SHA256_IV: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+/// Nodes of a program under construction, each of whose children are
+/// given by absolute index
+struct Builder<Ext> {
+    nodes: Vec<Term<(), Ext>>,
+    /// Index of the node computing each constant
+    consts: HashMap<Vec<bool>, usize>,
+}
+
+impl<Ext: extension::Jet> Builder<Ext> {
+    fn new() -> Self {
+        Builder {
+            nodes: vec![],
+            consts: HashMap::new(),
+        }
+    }
+
+    /// Add a node, given a function from its own index to the node,
+    /// returning that index
+    fn push<F: FnOnce(usize) -> Term<(), Ext>>(&mut self, node: F) -> usize {
+        let idx = self.nodes.len();
+        self.nodes.push(node(idx));
+        idx
+    }
+
+    fn take(&mut self, child: usize) -> usize {
+        self.push(|idx| Term::Take(idx - child))
+    }
+
+    fn drop(&mut self, child: usize) -> usize {
+        self.push(|idx| Term::Drop(idx - child))
+    }
+
+    fn pair(&mut self, left: usize, right: usize) -> usize {
+        self.push(|idx| Term::Pair(idx - left, idx - right))
+    }
+
+    fn comp(&mut self, left: usize, right: usize) -> usize {
+        self.push(|idx| Term::Comp(idx - left, idx - right))
+    }
+
+    /// A node outputting the given bits, of which there are a power of
+    /// two, built on the node `unit`
+    fn constant(&mut self, unit: usize, bits: &[bool]) -> usize {
+        if let Some(&idx) = self.consts.get(bits) {
+            return idx;
+        }
+        let idx = if bits.len() == 1 {
+            if bits[0] {
+                self.push(|idx| Term::InjR(idx - unit))
+            } else {
+                self.push(|idx| Term::InjL(idx - unit))
+            }
+        } else {
+            let (left, right) = bits.split_at(bits.len() / 2);
+            let left = self.constant(unit, left);
+            let right = self.constant(unit, right);
+            self.pair(left, right)
+        };
+        self.consts.insert(bits.to_vec(), idx);
+        idx
+    }
+
+    fn finalize(self) -> Program<Ext> {
+        Program::from_untyped_nodes(
+            UnTypedProg(self.nodes),
+            &mut BitIter::from(vec![0x00].into_iter()),
+        )
+        .expect("generated program typechecks")
+    }
+}
+
+/// A program computing the SHA-256 hash of a message of `msg_bits` bits
+/// with the `sha256hashblock` jet
+///
+/// The program's input is the message, as a product of words of
+/// decreasing size, one for each bit set in `msg_bits`, whose bits in
+/// order are those of the message. It may be read from the message bits
+/// with `Value::from_bits_and_type` and the program's source type. The
+/// output is the 256-bit hash.
+pub fn sha256<Ext: extension::Jet>(msg_bits: usize) -> Program<Ext> {
+    // The padded message: the message, a 1 bit, zeros, and the length
+    let n_blocks = (msg_bits + 1 + 64 + 511) / 512;
+    let mut padded = vec![false; n_blocks * 512];
+    padded[msg_bits] = true;
+    let len_start = padded.len() - 64;
+    for (i, bit) in padded[len_start..].iter_mut().enumerate() {
+        *bit = (msg_bits as u64) & (1 << (63 - i)) != 0;
+    }
+
+    // Offset and width of each word of the input
+    let mut words = vec![];
+    let mut offset = 0;
+    for k in (0..64).rev() {
+        if (msg_bits as u64) & (1 << k) != 0 {
+            words.push((offset, 1 << k));
+            offset += 1 << k;
+        }
+    }
+
+    let mut b = Builder::<Ext>::new();
+    let unit = b.push(|_| Term::Unit);
+    let jet = b.push(|_| Term::Jet(JetsNode::Sha256HashBlock));
+    let iv: Vec<bool> = SHA256_IV
+        .iter()
+        .flat_map(|word| (0..32).map(move |i| word & (1 << (31 - i)) != 0))
+        .collect();
+    let mut state = b.constant(unit, &iv);
+    for i in 0..n_blocks {
+        let block = sha256_bits(&mut b, unit, &padded, msg_bits, &words, i * 512, 512);
+        let input = b.pair(state, block);
+        state = b.comp(input, jet);
+    }
+    b.finalize()
+}
+
+/// A node outputting the `len` bits of the padded message starting at
+/// `start`, where `len` is a power of two and `start` a multiple of it
+fn sha256_bits<Ext: extension::Jet>(
+    b: &mut Builder<Ext>,
+    unit: usize,
+    padded: &[bool],
+    msg_bits: usize,
+    words: &[(usize, usize)],
+    start: usize,
+    len: usize,
+) -> usize {
+    if start >= msg_bits {
+        return b.constant(unit, &padded[start..start + len]);
+    }
+    if start + len <= msg_bits {
+        // Read the bits straight from the input if they are a whole
+        // word, or half of one, or a quarter of one, ...
+        let (n, &(offset, width)) = words
+            .iter()
+            .enumerate()
+            .find(|&(_, &(offset, width))| start < offset + width)
+            .expect("bits are within the message");
+        if start + len <= offset + width && (start - offset) % len == 0 {
+            // Whether to drop or take at each step from the input down
+            let mut path = vec![true; n];
+            if n + 1 < words.len() {
+                path.push(false);
+            }
+            let (mut lo, mut width) = (offset, width);
+            while width > len {
+                width /= 2;
+                path.push(start >= lo + width);
+                if start >= lo + width {
+                    lo += width;
+                }
+            }
+            let mut node = b.push(|_| Term::Iden);
+            for &drop in path.iter().rev() {
+                node = if drop { b.drop(node) } else { b.take(node) };
+            }
+            return node;
+        }
+    }
+    let left = sha256_bits(b, unit, padded, msg_bits, words, start, len / 2);
+    let right = sha256_bits(b, unit, padded, msg_bits, words, start + len / 2, len / 2);
+    b.pair(left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_hashes::hex::FromHex;
+    use crate::bitcoin_hashes::{sha256, Hash};
+    use crate::exec::BitMachine;
+    use crate::extension::dummy::{DummyNode, TxEnv};
+    use crate::Value;
+
+    /// Check that the program for a message's length gives its hash
+    fn check_sha256(msg: &[u8], hash: &[u8]) {
+        let prog = sha256::<DummyNode>(msg.len() * 8);
+        let root = prog.root_node();
+        let mut bits = BitIter::from(msg.iter().cloned());
+        let input = Value::from_bits_and_type(&mut bits, &root.source_ty).unwrap();
+        let mut bits = BitIter::from(hash.iter().cloned());
+        let expected = Value::from_bits_and_type(&mut bits, &root.target_ty).unwrap();
+        let mut mac = BitMachine::for_program(&prog);
+        assert_eq!(
+            mac.exec_with_input(&prog, &input, &TxEnv),
+            Ok(expected),
+            "message of {} bytes",
+            msg.len(),
+        );
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        let vectors = [
+            (
+                &b""[..],
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            // 448 bits, so that the padding takes a second block
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            ),
+        ];
+        for &(msg, hash) in &vectors {
+            check_sha256(msg, &Vec::<u8>::from_hex(hash).unwrap());
+        }
+
+        // Lengths around the block boundaries, and ones which are not
+        // whole words
+        for &len in &[1, 31, 55, 56, 63, 64, 65, 100, 119, 120, 128, 200] {
+            let msg: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            check_sha256(&msg, &sha256::Hash::hash(&msg));
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Test Vectors
+//!
+//! A language-neutral format for program test cases, stored as TOML. Each
+//! vector gives a serialized program, an input and either the expected
+//! output or the expectation that the program fails. Values are given by
+//! their compact bit encoding, zero-padded to a whole number of bytes, as
+//! hex.
+//!
+
+use std::fmt;
+
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use serde::{Deserialize, Serialize};
+
+use crate::bititer::BitIter;
+use crate::exec::BitMachine;
+use crate::extension::Jet;
+use crate::{Program, Value};
+
+/// A single program test case
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TestVector {
+    /// Hex of the serialized program
+    pub program_hex: String,
+    /// Hex of the encoded input value
+    pub input_hex: String,
+    /// Hex of the encoded expected output value, if it should be checked
+    #[serde(default)]
+    pub output_hex: Option<String>,
+    /// Whether decoding or executing the program on the input should fail
+    #[serde(default)]
+    pub should_fail: bool,
+    /// Description of what the vector tests
+    pub description: String,
+}
+
+/// The outcome of running a test vector
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TestResult {
+    /// The vector behaved as expected
+    Pass,
+    /// The vector did not behave as expected, for the given reason
+    Fail(String),
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TestResult::Pass => f.write_str("pass"),
+            TestResult::Fail(ref reason) => write!(f, "fail: {}", reason),
+        }
+    }
+}
+
+/// The layout of a TOML file of test vectors, as a `[[vector]]` array
+#[derive(Serialize, Deserialize)]
+struct TestVectorFile {
+    vector: Vec<TestVector>,
+}
+
+/// Parse a TOML file of test vectors
+pub fn from_toml(s: &str) -> Result<Vec<TestVector>, toml::de::Error> {
+    toml::from_str::<TestVectorFile>(s).map(|file| file.vector)
+}
+
+/// Write test vectors as a TOML file
+pub fn to_toml(vectors: &[TestVector]) -> Result<String, toml::ser::Error> {
+    toml::to_string(&TestVectorFile {
+        vector: vectors.to_vec(),
+    })
+}
+
+/// Encode a value as hex of its compact bit encoding
+fn value_hex(value: &Value) -> String {
+    let mut bits: Vec<bool> = value.iter_bits().collect();
+    bits.resize((bits.len() + 7) / 8 * 8, false);
+    crate::core::bitvec_to_bytevec(bits).to_hex()
+}
+
+impl TestVector {
+    /// Decode the program and input, execute the program and check its
+    /// output, or that one of these steps fails if `should_fail` is set
+    ///
+    /// Programs which hit a hidden node panic rather than fail cleanly,
+    /// so cannot be used as failing vectors; see `Program::hidden_to_fail`.
+    pub fn run<Ext: Jet>(&self) -> TestResult
+    where
+        Ext::TxEnv: Default,
+    {
+        match (self.execute::<Ext>(), self.should_fail) {
+            (Ok(output), false) => match self.output_hex {
+                Some(ref expected) if !expected.eq_ignore_ascii_case(&value_hex(&output)) => {
+                    TestResult::Fail(format!(
+                        "expected output {}, got {} ({})",
+                        expected,
+                        value_hex(&output),
+                        output,
+                    ))
+                }
+                _ => TestResult::Pass,
+            },
+            (Ok(output), true) => {
+                TestResult::Fail(format!("expected failure, got output {}", output))
+            }
+            (Err(_), true) => TestResult::Pass,
+            (Err(e), false) => TestResult::Fail(e),
+        }
+    }
+
+    /// Decode the program and input and execute the program
+    fn execute<Ext: Jet>(&self) -> Result<Value, String>
+    where
+        Ext::TxEnv: Default,
+    {
+        let program_bytes = Vec::<u8>::from_hex(&self.program_hex)
+            .map_err(|e| format!("bad program hex: {}", e))?;
+        let program = Program::<Ext>::deserialize(&program_bytes)
+            .map_err(|e| format!("decoding program: {}", e))?;
+
+        let input_bytes =
+            Vec::<u8>::from_hex(&self.input_hex).map_err(|e| format!("bad input hex: {}", e))?;
+        let mut input_bits = BitIter::from(input_bytes.into_iter());
+        let input = Value::from_bits_and_type(&mut input_bits, &program.root_node().source_ty)
+            .map_err(|e| format!("decoding input: {}", e))?;
+
+        let mut mac = BitMachine::for_program(&program);
+        mac.exec_with_input(&program, &input, &Ext::TxEnv::default())
+            .map_err(|e| format!("executing program: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::dummy::DummyNode;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn run_all_test_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_vectors");
+        let mut n_files = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("toml".as_ref()) {
+                continue;
+            }
+            n_files += 1;
+
+            let vectors = from_toml(&fs::read_to_string(&path).unwrap()).unwrap();
+            assert!(!vectors.is_empty(), "{} has no vectors", path.display());
+            for vector in &vectors {
+                assert_eq!(
+                    vector.run::<DummyNode>(),
+                    TestResult::Pass,
+                    "{}: {}",
+                    path.display(),
+                    vector.description,
+                );
+            }
+        }
+        assert!(n_files > 0);
+    }
+
+    #[test]
+    fn toml_roundtrip() {
+        // injr unit
+        let vectors = vec![
+            TestVector {
+                program_hex: "8928".to_owned(),
+                input_hex: "".to_owned(),
+                output_hex: Some("80".to_owned()),
+                should_fail: false,
+                description: "injr unit".to_owned(),
+            },
+            TestVector {
+                program_hex: "89".to_owned(),
+                input_hex: "".to_owned(),
+                output_hex: None,
+                should_fail: true,
+                description: "truncated program".to_owned(),
+            },
+        ];
+        let s = to_toml(&vectors).unwrap();
+        assert_eq!(from_toml(&s).unwrap(), vectors);
+
+        for vector in &vectors {
+            assert_eq!(vector.run::<DummyNode>(), TestResult::Pass);
+        }
+        let mut wrong = vectors[0].clone();
+        wrong.output_hex = Some("00".to_owned());
+        assert_matches!(wrong.run::<DummyNode>(), TestResult::Fail(_));
+    }
+}
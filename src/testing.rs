@@ -0,0 +1,117 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Testing Utilities
+//!
+//! Helpers for checking the behaviour of individual jets by running each
+//! one as a program of its own on the Bit Machine. Jets of an extension,
+//! such as Bitcoin or Elements, are run in a given transaction environment.
+//!
+
+use crate::bititer::BitIter;
+use crate::core::types;
+use crate::exec::BitMachine;
+use crate::extension::dummy::{DummyNode, TxEnv};
+use crate::extension::jets::JetsNode;
+use crate::extension::Jet;
+use crate::{Program, Term, UnTypedProg, Value};
+
+#[doc(inline)]
+pub use crate::{assert_jet, assert_jet_fails};
+
+/// Run a program consisting of the single jet `jet` on `input`, giving
+/// its output, or `None` if execution failed
+pub fn exec_jet(jet: JetsNode, input: &Value) -> Option<Value> {
+    let prog = Program::<DummyNode>::from_untyped_nodes(
+        UnTypedProg(vec![Term::Jet(jet)]),
+        &mut BitIter::from(vec![0x00].into_iter()),
+    )
+    .expect("a single jet typechecks");
+    let mut mac = BitMachine::for_program(&prog);
+    mac.exec_with_input(&prog, input, &TxEnv).ok()
+}
+
+/// Run a program consisting of the single jet `jet` on the input laid out
+/// as `bits`, as in a Bit Machine frame, giving its output laid out the
+/// same way, or `None` if `bits` is not an input of the jet or execution
+/// failed
+pub fn exec_jet_bits(jet: JetsNode, bits: &[bool]) -> Option<Vec<bool>> {
+    let source_ty = types::final_type_from_name(&jet.source_type());
+    let target_ty = types::final_type_from_name(&jet.target_type());
+    let input = Value::from_padded_bits(bits, &source_ty).ok()?;
+    let output = exec_jet(jet, &input)?;
+    let bits = output
+        .iter_padded_bits(&target_ty)
+        .expect("jet output has its target type");
+    Some(bits.collect())
+}
+
+/// Run a program consisting of the single extension jet `jet` on `input`
+/// in the environment `txenv`, giving its output, or `None` if execution
+/// failed
+pub fn exec_ext_jet<Ext: Jet>(jet: Ext, input: &Value, txenv: &Ext::TxEnv) -> Option<Value> {
+    let prog = Program::<Ext>::from_untyped_nodes(
+        UnTypedProg(vec![Term::Ext(jet)]),
+        &mut BitIter::from(vec![0x00].into_iter()),
+    )
+    .expect("a single jet typechecks");
+    let mut mac = BitMachine::for_program(&prog);
+    mac.exec_with_input(&prog, input, txenv).ok()
+}
+
+/// Assert that running a jet on an input gives the expected output
+///
+/// Extension jets take their transaction environment as a fourth argument.
+#[macro_export]
+macro_rules! assert_jet {
+    ($jet:expr, $input:expr, $expected:expr) => {
+        assert_eq!(
+            $crate::testing::exec_jet($jet, &$input),
+            Some($expected),
+            "jet {}",
+            $jet,
+        )
+    };
+    ($jet:expr, $input:expr, $expected:expr, $txenv:expr) => {
+        assert_eq!(
+            $crate::testing::exec_ext_jet($jet, &$input, &$txenv),
+            Some($expected),
+            "jet {}",
+            $jet,
+        )
+    };
+}
+
+/// Assert that running a jet on an input fails
+///
+/// Extension jets take their transaction environment as a third argument.
+#[macro_export]
+macro_rules! assert_jet_fails {
+    ($jet:expr, $input:expr) => {
+        assert_eq!(
+            $crate::testing::exec_jet($jet, &$input),
+            None,
+            "jet {}",
+            $jet,
+        )
+    };
+    ($jet:expr, $input:expr, $txenv:expr) => {
+        assert_eq!(
+            $crate::testing::exec_ext_jet($jet, &$input, &$txenv),
+            None,
+            "jet {}",
+            $jet,
+        )
+    };
+}
@@ -0,0 +1,831 @@
+// Rust Simplicity Library
+// Written in 2020 by
+//   Andrew Poelstra <apoelstra@blockstream.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Text Format
+//!
+//! Functionality to work with programs in the line-based text format used
+//! by the `Display` implementation of `ProgramNode`, where each line has
+//! the form `[index] combinator(children)`, optionally followed by a type
+//! annotation `: source → target`. Child references are relative indices,
+//! as in the binary encoding.
+//!
+//! There is also a surface syntax for writing programs by hand, read by
+//! `parse_program`. A program is a list of definitions `name := expr`,
+//! each starting on a new line and continuing onto any following lines
+//! which are indented or inside parentheses, and its root is the
+//! definition named `main`. An expression is a combinator applied to its arguments,
+//! as in `comp (pair iden iden) jet_adder32`, where each argument is the
+//! name of an earlier definition, a combinator without arguments, a jet
+//! `jet_<name>` or a parenthesized expression. Word constants are written
+//! `const 0x2a` or `const 0b1`, and must be a power of two bits wide;
+//! `hidden` takes the 256-bit CMR of the hidden node. Comments start with
+//! `--` and run to the end of the line. `Program::to_source` renders a
+//! program in this syntax.
+//!
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::{cmp, error, fmt};
+
+use crate::bititer::BitIter;
+use crate::core::term::DagTerm;
+use crate::core::types;
+use crate::extension::dummy::DummyNode;
+use crate::extension::{jets::JetsNode, Jet};
+use crate::{Error, Program, Term, UnTypedProg, Value};
+
+/// Error parsing or typing a program in the text format
+#[derive(Debug)]
+pub enum AnnotateError {
+    /// A line could not be parsed
+    Parse {
+        /// Line number (starting from 1) of the bad line
+        line: usize,
+        /// Description of the problem
+        reason: &'static str,
+    },
+    /// The program failed to typecheck
+    Type(Error),
+}
+
+impl fmt::Display for AnnotateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AnnotateError::Parse { line, reason } => write!(f, "line {}: {}", line, reason),
+            AnnotateError::Type(ref e) => write!(f, "type error: {}", e),
+        }
+    }
+}
+
+impl error::Error for AnnotateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            AnnotateError::Parse { .. } => None,
+            AnnotateError::Type(ref e) => error::Error::source(e),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<Error> for AnnotateError {
+    fn from(e: Error) -> AnnotateError {
+        AnnotateError::Type(e)
+    }
+}
+
+/// Remove the type annotation, if any, from a single line
+fn strip_line(line: &str) -> &str {
+    match line.find(':') {
+        Some(pos) => line[..pos].trim_end(),
+        None => line.trim_end(),
+    }
+}
+
+/// Parse the relative child indices of a combinator, checking that they
+/// point to earlier nodes
+fn parse_children(
+    args: Option<&str>,
+    idx: usize,
+    line: usize,
+) -> Result<Vec<usize>, AnnotateError> {
+    let args = match args {
+        Some(args) => args,
+        None => return Ok(vec![]),
+    };
+    args.split(',')
+        .map(|arg| match arg.trim().parse::<usize>() {
+            Ok(i) if i > 0 && i <= idx => Ok(i),
+            Ok(_) => Err(AnnotateError::Parse {
+                line: line,
+                reason: "child index out of range",
+            }),
+            Err(_) => Err(AnnotateError::Parse {
+                line: line,
+                reason: "bad child index",
+            }),
+        })
+        .collect()
+}
+
+/// Parse the combinator part of a line, without its index or annotation
+fn parse_term(body: &str, idx: usize, line: usize) -> Result<Term<(), DummyNode>, AnnotateError> {
+    let bad = |reason| AnnotateError::Parse {
+        line: line,
+        reason: reason,
+    };
+
+    if body.starts_with("[jet]") {
+        return body[5..]
+            .parse()
+            .map(Term::Jet)
+            .map_err(|_| bad("unknown jet"));
+    }
+    if body.starts_with("[ext]") {
+        return Err(bad("extension nodes are not supported"));
+    }
+
+    let (name, args) = match body.find('(') {
+        Some(pos) if body.ends_with(')') => (&body[..pos], Some(&body[pos + 1..body.len() - 1])),
+        Some(_) => return Err(bad("unclosed parenthesis")),
+        None => (body, None),
+    };
+    let children = parse_children(args, idx, line)?;
+    match (name, &children[..]) {
+        ("iden", []) => Ok(Term::Iden),
+        ("unit", []) => Ok(Term::Unit),
+        ("witness", []) => Ok(Term::Witness(())),
+        // The hidden CMR does not affect typing, and is not displayed
+        ("hidden", []) => Ok(Term::Hidden([0; 32].into())),
+        ("injl", &[i]) => Ok(Term::InjL(i)),
+        ("injr", &[i]) => Ok(Term::InjR(i)),
+        ("take", &[i]) => Ok(Term::Take(i)),
+        ("drop", &[i]) => Ok(Term::Drop(i)),
+        ("comp", &[i, j]) => Ok(Term::Comp(i, j)),
+        ("case", &[i, j]) => Ok(Term::Case(i, j)),
+        ("pair", &[i, j]) => Ok(Term::Pair(i, j)),
+        ("disconnect", &[i, j]) => Ok(Term::Disconnect(i, j)),
+        ("fail", _) => Err(bad("fail nodes cannot be typechecked")),
+        ("iden", _)
+        | ("unit", _)
+        | ("witness", _)
+        | ("hidden", _)
+        | ("injl", _)
+        | ("injr", _)
+        | ("take", _)
+        | ("drop", _)
+        | ("comp", _)
+        | ("case", _)
+        | ("pair", _)
+        | ("disconnect", _) => Err(bad("wrong number of children")),
+        _ => Err(bad("unknown combinator")),
+    }
+}
+
+/// Run type inference on a program in the text format, returning it with
+/// every node annotated with its source and target type. Any existing
+/// annotations are replaced.
+pub fn type_annotate(program_text: &str) -> Result<String, AnnotateError> {
+    let mut bodies = vec![];
+    let mut nodes = vec![];
+    for (n, line) in program_text.lines().enumerate() {
+        let stripped = strip_line(line).trim_start();
+        if stripped.is_empty() {
+            continue;
+        }
+
+        let idx = nodes.len();
+        let bad = |reason| AnnotateError::Parse {
+            line: n + 1,
+            reason: reason,
+        };
+        let close = match (stripped.starts_with('['), stripped.find(']')) {
+            (true, Some(close)) => close,
+            _ => return Err(bad("missing node index")),
+        };
+        if stripped[1..close].parse::<usize>() != Ok(idx) {
+            return Err(bad("node index out of sequence"));
+        }
+
+        let body = stripped[close + 1..].trim();
+        nodes.push(parse_term(body, idx, n + 1)?);
+        bodies.push(stripped);
+    }
+
+    let typed = types::type_check(UnTypedProg(nodes))?;
+    let mut ret = String::new();
+    for (body, node) in bodies.into_iter().zip(typed) {
+        ret.push_str(&format!(
+            "{}: {} → {}\n",
+            body, node.source_ty, node.target_ty
+        ));
+    }
+    Ok(ret)
+}
+
+/// Remove all type annotations from a program in the text format
+pub fn strip_annotations(program_text: &str) -> String {
+    let mut ret = String::new();
+    for line in program_text.lines() {
+        ret.push_str(strip_line(line));
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Error parsing a program in the surface syntax
+#[derive(Debug)]
+pub enum ParseError {
+    /// The source is not a well-formed program
+    Syntax {
+        /// Line number (starting from 1) of the problem
+        line: usize,
+        /// Column number (starting from 1) of the problem
+        column: usize,
+        /// Description of the problem
+        reason: &'static str,
+    },
+    /// The program failed to typecheck, or its witnesses could not be
+    /// read
+    Finalize(Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Syntax {
+                line,
+                column,
+                reason,
+            } => write!(f, "line {}, column {}: {}", line, column, reason),
+            ParseError::Finalize(ref e) => write!(f, "finalizing: {}", e),
+        }
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ParseError::Syntax { .. } => None,
+            ParseError::Finalize(ref e) => error::Error::source(e),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<Error> for ParseError {
+    fn from(e: Error) -> ParseError {
+        ParseError::Finalize(e)
+    }
+}
+
+/// A token of the surface syntax
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Token<'a> {
+    /// A name, keyword or jet
+    Name(&'a str),
+    /// A number, starting with a digit
+    Literal(&'a str),
+    /// `:=`
+    Define,
+    /// `(`
+    Open,
+    /// `)`
+    Close,
+    /// The end of a definition
+    Newline,
+    /// The end of the source
+    End,
+}
+
+/// Combinators which take no arguments
+const NULLARY: [&str; 3] = ["iden", "unit", "witness"];
+/// Words which are not combinators without arguments, but cannot be used
+/// as names
+const KEYWORDS: [&str; 10] = [
+    "injl",
+    "injr",
+    "take",
+    "drop",
+    "comp",
+    "case",
+    "pair",
+    "disconnect",
+    "const",
+    "hidden",
+];
+
+/// Split the source of a program into tokens, each with its line and
+/// column number
+fn tokenize(src: &str) -> Result<Vec<(Token<'_>, usize, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut depth = 0usize;
+    let mut end = (1, 1);
+    for (n, line) in src.lines().enumerate() {
+        let code = match line.find("--") {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        if code.trim().is_empty() {
+            continue;
+        }
+        // An unindented line outside parentheses starts a new definition
+        if depth == 0 && !code.starts_with(char::is_whitespace) && !tokens.is_empty() {
+            tokens.push((Token::Newline, end.0, end.1));
+        }
+        let column = |pos: usize| code[..pos].chars().count() + 1;
+
+        let mut chars = code.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '(' => {
+                    depth += 1;
+                    Token::Open
+                }
+                ')' => {
+                    depth = depth.saturating_sub(1);
+                    Token::Close
+                }
+                ':' if chars.peek().map(|&(_, c)| c) == Some('=') => {
+                    chars.next();
+                    Token::Define
+                }
+                c if c.is_ascii_alphanumeric() || c == '_' => {
+                    let mut stop = start + 1;
+                    while let Some(&(pos, c)) = chars.peek() {
+                        if !c.is_ascii_alphanumeric() && c != '_' {
+                            break;
+                        }
+                        stop = pos + 1;
+                        chars.next();
+                    }
+                    if c.is_ascii_digit() {
+                        Token::Literal(&code[start..stop])
+                    } else {
+                        Token::Name(&code[start..stop])
+                    }
+                }
+                _ => {
+                    return Err(ParseError::Syntax {
+                        line: n + 1,
+                        column: column(start),
+                        reason: "unexpected character",
+                    })
+                }
+            };
+            tokens.push((token, n + 1, column(start)));
+        }
+
+        end = (n + 1, code.trim_end().chars().count() + 1);
+    }
+    tokens.push((Token::End, end.0, end.1));
+    Ok(tokens)
+}
+
+/// Decode a `0x` or `0b` literal into its bits, most significant first
+fn literal_bits(literal: &str) -> Option<Vec<bool>> {
+    let (digits, radix, width) = if literal.starts_with("0x") {
+        (&literal[2..], 16, 4)
+    } else if literal.starts_with("0b") {
+        (&literal[2..], 2, 1)
+    } else {
+        return None;
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut bits = Vec::with_capacity(digits.len() * width);
+    for c in digits.chars() {
+        let digit = c.to_digit(radix)?;
+        bits.extend((0..width).rev().map(|i| digit & (1 << i) != 0));
+    }
+    Some(bits)
+}
+
+/// Parser state for a program in the surface syntax
+struct Parser<'a, Ext> {
+    tokens: Vec<(Token<'a>, usize, usize)>,
+    pos: usize,
+    /// Expression of each definition so far
+    names: HashMap<&'a str, Rc<DagTerm<(), Ext>>>,
+    /// Expression of each constant so far, so equal constants are shared
+    consts: HashMap<Value, Rc<DagTerm<(), Ext>>>,
+}
+
+impl<'a, Ext> Parser<'a, Ext> {
+    /// The token at `pos`, or the end of the source if it is past the
+    /// last token
+    fn token(&self, pos: usize) -> (Token<'a>, usize, usize) {
+        self.tokens[cmp::min(pos, self.tokens.len() - 1)]
+    }
+
+    fn peek(&self) -> Token<'a> {
+        self.token(self.pos).0
+    }
+
+    /// Move to the next token, returning the current one
+    fn next(&mut self) -> Token<'a> {
+        self.pos += 1;
+        self.token(self.pos - 1).0
+    }
+
+    /// An error at the position of the token `back` tokens before the
+    /// current one
+    fn error(&self, back: usize, reason: &'static str) -> ParseError {
+        let (_, line, column) = self.token(self.pos - back);
+        ParseError::Syntax {
+            line: line,
+            column: column,
+            reason: reason,
+        }
+    }
+
+    /// Parse the whole program, returning the expression named `main`
+    fn program(&mut self) -> Result<Rc<DagTerm<(), Ext>>, ParseError> {
+        loop {
+            let name = match self.next() {
+                Token::Newline => continue,
+                Token::End => break,
+                Token::Name(name) => name,
+                _ => return Err(self.error(1, "expected a definition")),
+            };
+            if name.starts_with("jet_") || NULLARY.contains(&name) || KEYWORDS.contains(&name) {
+                return Err(self.error(1, "reserved word"));
+            }
+            if self.names.contains_key(name) {
+                return Err(self.error(1, "name already defined"));
+            }
+            if self.next() != Token::Define {
+                return Err(self.error(1, "expected `:=`"));
+            }
+
+            let expr = self.expr()?;
+            match self.peek() {
+                Token::Newline | Token::End => {}
+                _ => return Err(self.error(0, "expected end of line")),
+            }
+            self.names.insert(name, expr);
+        }
+
+        match self.names.get("main") {
+            Some(main) => Ok(Rc::clone(main)),
+            None => Err(self.error(0, "no definition of `main`")),
+        }
+    }
+
+    /// Parse an expression, which may be a combinator applied to its
+    /// arguments
+    fn expr(&mut self) -> Result<Rc<DagTerm<(), Ext>>, ParseError> {
+        let name = match self.peek() {
+            Token::Name(name) if KEYWORDS.contains(&name) => name,
+            _ => return self.atom(),
+        };
+        self.next();
+
+        let term = match name {
+            "injl" => DagTerm::InjL(self.atom()?),
+            "injr" => DagTerm::InjR(self.atom()?),
+            "take" => DagTerm::Take(self.atom()?),
+            "drop" => DagTerm::Drop(self.atom()?),
+            "comp" => DagTerm::Comp(self.atom()?, self.atom()?),
+            "case" => DagTerm::Case(self.atom()?, self.atom()?),
+            "pair" => DagTerm::Pair(self.atom()?, self.atom()?),
+            "disconnect" => DagTerm::Disconnect(self.atom()?, self.atom()?),
+            "const" => {
+                let bits = self.literal()?;
+                let width = bits.len();
+                if !width.is_power_of_two() || width > 512 {
+                    return Err(self.error(1, "constant must be 1, 2, 4, ..., or 512 bits wide"));
+                }
+                let ty = &types::pow2_types()[width.trailing_zeros() as usize + 1];
+                let value = Value::from_bits_and_type(&mut bits.into_iter(), ty)
+                    .expect("bits match the width of the type");
+                return Ok(self.scribe(&value));
+            }
+            "hidden" => {
+                let bits = self.literal()?;
+                if bits.len() != 256 {
+                    return Err(self.error(1, "hidden CMR must be 256 bits wide"));
+                }
+                let mut cmr = [0; 32];
+                for (byte, chunk) in cmr.iter_mut().zip(bits.chunks(8)) {
+                    *byte = chunk.iter().fold(0, |acc, &bit| acc << 1 | bit as u8);
+                }
+                DagTerm::Hidden(cmr.into())
+            }
+            _ => unreachable!("all keywords are handled"),
+        };
+        Ok(Rc::new(term))
+    }
+
+    /// Parse an argument of a combinator
+    fn atom(&mut self) -> Result<Rc<DagTerm<(), Ext>>, ParseError> {
+        let term = match self.next() {
+            Token::Name("iden") => DagTerm::Iden,
+            Token::Name("unit") => DagTerm::Unit,
+            Token::Name("witness") => DagTerm::Witness(()),
+            Token::Name(name) if name.starts_with("jet_") => match name[4..].parse::<JetsNode>() {
+                Ok(jet) => DagTerm::Jet(jet),
+                Err(_) => return Err(self.error(1, "unknown jet")),
+            },
+            Token::Name(name) if KEYWORDS.contains(&name) => {
+                return Err(self.error(1, "combinator argument must be parenthesized"))
+            }
+            Token::Name(name) => {
+                return match self.names.get(name) {
+                    Some(expr) => Ok(Rc::clone(expr)),
+                    None => Err(self.error(1, "undefined name")),
+                }
+            }
+            Token::Open => {
+                let expr = self.expr()?;
+                if self.next() != Token::Close {
+                    return Err(self.error(1, "expected `)`"));
+                }
+                return Ok(expr);
+            }
+            _ => return Err(self.error(1, "expected an expression")),
+        };
+        Ok(Rc::new(term))
+    }
+
+    /// Parse a literal, giving its bits
+    fn literal(&mut self) -> Result<Vec<bool>, ParseError> {
+        match self.next() {
+            Token::Literal(literal) => {
+                literal_bits(literal).ok_or_else(|| self.error(1, "bad literal"))
+            }
+            _ => Err(self.error(1, "expected a `0x` or `0b` literal")),
+        }
+    }
+
+    /// Expression for the constant function with output `value`
+    fn scribe(&mut self, value: &Value) -> Rc<DagTerm<(), Ext>> {
+        if let Some(expr) = self.consts.get(value) {
+            return Rc::clone(expr);
+        }
+        let term = match *value {
+            Value::Unit => DagTerm::Unit,
+            Value::SumL(ref v) => DagTerm::InjL(self.scribe(v)),
+            Value::SumR(ref v) => DagTerm::InjR(self.scribe(v)),
+            Value::Prod(ref a, ref b) => DagTerm::Pair(self.scribe(a), self.scribe(b)),
+        };
+        let expr = Rc::new(term);
+        self.consts.insert(value.clone(), Rc::clone(&expr));
+        expr
+    }
+}
+
+/// Parse a program in the surface syntax into untyped nodes, with
+/// witness nodes as placeholders
+///
+/// Definitions are shared wherever they are used, as are equal constants,
+/// while definitions which `main` does not use are dropped.
+pub fn parse_untyped<Ext: Jet + Clone + Hash>(
+    src: &str,
+) -> Result<UnTypedProg<(), Ext>, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+        names: HashMap::new(),
+        consts: HashMap::new(),
+    };
+    let main = parser.program()?;
+    Ok((*main).clone().into_untyped_prog())
+}
+
+/// Parse a program in the surface syntax, then typecheck it and read the
+/// values of its witness nodes from `witness`, as `Program::decode` does
+/// after reading the nodes
+pub fn parse_program<Ext, I>(
+    src: &str,
+    witness: &mut BitIter<I>,
+) -> Result<Program<Ext>, ParseError>
+where
+    Ext: Jet + Clone + Hash,
+    I: Iterator<Item = u8>,
+{
+    let nodes = parse_untyped(src)?;
+    Ok(Program::from_untyped_nodes(nodes, witness)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::BitMachine;
+    use crate::extension::dummy::TxEnv;
+    use bitcoin_hashes::hex::{FromHex, ToHex};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn annotate_roundtrip() {
+        let text = "[0] unit\n[1] injl(1)\n[2] pair(1, 2)\n";
+        let annotated = type_annotate(text).unwrap();
+        assert_eq!(
+            annotated,
+            "[0] unit: 1 → 1\n[1] injl(1): 1 → 2\n[2] pair(1, 2): 1 → (2 × 1)\n"
+        );
+        assert_eq!(strip_annotations(&annotated), text);
+        // Annotating an annotated program replaces the annotations
+        assert_eq!(type_annotate(&annotated).unwrap(), annotated);
+
+        // The format is that of `ProgramNode`'s `Display`
+        let prog =
+            Program::<DummyNode>::decode(&mut BitIter::from(vec![0x89, 0x20].into_iter())).unwrap();
+        let displayed: String = prog.nodes.iter().map(|n| format!("{}\n", n)).collect();
+        assert_eq!(
+            type_annotate(&strip_annotations(&displayed)).unwrap(),
+            displayed
+        );
+    }
+
+    #[test]
+    fn annotate_jet() {
+        let annotated = type_annotate("[0] [jet]adder32").unwrap();
+        assert_eq!(annotated, "[0] [jet]adder32: 2^64 → (2 × 2^32)\n");
+    }
+
+    #[test]
+    fn annotate_errors() {
+        assert_matches!(
+            type_annotate("[0] unit\n\n[2] iden"),
+            Err(AnnotateError::Parse { line: 3, .. })
+        );
+        assert_matches!(
+            type_annotate("[0] injl(1)"),
+            Err(AnnotateError::Parse { line: 1, .. })
+        );
+        assert_matches!(
+            type_annotate("[0] bogus"),
+            Err(AnnotateError::Parse { line: 1, .. })
+        );
+        assert_matches!(
+            type_annotate("[0] unit\n[1] comp(1)"),
+            Err(AnnotateError::Parse { line: 2, .. })
+        );
+        // take iden : A × B → A, composed with itself cannot unify
+        assert_matches!(
+            type_annotate("[0] iden\n[1] take(1)\n[2] unit\n[3] case(1, 3)\n[4] comp(1, 1)"),
+            Err(AnnotateError::Type(..))
+        );
+    }
+
+    /// Bits of a value, as hex zero-padded to a whole number of bytes
+    fn value_hex(value: &Value) -> String {
+        let mut bits: Vec<bool> = value.iter_bits().collect();
+        bits.resize((bits.len() + 7) / 8 * 8, false);
+        crate::core::bitvec_to_bytevec(bits).to_hex()
+    }
+
+    #[test]
+    fn parse_corpus() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_vectors/text");
+        let mut n_files = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("simpl".as_ref()) {
+                continue;
+            }
+            n_files += 1;
+
+            // Each file gives its input, output and witness in comments
+            let src = fs::read_to_string(&path).unwrap();
+            let header = |key: &str| {
+                src.lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .map(|hex| Vec::<u8>::from_hex(hex.trim()).unwrap())
+            };
+            let input = header("-- input:").unwrap();
+            let output = header("-- output:").unwrap();
+            let witness = header("-- witness:").unwrap_or_else(|| vec![0x00]);
+
+            let prog = parse_program::<DummyNode, _>(&src, &mut BitIter::from(witness.into_iter()))
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let input = Value::from_bits_and_type(
+                &mut BitIter::from(input.into_iter()),
+                &prog.root_node().source_ty,
+            )
+            .unwrap();
+            let mut mac = BitMachine::for_program(&prog);
+            let result = mac.exec_with_input(&prog, &input, &TxEnv).unwrap();
+            assert_eq!(value_hex(&result), output.to_hex(), "{}", path.display());
+
+            // Printing the program gives source for the same program
+            let printed = parse_program::<DummyNode, _>(
+                &prog.to_source(),
+                &mut BitIter::from(vec![0x00; 16].into_iter()),
+            )
+            .unwrap();
+            assert_eq!(printed.root_node().cmr, prog.root_node().cmr);
+        }
+        assert!(n_files > 0);
+    }
+
+    #[test]
+    fn parse_sharing() {
+        // Definitions and equal constants are shared, witnesses are not,
+        // and unused definitions are dropped
+        let nodes = parse_untyped::<DummyNode>(
+            "unused := iden\nw := witness\nc := pair w w\nmain := pair (pair c c) (pair witness (const 0x11))",
+        )
+        .unwrap();
+        assert_eq!(
+            nodes.0,
+            vec![
+                Term::Witness(()),
+                Term::Pair(1, 1),
+                Term::Pair(1, 1),
+                Term::Witness(()),
+                // 0x11 = ((0, 0), (0, 1)), ((0, 0), (0, 1))
+                Term::Unit,
+                Term::InjL(1),
+                Term::Pair(1, 1),
+                Term::InjR(3),
+                Term::Pair(3, 1),
+                Term::Pair(3, 1),
+                Term::Pair(1, 1),
+                Term::Pair(8, 1),
+                Term::Pair(10, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let syntax_error = |src| match parse_untyped::<DummyNode>(src) {
+            Err(ParseError::Syntax {
+                line,
+                column,
+                reason,
+            }) => (line, column, reason),
+            Err(e) => panic!("{}: unexpected error {}", src, e),
+            Ok(_) => panic!("{}: parsed", src),
+        };
+
+        assert_eq!(syntax_error("main := bogus"), (1, 9, "undefined name"));
+        assert_eq!(
+            syntax_error("a := unit\nmain := comp a b"),
+            (2, 16, "undefined name")
+        );
+        assert_eq!(
+            syntax_error("main := unit\nmain := iden"),
+            (2, 1, "name already defined")
+        );
+        assert_eq!(syntax_error("iden := unit"), (1, 1, "reserved word"));
+        assert_eq!(syntax_error("main unit"), (1, 6, "expected `:=`"));
+        assert_eq!(syntax_error(":= unit"), (1, 1, "expected a definition"));
+        assert_eq!(syntax_error("main := (unit"), (1, 14, "expected `)`"));
+        assert_eq!(
+            syntax_error("main := pair unit"),
+            (1, 18, "expected an expression")
+        );
+        assert_eq!(syntax_error("main := )"), (1, 9, "expected an expression"));
+        // An unindented line outside parentheses ends the definition
+        assert_eq!(
+            syntax_error("main :=\npair unit unit"),
+            (1, 8, "expected an expression")
+        );
+        assert_eq!(
+            syntax_error("main := unit iden"),
+            (1, 14, "expected end of line")
+        );
+        assert_eq!(syntax_error("main := jet_bogus"), (1, 9, "unknown jet"));
+        assert_eq!(
+            syntax_error("main := injl injl unit"),
+            (1, 14, "combinator argument must be parenthesized")
+        );
+        assert_eq!(
+            syntax_error("main := const 0x123"),
+            (1, 15, "constant must be 1, 2, 4, ..., or 512 bits wide")
+        );
+        assert_eq!(syntax_error("main := const 0xzz"), (1, 15, "bad literal"));
+        assert_eq!(syntax_error("main := const 12"), (1, 15, "bad literal"));
+        assert_eq!(
+            syntax_error("main := const unit"),
+            (1, 15, "expected a `0x` or `0b` literal")
+        );
+        assert_eq!(
+            syntax_error("main := hidden 0x00"),
+            (1, 16, "hidden CMR must be 256 bits wide")
+        );
+        assert_eq!(
+            syntax_error("-- comment\nmain := unit $"),
+            (2, 14, "unexpected character")
+        );
+        assert_eq!(
+            syntax_error("x := unit"),
+            (1, 10, "no definition of `main`")
+        );
+    }
+
+    #[test]
+    fn parse_finalize_errors() {
+        let parse = |src, witness: Vec<u8>| {
+            parse_program::<DummyNode, _>(src, &mut BitIter::from(witness.into_iter()))
+        };
+        assert_matches!(
+            parse("main := comp jet_adder32 jet_adder32", vec![0x00]),
+            Err(ParseError::Finalize(Error::TypeCheck))
+        );
+        // Not enough witness data for a 64-bit witness
+        assert_matches!(
+            parse("main := comp witness jet_adder32", vec![0x00]),
+            Err(ParseError::Finalize(Error::EndOfStream))
+        );
+        assert!(parse("main := comp witness jet_adder32", vec![0x00; 9]).is_ok());
+    }
+}